@@ -73,8 +73,14 @@ Future<FlowyResult<Uint8List, Uint8List>> _extractPayload(
           case FFIStatusCode.Err:
             return FlowyFailure(Uint8List.fromList(response.payload));
           case FFIStatusCode.Internal:
+          case FFIStatusCode.InvalidParams:
+          case FFIStatusCode.NotFound:
+          case FFIStatusCode.Unauthorized:
+          case FFIStatusCode.Timeout:
+          case FFIStatusCode.Cancelled:
+          case FFIStatusCode.Busy:
             final error = utf8.decode(response.payload);
-            Log.error("Dispatch internal error: $error");
+            Log.error("Dispatch ${response.code} error: $error");
             return FlowyFailure(emptyBytes());
           default:
             Log.error("Impossible to here");
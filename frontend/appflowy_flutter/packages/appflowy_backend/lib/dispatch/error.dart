@@ -4,6 +4,13 @@ class FlowyInternalError {
   late FFIStatusCode _statusCode;
   late String _error;
 
+  /// The key of the message that can be translated, e.g. `dispatch.timeout`.
+  String? messageKey;
+  Map<String, String> messageArgs = {};
+
+  /// The message translated by the localizer of the dispatcher.
+  String? localizedMessage;
+
   FFIStatusCode get statusCode {
     return _statusCode;
   }
@@ -27,7 +34,16 @@ class FlowyInternalError {
   }
 
   factory FlowyInternalError.from(FFIResponse resp) {
-    return FlowyInternalError(statusCode: resp.code, error: "");
+    const argPrefix = "error-message-arg.";
+    final error = FlowyInternalError(statusCode: resp.code, error: "");
+    error.messageKey = resp.metadata["error-message-key"];
+    error.localizedMessage = resp.metadata["error-message"];
+    for (final entry in resp.metadata.entries) {
+      if (entry.key.startsWith(argPrefix)) {
+        error.messageArgs[entry.key.substring(argPrefix.length)] = entry.value;
+      }
+    }
+    return error;
   }
 }
 
@@ -110,6 +110,27 @@ typedef _set_stream_port_Dart = int Function(
   int port,
 );
 
+/// C function `alloc_payload_buffer`.
+Pointer<Uint8> alloc_payload_buffer(
+  int len,
+  Pointer<Int64> handle,
+) {
+  return _alloc_payload_buffer(len, handle);
+}
+
+final _alloc_payload_buffer_Dart _alloc_payload_buffer = _dart_ffi_lib
+    .lookupFunction<_alloc_payload_buffer_C, _alloc_payload_buffer_Dart>(
+        'alloc_payload_buffer');
+
+typedef _alloc_payload_buffer_C = Pointer<Uint8> Function(
+  Uint64 len,
+  Pointer<Int64> handle,
+);
+typedef _alloc_payload_buffer_Dart = Pointer<Uint8> Function(
+  int len,
+  Pointer<Int64> handle,
+);
+
 /// C function `set log stream port`.
 int set_log_stream_port(int port) {
   return _set_log_stream_port(port);
@@ -12,7 +12,7 @@ pub struct AFTauriRequest {
 
 impl std::convert::From<AFTauriRequest> for AFPluginRequest {
   fn from(event: AFTauriRequest) -> Self {
-    AFPluginRequest::new(event.ty).payload(event.payload)
+    AFPluginRequest::untyped(event.ty).payload(event.payload)
   }
 }
 
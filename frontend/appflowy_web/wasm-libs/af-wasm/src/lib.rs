@@ -142,7 +142,7 @@ impl WasmRequest {
 
 impl From<WasmRequest> for AFPluginRequest {
   fn from(request: WasmRequest) -> Self {
-    AFPluginRequest::new(request.name).payload(request.payload)
+    AFPluginRequest::untyped(request.name).payload(request.payload)
   }
 }
 
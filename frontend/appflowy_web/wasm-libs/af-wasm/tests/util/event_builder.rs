@@ -1,12 +1,7 @@
 use af_wasm::core::AppFlowyWASMCore;
 use flowy_error::{internal_error, FlowyError};
 use std::rc::Rc;
-use std::{
-  convert::TryFrom,
-  fmt::{Debug, Display},
-  hash::Hash,
-  sync::Arc,
-};
+use std::{convert::TryFrom, sync::Arc};
 
 use lib_dispatch::prelude::{
   AFPluginDispatcher, AFPluginEventResponse, AFPluginFromBytes, AFPluginRequest, ToBytes, *,
@@ -42,7 +37,7 @@ impl EventBuilder {
 
   pub fn event<Event>(mut self, event: Event) -> Self
   where
-    Event: Eq + Hash + Debug + Clone + Display,
+    Event: AFPluginEventType,
   {
     self.context.request = Some(AFPluginRequest::new(event));
     self
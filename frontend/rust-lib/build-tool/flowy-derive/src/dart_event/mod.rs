@@ -1,8 +1,14 @@
 use proc_macro2::TokenStream;
 
 // #[proc_macro_derive(DartEvent, attributes(event_ty))]
-pub fn expand_enum_derive(_input: &syn::DeriveInput) -> Result<TokenStream, Vec<syn::Error>> {
-  Ok(TokenStream::default())
+pub fn expand_enum_derive(input: &syn::DeriveInput) -> Result<TokenStream, Vec<syn::Error>> {
+  // The dart code of the events is generated by flowy-codegen, only marks the enum as an event
+  // that can be registered to the plugin.
+  let ident = &input.ident;
+  let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+  Ok(quote! {
+    impl #impl_generics ::lib_dispatch::prelude::AFPluginEventType for #ident #ty_generics #where_clause {}
+  })
 }
 
 // use flowy_ast::{ASTContainer, Ctxt};
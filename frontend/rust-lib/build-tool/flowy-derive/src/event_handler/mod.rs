@@ -0,0 +1,35 @@
+use proc_macro2::TokenStream;
+
+// #[event_handler(DocumentEvent::CreateDocument)]
+pub fn expand_event_handler(
+  attr: TokenStream,
+  item: &syn::ItemFn,
+) -> Result<TokenStream, Vec<syn::Error>> {
+  let event: syn::Path = syn::parse2(attr).map_err(|e| vec![e])?;
+  if !item.sig.generics.params.is_empty() {
+    return Err(vec![syn::Error::new_spanned(
+      &item.sig.generics,
+      "event_handler doesn't support the generic handlers",
+    )]);
+  }
+
+  let vis = &item.vis;
+  let ident = &item.sig.ident;
+  let doc = format!("Registers `{}` to the plugin.", ident);
+  // The module shares the handler's name, it lives in the type namespace so they don't collide.
+  Ok(quote! {
+    #item
+
+    #[doc(hidden)]
+    #[allow(non_snake_case)]
+    #vis mod #ident {
+      #[allow(unused_imports)]
+      use super::*;
+
+      #[doc = #doc]
+      pub fn register(plugin: ::lib_dispatch::prelude::AFPlugin) -> ::lib_dispatch::prelude::AFPlugin {
+        plugin.event(#event, super::#ident)
+      }
+    }
+  })
+}
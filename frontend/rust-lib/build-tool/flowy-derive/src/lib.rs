@@ -3,12 +3,13 @@ extern crate proc_macro;
 
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, DeriveInput};
+use syn::{parse_macro_input, DeriveInput, ItemFn};
 
 #[macro_use]
 extern crate quote;
 
 mod dart_event;
+mod event_handler;
 mod node;
 mod proto_buf;
 
@@ -37,6 +38,16 @@ pub fn derive_dart_event(input: TokenStream) -> TokenStream {
     .into()
 }
 
+/// Registers the async fn as the handler of the event with the generated `register` function,
+/// e.g. `AFPlugin::new().handler(create_document_handler::register)`.
+#[proc_macro_attribute]
+pub fn event_handler(attr: TokenStream, item: TokenStream) -> TokenStream {
+  let item = parse_macro_input!(item as ItemFn);
+  event_handler::expand_event_handler(attr.into(), &item)
+    .unwrap_or_else(to_compile_errors)
+    .into()
+}
+
 #[proc_macro_derive(Node, attributes(node, nodes, node_type))]
 pub fn derive_node(input: TokenStream) -> TokenStream {
   let input = parse_macro_input!(input as DeriveInput);
@@ -11,6 +11,8 @@ const uint8_t *sync_event(const uint8_t *input, uintptr_t len);
 
 int32_t set_stream_port(int64_t port);
 
+uint8_t *alloc_payload_buffer(uintptr_t len, int64_t *handle);
+
 int32_t set_log_stream_port(int64_t port);
 
 void link_me_please(void);
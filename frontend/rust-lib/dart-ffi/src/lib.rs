@@ -35,6 +35,7 @@ mod protobuf;
 lazy_static! {
   static ref APPFLOWY_CORE: MutexAppFlowyCore = MutexAppFlowyCore::new();
   static ref LOG_STREAM_ISOLATE: Mutex<Option<Isolate>> = Mutex::new(None);
+  static ref DART_SUBSCRIBER: Mutex<Option<SubscriberId>> = Mutex::new(None);
 }
 
 struct MutexAppFlowyCore(Arc<Mutex<Option<AppFlowyCore>>>);
@@ -101,7 +102,17 @@ pub extern "C" fn init_sdk(_port: i64, data: *mut c_char) -> i64 {
 #[no_mangle]
 #[allow(clippy::let_underscore_future)]
 pub extern "C" fn async_event(port: i64, input: *const u8, len: usize) {
-  let request: AFPluginRequest = FFIRequest::from_u8_pointer(input, len).into();
+  let ffi_request = match FFIRequest::from_u8_pointer(input, len) {
+    None => {
+      // Dart waits for the response of every request on the port.
+      let error = InternalError::DeserializeFromBytes("[FFI]: malformed request".to_string());
+      post_error_to_flutter(DispatchError::from(error), port);
+      return;
+    },
+    Some(ffi_request) => ffi_request,
+  };
+  let share_response_over = ffi_request.share_response_over;
+  let request = with_dart_subscriber(ffi_request.into());
   #[cfg(feature = "sync_verbose_log")]
   trace!(
     "[FFI]: {} Async Event: {:?} with {} port",
@@ -123,14 +134,17 @@ pub extern "C" fn async_event(port: i64, input: *const u8, len: usize) {
     move |resp: AFPluginEventResponse| {
       #[cfg(feature = "sync_verbose_log")]
       trace!("[FFI]: Post data to dart through {} port", port);
-      Box::pin(post_to_flutter(resp, port))
+      Box::pin(post_to_flutter(resp, port, share_response_over))
     },
   );
 }
 
 #[no_mangle]
 pub extern "C" fn sync_event(input: *const u8, len: usize) -> *const u8 {
-  let request: AFPluginRequest = FFIRequest::from_u8_pointer(input, len).into();
+  let request = match FFIRequest::from_u8_pointer(input, len) {
+    None => return forget_rust(Vec::default()),
+    Some(ffi_request) => with_dart_subscriber(ffi_request.into()),
+  };
   #[cfg(feature = "sync_verbose_log")]
   trace!("[FFI]: {} Sync Event: {:?}", &request.id, &request.event,);
 
@@ -154,9 +168,45 @@ pub extern "C" fn set_stream_port(notification_port: i64) -> i32 {
   // Make sure hot reload won't register the notification sender twice
   unregister_all_notification_sender();
   register_notification_sender(DartNotificationSender::new(notification_port));
+  unregister_all_notification_sinks();
+  register_notification_sink(DartNotificationSender::new(notification_port));
+  // The subscriptions of the previous isolate are gone with it.
+  let mut subscriber = DART_SUBSCRIBER.lock();
+  if let Some(old) = subscriber.take() {
+    disconnect_subscriber(old);
+  }
+  *subscriber = Some(connect_subscriber(DartNotificationSender::new(notification_port)));
   0
 }
 
+/// The requests from Dart subscribe the Dart side in the [SUBSCRIBE_EVENT].
+fn with_dart_subscriber(mut request: AFPluginRequest) -> AFPluginRequest {
+  if let Some(subscriber) = *DART_SUBSCRIBER.lock() {
+    request.context = request.context.extension(subscriber);
+  }
+  request
+}
+
+/// Allocates a buffer of `len` bytes for Dart to write a large payload into, and writes its
+/// handle to `handle`. The request carries the handle as its `shared_payload` instead of
+/// copying the payload, and the buffer is freed once the request takes it over. Dart owns the
+/// buffer until it sends the request, the `system.release_buffer` event doesn't free it.
+///
+/// Returns null if the buffers that Dart hasn't sent yet are too large, see
+/// `MAX_WRITABLE_BUFFER_BYTES`. Dart sends the payload inline then.
+#[no_mangle]
+pub extern "C" fn alloc_payload_buffer(len: usize, handle: *mut i64) -> *mut u8 {
+  if handle.is_null() {
+    return std::ptr::null_mut();
+  }
+  let buffer = match alloc_shared_buffer(len) {
+    None => return std::ptr::null_mut(),
+    Some(buffer) => buffer,
+  };
+  unsafe { *handle = buffer.handle.as_raw() as i64 };
+  buffer.ptr as *mut u8
+}
+
 #[no_mangle]
 pub extern "C" fn set_log_stream_port(port: i64) -> i32 {
   *LOG_STREAM_ISOLATE.lock() = Some(Isolate::new(port));
@@ -174,14 +224,24 @@ pub extern "C" fn set_log_stream_port(port: i64) -> i32 {
 #[no_mangle]
 pub extern "C" fn link_me_please() {}
 
+fn post_error_to_flutter(error: DispatchError, port: i64) {
+  let response: AFPluginEventResponse = error.into();
+  match FFIResponse::from(response).into_bytes() {
+    Ok(bytes) => {
+      allo_isolate::Isolate::new(port).post(Vec::from(bytes));
+    },
+    Err(e) => error!("[FFI]: encode the error response failed: {:?}", e),
+  }
+}
+
 #[inline(always)]
-async fn post_to_flutter(response: AFPluginEventResponse, port: i64) {
+async fn post_to_flutter(response: AFPluginEventResponse, port: i64, share_response_over: i64) {
   let isolate = allo_isolate::Isolate::new(port);
   #[allow(clippy::blocks_in_conditions)]
   match isolate
     .catch_unwind(async {
-      let ffi_resp = FFIResponse::from(response);
-      ffi_resp.into_bytes().unwrap().to_vec()
+      let ffi_resp = FFIResponse::with_shared_payload(response, share_response_over);
+      Vec::from(ffi_resp.into_bytes().unwrap())
     })
     .await
   {
@@ -1,6 +1,6 @@
 use bytes::Bytes;
 use flowy_derive::ProtoBuf;
-use lib_dispatch::prelude::AFPluginRequest;
+use lib_dispatch::prelude::{take_shared_buffer, AFPluginRequest, SharedBufferHandle};
 use std::convert::TryFrom;
 
 #[derive(Default, ProtoBuf)]
@@ -10,19 +10,61 @@ pub struct FFIRequest {
 
   #[pb(index = 2)]
   pub(crate) payload: Vec<u8>,
+
+  /// Zero if the payload is not versioned.
+  #[pb(index = 3)]
+  pub(crate) payload_version: u32,
+
+  /// The handle of the shared buffer that holds the payload instead of the `payload`, see
+  /// `alloc_shared_buffer`. Zero if the payload is inline.
+  #[pb(index = 4)]
+  pub(crate) shared_payload: i64,
+
+  /// The response payloads of at least this many bytes are returned in a shared buffer instead
+  /// of being copied into the response. Zero if they are always copied.
+  #[pb(index = 5)]
+  pub(crate) share_response_over: i64,
 }
 
 impl FFIRequest {
-  pub fn from_u8_pointer(pointer: *const u8, len: usize) -> Self {
-    let buffer = unsafe { std::slice::from_raw_parts(pointer, len) }.to_vec();
-    let bytes = Bytes::from(buffer);
-    let request: FFIRequest = FFIRequest::try_from(bytes).unwrap();
-    request
+  /// Returns `None` if the buffer is not a valid request, so the malformed input is dropped
+  /// instead of panicking across the FFI boundary.
+  pub fn from_u8_pointer(pointer: *const u8, len: usize) -> Option<Self> {
+    if pointer.is_null() {
+      return None;
+    }
+    // Parse from the buffer of dart directly, the payload is copied once into the request.
+    let buffer = unsafe { std::slice::from_raw_parts(pointer, len) };
+    match FFIRequest::try_from(buffer) {
+      Ok(request) => Some(request),
+      Err(e) => {
+        tracing::error!("[FFI]: parse the request failed: {:?}", e);
+        None
+      },
+    }
   }
 }
 
 impl std::convert::From<FFIRequest> for AFPluginRequest {
   fn from(ffi_request: FFIRequest) -> Self {
-    AFPluginRequest::new(ffi_request.event).payload(ffi_request.payload)
+    // Converting the Vec<u8> into Bytes takes over the allocation without copying.
+    let payload = match ffi_request.shared_payload {
+      0 => Bytes::from(ffi_request.payload),
+      handle => {
+        take_shared_buffer(SharedBufferHandle::from_raw(handle as u64)).unwrap_or_else(|| {
+          tracing::error!(
+            "[FFI]: the shared buffer {} of {} is released",
+            handle,
+            ffi_request.event
+          );
+          Bytes::new()
+        })
+      },
+    };
+    let request = AFPluginRequest::untyped(ffi_request.event).payload(payload);
+    match ffi_request.payload_version {
+      0 => request,
+      version => request.payload_version(version),
+    }
   }
 }
@@ -1,5 +1,7 @@
+use std::collections::HashMap;
+
 use flowy_derive::{ProtoBuf, ProtoBuf_Enum};
-use lib_dispatch::prelude::{AFPluginEventResponse, Payload, StatusCode};
+use lib_dispatch::prelude::{share_buffer, AFPluginEventResponse, Payload, StatusCode};
 
 #[derive(ProtoBuf_Enum, Clone, Copy, Default)]
 pub enum FFIStatusCode {
@@ -7,6 +9,12 @@ pub enum FFIStatusCode {
   Ok = 0,
   Err = 1,
   Internal = 2,
+  InvalidParams = 3,
+  NotFound = 4,
+  Unauthorized = 5,
+  Timeout = 6,
+  Cancelled = 7,
+  Busy = 8,
 }
 
 #[derive(ProtoBuf, Default)]
@@ -16,18 +24,62 @@ pub struct FFIResponse {
 
   #[pb(index = 2)]
   code: FFIStatusCode,
+
+  #[pb(index = 3)]
+  metadata: HashMap<String, String>,
+
+  /// The handle of the shared buffer that holds the payload instead of the `payload`. Dart reads
+  /// it in place, then frees it with the `system.release_buffer` event. Zero if the payload is
+  /// inline.
+  #[pb(index = 4)]
+  shared_buffer: i64,
+
+  #[pb(index = 5)]
+  shared_buffer_ptr: i64,
+
+  #[pb(index = 6)]
+  shared_buffer_len: i64,
+}
+
+impl FFIResponse {
+  /// Moves the payload into a shared buffer if it has at least `share_over` bytes, so it's not
+  /// copied into the response and again into Dart.
+  pub fn with_shared_payload(mut resp: AFPluginEventResponse, share_over: i64) -> Self {
+    let shared = match &resp.payload {
+      Payload::Bytes(bytes) if share_over > 0 && bytes.len() as i64 >= share_over => {
+        Some(share_buffer(bytes.clone()))
+      },
+      _ => None,
+    };
+    match shared {
+      None => FFIResponse::from(resp),
+      Some(buffer) => {
+        resp.payload = Payload::None;
+        FFIResponse {
+          shared_buffer: buffer.handle.as_raw() as i64,
+          shared_buffer_ptr: buffer.ptr as i64,
+          shared_buffer_len: buffer.len as i64,
+          ..FFIResponse::from(resp)
+        }
+      },
+    }
+  }
 }
 
 impl std::convert::From<AFPluginEventResponse> for FFIResponse {
   fn from(resp: AFPluginEventResponse) -> Self {
-    let payload = match resp.payload {
-      Payload::Bytes(bytes) => bytes.to_vec(),
-      Payload::None => vec![],
-    };
+    let payload = resp.payload.to_vec();
 
     let code = match resp.status_code {
       StatusCode::Ok => FFIStatusCode::Ok,
       StatusCode::Err => FFIStatusCode::Err,
+      StatusCode::InvalidParams => FFIStatusCode::InvalidParams,
+      StatusCode::NotFound => FFIStatusCode::NotFound,
+      StatusCode::Unauthorized => FFIStatusCode::Unauthorized,
+      StatusCode::Timeout => FFIStatusCode::Timeout,
+      StatusCode::Internal => FFIStatusCode::Internal,
+      StatusCode::Cancelled => FFIStatusCode::Cancelled,
+      StatusCode::Busy => FFIStatusCode::Busy,
     };
 
     // let msg = match resp.error {
@@ -35,6 +87,11 @@ impl std::convert::From<AFPluginEventResponse> for FFIResponse {
     //     Some(e) => format!("{:?}", e),
     // };
 
-    FFIResponse { payload, code }
+    FFIResponse {
+      payload,
+      code,
+      metadata: resp.metadata,
+      ..Default::default()
+    }
   }
 }
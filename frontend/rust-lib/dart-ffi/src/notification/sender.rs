@@ -2,6 +2,7 @@ use allo_isolate::Isolate;
 use bytes::Bytes;
 use flowy_notification::entities::SubscribeObject;
 use flowy_notification::NotificationSender;
+use lib_dispatch::prelude::{DispatchNotification, NotificationSink};
 use std::convert::TryInto;
 
 pub struct DartNotificationSender {
@@ -23,3 +24,16 @@ impl NotificationSender for DartNotificationSender {
     Ok(())
   }
 }
+
+impl NotificationSink for DartNotificationSender {
+  fn send_notification(&self, notification: &DispatchNotification) -> Result<(), String> {
+    let subject = SubscribeObject {
+      source: notification.source.clone(),
+      ty: notification.ty,
+      id: notification.id.clone(),
+      payload: Some(notification.payload.to_vec()),
+      error: None,
+    };
+    self.send_subject(subject)
+  }
+}
@@ -1,9 +1,4 @@
-use std::{
-  convert::TryFrom,
-  fmt::{Debug, Display},
-  hash::Hash,
-  sync::Arc,
-};
+use std::{convert::TryFrom, sync::Arc};
 
 use flowy_user::errors::{internal_error, FlowyError};
 use lib_dispatch::prelude::{
@@ -42,7 +37,7 @@ impl EventBuilder {
 
   pub fn event<Event>(mut self, event: Event) -> Self
   where
-    Event: Eq + Hash + Debug + Clone + Display,
+    Event: AFPluginEventType,
   {
     self.context.request = Some(AFPluginRequest::new(event));
     self
@@ -0,0 +1,67 @@
+use anyhow::anyhow;
+use flowy_sqlite::sql_types::{BigInt, Integer, Nullable, Text};
+use flowy_sqlite::{sql_query, Database, PoolConfig, RunQueryDsl};
+use lib_dispatch::prelude::{AuditRecord, AuditSink};
+use tracing::error;
+
+const AUDIT_DB_NAME: &str = "audit.db";
+
+const AUDIT_SQL: &str = r#"CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp BIGINT NOT NULL,
+    request_id TEXT NOT NULL,
+    event TEXT NOT NULL,
+    plugin TEXT NOT NULL,
+    user_id TEXT,
+    device_id TEXT,
+    correlation_id TEXT,
+    status INTEGER NOT NULL,
+    elapsed_ms BIGINT NOT NULL
+);"#;
+
+/// Appends the audit records of the dispatcher to the `audit_log` table of its own database, so
+/// the records survive the reset of the user's data.
+pub struct SqliteAuditSink {
+  database: Database,
+}
+
+impl SqliteAuditSink {
+  pub fn new(root: &str) -> Result<Self, anyhow::Error> {
+    let database = Database::new(root, AUDIT_DB_NAME, PoolConfig::default())
+      .map_err(|e| anyhow!("Open the audit database failed: {:?}", e))?;
+    let mut conn = database
+      .get_connection()
+      .map_err(|e| anyhow!("Connect the audit database failed: {:?}", e))?;
+    sql_query(AUDIT_SQL).execute(&mut *conn)?;
+    Ok(Self { database })
+  }
+}
+
+impl AuditSink for SqliteAuditSink {
+  fn append(&self, record: &AuditRecord) {
+    let mut conn = match self.database.get_connection() {
+      Ok(conn) => conn,
+      Err(e) => {
+        error!("Connect the audit database failed: {:?}", e);
+        return;
+      },
+    };
+    let result = sql_query(
+      "INSERT INTO audit_log (timestamp, request_id, event, plugin, user_id, device_id, \
+       correlation_id, status, elapsed_ms) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind::<BigInt, _>(record.timestamp as i64)
+    .bind::<Text, _>(&record.request_id)
+    .bind::<Text, _>(&record.event)
+    .bind::<Text, _>(&record.plugin)
+    .bind::<Nullable<Text>, _>(record.user_id.as_deref())
+    .bind::<Nullable<Text>, _>(record.device_id.as_deref())
+    .bind::<Nullable<Text>, _>(record.correlation_id.as_deref())
+    .bind::<Integer, _>(record.status as i32)
+    .bind::<BigInt, _>(record.elapsed.as_millis() as i64)
+    .execute(&mut *conn);
+    if let Err(e) = result {
+      error!("Append the audit record failed: {:?}", e);
+    }
+  }
+}
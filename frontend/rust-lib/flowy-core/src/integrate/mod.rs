@@ -1,3 +1,4 @@
+pub mod audit;
 pub(crate) mod collab_interact;
 pub mod log;
 pub(crate) mod server;
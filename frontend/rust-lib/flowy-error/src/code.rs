@@ -280,6 +280,18 @@ pub enum ErrorCode {
 
   #[error("Workspace data not match")]
   WorkspaceDataNotMatch = 97,
+
+  #[error("No handler handles the event")]
+  EventNotFound = 98,
+
+  #[error("The event is timed out")]
+  EventTimeout = 99,
+
+  #[error("The event is cancelled")]
+  EventCancelled = 100,
+
+  #[error("The dispatcher is busy")]
+  EventBusy = 101,
 }
 
 impl ErrorCode {
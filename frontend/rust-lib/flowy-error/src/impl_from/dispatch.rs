@@ -1,14 +1,46 @@
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 
 use bytes::Bytes;
 
-use lib_dispatch::prelude::{AFPluginEventResponse, ResponseBuilder};
+use lib_dispatch::prelude::{
+  AFPluginEventResponse, DispatchError, Payload, ResponseBuilder, StatusCode,
+};
 
-use crate::FlowyError;
+use crate::{ErrorCode, FlowyError};
 
 impl lib_dispatch::Error for FlowyError {
   fn as_response(&self) -> AFPluginEventResponse {
-    let bytes: Bytes = self.clone().try_into().unwrap();
-    ResponseBuilder::Err().data(bytes).build()
+    match TryInto::<Bytes>::try_into(self.clone()) {
+      Ok(bytes) => ResponseBuilder::Err().data(bytes).build(),
+      Err(e) => ResponseBuilder::Internal()
+        .data(format!("Serialize {} failed: {:?}", self, e))
+        .build(),
+    }
+  }
+}
+
+/// Keeps the error of the handler, e.g. the error of the nested dispatch, and converts the errors
+/// of the dispatcher by their status codes.
+impl From<DispatchError> for FlowyError {
+  fn from(err: DispatchError) -> Self {
+    let response = err.inner_error().as_response();
+    if response.status_code == StatusCode::Err {
+      if let Payload::Bytes(bytes) = &response.payload {
+        if let Ok(error) = FlowyError::try_from(bytes) {
+          return error;
+        }
+      }
+    }
+
+    let code = match response.status_code {
+      StatusCode::InvalidParams => ErrorCode::InvalidParams,
+      StatusCode::NotFound => ErrorCode::EventNotFound,
+      StatusCode::Unauthorized => ErrorCode::UserUnauthorized,
+      StatusCode::Timeout => ErrorCode::EventTimeout,
+      StatusCode::Cancelled => ErrorCode::EventCancelled,
+      StatusCode::Busy => ErrorCode::EventBusy,
+      _ => ErrorCode::Internal,
+    };
+    FlowyError::new(code, err)
   }
 }
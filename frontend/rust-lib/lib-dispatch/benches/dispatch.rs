@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures_util::future::join_all;
+
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+
+const NOOP_EVENT: &str = "bench.noop";
+const PAYLOAD_SIZES: [usize; 4] = [0, 1024, 64 * 1024, 1024 * 1024];
+const CONCURRENCY_LEVELS: [usize; 4] = [1, 16, 128, 1024];
+
+async fn noop() {}
+
+fn setup() -> (Arc<AFPluginRuntime>, Arc<AFPluginDispatcher>) {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatcher = Arc::new(AFPluginDispatcher::new(
+    runtime.clone(),
+    vec![AFPlugin::new().name("bench").event(NOOP_EVENT, noop)],
+  ));
+  (runtime, dispatcher)
+}
+
+fn request(payload: &[u8]) -> AFPluginRequest {
+  AFPluginRequest::new(NOOP_EVENT).payload(payload.to_vec())
+}
+
+/// The round trip of one request at a time.
+fn latency(c: &mut Criterion) {
+  let (runtime, dispatcher) = setup();
+  let mut group = c.benchmark_group("latency");
+  for size in PAYLOAD_SIZES {
+    let payload = vec![0u8; size];
+    group.throughput(Throughput::Bytes(size as u64));
+    group.bench_with_input(BenchmarkId::from_parameter(size), &payload, |b, payload| {
+      b.iter(|| {
+        runtime.block_on(AFPluginDispatcher::async_send(
+          dispatcher.as_ref(),
+          request(payload),
+        ))
+      })
+    });
+  }
+  group.finish();
+}
+
+/// The requests that are in flight together and awaited for their responses.
+fn throughput(c: &mut Criterion) {
+  let (runtime, dispatcher) = setup();
+  let mut group = c.benchmark_group("throughput");
+  for concurrency in CONCURRENCY_LEVELS {
+    group.throughput(Throughput::Elements(concurrency as u64));
+    group.bench_with_input(
+      BenchmarkId::from_parameter(concurrency),
+      &concurrency,
+      |b, &concurrency| {
+        b.iter(|| {
+          let responses = (0..concurrency).map(|_| {
+            AFPluginDispatcher::async_send_with_response(dispatcher.as_ref(), request(&[]))
+          });
+          runtime.block_on(join_all(responses))
+        })
+      },
+    );
+  }
+  group.finish();
+}
+
+/// The fire and forget requests, measured until the dispatcher is drained.
+fn fire_and_forget(c: &mut Criterion) {
+  let (runtime, dispatcher) = setup();
+  let mut group = c.benchmark_group("fire_and_forget");
+  for concurrency in CONCURRENCY_LEVELS {
+    group.throughput(Throughput::Elements(concurrency as u64));
+    group.bench_with_input(
+      BenchmarkId::from_parameter(concurrency),
+      &concurrency,
+      |b, &concurrency| {
+        b.iter(|| {
+          for _ in 0..concurrency {
+            AFPluginDispatcher::fire_and_forget(dispatcher.as_ref(), request(&[]));
+          }
+          runtime.block_on(dispatcher.wait_drained())
+        })
+      },
+    );
+  }
+  group.finish();
+}
+
+/// The requests that are enqueued in a single step.
+fn batch(c: &mut Criterion) {
+  let (runtime, dispatcher) = setup();
+  let mut group = c.benchmark_group("batch");
+  for concurrency in CONCURRENCY_LEVELS {
+    group.throughput(Throughput::Elements(concurrency as u64));
+    group.bench_with_input(
+      BenchmarkId::from_parameter(concurrency),
+      &concurrency,
+      |b, &concurrency| {
+        b.iter(|| {
+          let requests = (0..concurrency).map(|_| request(&[])).collect::<Vec<_>>();
+          runtime.block_on(AFPluginDispatcher::async_send_batch(
+            dispatcher.as_ref(),
+            requests,
+          ))
+        })
+      },
+    );
+  }
+  group.finish();
+}
+
+criterion_group!(benches, latency, throughput, fire_and_forget, batch);
+criterion_main!(benches);
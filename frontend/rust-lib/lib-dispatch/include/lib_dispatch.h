@@ -0,0 +1,36 @@
+// The C ABI of lib-dispatch, built with the `c_abi` feature. See `src/ffi.rs` for the framing of
+// the requests and the responses.
+#ifndef LIB_DISPATCH_H
+#define LIB_DISPATCH_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define AF_DISPATCH_OK 0
+#define AF_DISPATCH_NO_PLUGINS -1
+#define AF_DISPATCH_RUNTIME_ERROR -2
+#define AF_DISPATCH_START_FAILED -3
+#define AF_DISPATCH_NOT_INITIALIZED -4
+#define AF_DISPATCH_INVALID_FRAME -5
+#define AF_DISPATCH_SHUTDOWN_TIMEOUT -6
+#define AF_DISPATCH_BLOCKING_IN_RUNTIME -7
+#define AF_DISPATCH_ALREADY_INITIALIZED -8
+#define AF_DISPATCH_PANIC -9
+
+// The frame is only valid during the call.
+typedef void (*AFDispatchCallback)(void *context, const uint8_t *frame, size_t len);
+
+// Call af_dispatch_init and af_dispatch_shutdown from a thread of the host, not of a tokio runtime.
+int32_t af_dispatch_init(void);
+
+int32_t af_dispatch_async_send(const uint8_t *input, size_t len, AFDispatchCallback callback,
+                               void *context);
+
+// Returns NULL if the request can't be sent or output_len is NULL. Free the frame with af_dispatch_free.
+uint8_t *af_dispatch_sync_send(const uint8_t *input, size_t len, size_t *output_len);
+
+void af_dispatch_free(uint8_t *frame, size_t len);
+
+int32_t af_dispatch_shutdown(uint64_t timeout_ms);
+
+#endif
@@ -0,0 +1,127 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::module::AFPluginRequest;
+use crate::prelude::AFConcurrent;
+use crate::response::{AFPluginEventResponse, StatusCode};
+
+/// A completed request of a mutating event. See [AFPlugin::mutating].
+///
+/// [AFPlugin::mutating]: crate::prelude::AFPlugin::mutating
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "use_serde", derive(serde::Serialize))]
+pub struct AuditRecord {
+  /// The milliseconds since the unix epoch when the request completed.
+  pub timestamp: u64,
+  pub request_id: String,
+  pub event: String,
+  pub plugin: String,
+  pub user_id: Option<String>,
+  pub device_id: Option<String>,
+  pub correlation_id: Option<String>,
+  pub status: StatusCode,
+  pub elapsed: Duration,
+}
+
+impl AuditRecord {
+  pub(crate) fn new(
+    request: &AFPluginRequest,
+    plugin: &str,
+    response: &AFPluginEventResponse,
+    elapsed: Duration,
+  ) -> Self {
+    Self {
+      timestamp: unix_timestamp_millis(),
+      request_id: request.id.clone(),
+      event: request.event.as_str().to_string(),
+      plugin: plugin.to_string(),
+      user_id: request.context.user_id.clone(),
+      device_id: request.context.device_id.clone(),
+      correlation_id: request.correlation_id.clone(),
+      status: response.status_code,
+      elapsed,
+    }
+  }
+}
+
+impl fmt::Display for AuditRecord {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{}\t{}\t{}\t{}\t{}\t{:?}\t{}ms",
+      self.timestamp,
+      self.request_id,
+      self.event,
+      self.plugin,
+      self.user_id.as_deref().unwrap_or("-"),
+      self.status,
+      self.elapsed.as_millis()
+    )
+  }
+}
+
+/// Appends the [AuditRecord]s to the storage. The records are never updated or deleted by the
+/// dispatcher. See [AFPluginDispatcherBuilder::audit].
+///
+/// [AFPluginDispatcherBuilder::audit]: crate::prelude::AFPluginDispatcherBuilder::audit
+pub trait AuditSink: AFConcurrent {
+  fn append(&self, record: &AuditRecord);
+}
+
+impl<F> AuditSink for F
+where
+  F: Fn(&AuditRecord) + AFConcurrent,
+{
+  fn append(&self, record: &AuditRecord) {
+    (self)(record)
+  }
+}
+
+/// Appends a line per record to the file, the json of the record if the `use_serde` feature is
+/// enabled, otherwise the tab-separated fields.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct FileAuditSink {
+  file: parking_lot::Mutex<std::fs::File>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileAuditSink {
+  /// Creates the file if it doesn't exist.
+  pub fn open<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+    let file = std::fs::OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(path)?;
+    Ok(Self {
+      file: parking_lot::Mutex::new(file),
+    })
+  }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl AuditSink for FileAuditSink {
+  fn append(&self, record: &AuditRecord) {
+    use std::io::Write;
+
+    #[cfg(feature = "use_serde")]
+    let line = serde_json::to_string(record).unwrap_or_else(|_| record.to_string());
+    #[cfg(not(feature = "use_serde"))]
+    let line = record.to_string();
+    if let Err(e) = writeln!(self.file.lock(), "{}", line) {
+      tracing::error!("[dispatch]: append the audit record failed: {}", e);
+    }
+  }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn unix_timestamp_millis() -> u64 {
+  std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .map(|elapsed| elapsed.as_millis() as u64)
+    .unwrap_or_default()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn unix_timestamp_millis() -> u64 {
+  js_sys::Date::now() as u64
+}
@@ -0,0 +1,380 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::runtime::Handle;
+
+use crate::audit::AuditSink;
+use crate::config::DispatchConfig;
+use crate::dead_letter::DeadLetterSink;
+use crate::dispatcher::{plugin_info, AFConcurrent, AFPluginDispatcher};
+use crate::errors::{DispatchError, InternalError};
+#[cfg(all(feature = "forward", not(target_arch = "wasm32")))]
+use crate::forward::DispatchForwarder;
+use crate::history::DispatchHistory;
+use crate::idempotency::DispatchIdempotency;
+use crate::interceptor::DispatchInterceptor;
+use crate::journal::DispatchJournal;
+use crate::localize::DispatchLocalizer;
+use crate::logger::DispatchLogger;
+use crate::metrics::{HighWaterListener, HighWaterMark};
+use crate::module::{plugin_routes, AFPlugin, AFPluginEvent, AFPluginState, DuplicatePolicy};
+use crate::observer::DispatchErrorObserver;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::replay::DispatchRecorder;
+use crate::retry::DispatchRetryPolicy;
+use crate::runtime::{AFPluginRuntime, AFPluginSpawner};
+use crate::scheduler::DispatchLoadShedding;
+use crate::supervisor::DispatchSupervisor;
+use crate::watchdog::DispatchWatchdog;
+
+/// What the dispatcher does if a handler panics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DispatchPanicPolicy {
+  /// Resolves the request with the `HandlerPanic` error and keeps running.
+  #[default]
+  Respond,
+  /// Aborts the process after logging the panic, e.g. in the tests to surface the bug early.
+  Abort,
+}
+
+/// Creates the dispatcher with the configurations that are otherwise hard-coded by
+/// [AFPluginDispatcher::new]:
+///
+/// ```ignore
+/// let dispatcher = AFPluginDispatcher::builder()
+///   .worker_threads(4)
+///   .capacity(256)
+///   .default_timeout(Duration::from_secs(30))
+///   .retry_policy(DispatchRetryPolicy::default())
+///   .plugins(make_plugins())
+///   .build()?;
+/// ```
+#[derive(Default)]
+pub struct AFPluginDispatcherBuilder {
+  runtime: Option<Arc<AFPluginRuntime>>,
+  worker_threads: Option<usize>,
+  #[cfg(not(target_arch = "wasm32"))]
+  blocking_threads: Option<usize>,
+  capacity: Option<usize>,
+  log_plugins: bool,
+  plugins: Vec<AFPlugin>,
+  config: DispatchConfig,
+}
+
+impl AFPluginDispatcherBuilder {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Runs the dispatcher on the `runtime` instead of creating one.
+  pub fn runtime(mut self, runtime: Arc<AFPluginRuntime>) -> Self {
+    self.runtime = Some(runtime);
+    self
+  }
+
+  /// Runs the dispatcher on the runtime of the host application. See
+  /// [AFPluginRuntime::from_handle].
+  pub fn runtime_handle(mut self, handle: Handle) -> Self {
+    self.runtime = Some(Arc::new(AFPluginRuntime::from_handle(handle)));
+    self
+  }
+
+  /// The number of the worker threads of the created runtime. It's ignored if the runtime is
+  /// given or in the local set mode.
+  pub fn worker_threads(mut self, worker_threads: usize) -> Self {
+    self.worker_threads = Some(worker_threads);
+    self
+  }
+
+  /// The max number of the threads of the blocking pool. See [set_blocking_threads].
+  ///
+  /// [set_blocking_threads]: crate::runtime::set_blocking_threads
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn blocking_threads(mut self, threads: usize) -> Self {
+    self.blocking_threads = Some(threads);
+    self
+  }
+
+  /// See [AFPluginDispatcher::with_capacity].
+  pub fn capacity(mut self, capacity: usize) -> Self {
+    self.capacity = Some(capacity);
+    self
+  }
+
+  /// Limits the number of requests that are executed at the same time. The exceeding requests
+  /// wait in the dispatcher and are started according to their [DispatchPriority], so a burst of
+  /// background events can't delay the user-facing ones. Without the limit, the requests are
+  /// started right away regardless of their priority.
+  ///
+  /// [DispatchPriority]: crate::prelude::DispatchPriority
+  pub fn max_concurrent(mut self, max_concurrent: usize) -> Self {
+    self.config.max_concurrent = Some(max_concurrent);
+    self
+  }
+
+  /// Sheds the requests that exceed [AFPluginDispatcherBuilder::max_concurrent] according to the
+  /// `policy`, so a burst of requests can't pile up in the queue. See [DispatchLoadShedding].
+  pub fn load_shedding(mut self, policy: DispatchLoadShedding) -> Self {
+    self.config.load_shedding = policy;
+    self
+  }
+
+  /// The timeout of the requests that don't set their own. See [AFPluginRequest::timeout].
+  ///
+  /// [AFPluginRequest::timeout]: crate::prelude::AFPluginRequest::timeout
+  pub fn default_timeout(mut self, timeout: Duration) -> Self {
+    self.config.default_timeout = Some(timeout);
+    self
+  }
+
+  /// See [DispatchPanicPolicy].
+  pub fn panic_policy(mut self, policy: DispatchPanicPolicy) -> Self {
+    self.config.panic_policy = policy;
+    self
+  }
+
+  /// Reports the handlers that run past the threshold of the `watchdog`. See [DispatchWatchdog].
+  pub fn watchdog(mut self, watchdog: DispatchWatchdog) -> Self {
+    self.config.watchdog = Some(Arc::new(watchdog));
+    self
+  }
+
+  /// Warns if a single poll of a handler takes longer than the `budget`, which means the handler
+  /// blocks the async thread, e.g. with the synchronous IO. The warning names the event.
+  pub fn slow_poll_budget(mut self, budget: Duration) -> Self {
+    self.config.slow_poll_budget = Some(budget);
+    self
+  }
+
+  /// Spawns the dispatch tasks with the `spawner` instead of the runtime, e.g. to run them on
+  /// the event loop of the host. It's the `WasmSpawner` on wasm32.
+  pub fn spawner<S>(mut self, spawner: S) -> Self
+  where
+    S: AFPluginSpawner + 'static,
+  {
+    self.config.spawner = Some(Arc::new(spawner));
+    self
+  }
+
+  /// Catches the crashes of the dispatch tasks and respawns them. See [DispatchSupervisor].
+  pub fn supervisor(mut self, supervisor: DispatchSupervisor) -> Self {
+    self.config.supervisor = Some(Arc::new(supervisor));
+    self
+  }
+
+  /// Records the requests of the mutating events to the `sink`, e.g. a [FileAuditSink]. See
+  /// [AFPlugin::mutating].
+  ///
+  /// [FileAuditSink]: crate::prelude::FileAuditSink
+  pub fn audit<S>(mut self, sink: S) -> Self
+  where
+    S: AuditSink + 'static,
+  {
+    self.config.audit = Some(Arc::new(sink));
+    self
+  }
+
+  /// Journals the requests of the mutating events until they are completed, e.g. to a
+  /// [FileJournal], so they are not lost with the queue if the app crashes. Call
+  /// [AFPluginDispatcher::redispatch_journaled] after the start to send them again. See
+  /// [AFPlugin::mutating].
+  ///
+  /// [FileJournal]: crate::prelude::FileJournal
+  pub fn journal<J>(mut self, journal: J) -> Self
+  where
+    J: DispatchJournal + 'static,
+  {
+    self.config.journal = Some(Arc::new(journal));
+    self
+  }
+
+  /// Forwards the events that start with the prefix of the `forwarder` to another process,
+  /// unless a local plugin registers them. See [DispatchForwarder].
+  #[cfg(all(feature = "forward", not(target_arch = "wasm32")))]
+  pub fn forward(mut self, forwarder: DispatchForwarder) -> Self {
+    self.config.forwarders.push(Arc::new(forwarder));
+    self
+  }
+
+  /// Writes the requests to the recording of the `recorder`, to replay them later with
+  /// [DispatchReplay].
+  ///
+  /// [DispatchReplay]: crate::prelude::DispatchReplay
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn record_to(mut self, recorder: DispatchRecorder) -> Self {
+    self.config.recorder = Some(Arc::new(recorder));
+    self
+  }
+
+  /// Logs the start and the finish of the requests. See [DispatchLogger].
+  pub fn log_requests(mut self, logger: DispatchLogger) -> Self {
+    self.config.logger = Some(Arc::new(logger));
+    self
+  }
+
+  /// Retries the requests whose handlers return the retryable errors. See [DispatchRetryPolicy].
+  pub fn retry_policy(mut self, retry_policy: DispatchRetryPolicy) -> Self {
+    self.config.retry_policy = Some(retry_policy);
+    self
+  }
+
+  /// Hands over the requests that have no handler to the `sink`. See [DeadLetterSink].
+  pub fn dead_letter_sink<S>(mut self, sink: S) -> Self
+  where
+    S: DeadLetterSink + 'static,
+  {
+    self.config.dead_letter = Some(Arc::new(sink));
+    self
+  }
+
+  /// Calls the `observer` with every failed request, see [DispatchErrorObserver].
+  pub fn on_error<O>(mut self, observer: O) -> Self
+  where
+    O: DispatchErrorObserver + 'static,
+  {
+    self.config.error_observer = Some(Arc::new(observer));
+    self
+  }
+
+  /// Translates the messages of the errors with the `localizer`, see [DispatchLocalizer].
+  pub fn localizer<L>(mut self, localizer: L) -> Self
+  where
+    L: DispatchLocalizer + 'static,
+  {
+    self.config.localizer = Some(Arc::new(localizer));
+    self
+  }
+
+  /// Adds the interceptor that inspects the requests before they are routed. See
+  /// [DispatchInterceptor].
+  pub fn interceptor<I>(mut self, interceptor: I) -> Self
+  where
+    I: DispatchInterceptor + 'static,
+  {
+    self.config.interceptors.push(Box::new(interceptor));
+    self
+  }
+
+  /// Remembers the responses of the requests that carry the idempotency key for the `window`, at
+  /// most `capacity` of them. Defaults to [DEFAULT_IDEMPOTENCY_WINDOW] and
+  /// [DEFAULT_IDEMPOTENCY_CAPACITY]. See [AFPluginRequest::idempotency_key].
+  ///
+  /// [DEFAULT_IDEMPOTENCY_WINDOW]: crate::prelude::DEFAULT_IDEMPOTENCY_WINDOW
+  /// [DEFAULT_IDEMPOTENCY_CAPACITY]: crate::prelude::DEFAULT_IDEMPOTENCY_CAPACITY
+  /// [AFPluginRequest::idempotency_key]: crate::prelude::AFPluginRequest::idempotency_key
+  pub fn idempotency_window(mut self, window: Duration, capacity: usize) -> Self {
+    self.config.idempotency = DispatchIdempotency::new(window, capacity);
+    self
+  }
+
+  /// The response payloads that exceed the `threshold` are compressed if the request accepts the
+  /// compression. Defaults to [DEFAULT_COMPRESSION_THRESHOLD].
+  ///
+  /// [DEFAULT_COMPRESSION_THRESHOLD]: crate::prelude::DEFAULT_COMPRESSION_THRESHOLD
+  pub fn compression_threshold(mut self, threshold: usize) -> Self {
+    self.config.compression_threshold = threshold;
+    self
+  }
+
+  /// Calls the `listener` when the number of queued requests exceeds the `mark` and when it
+  /// drains back, e.g. to show the syncing indicator or to shed the load.
+  pub fn high_water_mark<L>(mut self, mark: usize, listener: L) -> Self
+  where
+    L: HighWaterListener + 'static,
+  {
+    self.config.high_water = Some(HighWaterMark {
+      mark,
+      listener: Box::new(listener),
+    });
+    self
+  }
+
+  /// Keeps the last `capacity` dispatched requests and their responses in memory, which can be
+  /// dumped by [AFPluginDispatcher::history] to reproduce the bug reports.
+  ///
+  /// [AFPluginDispatcher::history]: crate::prelude::AFPluginDispatcher::history
+  pub fn record_history(mut self, capacity: usize) -> Self {
+    self.config.history = Some(Arc::new(DispatchHistory::new(capacity)));
+    self
+  }
+
+  /// Decides what [AFPluginDispatcher::register_plugin] does if the event of the plugin is
+  /// already registered, including the plugins that are passed to the builder. Defaults to
+  /// [DuplicatePolicy::Error].
+  ///
+  /// [AFPluginDispatcher::register_plugin]: crate::prelude::AFPluginDispatcher::register_plugin
+  pub fn duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+    self.config.duplicate_policy = policy;
+    self
+  }
+
+  /// Registers the state that can be read by the handlers of all the plugins with the
+  /// [AFPluginState] extractor, e.g. the database pool or the config. The plugin's own state of
+  /// the same type takes precedence.
+  pub fn state<D: AFConcurrent + 'static>(mut self, data: D) -> Self {
+    self.config.states.insert(AFPluginState::new(data));
+    self
+  }
+
+  /// Routes the `legacy` event to the `event`, so the frontend can keep sending the event after
+  /// it's renamed. The alias is only used if no plugin registers the `legacy` event, and it's
+  /// not resolved recursively.
+  pub fn alias<L, E>(mut self, legacy: L, event: E) -> Self
+  where
+    L: Into<AFPluginEvent>,
+    E: Into<AFPluginEvent>,
+  {
+    self.config.renames.insert(legacy.into(), event.into());
+    self
+  }
+
+  /// Logs the loaded plugins at the info level instead of the trace level.
+  pub fn log_plugins(mut self, log_plugins: bool) -> Self {
+    self.log_plugins = log_plugins;
+    self
+  }
+
+  pub fn plugin(mut self, plugin: AFPlugin) -> Self {
+    self.plugins.push(plugin);
+    self
+  }
+
+  pub fn plugins(mut self, plugins: Vec<AFPlugin>) -> Self {
+    self.plugins.extend(plugins);
+    self
+  }
+
+  /// Fails if the runtime can't be created, if the blocking pool is already created when the
+  /// [AFPluginDispatcherBuilder::blocking_threads] is set, or if the events of the plugins
+  /// conflict, see [DuplicatePolicy].
+  pub fn build(self) -> Result<AFPluginDispatcher, DispatchError> {
+    let runtime = match (self.runtime, self.worker_threads) {
+      (Some(runtime), _) => runtime,
+      (None, Some(worker_threads)) => {
+        Arc::new(AFPluginRuntime::with_worker_threads(worker_threads).map_err(runtime_error)?)
+      },
+      (None, None) => Arc::new(AFPluginRuntime::new().map_err(runtime_error)?),
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(threads) = self.blocking_threads {
+      crate::runtime::set_blocking_threads(threads)?;
+    }
+    if self.log_plugins {
+      tracing::info!("{}", plugin_info(&self.plugins));
+    } else {
+      tracing::trace!("{}", plugin_info(&self.plugins));
+    }
+    let routes = plugin_routes(self.plugins, self.config.duplicate_policy)?;
+    Ok(AFPluginDispatcher::with_config(
+      runtime,
+      routes,
+      self.config,
+      self.capacity,
+    ))
+  }
+}
+
+fn runtime_error(err: io::Error) -> DispatchError {
+  InternalError::Other(format!("[dispatch]: create the runtime failed: {}", err)).into()
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tokio::time::Instant;
+
+use crate::errors::DispatchError;
+use crate::module::{AFPluginEvent, AFPluginRequest};
+use crate::request::{AFPluginEventRequest, FromAFPluginRequest, Payload};
+use crate::response::AFPluginEventResponse;
+use crate::util::ready::{ready, Ready};
+
+/// The cached response is reused by the request with the same event and payload.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey {
+  event: AFPluginEvent,
+  payload: Option<Bytes>,
+}
+
+impl CacheKey {
+  pub(crate) fn new(request: &AFPluginRequest) -> Self {
+    Self {
+      event: request.event.clone(),
+      payload: match &request.payload {
+        Payload::None => None,
+        Payload::Bytes(bytes) => Some(bytes.clone()),
+      },
+    }
+  }
+}
+
+struct CacheEntry {
+  response: AFPluginEventResponse,
+  expires_at: Instant,
+}
+
+/// Caches the successful responses of the events that are registered by [AFPlugin::cache].
+///
+/// The handlers that change the cached data extract it to invalidate the stale responses:
+///
+/// ```ignore
+/// async fn update_workspace_handler(data: AFPluginData<UpdateWorkspacePB>, cache: DispatchCache) {
+///   ..
+///   cache.invalidate(FolderEvent::ReadAllWorkspaces);
+/// }
+/// ```
+///
+/// [AFPlugin::cache]: crate::prelude::AFPlugin::cache
+#[derive(Clone, Default)]
+pub struct DispatchCache {
+  entries: Arc<Mutex<HashMap<CacheKey, CacheEntry>>>,
+}
+
+impl DispatchCache {
+  /// Removes the cached responses of the `event`, whatever their payloads are.
+  pub fn invalidate<E: Into<AFPluginEvent>>(&self, event: E) {
+    let event = event.into();
+    self.entries.lock().retain(|key, _| key.event != event);
+  }
+
+  pub fn invalidate_all(&self) {
+    self.entries.lock().clear();
+  }
+
+  pub(crate) fn get(&self, key: &CacheKey) -> Option<AFPluginEventResponse> {
+    let mut entries = self.entries.lock();
+    let entry = entries.get(key)?;
+    if entry.expires_at <= Instant::now() {
+      entries.remove(key);
+      return None;
+    }
+    tracing::trace!("[dispatch]: cache hit {:?}", &key.event);
+    Some(entry.response.clone())
+  }
+
+  pub(crate) fn put(&self, key: CacheKey, ttl: Duration, response: &AFPluginEventResponse) {
+    let mut entries = self.entries.lock();
+    // Drop the expired entries, so the events with many different payloads don't pile up.
+    let now = Instant::now();
+    entries.retain(|_, entry| entry.expires_at > now);
+    entries.insert(
+      key,
+      CacheEntry {
+        response: response.clone(),
+        expires_at: now + ttl,
+      },
+    );
+  }
+}
+
+#[doc(hidden)]
+impl FromAFPluginRequest for DispatchCache {
+  type Error = DispatchError;
+  type Future = Ready<Result<Self, Self::Error>>;
+
+  fn from_request(req: &AFPluginEventRequest, _payload: &mut Payload) -> Self::Future {
+    ready(Ok(req.get_state::<DispatchCache>().unwrap_or_default()))
+  }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+
+use crate::errors::DispatchError;
+use crate::module::{AFPluginEvent, AFPluginMap, AFPluginRequest};
+use crate::request::Payload;
+use crate::response::AFPluginEventResponse;
+
+type CoalescedResult = Result<AFPluginEventResponse, DispatchError>;
+
+/// Two requests are identical if they have the same event and payload.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct CoalesceKey {
+  event: AFPluginEvent,
+  payload: Option<Bytes>,
+}
+
+/// Tracks the in-flight requests of the events that are registered by [AFPlugin::coalesce].
+///
+/// The first request of a key runs the handler. The identical requests that arrive while it is
+/// running wait for its result instead of running the handler again. The shared execution is
+/// cancelled only when the leader and all the followers are cancelled.
+///
+/// [AFPlugin::coalesce]: crate::module::AFPlugin::coalesce
+#[derive(Default)]
+pub(crate) struct DispatchCoalescer {
+  inflight: Mutex<HashMap<CoalesceKey, InflightExecution>>,
+}
+
+struct InflightExecution {
+  followers: Vec<oneshot::Sender<CoalescedResult>>,
+  waiters: Arc<CoalesceWaiters>,
+}
+
+/// Counts the requests that wait for the shared execution.
+pub(crate) struct CoalesceWaiters {
+  count: AtomicUsize,
+  cancel_token: CancellationToken,
+}
+
+impl CoalesceWaiters {
+  /// Called once the request is cancelled. The last one cancels the shared execution.
+  pub(crate) fn leave(&self) {
+    if self.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+      self.cancel_token.cancel();
+    }
+  }
+}
+
+pub(crate) enum Coalesced {
+  /// Runs the handler and shares the result with the followers.
+  Leader(CoalesceGuard),
+  /// Waits for the result of the leader.
+  Follower(CoalesceFollower),
+}
+
+pub(crate) struct CoalesceFollower {
+  pub(crate) rx: oneshot::Receiver<CoalescedResult>,
+  pub(crate) waiters: Arc<CoalesceWaiters>,
+}
+
+impl DispatchCoalescer {
+  /// Returns `None` if the event of the request doesn't opt in the coalescing.
+  pub(crate) fn join(
+    self: &Arc<Self>,
+    plugins: &AFPluginMap,
+    request: &AFPluginRequest,
+  ) -> Option<Coalesced> {
+    let plugin = plugins.get(&request.event)?;
+    if !plugin.is_coalesced(&request.event) {
+      return None;
+    }
+
+    let key = CoalesceKey {
+      event: request.event.clone(),
+      payload: match &request.payload {
+        Payload::None => None,
+        Payload::Bytes(bytes) => Some(bytes.clone()),
+      },
+    };
+    let mut inflight = self.inflight.lock();
+    match inflight.get_mut(&key) {
+      // The execution that all its waiters have left is being cancelled, the request starts a
+      // new one instead of waiting for the cancelled result.
+      Some(execution) if !execution.waiters.cancel_token.is_cancelled() => {
+        tracing::trace!("[dispatch]: coalesce event {:?}", &key.event);
+        let (tx, rx) = oneshot::channel();
+        execution.followers.push(tx);
+        execution.waiters.count.fetch_add(1, Ordering::SeqCst);
+        Some(Coalesced::Follower(CoalesceFollower {
+          rx,
+          waiters: execution.waiters.clone(),
+        }))
+      },
+      _ => {
+        let waiters = Arc::new(CoalesceWaiters {
+          count: AtomicUsize::new(1),
+          cancel_token: CancellationToken::new(),
+        });
+        let execution = InflightExecution {
+          followers: vec![],
+          waiters: waiters.clone(),
+        };
+        inflight.insert(key.clone(), execution);
+        Some(Coalesced::Leader(CoalesceGuard {
+          key,
+          waiters,
+          coalescer: self.clone(),
+        }))
+      },
+    }
+  }
+
+  /// Removes the execution of the `key` if it's the one of the `waiters`, the key may be taken
+  /// over by a new execution after the previous one is cancelled.
+  fn remove(&self, key: &CoalesceKey, waiters: &Arc<CoalesceWaiters>) -> Option<InflightExecution> {
+    let mut inflight = self.inflight.lock();
+    match inflight.get(key) {
+      Some(execution) if Arc::ptr_eq(&execution.waiters, waiters) => inflight.remove(key),
+      _ => None,
+    }
+  }
+}
+
+/// Removes the key when it gets dropped. If the leader is dropped without calling `complete`,
+/// e.g. its task is aborted, the followers are resolved with an error.
+pub(crate) struct CoalesceGuard {
+  key: CoalesceKey,
+  waiters: Arc<CoalesceWaiters>,
+  coalescer: Arc<DispatchCoalescer>,
+}
+
+impl CoalesceGuard {
+  /// The token of the shared execution, it's cancelled once all the waiters are cancelled.
+  pub(crate) fn cancel_token(&self) -> CancellationToken {
+    self.waiters.cancel_token.clone()
+  }
+
+  pub(crate) fn waiters(&self) -> &Arc<CoalesceWaiters> {
+    &self.waiters
+  }
+
+  pub(crate) fn complete(self, result: &CoalescedResult) {
+    let execution = self.coalescer.remove(&self.key, &self.waiters);
+    for follower in execution
+      .into_iter()
+      .flat_map(|execution| execution.followers)
+    {
+      let _ = follower.send(result.clone());
+    }
+  }
+}
+
+impl Drop for CoalesceGuard {
+  fn drop(&mut self) {
+    self.coalescer.remove(&self.key, &self.waiters);
+  }
+}
@@ -0,0 +1,252 @@
+use std::ops;
+
+use bytes::Bytes;
+
+use crate::{
+  errors::{DispatchError, InternalError},
+  request::{unexpected_none_payload, AFPluginEventRequest, FromAFPluginRequest, Payload},
+  response::{AFPluginEventResponse, AFPluginResponder, ResponseBuilder},
+  util::ready::{ready, Ready},
+};
+
+/// Declares how the payload of the request is encoded. The handler parses the payload with the
+/// codec of the request, so the same handler serves the clients that send protobuf and the ones
+/// that send json.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum PayloadCodec {
+  #[default]
+  Protobuf,
+  Json,
+}
+
+/// Encodes `T` into the payload and decodes the payload into `T`.
+pub trait AFPluginCodec<T> {
+  fn encode(data: &T) -> Result<Bytes, DispatchError>;
+  fn decode(bytes: Bytes) -> Result<T, DispatchError>;
+}
+
+pub struct ProtobufCodec;
+
+#[cfg(feature = "use_protobuf")]
+impl<T> AFPluginCodec<T> for ProtobufCodec
+where
+  T: protobuf::Message,
+{
+  fn encode(data: &T) -> Result<Bytes, DispatchError> {
+    Ok(Bytes::from(data.write_to_bytes()?))
+  }
+
+  fn decode(bytes: Bytes) -> Result<T, DispatchError> {
+    Ok(T::parse_from_bytes(&bytes)?)
+  }
+}
+
+/// Uses the json mapping of protobuf if the `use_protobuf` feature is enabled, otherwise serde.
+pub struct JsonCodec;
+
+#[cfg(feature = "use_protobuf")]
+impl<T> AFPluginCodec<T> for JsonCodec
+where
+  T: protobuf::Message,
+{
+  fn encode(data: &T) -> Result<Bytes, DispatchError> {
+    match protobuf::json::print_to_string(data) {
+      Ok(s) => Ok(Bytes::from(s)),
+      Err(e) => Err(
+        InternalError::ProtobufError(format!(
+          "Serial {} to json failed: {:?}",
+          std::any::type_name::<T>(),
+          e
+        ))
+        .into(),
+      ),
+    }
+  }
+
+  fn decode(bytes: Bytes) -> Result<T, DispatchError> {
+    let s = std::str::from_utf8(&bytes)
+      .map_err(|e| InternalError::DeserializeFromBytes(format!("{}", e)))?;
+    protobuf::json::parse_from_str::<T>(s).map_err(|e| {
+      InternalError::DeserializeFromBytes(format!(
+        "Parse json to {} failed: {:?}",
+        std::any::type_name::<T>(),
+        e
+      ))
+      .into()
+    })
+  }
+}
+
+#[cfg(all(feature = "use_serde", not(feature = "use_protobuf")))]
+impl<T> AFPluginCodec<T> for JsonCodec
+where
+  T: serde::Serialize + serde::de::DeserializeOwned,
+{
+  fn encode(data: &T) -> Result<Bytes, DispatchError> {
+    serde_json::to_vec(data).map(Bytes::from).map_err(|e| {
+      InternalError::Other(format!(
+        "Serial {} to json failed: {}",
+        std::any::type_name::<T>(),
+        e
+      ))
+      .into()
+    })
+  }
+
+  fn decode(bytes: Bytes) -> Result<T, DispatchError> {
+    serde_json::from_slice::<T>(&bytes).map_err(|e| {
+      InternalError::DeserializeFromBytes(format!(
+        "Parse json to {} failed: {}",
+        std::any::type_name::<T>(),
+        e
+      ))
+      .into()
+    })
+  }
+}
+
+/// The types that can be parsed with any [PayloadCodec]. It's implemented for the protobuf
+/// messages, or for the serde types if only the `use_serde` feature is enabled.
+pub trait AFPluginDecode: Sized {
+  fn decode_with(codec: PayloadCodec, bytes: Bytes) -> Result<Self, DispatchError>;
+}
+
+#[cfg(feature = "use_protobuf")]
+impl<T> AFPluginDecode for T
+where
+  T: protobuf::Message,
+{
+  fn decode_with(codec: PayloadCodec, bytes: Bytes) -> Result<Self, DispatchError> {
+    match codec {
+      PayloadCodec::Protobuf => <ProtobufCodec as AFPluginCodec<T>>::decode(bytes),
+      PayloadCodec::Json => <JsonCodec as AFPluginCodec<T>>::decode(bytes),
+    }
+  }
+}
+
+#[cfg(all(feature = "use_serde", not(feature = "use_protobuf")))]
+impl<T> AFPluginDecode for T
+where
+  T: serde::Serialize + serde::de::DeserializeOwned,
+{
+  fn decode_with(codec: PayloadCodec, bytes: Bytes) -> Result<Self, DispatchError> {
+    match codec {
+      PayloadCodec::Protobuf => Err(
+        InternalError::DeserializeFromBytes(
+          "The protobuf codec requires the use_protobuf feature".to_string(),
+        )
+        .into(),
+      ),
+      PayloadCodec::Json => <JsonCodec as AFPluginCodec<T>>::decode(bytes),
+    }
+  }
+}
+
+/// The types that can be encoded with any [PayloadCodec]. See [AFPluginDecode].
+pub trait AFPluginEncode {
+  fn encode_with(&self, codec: PayloadCodec) -> Result<Bytes, DispatchError>;
+}
+
+#[cfg(feature = "use_protobuf")]
+impl<T> AFPluginEncode for T
+where
+  T: protobuf::Message,
+{
+  fn encode_with(&self, codec: PayloadCodec) -> Result<Bytes, DispatchError> {
+    match codec {
+      PayloadCodec::Protobuf => <ProtobufCodec as AFPluginCodec<T>>::encode(self),
+      PayloadCodec::Json => <JsonCodec as AFPluginCodec<T>>::encode(self),
+    }
+  }
+}
+
+#[cfg(all(feature = "use_serde", not(feature = "use_protobuf")))]
+impl<T> AFPluginEncode for T
+where
+  T: serde::Serialize + serde::de::DeserializeOwned,
+{
+  fn encode_with(&self, codec: PayloadCodec) -> Result<Bytes, DispatchError> {
+    match codec {
+      PayloadCodec::Protobuf => Err(
+        InternalError::Other("The protobuf codec requires the use_protobuf feature".to_string())
+          .into(),
+      ),
+      PayloadCodec::Json => <JsonCodec as AFPluginCodec<T>>::encode(self),
+    }
+  }
+}
+
+pub(crate) fn parse_with_codec<T>(
+  codec: PayloadCodec,
+  payload: &Payload,
+) -> Result<T, DispatchError>
+where
+  T: AFPluginDecode,
+{
+  match payload {
+    Payload::None => Err(
+      InternalError::UnexpectedNone(format!(
+        "Parse fail, expected payload:{:?}",
+        std::any::type_name::<T>()
+      ))
+      .into(),
+    ),
+    Payload::Bytes(bytes) => T::decode_with(codec, bytes.clone()),
+  }
+}
+
+/// Extracts the payload with the codec of the request. Unlike [AFPluginData], it accepts the
+/// payload in any [PayloadCodec]. It's also the way to accept and return the protobuf messages
+/// directly:
+///
+/// ```ignore
+/// async fn handler(data: AFPluginParsed<pb::CreateDocPB>) -> AFPluginParsed<pb::DocPB> { .. }
+/// ```
+///
+/// The malformed payload is responded with the `DeserializeFromBytes` error before the handler
+/// runs, and the response is encoded with the codec of the request.
+///
+/// [AFPluginData]: crate::prelude::AFPluginData
+pub struct AFPluginParsed<T>(pub T);
+
+impl<T> AFPluginParsed<T> {
+  pub fn into_inner(self) -> T {
+    self.0
+  }
+}
+
+impl<T> ops::Deref for AFPluginParsed<T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    &self.0
+  }
+}
+
+impl<T> FromAFPluginRequest for AFPluginParsed<T>
+where
+  T: AFPluginDecode + 'static,
+{
+  type Error = DispatchError;
+  type Future = Ready<Result<Self, DispatchError>>;
+
+  #[inline]
+  fn from_request(req: &AFPluginEventRequest, payload: &mut Payload) -> Self::Future {
+    match payload {
+      Payload::None => ready(Err(unexpected_none_payload(req))),
+      Payload::Bytes(_) => ready(parse_with_codec(req.codec, payload).map(AFPluginParsed)),
+    }
+  }
+}
+
+impl<T> AFPluginResponder for AFPluginParsed<T>
+where
+  T: AFPluginEncode,
+{
+  fn respond_to(self, request: &AFPluginEventRequest) -> AFPluginEventResponse {
+    match self.0.encode_with(request.codec) {
+      Ok(bytes) => ResponseBuilder::Ok().data(bytes).build(),
+      Err(e) => e.into(),
+    }
+  }
+}
@@ -0,0 +1,105 @@
+use bytes::Bytes;
+
+use crate::errors::{DispatchError, InternalError};
+use crate::request::Payload;
+use crate::response::AFPluginEventResponse;
+
+/// The metadata key of the response that names the compression of the payload.
+pub const CONTENT_ENCODING: &str = "content-encoding";
+
+/// The payloads that are smaller than it are not worth compressing.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 64 * 1024;
+
+/// The compression that the caller accepts for the response payload. It's set via
+/// [AFPluginRequest::accept_compression], and the algorithm is enabled by the `compress_lz4` or
+/// `compress_zstd` feature. The payload is sent uncompressed if the feature is disabled.
+///
+/// [AFPluginRequest::accept_compression]: crate::prelude::AFPluginRequest::accept_compression
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PayloadCompression {
+  Lz4,
+  Zstd,
+}
+
+impl PayloadCompression {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      PayloadCompression::Lz4 => "lz4",
+      PayloadCompression::Zstd => "zstd",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "lz4" => Some(PayloadCompression::Lz4),
+      "zstd" => Some(PayloadCompression::Zstd),
+      _ => None,
+    }
+  }
+
+  /// Returns None if the algorithm is not enabled.
+  #[allow(unused_variables)]
+  pub(crate) fn compress(&self, data: &[u8]) -> Option<Bytes> {
+    match self {
+      #[cfg(feature = "compress_lz4")]
+      PayloadCompression::Lz4 => Some(Bytes::from(lz4_flex::compress_prepend_size(data))),
+      #[cfg(feature = "compress_zstd")]
+      PayloadCompression::Zstd => zstd::bulk::compress(data, 0)
+        .map(Bytes::from)
+        .map_err(|e| tracing::warn!("[dispatch]: zstd compression failed: {}", e))
+        .ok(),
+      #[allow(unreachable_patterns)]
+      _ => None,
+    }
+  }
+
+  #[allow(unused_variables)]
+  pub fn decompress(&self, data: &[u8]) -> Result<Bytes, DispatchError> {
+    let result: Result<Vec<u8>, String> = match self {
+      #[cfg(feature = "compress_lz4")]
+      PayloadCompression::Lz4 => {
+        lz4_flex::decompress_size_prepended(data).map_err(|e| e.to_string())
+      },
+      #[cfg(feature = "compress_zstd")]
+      PayloadCompression::Zstd => zstd::stream::decode_all(data).map_err(|e| e.to_string()),
+      #[allow(unreachable_patterns)]
+      _ => Err(format!("{} compression is not enabled", self.as_str())),
+    };
+    result
+      .map(Bytes::from)
+      .map_err(|e| InternalError::DeserializeFromBytes(e).into())
+  }
+}
+
+/// Compresses the payload in place if it exceeds the `threshold`, and names the compression in the
+/// metadata.
+pub(crate) fn compress_response(
+  response: &mut AFPluginEventResponse,
+  compression: PayloadCompression,
+  threshold: usize,
+) {
+  let compressed = match &response.payload {
+    Payload::Bytes(bytes) if bytes.len() >= threshold => compression.compress(bytes),
+    _ => None,
+  };
+  if let Some(compressed) = compressed {
+    response.payload = Payload::Bytes(compressed);
+    response.insert_metadata(CONTENT_ENCODING, compression.as_str());
+  }
+}
+
+impl AFPluginEventResponse {
+  /// Decompresses the payload if it's compressed. See [PayloadCompression].
+  pub fn decompress(mut self) -> Result<Self, DispatchError> {
+    let compression = match self.metadata.remove(CONTENT_ENCODING) {
+      None => return Ok(self),
+      Some(name) => PayloadCompression::from_name(&name).ok_or_else(|| {
+        InternalError::DeserializeFromBytes(format!("Unknown compression: {}", name))
+      })?,
+    };
+    if let Payload::Bytes(bytes) = &self.payload {
+      self.payload = Payload::Bytes(compression.decompress(bytes)?);
+    }
+    Ok(self)
+  }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::audit::AuditSink;
+use crate::builder::DispatchPanicPolicy;
+use crate::compression::DEFAULT_COMPRESSION_THRESHOLD;
+use crate::dead_letter::DeadLetterSink;
+#[cfg(all(feature = "forward", not(target_arch = "wasm32")))]
+use crate::forward::DispatchForwarder;
+use crate::history::DispatchHistory;
+use crate::idempotency::DispatchIdempotency;
+use crate::interceptor::DispatchInterceptor;
+use crate::journal::DispatchJournal;
+use crate::localize::DispatchLocalizer;
+use crate::logger::DispatchLogger;
+use crate::metrics::HighWaterMark;
+use crate::module::{AFPluginEvent, AFPluginStateMap, DuplicatePolicy};
+use crate::observer::DispatchErrorObserver;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::replay::DispatchRecorder;
+use crate::retry::DispatchRetryPolicy;
+use crate::runtime::AFPluginSpawner;
+use crate::scheduler::DispatchLoadShedding;
+use crate::supervisor::DispatchSupervisor;
+use crate::watchdog::DispatchWatchdog;
+
+/// The configurations of the dispatcher, which are set by [AFPluginDispatcherBuilder]. They are
+/// moved into the dispatcher when it's built, so they can't be changed while the requests are
+/// running.
+///
+/// [AFPluginDispatcherBuilder]: crate::prelude::AFPluginDispatcherBuilder
+pub(crate) struct DispatchConfig {
+  pub(crate) max_concurrent: Option<usize>,
+  pub(crate) spawner: Option<Arc<dyn AFPluginSpawner>>,
+  pub(crate) load_shedding: DispatchLoadShedding,
+  pub(crate) duplicate_policy: DuplicatePolicy,
+  pub(crate) retry_policy: Option<DispatchRetryPolicy>,
+  pub(crate) default_timeout: Option<Duration>,
+  pub(crate) panic_policy: DispatchPanicPolicy,
+  pub(crate) watchdog: Option<Arc<DispatchWatchdog>>,
+  pub(crate) slow_poll_budget: Option<Duration>,
+  pub(crate) supervisor: Option<Arc<DispatchSupervisor>>,
+  pub(crate) dead_letter: Option<Arc<dyn DeadLetterSink>>,
+  pub(crate) error_observer: Option<Arc<dyn DispatchErrorObserver>>,
+  pub(crate) localizer: Option<Arc<dyn DispatchLocalizer>>,
+  pub(crate) interceptors: Vec<Box<dyn DispatchInterceptor>>,
+  pub(crate) idempotency: DispatchIdempotency,
+  pub(crate) compression_threshold: usize,
+  pub(crate) high_water: Option<HighWaterMark>,
+  pub(crate) history: Option<Arc<DispatchHistory>>,
+  pub(crate) logger: Option<Arc<DispatchLogger>>,
+  pub(crate) audit: Option<Arc<dyn AuditSink>>,
+  pub(crate) journal: Option<Arc<dyn DispatchJournal>>,
+  #[cfg(not(target_arch = "wasm32"))]
+  pub(crate) recorder: Option<Arc<DispatchRecorder>>,
+  #[cfg(all(feature = "forward", not(target_arch = "wasm32")))]
+  pub(crate) forwarders: Vec<Arc<DispatchForwarder>>,
+  pub(crate) states: AFPluginStateMap,
+  pub(crate) renames: HashMap<AFPluginEvent, AFPluginEvent>,
+}
+
+impl std::default::Default for DispatchConfig {
+  fn default() -> Self {
+    Self {
+      max_concurrent: None,
+      spawner: None,
+      load_shedding: DispatchLoadShedding::default(),
+      duplicate_policy: DuplicatePolicy::default(),
+      retry_policy: None,
+      default_timeout: None,
+      panic_policy: DispatchPanicPolicy::default(),
+      watchdog: None,
+      slow_poll_budget: None,
+      supervisor: None,
+      dead_letter: None,
+      error_observer: None,
+      localizer: None,
+      interceptors: vec![],
+      idempotency: DispatchIdempotency::default(),
+      compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+      high_water: None,
+      history: None,
+      logger: None,
+      audit: None,
+      journal: None,
+      #[cfg(not(target_arch = "wasm32"))]
+      recorder: None,
+      #[cfg(all(feature = "forward", not(target_arch = "wasm32")))]
+      forwarders: vec![],
+      states: AFPluginStateMap::default(),
+      renames: HashMap::new(),
+    }
+  }
+}
@@ -131,9 +131,9 @@ where
       ))
       .into(),
     ),
-    Payload::Bytes(bytes) => {
-      let data = T::parse_from_bytes(bytes.clone())?;
-      Ok(AFPluginData(data))
+    Payload::Bytes(bytes) => match T::parse_from_bytes(bytes.clone()) {
+      Ok(data) => Ok(AFPluginData(data)),
+      Err(e) => Err(InternalError::DeserializeFromBytes(format!("{}", e)).into()),
     },
   }
 }
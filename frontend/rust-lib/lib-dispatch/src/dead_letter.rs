@@ -0,0 +1,31 @@
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::errors::DispatchError;
+use crate::module::AFPluginRequest;
+use crate::prelude::AFConcurrent;
+
+/// A request that can't be routed to any plugin.
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+  pub request: AFPluginRequest,
+  pub error: DispatchError,
+}
+
+/// Receives the requests that have no handler, so the app can inspect, persist or replay them.
+///
+/// The dispatcher still resolves the request with the error response after handing it over to
+/// the sink.
+pub trait DeadLetterSink: AFConcurrent {
+  fn receive(&self, letter: DeadLetter);
+}
+
+impl DeadLetterSink for UnboundedSender<DeadLetter> {
+  fn receive(&self, letter: DeadLetter) {
+    if let Err(err) = self.send(letter) {
+      tracing::warn!(
+        "[dispatch]: dead letter of {:?} is dropped, the receiver is closed",
+        err.0.request.event
+      );
+    }
+  }
+}
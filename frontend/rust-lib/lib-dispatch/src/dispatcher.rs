@@ -1,18 +1,63 @@
 use std::any::Any;
+use std::panic::AssertUnwindSafe;
 use std::pin::Pin;
+use std::sync::Weak;
 use std::task::{Context, Poll};
+use std::time::Duration;
 use std::{future::Future, sync::Arc};
 
 use derivative::*;
+use futures::FutureExt;
+use nanoid::nanoid;
 use pin_project::pin_project;
+use tokio::sync::oneshot::error::RecvError;
+use tokio::sync::{oneshot, OwnedSemaphorePermit, Semaphore, TryAcquireError};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 use tracing::event;
+#[cfg(feature = "trace_requests")]
+use tracing::Instrument;
 
+use crate::audit::{AuditRecord, AuditSink};
+use crate::builder::{AFPluginDispatcherBuilder, DispatchPanicPolicy};
+use crate::cache::{CacheKey, DispatchCache};
+use crate::coalesce::{Coalesced, DispatchCoalescer};
+use crate::compression::compress_response;
+use crate::config::DispatchConfig;
+use crate::dead_letter::{DeadLetter, DeadLetterSink};
+#[cfg(all(feature = "forward", not(target_arch = "wasm32")))]
+use crate::forward::{find_forwarder, DispatchForwarder};
+use crate::graph::{self, EventGraph};
+use crate::health::{check_health, health_response, is_health_event, DispatchHealth};
+use crate::history::{DispatchHistory, DispatchRecord};
+use crate::idempotency::DispatchIdempotency;
+use crate::inflight::InFlightRequest;
+use crate::interceptor::DispatchInterceptor;
+use crate::lifecycle::{DispatchLifecycle, DispatchLifecycleChannel};
+use crate::localize::{localize_response, DispatchLocalizer};
+use crate::logger::{log_finish, log_start, DispatchLogger};
+use crate::metrics::DispatchMetrics;
+#[cfg(feature = "test_helper")]
+use crate::mock::{DispatchMocks, MockHandler};
 use crate::module::AFPluginStateMap;
+use crate::observer::{DispatchErrorObserver, DispatchErrorReport};
+use crate::request::DispatchRequestBuilder;
+use crate::retry::DispatchRetryPolicy;
 use crate::runtime::AFPluginRuntime;
+use crate::scheduler::{DispatchScheduler, DispatchTask};
+use crate::shared_buffer::{is_release_buffer_event, release_buffer_response};
+use crate::slow_poll::SlowPoll;
+use crate::stats::{is_stats_event, stats_response, DispatchStats, EventStats};
+use crate::subscription::{is_subscription_event, subscription_response};
+use crate::watchdog::{watch, DispatchWatchdog, StuckHandler};
 use crate::{
-  errors::{DispatchError, Error, InternalError},
-  module::{plugin_map_or_crash, AFPlugin, AFPluginMap, AFPluginRequest},
-  response::AFPluginEventResponse,
+  errors::{DispatchError, DispatchErrorCode, DispatchTimeout, Error, InternalError, TrySendError},
+  module::{
+    plugin_routes, plugin_routes_lossy, AFPlugin, AFPluginBundle, AFPluginEvent, AFPluginFactory,
+    AFPluginMap, AFPluginRequest, DispatchEventInfo, DispatchMode, DispatchRoutes, DuplicatePolicy,
+    PluginHook,
+  },
+  response::{AFPluginEventResponse, ResponseBuilder, StatusCode},
   service::{AFPluginServiceFactory, Service},
 };
 
@@ -79,19 +124,201 @@ where
 }
 
 pub struct AFPluginDispatcher {
-  plugins: AFPluginMap,
-  runtime: Arc<AFPluginRuntime>,
+  pub(crate) runtime: Arc<AFPluginRuntime>,
+  /// Limits the number of requests that can be in flight at the same time. `None` means the
+  /// dispatcher accepts requests without any limit.
+  capacity: Option<Arc<Semaphore>>,
+  pub(crate) scheduler: Arc<DispatchScheduler>,
 }
 
 impl AFPluginDispatcher {
+  /// The plugins whose events conflict with the ones registered before them are left out and
+  /// logged, see [DuplicatePolicy]. Build the dispatcher with [AFPluginDispatcher::builder] to
+  /// handle the error instead.
   pub fn new(runtime: Arc<AFPluginRuntime>, plugins: Vec<AFPlugin>) -> AFPluginDispatcher {
     tracing::trace!("{}", plugin_info(&plugins));
+    let routes = plugin_routes_lossy(plugins, DuplicatePolicy::default());
+    Self::with_config(runtime, routes, DispatchConfig::default(), None)
+  }
+
+  /// Builds the plugins concurrently, e.g. to wait for the database migrations, then creates
+  /// the dispatcher. Unlike [AFPluginDispatcher::new], it fails if any of the plugins fails to
+  /// build or their events conflict.
+  pub async fn new_async(
+    runtime: Arc<AFPluginRuntime>,
+    factories: Vec<AFPluginFactory>,
+  ) -> Result<AFPluginDispatcher, DispatchError> {
+    let plugins = futures::future::try_join_all(factories).await?;
+    tracing::trace!("{}", plugin_info(&plugins));
+    let routes = plugin_routes(plugins, DuplicatePolicy::default())?;
+    Ok(Self::with_config(
+      runtime,
+      routes,
+      DispatchConfig::default(),
+      None,
+    ))
+  }
+
+  pub(crate) fn with_config(
+    runtime: Arc<AFPluginRuntime>,
+    routes: DispatchRoutes,
+    config: DispatchConfig,
+    capacity: Option<usize>,
+  ) -> AFPluginDispatcher {
+    let scheduler = Arc::new(DispatchScheduler::new(routes, runtime.clone(), config));
     AFPluginDispatcher {
-      plugins: plugin_map_or_crash(plugins),
       runtime,
+      capacity: capacity.map(|capacity| Arc::new(Semaphore::new(capacity))),
+      scheduler,
+    }
+  }
+
+  /// See [AFPluginDispatcherBuilder].
+  pub fn builder() -> AFPluginDispatcherBuilder {
+    AFPluginDispatcherBuilder::new()
+  }
+
+  /// Creates a dispatcher that allows at most `capacity` requests to be in flight at once.
+  ///
+  /// When the dispatcher is full, `async_send` waits until one of the in-flight requests
+  /// completes, `try_async_send` returns [TrySendError::Full] and the boxed variants resolve
+  /// with an error response. The plugins whose events conflict are left out, the same as
+  /// [AFPluginDispatcher::new].
+  pub fn with_capacity(
+    runtime: Arc<AFPluginRuntime>,
+    plugins: Vec<AFPlugin>,
+    capacity: usize,
+  ) -> AFPluginDispatcher {
+    tracing::trace!("{}", plugin_info(&plugins));
+    let routes = plugin_routes_lossy(plugins, DuplicatePolicy::default());
+    Self::with_config(runtime, routes, DispatchConfig::default(), Some(capacity))
+  }
+
+  /// Returns the recorded requests from the oldest to the newest. It's empty if the history is
+  /// not enabled by [AFPluginDispatcherBuilder::record_history].
+  pub fn history(&self) -> Vec<DispatchRecord> {
+    match &self.scheduler.history {
+      None => vec![],
+      Some(history) => history.records(),
     }
   }
 
+  /// Returns the current queue depth and the enqueue-to-start latency.
+  pub fn metrics(&self) -> DispatchMetrics {
+    self.scheduler.metrics()
+  }
+
+  /// Returns the execution statistics of the events that have run their handlers, sorted by the
+  /// event. It's also answered to the frontend by the built-in [STATS_EVENT] unless a plugin
+  /// registers the event.
+  ///
+  /// [STATS_EVENT]: crate::prelude::STATS_EVENT
+  pub fn stats(&self) -> Vec<EventStats> {
+    self.scheduler.stats.snapshot()
+  }
+
+  /// Returns the nested dispatches that are observed so far, e.g. to render the cascading
+  /// dispatch chains with [EventGraph::to_dot] or to find the cycles.
+  ///
+  /// [EventGraph::to_dot]: crate::prelude::EventGraph::to_dot
+  pub fn event_graph(&self) -> EventGraph {
+    self.scheduler.event_graph.snapshot()
+  }
+
+  /// Returns the requests that are being handled, from the longest running to the latest, e.g.
+  /// to show what the backend is doing when the saving gets stuck. The requests waiting in the
+  /// queue are not included, see [AFPluginDispatcher::metrics].
+  pub fn in_flight(&self) -> Vec<InFlightRequest> {
+    self.scheduler.in_flight.snapshot()
+  }
+
+  /// Runs the health checks of the plugins. It's also answered to the frontend by the built-in
+  /// [HEALTH_EVENT] unless a plugin registers the event.
+  ///
+  /// [HEALTH_EVENT]: crate::prelude::HEALTH_EVENT
+  pub async fn health(&self) -> DispatchHealth {
+    check_health(&self.scheduler.routes()).await
+  }
+
+  /// Returns all the registered events with their plugins, e.g. to answer the frontend's
+  /// capability query or to assert that the handlers are wired up in the tests. The legacy
+  /// aliases and the fallback handler are not listed.
+  pub fn events(&self) -> Vec<DispatchEventInfo> {
+    self.scheduler.routes().events()
+  }
+
+  /// Returns the builder of the request of the `event`. See [DispatchRequestBuilder].
+  pub fn request<E>(&self, event: E) -> DispatchRequestBuilder<'_>
+  where
+    E: Into<AFPluginEvent>,
+  {
+    DispatchRequestBuilder::new(self, event)
+  }
+
+  /// Registers the plugin after the dispatcher is created, e.g. the plugin that is loaded on
+  /// demand. The routing table is updated atomically, the requests that are already running are
+  /// not affected. See [AFPluginDispatcherBuilder::duplicate_policy] for the duplicate events.
+  ///
+  /// The plugins whose events are all overridden by the plugin are unregistered: their teardown
+  /// hooks run in the background once their running handlers complete.
+  pub fn register_plugin(&self, plugin: AFPlugin) -> Result<(), DispatchError> {
+    let name = plugin.name.clone();
+    let overridden = self.scheduler.register(plugin)?;
+    tracing::info!("[dispatch]: plugin {} registered", name);
+    let lifecycle = DispatchLifecycle::PluginRegistered(name);
+    self.scheduler.lifecycle.publish(lifecycle);
+
+    for plugin in overridden {
+      tracing::info!(
+        "[dispatch]: plugin {} is overridden, tear it down",
+        plugin.name
+      );
+      let lifecycle = self.scheduler.lifecycle.clone();
+      self.scheduler.spawner.spawn_detached(Box::pin(async move {
+        plugin.wait_idle().await;
+        plugin.run_hook(PluginHook::Teardown).await;
+        let name = plugin.name.clone();
+        lifecycle.publish(DispatchLifecycle::PluginUnregistered(name));
+      }));
+    }
+    Ok(())
+  }
+
+  /// Registers all the plugins of the bundle. It stops at the first plugin that fails to
+  /// register, the plugins before it stay registered.
+  pub fn register_bundle(&self, bundle: AFPluginBundle) -> Result<(), DispatchError> {
+    let name = bundle.name.clone();
+    for plugin in bundle.into_plugins() {
+      self.register_plugin(plugin)?;
+    }
+    tracing::info!("[dispatch]: bundle {} registered", name);
+    Ok(())
+  }
+
+  /// Unregisters the plugin with the `name`, e.g. the plugin that is disabled by the user.
+  ///
+  /// The plugin's events are removed from the routing table right away, so the new requests of
+  /// them fail with the handler not found error, or are handled by the plugins that the plugin
+  /// chained onto. The plugin is removed from the chains of the plugins registered later too.
+  /// Then it waits for the running handlers of the plugin to complete and runs the plugin's
+  /// teardown hook.
+  pub async fn unregister_plugin(&self, name: &str) -> Result<(), DispatchError> {
+    let plugin = self.scheduler.unregister(name).ok_or_else(|| {
+      InternalError::ServiceNotFound(format!("[dispatch]: plugin {} is not registered", name))
+    })?;
+    self
+      .runtime
+      .run_until(async move {
+        plugin.wait_idle().await;
+        plugin.run_hook(PluginHook::Teardown).await;
+      })
+      .await;
+    tracing::info!("[dispatch]: plugin {} unregistered", name);
+    let lifecycle = DispatchLifecycle::PluginUnregistered(name.to_string());
+    self.scheduler.lifecycle.publish(lifecycle);
+    Ok(())
+  }
+
   pub async fn async_send<Req>(dispatch: &AFPluginDispatcher, request: Req) -> AFPluginEventResponse
   where
     Req: Into<AFPluginRequest>,
@@ -108,37 +335,233 @@ impl AFPluginDispatcher {
     Req: Into<AFPluginRequest>,
     Callback: FnOnce(AFPluginEventResponse) -> AFBoxFuture<'static, ()> + AFConcurrent + 'static,
   {
-    let request: AFPluginRequest = request.into();
-    let plugins = dispatch.plugins.clone();
-    let service = Box::new(DispatchService { plugins });
+    let request = into_request(request);
     tracing::trace!("Async event: {:?}", &request.event);
-    let service_ctx = DispatchContext {
-      request,
-      callback: Some(Box::new(callback)),
-    };
+    let fut = dispatch.send_request(request, Some(Box::new(callback)));
+    dispatch.runtime.run_until(fut).await
+  }
 
-    // Spawns a future onto the runtime.
-    //
-    // This spawns the given future onto the runtime's executor, usually a
-    // thread pool. The thread pool is then responsible for polling the future
-    // until it completes.
-    //
-    // The provided future will start running in the background immediately
-    // when `spawn` is called, even if you don't await the returned
-    // `JoinHandle`.
-    let handle = dispatch.runtime.spawn(async move {
-      service.call(service_ctx).await.unwrap_or_else(|e| {
-        tracing::error!("Dispatch runtime error: {:?}", e);
-        InternalError::Other(format!("{:?}", e)).as_response()
+  /// Sends the request and calls `on_progress` for every partial response that the handler reports
+  /// through the [AFPluginProgress] extractor. Returns the final response after all the partial
+  /// responses are delivered.
+  ///
+  /// [AFPluginProgress]: crate::prelude::AFPluginProgress
+  pub async fn async_send_with_progress<Req, Callback>(
+    dispatch: &AFPluginDispatcher,
+    request: Req,
+    mut on_progress: Callback,
+  ) -> AFPluginEventResponse
+  where
+    Req: Into<AFPluginRequest>,
+    Callback: FnMut(AFPluginEventResponse) -> AFBoxFuture<'static, ()> + AFConcurrent + 'static,
+  {
+    let mut request = into_request(request);
+    tracing::trace!(
+      "[dispatch]: Async event with progress: {:?}",
+      &request.event
+    );
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    request.progress = Some(tx);
+    let mut fut = dispatch.send_request(request, None);
+    dispatch
+      .runtime
+      .run_until(async move {
+        loop {
+          tokio::select! {
+            biased;
+            Some(progress) = rx.recv() => on_progress(progress).await,
+            response = &mut fut => {
+              while let Ok(progress) = rx.try_recv() {
+                on_progress(progress).await;
+              }
+              return response;
+            },
+          }
+        }
       })
-    });
+      .await
+  }
+
+  /// Sends the request whose handler returns the [AFPluginResponseStream], and calls `on_chunk`
+  /// for every chunk. Returns the final response after the stream ends.
+  ///
+  /// The stream outlives the handler, so the chunks are received until all the senders of the
+  /// request are dropped rather than until the handler returns.
+  ///
+  /// [AFPluginResponseStream]: crate::prelude::AFPluginResponseStream
+  pub async fn async_send_with_stream<Req, Callback>(
+    dispatch: &AFPluginDispatcher,
+    request: Req,
+    mut on_chunk: Callback,
+  ) -> AFPluginEventResponse
+  where
+    Req: Into<AFPluginRequest>,
+    Callback: FnMut(AFPluginEventResponse) -> AFBoxFuture<'static, ()> + AFConcurrent + 'static,
+  {
+    let mut request = into_request(request);
+    tracing::trace!("[dispatch]: Async event with stream: {:?}", &request.event);
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    request.progress = Some(tx);
+    let fut = dispatch.send_request(request, None);
+    dispatch
+      .runtime
+      .run_until(async move {
+        let (response, _) = futures::future::join(fut, async {
+          while let Some(chunk) = rx.recv().await {
+            on_chunk(chunk).await;
+          }
+        })
+        .await;
+        response
+      })
+      .await
+  }
 
-    let result = dispatch.runtime.run_until(handle).await;
-    result.unwrap_or_else(|e| {
-      let msg = format!("EVENT_DISPATCH join error: {:?}", e);
-      tracing::error!("{}", msg);
-      let error = InternalError::JoinError(msg);
-      error.as_response()
+  /// Sends the request right away and returns a future that resolves with its response.
+  ///
+  /// Unlike `async_send`, the request is dispatched when this function is called instead of when
+  /// the returned future is first polled, so the caller can fire several requests and await their
+  /// responses later without inventing its own callback plumbing. If the dispatcher is full, the
+  /// request is dispatched as soon as the capacity is available.
+  pub fn async_send_with_response<Req>(
+    dispatch: &AFPluginDispatcher,
+    request: Req,
+  ) -> impl Future<Output = AFPluginEventResponse>
+  where
+    Req: Into<AFPluginRequest>,
+  {
+    let request = into_request(request);
+    tracing::trace!(
+      "[dispatch]: Async event with response: {:?}",
+      &request.event
+    );
+    let fut: AFBoxFuture<'static, AFPluginEventResponse> = match dispatch.try_acquire_permit() {
+      Ok(permit) => {
+        let cancel_token = request.cancel_token.clone();
+        let rx = schedule(&dispatch.scheduler, request, None, permit, Instant::now());
+        let scheduler = Arc::downgrade(&dispatch.scheduler);
+        Box::pin(wait_response(rx, cancel_token, scheduler))
+      },
+      Err(TryAcquireError::NoPermits) => dispatch.send_request(request, None),
+      Err(TryAcquireError::Closed) => {
+        // Stays pending in the journal, it's dispatched again on the next launch.
+        dispatch.scheduler.journal_request(&request);
+        let error = InternalError::Shutdown("[dispatch]: the dispatcher is closed".to_string());
+        Box::pin(reject(error, request.correlation_id, None))
+      },
+    };
+    let runtime = dispatch.runtime.clone();
+    async move { runtime.run_until(fut).await }
+  }
+
+  /// Sends the requests at once and resolves with their responses in the same order.
+  ///
+  /// The requests are enqueued in a single step instead of one by one, which saves the overhead
+  /// when lots of events are fired together, e.g. updating many cells. If the dispatcher doesn't
+  /// have enough capacity, the requests are enqueued in several batches.
+  pub async fn async_send_batch<Req>(
+    dispatch: &AFPluginDispatcher,
+    requests: Vec<Req>,
+  ) -> Vec<AFPluginEventResponse>
+  where
+    Req: Into<AFPluginRequest>,
+  {
+    let requests = requests.into_iter().map(into_request).collect::<Vec<_>>();
+    tracing::trace!("[dispatch]: Async batch of {} events", requests.len());
+    let fut = dispatch.send_batch(requests);
+    dispatch.runtime.run_until(fut).await
+  }
+
+  /// Sends the request and returns a handle that can cancel it.
+  ///
+  /// Cancelling a request that is still waiting in the dispatcher removes it from the queue.
+  /// Cancelling a running request drops its handler, and the handler can observe the
+  /// cancellation through the [CancellationToken] extractor to stop cooperatively. In both
+  /// cases, the returned future resolves with a cancelled error response.
+  pub fn cancellable_async_send<Req>(
+    dispatch: &AFPluginDispatcher,
+    request: Req,
+  ) -> (DispatchCancelHandle, DispatchFuture<AFPluginEventResponse>)
+  where
+    Req: Into<AFPluginRequest>,
+  {
+    let request = into_request(request);
+    tracing::trace!("[dispatch]: Cancellable async event: {:?}", &request.event);
+    let handle = DispatchCancelHandle {
+      cancel_token: request.cancel_token.clone(),
+      scheduler: Arc::downgrade(&dispatch.scheduler),
+    };
+    let fut = dispatch.send_request(request, None);
+    let runtime = dispatch.runtime.clone();
+    let fut = DispatchFuture {
+      fut: Box::pin(async move { runtime.run_until(fut).await }),
+    };
+    (handle, fut)
+  }
+
+  /// Sends the request without waiting for its response. See [DispatchMode::FireAndForget].
+  pub fn fire_and_forget<Req>(dispatch: &AFPluginDispatcher, request: Req)
+  where
+    Req: Into<AFPluginRequest>,
+  {
+    let request = into_request(request).mode(DispatchMode::FireAndForget);
+    tracing::trace!("[dispatch]: Fire and forget event: {:?}", &request.event);
+    match dispatch.try_acquire_permit() {
+      Ok(permit) => {
+        let _ = schedule(&dispatch.scheduler, request, None, permit, Instant::now());
+      },
+      // Waits for the capacity in the background.
+      Err(TryAcquireError::NoPermits) => {
+        let fut = dispatch.send_request(request, None);
+        dispatch
+          .scheduler
+          .spawner
+          .spawn_detached(Box::pin(async move {
+            fut.await;
+          }));
+      },
+      Err(TryAcquireError::Closed) => {
+        // Stays pending in the journal, it's dispatched again on the next launch.
+        dispatch.scheduler.journal_request(&request);
+        tracing::warn!(
+          "[dispatch]: drop event {:?}, the dispatcher is closed",
+          &request.event
+        );
+      },
+    }
+  }
+
+  /// Sends the request without waiting for the capacity of the dispatcher.
+  ///
+  /// Returns [TrySendError::Full] with the passed-in request if the dispatcher has reached its
+  /// capacity, so the caller can decide to retry, drop or queue the request by itself.
+  pub fn try_async_send<Req>(
+    dispatch: &AFPluginDispatcher,
+    request: Req,
+  ) -> Result<DispatchFuture<AFPluginEventResponse>, TrySendError>
+  where
+    Req: Into<AFPluginRequest>,
+  {
+    let request = into_request(request);
+    if dispatch.scheduler.is_closed() {
+      return Err(TrySendError::Closed(request));
+    }
+    let permit = match dispatch.try_acquire_permit() {
+      Ok(permit) => permit,
+      Err(TryAcquireError::NoPermits) => return Err(TrySendError::Full(request)),
+      Err(TryAcquireError::Closed) => return Err(TrySendError::Closed(request)),
+    };
+
+    tracing::trace!("[dispatch]: Try async event: {:?}", &request.event);
+    let cancel_token = request.cancel_token.clone();
+    let rx = schedule(&dispatch.scheduler, request, None, permit, Instant::now());
+    let runtime = dispatch.runtime.clone();
+    let scheduler = Arc::downgrade(&dispatch.scheduler);
+    Ok(DispatchFuture {
+      fut: Box::pin(async move {
+        let fut = wait_response(rx, cancel_token, scheduler);
+        runtime.run_until(fut).await
+      }),
     })
   }
 
@@ -161,34 +584,49 @@ impl AFPluginDispatcher {
     Req: Into<AFPluginRequest> + 'static,
     Callback: FnOnce(AFPluginEventResponse) -> AFBoxFuture<'static, ()> + AFConcurrent + 'static,
   {
-    let request: AFPluginRequest = request.into();
-    let plugins = dispatch.plugins.clone();
-    let service = Box::new(DispatchService { plugins });
+    let request = into_request(request);
     tracing::trace!("[dispatch]: Async event: {:?}", &request.event);
-    let service_ctx = DispatchContext {
-      request,
-      callback: Some(Box::new(callback)),
+
+    // The boxed variants are called from the non-async context, so they can't wait for the
+    // capacity. The request is rejected with an error response when the dispatcher is full.
+    let rx = match dispatch.try_acquire_permit() {
+      Ok(permit) => schedule(
+        &dispatch.scheduler,
+        request,
+        Some(Box::new(callback)),
+        permit,
+        Instant::now(),
+      ),
+      Err(err) => {
+        let msg = format!("[dispatch]: reject event {:?}: {}", &request.event, err);
+        tracing::warn!("{}", msg);
+        let mut response = InternalError::QueueFull(msg).as_response();
+        response.correlation_id = request.correlation_id;
+        let (tx, rx) = oneshot::channel();
+        dispatch
+          .scheduler
+          .spawner
+          .spawn_detached(Box::pin(async move {
+            callback(response.clone()).await;
+            let _ = tx.send(response);
+          }));
+        rx
+      },
     };
 
-    let handle = dispatch.runtime.spawn(async move {
-      service.call(service_ctx).await.unwrap_or_else(|e| {
-        tracing::error!("[dispatch]: runtime error: {:?}", e);
-        InternalError::Other(format!("{:?}", e)).as_response()
-      })
-    });
+    // The browser can't block, the request runs on its event loop while the caller awaits.
+    #[cfg(target_arch = "wasm32")]
+    {
+      DispatchFuture {
+        fut: Box::pin(async move { recv_response(rx.await) }),
+      }
+    }
 
-    #[cfg(any(target_arch = "wasm32", feature = "local_set"))]
+    #[cfg(all(not(target_arch = "wasm32"), feature = "local_set"))]
     {
-      let result = dispatch.runtime.block_on(handle);
+      let result = dispatch.runtime.block_on(rx);
       DispatchFuture {
-        fut: Box::pin(async move {
-          result.unwrap_or_else(|e| {
-            let msg = format!("EVENT_DISPATCH join error: {:?}", e);
-            tracing::error!("{}", msg);
-            let error = InternalError::JoinError(msg);
-            error.as_response()
-          })
-        }),
+        fut: Box::pin(async move { recv_response(result) }),
       }
     }
 
@@ -196,29 +634,242 @@ impl AFPluginDispatcher {
     {
       let runtime = dispatch.runtime.clone();
       DispatchFuture {
-        fut: Box::pin(async move {
-          let result = runtime.run_until(handle).await;
-          result.unwrap_or_else(|e| {
-            let msg = format!("EVENT_DISPATCH join error: {:?}", e);
-            tracing::error!("{}", msg);
-            let error = InternalError::JoinError(msg);
-            error.as_response()
-          })
-        }),
+        fut: Box::pin(async move { recv_response(runtime.run_until(rx).await) }),
       }
     }
   }
 
+  /// Runs the `on_start` hooks of the plugins in the order of their dependencies. Call it once
+  /// after the dispatcher is created.
+  ///
+  /// Fails before any hook is called if a dependency is missing or the dependencies are
+  /// circular.
+  pub async fn start(&self) -> Result<(), DispatchError> {
+    tracing::info!("[dispatch]: starting");
+    let scheduler = self.scheduler.clone();
+    self
+      .runtime
+      .run_until(async move { scheduler.run_hooks(PluginHook::Start).await })
+      .await?;
+    self.scheduler.lifecycle.publish(DispatchLifecycle::Started);
+    Ok(())
+  }
+
+  /// Sends the requests that are left in the journal by the previous run again, in the order
+  /// they were sent, without waiting for their responses. Returns the number of the requests.
+  ///
+  /// The handler may have applied a request before the crash, so a journaled event should be
+  /// safe to apply twice, e.g. by checking its idempotency key.
+  pub fn redispatch_journaled(&self) -> usize {
+    let entries = match &self.scheduler.journal {
+      None => return 0,
+      Some(journal) => journal.pending(),
+    };
+    if !entries.is_empty() {
+      tracing::info!(
+        "[dispatch]: redispatch {} journaled requests",
+        entries.len()
+      );
+    }
+    for entry in entries.iter() {
+      AFPluginDispatcher::fire_and_forget(self, entry.to_request());
+    }
+    entries.len()
+  }
+
+  /// Pauses the dispatching, e.g. while the application migrates its database.
+  ///
+  /// The requests sent while paused are still accepted but wait in the dispatcher until
+  /// [AFPluginDispatcher::resume] is called. The running requests are not affected.
+  pub fn pause(&self) {
+    tracing::info!("[dispatch]: paused");
+    self.scheduler.pause();
+  }
+
+  /// Resumes the dispatching and starts the requests that were sent while paused.
+  pub fn resume(&self) {
+    tracing::info!("[dispatch]: resumed");
+    self.scheduler.resume();
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.scheduler.is_paused()
+  }
+
+  /// Resolves when all the running and pending requests are completed, including the fire and
+  /// forget ones, without closing the dispatcher, e.g. to measure the throughput of a burst of
+  /// requests in the benchmarks.
+  pub async fn wait_drained(&self) {
+    let scheduler = self.scheduler.clone();
+    self
+      .runtime
+      .run_until(async move { scheduler.wait_idle().await })
+      .await;
+  }
+
+  /// Replaces the handler of the `event` with the `mock` until it's removed, including the
+  /// event that no plugin registers. The mocks can be changed while the dispatcher is used, so
+  /// a test can isolate a plugin from the plugins it dispatches into. See [MockResponse] and
+  /// [MockRecorder].
+  ///
+  /// [MockResponse]: crate::prelude::MockResponse
+  /// [MockRecorder]: crate::prelude::MockRecorder
+  #[cfg(feature = "test_helper")]
+  pub fn mock_event<E, M>(&self, event: E, mock: M)
+  where
+    E: Into<AFPluginEvent>,
+    M: MockHandler + 'static,
+  {
+    self.scheduler.mocks.insert(event.into(), Arc::new(mock));
+  }
+
+  /// Restores the handler of the `event`.
+  #[cfg(feature = "test_helper")]
+  pub fn unmock_event<E: Into<AFPluginEvent>>(&self, event: E) {
+    self.scheduler.mocks.remove(&event.into());
+  }
+
+  #[cfg(feature = "test_helper")]
+  pub fn clear_mocks(&self) {
+    self.scheduler.mocks.clear();
+  }
+
+  /// Shuts down the dispatcher gracefully.
+  ///
+  /// The dispatcher stops accepting new requests right away, the requests sent afterwards are
+  /// resolved with the shutdown error, and so are the requests that are still waiting for the
+  /// capacity of the dispatcher. Then it waits for the running and pending requests to be
+  /// completed, so the writes are not lost when the application exits. Returns the timeout error
+  /// if the requests are not completed within the `timeout`.
+  pub async fn shutdown(&self, timeout: Duration) -> Result<(), DispatchError> {
+    tracing::info!("[dispatch]: shutting down");
+    self.scheduler.close();
+    self
+      .scheduler
+      .lifecycle
+      .publish(DispatchLifecycle::ShuttingDown);
+    if let Some(capacity) = &self.capacity {
+      // Wakes up the requests that are waiting for the capacity, they are rejected with the
+      // shutdown error.
+      capacity.close();
+    }
+    // The requests that were sent while paused need to be drained too.
+    self.scheduler.resume();
+
+    let wait_idle = tokio::time::timeout(timeout, self.scheduler.wait_idle());
+    let result = match self.runtime.run_until(wait_idle).await {
+      Ok(_) => Ok(()),
+      Err(_) => {
+        let msg = format!(
+          "[dispatch]: shutdown timeout after {:?}, some requests are not completed",
+          timeout
+        );
+        tracing::warn!("{}", msg);
+        Err(InternalError::Timeout(msg).into())
+      },
+    };
+
+    // The plugins release their resources even if some requests are not completed.
+    let on_stop = self.scheduler.run_hooks(PluginHook::Stop);
+    if let Err(e) = self.runtime.run_until(on_stop).await {
+      tracing::error!("{}", e);
+    }
+    tracing::info!("[dispatch]: shut down");
+    self.scheduler.mark_stopped();
+    result
+  }
+
+  /// Shuts down the dispatcher in the background and calls the `on_stopped` with the result of
+  /// [AFPluginDispatcher::shutdown] once everything has quiesced. It's for the callers that can't
+  /// await, e.g. the lifecycle callback of the mobile app.
+  pub fn stop<F>(dispatch: Arc<AFPluginDispatcher>, timeout: Duration, on_stopped: F)
+  where
+    F: FnOnce(Result<(), DispatchError>) + AFConcurrent + 'static,
+  {
+    let spawner = dispatch.scheduler.spawner.clone();
+    spawner.spawn_detached(Box::pin(async move {
+      let result = dispatch.shutdown(timeout).await;
+      on_stopped(result);
+    }));
+  }
+
+  /// Publishes the lifecycle transitions of the dispatcher. See [DispatchLifecycleChannel].
+  pub fn lifecycle(&self) -> DispatchLifecycleChannel {
+    self.scheduler.lifecycle.clone()
+  }
+
+  /// Resolves when the shutdown is completed, right away if it's already completed.
+  pub async fn stopped(&self) {
+    self.scheduler.wait_stopped().await
+  }
+
+  pub fn is_stopped(&self) -> bool {
+    self.scheduler.is_stopped()
+  }
+
+  /// Sends the request and blocks the current thread until the response is received.
+  ///
+  /// Returns the error response instead of blocking if it's called inside a tokio runtime. See
+  /// [AFPluginDispatcher::try_sync_send].
   #[cfg(not(target_arch = "wasm32"))]
   pub fn sync_send(
     dispatch: Arc<AFPluginDispatcher>,
     request: AFPluginRequest,
   ) -> AFPluginEventResponse {
-    futures::executor::block_on(AFPluginDispatcher::async_send_with_callback(
-      dispatch.as_ref(),
-      request,
-      |_| Box::pin(async {}),
-    ))
+    AFPluginDispatcher::try_sync_send(dispatch.as_ref(), request).unwrap_or_else(|e| e.into())
+  }
+
+  /// Sends the request and blocks the current thread until the response is received.
+  ///
+  /// Returns an error if it's called inside a tokio runtime instead of blocking.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn try_sync_send(
+    dispatch: &AFPluginDispatcher,
+    request: AFPluginRequest,
+  ) -> Result<AFPluginEventResponse, DispatchError> {
+    check_blocking(&request.event)?;
+    let fut = AFPluginDispatcher::async_send(dispatch, request);
+    Ok(dispatch.runtime.block_on(fut))
+  }
+
+  /// Same as [AFPluginDispatcher::sync_send] but gives up after the `timeout`, so a wedged handler
+  /// can't hang the caller's thread. The request is cancelled when it's timed out.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn sync_send_timeout(
+    dispatch: &AFPluginDispatcher,
+    request: AFPluginRequest,
+    timeout: Duration,
+  ) -> Result<AFPluginEventResponse, DispatchTimeout> {
+    if let Err(e) = check_blocking(&request.event) {
+      return Ok(e.into());
+    }
+
+    let event = request.event.clone();
+    let (handle, fut) = AFPluginDispatcher::cancellable_async_send(dispatch, request);
+    match dispatch
+      .runtime
+      .block_on(tokio::time::timeout(timeout, fut))
+    {
+      Ok(response) => Ok(response),
+      Err(_) => {
+        tracing::warn!("[dispatch]: event {:?} timeout after {:?}", event, timeout);
+        handle.cancel();
+        Err(DispatchTimeout { event, timeout })
+      },
+    }
+  }
+
+  /// Sends the request and blocks the current thread until the response is received, without
+  /// checking the runtime context.
+  ///
+  /// Only call it from the threads that are not managed by any runtime, e.g. the FFI threads.
+  /// Calling it inside a tokio runtime can deadlock.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn blocking_send(
+    dispatch: &AFPluginDispatcher,
+    request: AFPluginRequest,
+  ) -> AFPluginEventResponse {
+    futures::executor::block_on(AFPluginDispatcher::async_send(dispatch, request))
   }
 
   #[cfg(any(target_arch = "wasm32", feature = "local_set"))]
@@ -259,6 +910,315 @@ impl AFPluginDispatcher {
   }
 }
 
+impl AFPluginDispatcher {
+  /// Returns a future that waits for the capacity of the dispatcher, schedules the request and
+  /// then resolves with its response.
+  fn send_request(
+    &self,
+    request: AFPluginRequest,
+    callback: Option<BoxFutureCallback>,
+  ) -> AFBoxFuture<'static, AFPluginEventResponse> {
+    let capacity = self.capacity.clone();
+    let scheduler = self.scheduler.clone();
+    Box::pin(async move {
+      let enqueued_at = Instant::now();
+      let cancel_token = request.cancel_token.clone();
+      // Waits for a free slot when the dispatcher is bounded. The permit is released after the
+      // request is completed. The request is counted as queued and journaled while it waits.
+      let permit = match capacity {
+        None => None,
+        Some(capacity) => {
+          let journal = scheduler.journal_request(&request);
+          let waiters = scheduler.wait_capacity(1);
+          let acquired = tokio::select! {
+            biased;
+            _ = cancel_token.cancelled() => None,
+            permit = capacity.acquire_owned() => Some(permit),
+          };
+          drop(waiters);
+          match acquired {
+            Some(Ok(permit)) => Some(permit),
+            // Stays pending in the journal, it's dispatched again on the next launch.
+            Some(Err(_)) => {
+              let error = capacity_closed_error(&request.event);
+              return reject(error, request.correlation_id, callback).await;
+            },
+            None => {
+              if let Some(journal) = journal {
+                journal.complete(&request.id);
+              }
+              let error = cancelled_error(&request.event);
+              return reject(error, request.correlation_id, callback).await;
+            },
+          }
+        },
+      };
+      let rx = schedule(&scheduler, request, callback, permit, enqueued_at);
+      wait_response(rx, cancel_token, Arc::downgrade(&scheduler)).await
+    })
+  }
+
+  /// Returns a future that schedules the requests in batches and then resolves with all their
+  /// responses.
+  fn send_batch(
+    &self,
+    requests: Vec<AFPluginRequest>,
+  ) -> AFBoxFuture<'static, Vec<AFPluginEventResponse>> {
+    let capacity = self.capacity.clone();
+    let scheduler = self.scheduler.clone();
+    Box::pin(async move {
+      let enqueued_at = Instant::now();
+      let cancel_tokens = requests
+        .iter()
+        .map(|request| request.cancel_token.clone())
+        .collect::<Vec<_>>();
+      // A slot per request, so the responses are in the order of the requests whatever order
+      // they are scheduled in.
+      let mut slots = Vec::with_capacity(requests.len());
+      slots.resize_with(requests.len(), || None);
+      let mut acquired = vec![];
+      // The requests that don't have the capacity yet are counted as queued and journaled. The
+      // ones that are rejected by the shutdown stay pending in the journal.
+      let mut waiters = capacity.as_ref().map(|_| {
+        for request in requests.iter() {
+          scheduler.journal_request(request);
+        }
+        scheduler.wait_capacity(requests.len())
+      });
+      for (index, request) in requests.into_iter().enumerate() {
+        let capacity = match &capacity {
+          None => {
+            acquired.push((index, request, None));
+            continue;
+          },
+          Some(capacity) => capacity.clone(),
+        };
+
+        let permit = match capacity.clone().try_acquire_owned() {
+          Ok(permit) => Ok(permit),
+          Err(TryAcquireError::NoPermits) => {
+            // Schedules the acquired ones, so they can complete and release their permits.
+            let requests = std::mem::take(&mut acquired);
+            schedule_batch_into(&scheduler, requests, &mut slots, enqueued_at);
+            capacity
+              .acquire_owned()
+              .await
+              .map_err(|_| capacity_closed_error(&request.event))
+          },
+          Err(TryAcquireError::Closed) => Err(capacity_closed_error(&request.event)),
+        };
+        if let Some(waiters) = waiters.as_mut() {
+          waiters.leave();
+        }
+        match permit {
+          Ok(permit) => acquired.push((index, request, Some(permit))),
+          Err(error) => {
+            let (tx, rx) = oneshot::channel();
+            let _ = tx.send(reject(error, request.correlation_id, None).await);
+            slots[index] = Some(rx);
+          },
+        }
+      }
+      schedule_batch_into(&scheduler, acquired, &mut slots, enqueued_at);
+
+      let mut responses = Vec::with_capacity(slots.len());
+      let receivers = slots
+        .into_iter()
+        .zip(cancel_tokens)
+        .filter_map(|(rx, cancel_token)| Some((rx?, cancel_token)));
+      for (rx, cancel_token) in receivers {
+        let scheduler = Arc::downgrade(&scheduler);
+        responses.push(wait_response(rx, cancel_token, scheduler).await);
+      }
+      responses
+    })
+  }
+
+  /// Sends the request and drives it on the dispatcher's runtime.
+  pub(crate) async fn send_on_runtime(
+    &self,
+    request: AFPluginRequest,
+    callback: Option<BoxFutureCallback>,
+  ) -> AFPluginEventResponse {
+    let fut = self.send_request(request, callback);
+    self.runtime.run_until(fut).await
+  }
+
+  fn try_acquire_permit(&self) -> Result<Option<OwnedSemaphorePermit>, TryAcquireError> {
+    match &self.capacity {
+      None => Ok(None),
+      Some(capacity) => capacity.clone().try_acquire_owned().map(Some),
+    }
+  }
+}
+
+/// Blocking inside a tokio runtime would stall the worker thread, or deadlock if the request
+/// needs that thread to complete.
+#[cfg(not(target_arch = "wasm32"))]
+fn check_blocking(event: &AFPluginEvent) -> Result<(), DispatchError> {
+  check_blocking_on(&format!("event {:?}", event), "use async_send instead")
+}
+
+/// Same as [check_blocking] for the calls that block on other than an event, e.g. the C ABI.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn check_blocking_on(target: &str, hint: &str) -> Result<(), DispatchError> {
+  if tokio::runtime::Handle::try_current().is_ok() {
+    let msg = format!(
+      "[dispatch]: can not block on {} inside a tokio runtime, {}",
+      target, hint
+    );
+    tracing::error!("{}", msg);
+    return Err(InternalError::BlockingInRuntime(msg).into());
+  }
+  Ok(())
+}
+
+/// Converts the request and generates its correlation id if absent, so the nested dispatches
+/// of a single user action can be traced.
+pub(crate) fn into_request<Req: Into<AFPluginRequest>>(request: Req) -> AFPluginRequest {
+  let mut request: AFPluginRequest = request.into();
+  if request.correlation_id.is_none() {
+    request.correlation_id = Some(nanoid!(10));
+  }
+  // The earlier of the deadline and the timeout wins.
+  if let Some(remaining) = request.context.remaining() {
+    request.timeout = Some(match request.timeout {
+      Some(timeout) => timeout.min(remaining),
+      None => remaining,
+    });
+  }
+  request
+}
+
+/// Hands the request over to the scheduler. The `permit` is held until the request is completed.
+fn schedule(
+  scheduler: &Arc<DispatchScheduler>,
+  request: AFPluginRequest,
+  callback: Option<BoxFutureCallback>,
+  permit: Option<OwnedSemaphorePermit>,
+  enqueued_at: Instant,
+) -> oneshot::Receiver<AFPluginEventResponse> {
+  let (task, rx) = new_task(request, callback, permit, enqueued_at);
+  scheduler.schedule(task);
+  rx
+}
+
+/// Hands the requests over to the scheduler at once.
+fn schedule_batch(
+  scheduler: &Arc<DispatchScheduler>,
+  requests: Vec<(AFPluginRequest, Option<OwnedSemaphorePermit>)>,
+  enqueued_at: Instant,
+) -> Vec<oneshot::Receiver<AFPluginEventResponse>> {
+  if requests.is_empty() {
+    return vec![];
+  }
+  let (tasks, receivers) = requests
+    .into_iter()
+    .map(|(request, permit)| new_task(request, None, permit, enqueued_at))
+    .unzip();
+  scheduler.schedule_batch(tasks);
+  receivers
+}
+
+/// Same as [schedule_batch] for the requests of a batch that are tagged with their indexes. The
+/// receivers are put in the `slots` of the indexes.
+fn schedule_batch_into(
+  scheduler: &Arc<DispatchScheduler>,
+  requests: Vec<(usize, AFPluginRequest, Option<OwnedSemaphorePermit>)>,
+  slots: &mut [Option<oneshot::Receiver<AFPluginEventResponse>>],
+  enqueued_at: Instant,
+) {
+  let (indexes, requests): (Vec<_>, Vec<_>) = requests
+    .into_iter()
+    .map(|(index, request, permit)| (index, (request, permit)))
+    .unzip();
+  for (index, rx) in indexes
+    .into_iter()
+    .zip(schedule_batch(scheduler, requests, enqueued_at))
+  {
+    slots[index] = Some(rx);
+  }
+}
+
+fn new_task(
+  request: AFPluginRequest,
+  callback: Option<BoxFutureCallback>,
+  permit: Option<OwnedSemaphorePermit>,
+  enqueued_at: Instant,
+) -> (DispatchTask, oneshot::Receiver<AFPluginEventResponse>) {
+  let (ret, rx) = oneshot::channel();
+  let (ret, callback) = match request.mode {
+    DispatchMode::RequestReply => (Some(ret), callback),
+    DispatchMode::FireAndForget => {
+      let _ = ret.send(ResponseBuilder::Ok().build());
+      (None, None)
+    },
+  };
+  let ctx = DispatchContext { request, callback };
+  (DispatchTask::new(ctx, permit, ret, enqueued_at), rx)
+}
+
+/// Waits for the response of the request. The request is cancelled if the caller drops the
+/// future before the response is received, e.g. the widget that sent it is disposed.
+async fn wait_response(
+  rx: oneshot::Receiver<AFPluginEventResponse>,
+  cancel_token: CancellationToken,
+  scheduler: Weak<DispatchScheduler>,
+) -> AFPluginEventResponse {
+  let mut guard = CancelOnDrop(Some(DispatchCancelHandle {
+    cancel_token: cancel_token.clone(),
+    scheduler,
+  }));
+  let response = tokio::select! {
+    biased;
+    result = rx => recv_response(result),
+    _ = cancel_token.cancelled() => {
+      InternalError::Cancelled("[dispatch]: the request is cancelled".to_string()).as_response()
+    },
+  };
+  guard.0 = None;
+  response
+}
+
+/// Resolves the request that never reaches the scheduler with the given error.
+async fn reject(
+  error: InternalError,
+  correlation_id: Option<String>,
+  callback: Option<BoxFutureCallback>,
+) -> AFPluginEventResponse {
+  let mut response = error.as_response();
+  response.correlation_id = correlation_id;
+  if let Some(callback) = callback {
+    callback(response.clone()).await;
+  }
+  response
+}
+
+/// The capacity is closed by [AFPluginDispatcher::shutdown].
+fn capacity_closed_error(event: &AFPluginEvent) -> InternalError {
+  let msg = format!(
+    "[dispatch]: reject event {:?}, the dispatcher is shutting down",
+    event
+  );
+  tracing::warn!("{}", msg);
+  InternalError::Shutdown(msg)
+}
+
+fn cancelled_error(event: &AFPluginEvent) -> InternalError {
+  let msg = format!("[dispatch]: event {:?} is cancelled", event);
+  tracing::debug!("{}", msg);
+  InternalError::Cancelled(msg)
+}
+
+fn recv_response(result: Result<AFPluginEventResponse, RecvError>) -> AFPluginEventResponse {
+  result.unwrap_or_else(|e| {
+    let msg = format!("EVENT_DISPATCH join error: {:?}", e);
+    tracing::error!("{}", msg);
+    let error = InternalError::JoinError(msg);
+    error.as_response()
+  })
+}
+
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct DispatchContext {
@@ -275,7 +1235,29 @@ impl DispatchContext {
 }
 
 pub(crate) struct DispatchService {
-  pub(crate) plugins: AFPluginMap,
+  pub(crate) routes: DispatchRoutes,
+  pub(crate) retry_policy: Option<DispatchRetryPolicy>,
+  pub(crate) default_timeout: Option<Duration>,
+  pub(crate) panic_policy: DispatchPanicPolicy,
+  pub(crate) watchdog: Option<Arc<DispatchWatchdog>>,
+  pub(crate) slow_poll_budget: Option<Duration>,
+  pub(crate) dead_letter: Option<Arc<dyn DeadLetterSink>>,
+  pub(crate) error_observer: Option<Arc<dyn DispatchErrorObserver>>,
+  pub(crate) localizer: Option<Arc<dyn DispatchLocalizer>>,
+  pub(crate) history: Option<Arc<DispatchHistory>>,
+  pub(crate) stats: Arc<DispatchStats>,
+  pub(crate) logger: Option<Arc<DispatchLogger>>,
+  pub(crate) audit: Option<Arc<dyn AuditSink>>,
+  pub(crate) states: AFStateMap,
+  pub(crate) interceptors: Arc<Vec<Box<dyn DispatchInterceptor>>>,
+  pub(crate) compression_threshold: usize,
+  pub(crate) cache: DispatchCache,
+  pub(crate) idempotency: Arc<DispatchIdempotency>,
+  pub(crate) coalescer: Arc<DispatchCoalescer>,
+  #[cfg(all(feature = "forward", not(target_arch = "wasm32")))]
+  pub(crate) forwarders: Arc<Vec<Arc<DispatchForwarder>>>,
+  #[cfg(feature = "test_helper")]
+  pub(crate) mocks: Arc<DispatchMocks>,
 }
 
 impl Service<DispatchContext> for DispatchService {
@@ -283,55 +1265,489 @@ impl Service<DispatchContext> for DispatchService {
   type Error = DispatchError;
   type Future = AFBoxFuture<'static, Result<Self::Response, Self::Error>>;
 
-  #[tracing::instrument(name = "DispatchService", level = "debug", skip(self, ctx))]
   fn call(&self, ctx: DispatchContext) -> Self::Future {
-    let module_map = self.plugins.clone();
-    let (request, callback) = ctx.into_parts();
+    let routes = self.routes.clone();
+    let retry_policy = self.retry_policy.clone();
+    let coalescer = self.coalescer.clone();
+    let dead_letter = self.dead_letter.clone();
+    let error_observer = self.error_observer.clone();
+    let localizer = self.localizer.clone();
+    let history = self.history.clone();
+    let stats = self.stats.clone();
+    let audit = self.audit.clone();
+    let compression_threshold = self.compression_threshold;
+    let cache = self.cache.clone();
+    let idempotency = self.idempotency.clone();
+    let panic_policy = self.panic_policy;
+    let watchdog = self.watchdog.clone();
+    let slow_poll_budget = self.slow_poll_budget;
+    let (mut request, callback) = ctx.into_parts();
+    request.shared_states = self.states.clone();
+    if request.timeout.is_none() {
+      request.timeout = self.default_timeout;
+    }
+    let intercepted = self
+      .interceptors
+      .iter()
+      .find_map(|interceptor| interceptor.intercept(&mut request));
+    if let Some(event) = routes.resolve(&request.event) {
+      tracing::debug!("[dispatch]: route {:?} to {:?}", request.event, event);
+      request.event = event.clone();
+    }
+    let correlation_id = request.correlation_id.clone();
+    let scoped_event = request.event.clone();
+    #[cfg(all(feature = "forward", not(target_arch = "wasm32")))]
+    let forwarder = find_forwarder(&self.forwarders, &routes, &request.event);
+    #[cfg(feature = "test_helper")]
+    let mock = self.mocks.get(&request.event);
+    let accept_compression = request.accept_compression;
+    let plugin = routes
+      .lookup(&request.event)
+      .map(|plugin| plugin.name.clone())
+      .unwrap_or_default();
+    let log_level = self
+      .logger
+      .as_ref()
+      .and_then(|logger| logger.level_of(&plugin, &request.event));
+    // The logs of the handler are nested in the span, including the handlers that run on the
+    // other runtimes.
+    #[cfg(feature = "trace_requests")]
+    let span = tracing::info_span!(
+      "dispatch",
+      event = request.event.as_str(),
+      request_id = request.id.as_str(),
+      plugin = plugin.as_str(),
+      correlation_id = correlation_id.as_deref().unwrap_or_default()
+    );
 
-    Box::pin(async move {
-      let result = {
-        match module_map.get(&request.event) {
-          Some(module) => {
-            let event = format!("{:?}", request.event);
-            event!(
-              tracing::Level::TRACE,
-              "[dispatch]: {:?} exec event:{}",
-              &module.name,
-              &event,
-            );
-            let fut = module.new_service(());
-            let service_fut = fut.await?.call(request);
-            let result = service_fut.await;
-            event!(
-              tracing::Level::TRACE,
-              "[dispatch]: {:?} exec event:{} with result: {}",
-              &module.name,
-              &event,
-              result.is_ok()
-            );
-            result
+    let fut = async move {
+      let cancel_token = request.cancel_token.clone();
+      let event = request.event.clone();
+      let started_at = Instant::now();
+      let request_id = request.id.clone();
+      if let Some(level) = log_level {
+        log_start(level, &plugin, &event, &request_id);
+      }
+      let recorded_request = history.as_ref().map(|_| request.clone());
+      let watched = StuckHandler {
+        event: event.clone(),
+        request_id: request.id.clone(),
+        correlation_id: correlation_id.clone(),
+        elapsed: Duration::ZERO,
+      };
+      let context = error_observer.as_ref().map(|_| request.context.clone());
+      let locale = localizer.as_ref().and(request.context.locale.clone());
+      let cache_entry = routes
+        .lookup(&event)
+        .and_then(|plugin| plugin.cache_ttl(&event))
+        .map(|ttl| (CacheKey::new(&request), ttl));
+      let cached = cache_entry.as_ref().and_then(|(key, _)| cache.get(key));
+      // Only the responses of the handlers are cached.
+      let cache_entry = cache_entry.filter(|_| intercepted.is_none() && cached.is_none());
+      let idempotency_key = request.idempotency_key.clone();
+      let replayed = idempotency_key
+        .as_ref()
+        .and_then(|key| idempotency.get(&event, key));
+      let idempotency_key = idempotency_key.filter(|_| replayed.is_none());
+      let is_replayed = replayed.is_some();
+      // Only the requests that run the handlers are counted in the stats.
+      let is_executed = replayed.is_none()
+        && intercepted.is_none()
+        && cached.is_none()
+        && routes.lookup(&event).is_some();
+      let audited_request = audit
+        .as_ref()
+        .filter(|_| is_executed)
+        .filter(|_| {
+          routes
+            .lookup(&event)
+            .map_or(false, |p| p.is_mutating(&event))
+        })
+        .map(|_| request.clone());
+      let result = match replayed.or(intercepted).or(cached) {
+        Some(response) => Ok(response),
+        None => match coalescer.join(&routes.plugins, &request) {
+          #[cfg(feature = "test_helper")]
+          None if mock.is_some() => Ok(mock.expect("checked by the guard").handle(&request)),
+          None if is_health_event(&routes, &event) => {
+            Ok(health_response(&check_health(&routes).await))
+          },
+          None if is_stats_event(&routes, &event) => Ok(stats_response(&stats.snapshot())),
+          None if is_subscription_event(&routes, &event) => Ok(subscription_response(&request)),
+          None if is_release_buffer_event(&routes, &event) => Ok(release_buffer_response(&request)),
+          #[cfg(all(feature = "forward", not(target_arch = "wasm32")))]
+          None if forwarder.is_some() => {
+            let forwarder = forwarder.expect("checked by the guard");
+            Ok(forwarder.forward(&request).await)
+          },
+          None if routes.lookup(&event).is_none() => {
+            let error = handle_not_found(&request);
+            if let Some(sink) = dead_letter {
+              sink.receive(DeadLetter {
+                request,
+                error: error.clone(),
+              });
+            }
+            Err(error)
           },
           None => {
-            let msg = format!("[dispatch]: can not find the event handler. {:?}", request);
-            event!(tracing::Level::ERROR, "{}", msg);
-            Err(InternalError::HandleNotFound(msg).into())
+            let fut = exec_request_or_cancel(routes, request, retry_policy);
+            let fut = SlowPoll::new(fut, slow_poll_budget, event.clone());
+            watch(watchdog, watched, fut).await
           },
-        }
+          Some(Coalesced::Leader(guard)) => {
+            // The handler runs with the token of the shared execution, so cancelling the leader
+            // doesn't abort the followers. The leader keeps running for them.
+            let mut request = request;
+            request.cancel_token = guard.cancel_token();
+            let fut = exec_request_or_cancel(routes, request, retry_policy);
+            let fut = SlowPoll::new(fut, slow_poll_budget, event.clone());
+            let fut = watch(watchdog, watched, fut);
+            tokio::pin!(fut);
+            let result = tokio::select! {
+              biased;
+              result = &mut fut => result,
+              _ = cancel_token.cancelled() => {
+                guard.waiters().leave();
+                fut.await
+              },
+            };
+            guard.complete(&result);
+            if cancel_token.is_cancelled() {
+              Err(cancelled_error(&event).into())
+            } else {
+              result
+            }
+          },
+          Some(Coalesced::Follower(follower)) => {
+            tokio::select! {
+              biased;
+              _ = cancel_token.cancelled() => {
+                follower.waiters.leave();
+                Err(cancelled_error(&event).into())
+              },
+              result = follower.rx => result.unwrap_or_else(|_| {
+                let msg = format!("[dispatch]: the coalesced request of {:?} is aborted", event);
+                Err(InternalError::Other(msg).into())
+              }),
+            }
+          },
+        },
       };
 
-      let response = result.unwrap_or_else(|e| e.into());
+      if let Err(err) = &result {
+        if panic_policy == DispatchPanicPolicy::Abort
+          && err.code() == DispatchErrorCode::HandlerPanic
+        {
+          tracing::error!("[dispatch]: abort the process, {}", err);
+          std::process::abort();
+        }
+      }
+      let (mut response, error): (AFPluginEventResponse, _) = match result {
+        Ok(response) => (response, None),
+        Err(e) => (e.clone().into(), Some(e)),
+      };
+      if let (Some(observer), Some(context)) = (error_observer, context) {
+        if !response.status_code.is_ok() && !is_replayed {
+          observer.on_error(&DispatchErrorReport {
+            event: &event,
+            correlation_id: correlation_id.as_deref(),
+            context: &context,
+            response: &response,
+            error: error.as_ref(),
+            elapsed: started_at.elapsed(),
+          });
+        }
+      }
+      if is_executed {
+        stats.record(&event, &response, error.as_ref(), started_at.elapsed());
+      }
+      if let (Some(audit), Some(request)) = (audit, audited_request) {
+        audit.append(&AuditRecord::new(
+          &request,
+          &plugin,
+          &response,
+          started_at.elapsed(),
+        ));
+      }
+      if let Some(key) = idempotency_key {
+        idempotency.complete(&event, key, &response);
+      }
+      if let Some((key, ttl)) = cache_entry {
+        if response.status_code.is_ok() {
+          cache.put(key, ttl, &response);
+        }
+      }
+      if let Some(localizer) = localizer {
+        localize_response(localizer.as_ref(), &mut response, locale.as_deref());
+      }
+      response.correlation_id = correlation_id;
+      if let Some(compression) = accept_compression {
+        compress_response(&mut response, compression, compression_threshold);
+      }
       event!(tracing::Level::TRACE, "Dispatch result: {:?}", response);
+      if let (Some(history), Some(request)) = (history, recorded_request) {
+        history.record(&request, &response, started_at.elapsed());
+      }
+      if let Some(level) = log_level {
+        log_finish(
+          level,
+          &plugin,
+          &event,
+          &request_id,
+          &response,
+          started_at.elapsed(),
+        );
+      }
       if let Some(callback) = callback {
         callback(response.clone()).await;
       }
 
       Ok(response)
-    })
+    };
+    // The requests that the handler dispatches are recorded as the nested events of this one.
+    let fut = graph::scope(scoped_event, fut);
+    // Every log line of the request, including the handler's, carries the correlation id.
+    #[cfg(feature = "trace_requests")]
+    let fut = fut.instrument(span);
+    Box::pin(fut)
+  }
+}
+
+/// Dropping the handler's future aborts it if the request gets cancelled, unless the event must
+/// complete.
+async fn exec_request_or_cancel(
+  routes: DispatchRoutes,
+  request: AFPluginRequest,
+  retry_policy: Option<DispatchRetryPolicy>,
+) -> Result<AFPluginEventResponse, DispatchError> {
+  let cancel_token = request.cancel_token.clone();
+  let event = request.event.clone();
+  let must_complete = routes
+    .lookup(&event)
+    .map(|plugin| plugin.is_must_complete(&event))
+    .unwrap_or(false);
+  if must_complete {
+    return exec_request_with_retry(routes, request, retry_policy).await;
+  }
+
+  tokio::select! {
+    biased;
+    _ = cancel_token.cancelled() => Err(cancelled_error(&event).into()),
+    result = exec_request_with_retry(routes, request, retry_policy) => result,
+  }
+}
+
+async fn exec_request_with_retry(
+  routes: DispatchRoutes,
+  request: AFPluginRequest,
+  retry_policy: Option<DispatchRetryPolicy>,
+) -> Result<AFPluginEventResponse, DispatchError> {
+  let retry_policy = match retry_policy {
+    None => return exec_request(routes, request).await,
+    Some(retry_policy) => retry_policy,
+  };
+
+  let mut attempt = 1;
+  loop {
+    let result = exec_request(routes.clone(), request.clone()).await;
+    let retryable = match &result {
+      Ok(response) => response.is_retryable(),
+      Err(err) => err.is_retryable(),
+    };
+    if !retryable || attempt >= retry_policy.max_attempts {
+      return result;
+    }
+
+    let backoff = retry_policy.backoff(attempt);
+    tracing::warn!(
+      "[dispatch]: retry event {:?} in {:?}, attempt {}/{}",
+      &request.event,
+      backoff,
+      attempt + 1,
+      retry_policy.max_attempts
+    );
+    tokio::time::sleep(backoff).await;
+    attempt += 1;
+  }
+}
+
+async fn exec_request(
+  routes: DispatchRoutes,
+  request: AFPluginRequest,
+) -> Result<AFPluginEventResponse, DispatchError> {
+  match routes.lookup(&request.event) {
+    Some(module) => exec_plugin(module.clone(), request).await,
+    None => Err(handle_not_found(&request)),
+  }
+}
+
+fn exec_plugin(
+  module: Arc<AFPlugin>,
+  request: AFPluginRequest,
+) -> AFBoxFuture<'static, Result<AFPluginEventResponse, DispatchError>> {
+  Box::pin(async move {
+    // The routed plugin is already entered by the scheduler, the chained ones are entered here.
+    let _inflight = module.enter();
+    // The handlers of the plugins that are registered earlier run first. See [DuplicatePolicy].
+    match module.chained(&request.event) {
+      None => {},
+      Some((DuplicatePolicy::FirstSuccess, previous)) => {
+        if let Ok(response) = exec_plugin(previous, request.clone()).await {
+          if response.status_code == StatusCode::Ok {
+            return Ok(response);
+          }
+        }
+      },
+      Some((DuplicatePolicy::Observe, previous)) => {
+        let result = exec_plugin(previous, request.clone()).await;
+        let mut request = request;
+        request.progress = None;
+        let inflight = module.enter();
+        af_spawn(async move {
+          let _inflight = inflight;
+          if let Err(err) = exec_handler(module.clone(), request).await {
+            tracing::warn!("[dispatch]: observer {:?} failed: {:?}", module.name, err);
+          }
+        });
+        return result;
+      },
+      Some((_, previous)) => {
+        let response = exec_plugin(previous, request.clone()).await?;
+        if response.status_code != StatusCode::Ok {
+          return Ok(response);
+        }
+      },
+    }
+    exec_handler(module, request).await
+  })
+}
+
+/// Runs the plugin's own handler of the request.
+fn exec_handler(
+  module: Arc<AFPlugin>,
+  request: AFPluginRequest,
+) -> AFBoxFuture<'static, Result<AFPluginEventResponse, DispatchError>> {
+  Box::pin(async move {
+    if let Some(plugin) = module.lazy_plugin(&request.event).await? {
+      return exec_handler(plugin, request).await;
+    }
+
+    let event = format!("{:?}", request.event);
+    event!(
+      tracing::Level::TRACE,
+      "[dispatch]: {:?} exec event:{}",
+      &module.name,
+      &event,
+    );
+    let timeout = request.timeout;
+    let _permit = module.acquire_concurrency().await;
+    let fut = module.new_service(());
+    let service_fut = fut.await?.call(request);
+    // A panicking handler is resolved with the error response, the dispatcher keeps running.
+    let (plugin_name, event_name) = (module.name.clone(), event.clone());
+    let service_fut = async move {
+      match AssertUnwindSafe(service_fut).catch_unwind().await {
+        Ok(result) => result,
+        Err(panic) => Err(handler_panicked(&plugin_name, &event_name, panic).into()),
+      }
+    };
+    #[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
+    let service_fut = module.plugin_executor().run(service_fut);
+    // The runtime of wasm32 has no timers, `tokio::time::timeout` would panic there.
+    #[cfg(target_arch = "wasm32")]
+    let result = {
+      if let Some(duration) = timeout {
+        tracing::warn!(
+          "[dispatch]: ignore the timeout {:?} of event:{} on wasm32",
+          duration,
+          &event
+        );
+      }
+      service_fut.await
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    let result = match timeout {
+      None => service_fut.await,
+      Some(duration) => match tokio::time::timeout(duration, service_fut).await {
+        Ok(result) => result,
+        Err(_) => {
+          let msg = format!(
+            "[dispatch]: {:?} exec event:{} timeout after {:?}",
+            &module.name, &event, duration
+          );
+          event!(tracing::Level::WARN, "{}", msg);
+          Err(InternalError::Timeout(msg).into())
+        },
+      },
+    };
+    event!(
+      tracing::Level::TRACE,
+      "[dispatch]: {:?} exec event:{} with result: {}",
+      &module.name,
+      &event,
+      result.is_ok()
+    );
+    result
+  })
+}
+
+pub(crate) fn panic_reason(panic: &(dyn Any + Send)) -> String {
+  panic
+    .downcast_ref::<&str>()
+    .map(|s| s.to_string())
+    .or_else(|| panic.downcast_ref::<String>().cloned())
+    .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn handler_panicked(plugin: &str, event: &str, panic: Box<dyn Any + Send>) -> InternalError {
+  let reason = panic_reason(panic.as_ref());
+  let msg = format!(
+    "[dispatch]: {:?} exec event:{} panicked: {}",
+    plugin, event, reason
+  );
+  tracing::error!("{}", msg);
+  InternalError::Panic(msg)
+}
+
+fn handle_not_found(request: &AFPluginRequest) -> DispatchError {
+  let msg = format!("[dispatch]: can not find the event handler. {:?}", request);
+  event!(tracing::Level::ERROR, "{}", msg);
+  InternalError::HandleNotFound(msg).into()
+}
+
+/// Cancels the request that was sent by [AFPluginDispatcher::cancellable_async_send].
+#[derive(Clone)]
+pub struct DispatchCancelHandle {
+  cancel_token: CancellationToken,
+  scheduler: Weak<DispatchScheduler>,
+}
+
+struct CancelOnDrop(Option<DispatchCancelHandle>);
+
+impl Drop for CancelOnDrop {
+  fn drop(&mut self) {
+    if let Some(handle) = self.0.take() {
+      handle.cancel();
+    }
+  }
+}
+
+impl DispatchCancelHandle {
+  pub fn cancel(&self) {
+    self.cancel_token.cancel();
+    if let Some(scheduler) = self.scheduler.upgrade() {
+      scheduler.remove_cancelled();
+    }
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.cancel_token.is_cancelled()
   }
 }
 
 #[allow(dead_code)]
-fn plugin_info(plugins: &[AFPlugin]) -> String {
+pub(crate) fn plugin_info(plugins: &[AFPlugin]) -> String {
   let mut info = format!("{} plugins loaded\n", plugins.len());
   for module in plugins {
     info.push_str(&format!("-> {} loaded \n", module.name));
@@ -1,4 +1,9 @@
+#[cfg(feature = "backtrace")]
+use std::backtrace::Backtrace;
 use std::fmt;
+#[cfg(feature = "backtrace")]
+use std::sync::Arc;
+use std::time::Duration;
 
 use bytes::Bytes;
 use dyn_clone::DynClone;
@@ -7,12 +12,47 @@ use tokio::{sync::mpsc::error::SendError, task::JoinError};
 use crate::prelude::AFConcurrent;
 use crate::{
   byte_trait::AFPluginFromBytes,
-  request::AFPluginEventRequest,
-  response::{AFPluginEventResponse, ResponseBuilder},
+  localize::AFPluginErrorMessage,
+  module::{AFPluginEvent, AFPluginRequest},
+  request::{AFPluginEventRequest, Payload},
+  response::{AFPluginEventResponse, ResponseBuilder, StatusCode},
 };
 
 pub trait Error: fmt::Debug + DynClone + AFConcurrent {
   fn as_response(&self) -> AFPluginEventResponse;
+
+  /// Returns true if the error is transient, e.g. the database is locked. The dispatcher retries
+  /// the request if the `DispatchRetryPolicy` is set.
+  fn is_retryable(&self) -> bool {
+    false
+  }
+
+  /// Tells the callers whether to retry, queue or surface the failure. Defaults to
+  /// [DispatchErrorKind::Transient] if the error is retryable, otherwise
+  /// [DispatchErrorKind::Fatal].
+  fn kind(&self) -> DispatchErrorKind {
+    if self.is_retryable() {
+      DispatchErrorKind::Transient
+    } else {
+      DispatchErrorKind::Fatal
+    }
+  }
+
+  /// The errors of the handlers are [DispatchErrorCode::Handler], the errors of the dispatcher
+  /// have their own codes.
+  fn code(&self) -> DispatchErrorCode {
+    DispatchErrorCode::Handler
+  }
+
+  /// The error that causes this one, see [DispatchError::context].
+  fn source_error(&self) -> Option<&DispatchError> {
+    None
+  }
+
+  /// The message that can be translated for the user, see [AFPluginErrorMessage].
+  fn message(&self) -> Option<AFPluginErrorMessage> {
+    None
+  }
 }
 
 dyn_clone::clone_trait_object!(Error);
@@ -21,6 +61,8 @@ impl<T: Error + 'static> From<T> for DispatchError {
   fn from(err: T) -> DispatchError {
     DispatchError {
       inner: Box::new(err),
+      #[cfg(feature = "backtrace")]
+      backtrace: Some(Arc::new(Backtrace::force_capture())),
     }
   }
 }
@@ -28,12 +70,424 @@ impl<T: Error + 'static> From<T> for DispatchError {
 #[derive(Clone)]
 pub struct DispatchError {
   inner: Box<dyn Error>,
+  /// Captured when the error is created, the wrappers of the error share the backtrace of their
+  /// sources.
+  #[cfg(feature = "backtrace")]
+  backtrace: Option<Arc<Backtrace>>,
 }
 
 impl DispatchError {
   pub fn inner_error(&self) -> &dyn Error {
     self.inner.as_ref()
   }
+
+  /// Wraps the error without capturing another backtrace.
+  fn wrap<E: Error + 'static>(err: E) -> DispatchError {
+    DispatchError {
+      inner: Box::new(err),
+      #[cfg(feature = "backtrace")]
+      backtrace: None,
+    }
+  }
+
+  /// Returns the backtrace of the root cause. It's captured if the `backtrace` feature is
+  /// enabled.
+  #[cfg(feature = "backtrace")]
+  pub fn backtrace(&self) -> Option<&Backtrace> {
+    self.chain().find_map(|err| err.backtrace.as_deref())
+  }
+
+  pub fn is_retryable(&self) -> bool {
+    self.kind().is_retryable()
+  }
+
+  pub fn kind(&self) -> DispatchErrorKind {
+    self.inner.kind()
+  }
+
+  /// Marks the error with the `kind`, e.g. the handler marks the error of the request as
+  /// [DispatchErrorKind::Transient] to let the dispatcher retry it.
+  pub fn with_kind(self, kind: DispatchErrorKind) -> DispatchError {
+    Self::wrap(AFPluginErrorWithKind { kind, source: self })
+  }
+
+  pub fn transient(self) -> DispatchError {
+    self.with_kind(DispatchErrorKind::Transient)
+  }
+
+  pub fn unavailable(self) -> DispatchError {
+    self.with_kind(DispatchErrorKind::Unavailable)
+  }
+
+  pub fn code(&self) -> DispatchErrorCode {
+    self.inner.code()
+  }
+
+  pub fn message(&self) -> Option<AFPluginErrorMessage> {
+    self.inner.message()
+  }
+
+  /// Attaches the message that can be translated for the user, e.g. the handler names the
+  /// message of its error:
+  ///
+  /// ```ignore
+  /// err.localized(AFPluginErrorMessage::new("document.not_found").arg("id", &id))
+  /// ```
+  pub fn localized(self, message: AFPluginErrorMessage) -> DispatchError {
+    Self::wrap(AFPluginLocalizedError {
+      message,
+      source: self,
+    })
+  }
+
+  /// Wraps the error with the context, keeping the error as the source. The errors of the
+  /// dispatcher are responded with the whole chain, e.g. "create_doc -> parse payload -> invalid
+  /// utf8". The errors of the handlers keep their payloads, so the frontend can still parse them.
+  pub fn context<C: fmt::Display>(self, context: C) -> DispatchError {
+    Self::wrap(AFPluginErrorContext {
+      context: context.to_string(),
+      source: self,
+    })
+  }
+
+  /// Returns the error and its sources, from the outermost to the root cause.
+  pub fn chain(&self) -> impl Iterator<Item = &DispatchError> {
+    std::iter::successors(Some(self), |err| err.inner.source_error())
+  }
+
+  pub fn root_cause(&self) -> &DispatchError {
+    self.chain().last().unwrap_or(self)
+  }
+}
+
+/// The metadata key of the response that carries the backtrace of the error. It's only set in the
+/// debug build if the `backtrace` feature is enabled.
+pub const BACKTRACE: &str = "backtrace";
+
+/// The metadata key of the response that names the [DispatchErrorKind] of the error. It's only
+/// set if the error is not fatal.
+pub const ERROR_KIND: &str = "error-kind";
+
+/// The classification of the errors, which decides how the failure is handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DispatchErrorKind {
+  /// The error is likely gone soon, e.g. the database is locked. The request is retried by the
+  /// `DispatchRetryPolicy`.
+  Transient,
+  /// The service that the request depends on is unavailable, e.g. the device is offline. The
+  /// request should be queued and sent again after the service is back.
+  Unavailable,
+  /// Sending the request again gives the same error, so the failure is surfaced to the user.
+  Fatal,
+}
+
+impl DispatchErrorKind {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      DispatchErrorKind::Transient => "transient",
+      DispatchErrorKind::Unavailable => "unavailable",
+      DispatchErrorKind::Fatal => "fatal",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "transient" => Some(DispatchErrorKind::Transient),
+      "unavailable" => Some(DispatchErrorKind::Unavailable),
+      "fatal" => Some(DispatchErrorKind::Fatal),
+      _ => None,
+    }
+  }
+
+  pub fn is_retryable(&self) -> bool {
+    *self == DispatchErrorKind::Transient
+  }
+}
+
+#[derive(Clone)]
+struct AFPluginErrorWithKind {
+  kind: DispatchErrorKind,
+  source: DispatchError,
+}
+
+impl fmt::Debug for AFPluginErrorWithKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} ({})", self.source, self.kind.as_str())
+  }
+}
+
+impl Error for AFPluginErrorWithKind {
+  fn as_response(&self) -> AFPluginEventResponse {
+    self.source.inner_error().as_response()
+  }
+
+  fn is_retryable(&self) -> bool {
+    self.kind.is_retryable()
+  }
+
+  fn kind(&self) -> DispatchErrorKind {
+    self.kind
+  }
+
+  fn code(&self) -> DispatchErrorCode {
+    self.source.code()
+  }
+
+  fn source_error(&self) -> Option<&DispatchError> {
+    self.source.inner_error().source_error()
+  }
+
+  fn message(&self) -> Option<AFPluginErrorMessage> {
+    self.source.message()
+  }
+}
+
+#[derive(Clone)]
+struct AFPluginLocalizedError {
+  message: AFPluginErrorMessage,
+  source: DispatchError,
+}
+
+impl fmt::Debug for AFPluginLocalizedError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.source)
+  }
+}
+
+impl Error for AFPluginLocalizedError {
+  fn as_response(&self) -> AFPluginEventResponse {
+    self.source.inner_error().as_response()
+  }
+
+  fn is_retryable(&self) -> bool {
+    self.source.is_retryable()
+  }
+
+  fn kind(&self) -> DispatchErrorKind {
+    self.source.kind()
+  }
+
+  fn code(&self) -> DispatchErrorCode {
+    self.source.code()
+  }
+
+  fn source_error(&self) -> Option<&DispatchError> {
+    self.source.inner_error().source_error()
+  }
+
+  fn message(&self) -> Option<AFPluginErrorMessage> {
+    Some(self.message.clone())
+  }
+}
+
+/// Adds the context to the error of the result. See [DispatchError::context].
+pub trait DispatchResultExt<T> {
+  fn context<C: fmt::Display>(self, context: C) -> Result<T, DispatchError>;
+
+  fn with_context<C: fmt::Display, F: FnOnce() -> C>(self, f: F) -> Result<T, DispatchError>;
+}
+
+impl<T, E> DispatchResultExt<T> for Result<T, E>
+where
+  E: Into<DispatchError>,
+{
+  fn context<C: fmt::Display>(self, context: C) -> Result<T, DispatchError> {
+    self.map_err(|e| e.into().context(context))
+  }
+
+  fn with_context<C: fmt::Display, F: FnOnce() -> C>(self, f: F) -> Result<T, DispatchError> {
+    self.map_err(|e| e.into().context(f()))
+  }
+}
+
+#[derive(Clone)]
+struct AFPluginErrorContext {
+  context: String,
+  source: DispatchError,
+}
+
+impl fmt::Debug for AFPluginErrorContext {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} -> {}", self.context, self.source)
+  }
+}
+
+impl Error for AFPluginErrorContext {
+  fn as_response(&self) -> AFPluginEventResponse {
+    context_response(&self.context, &self.source)
+  }
+
+  fn is_retryable(&self) -> bool {
+    self.source.is_retryable()
+  }
+
+  fn kind(&self) -> DispatchErrorKind {
+    self.source.kind()
+  }
+
+  fn code(&self) -> DispatchErrorCode {
+    self.source.code()
+  }
+
+  fn source_error(&self) -> Option<&DispatchError> {
+    Some(&self.source)
+  }
+
+  fn message(&self) -> Option<AFPluginErrorMessage> {
+    self.source.message()
+  }
+}
+
+/// Prefixes the message of the dispatcher's error with the context.
+fn context_response(context: &str, source: &DispatchError) -> AFPluginEventResponse {
+  let mut response = source.inner_error().as_response();
+  if response.status_code != StatusCode::Err {
+    if let Payload::Bytes(bytes) = &response.payload {
+      let msg = format!("{} -> {}", context, String::from_utf8_lossy(bytes));
+      response.payload = Payload::Bytes(Bytes::from(msg));
+    }
+  }
+  response
+}
+
+/// The metadata key of the response that names the [DispatchErrorCode] of the error.
+pub const ERROR_CODE: &str = "error-code";
+
+/// Lets the callers and the tests match on the kind of the error instead of its message. The
+/// responses of the dispatcher's errors carry the code in the [ERROR_CODE] metadata, see
+/// [AFPluginEventResponse::error_code].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DispatchErrorCode {
+  /// The error is returned by the handler.
+  Handler,
+  /// No handler handles the event.
+  Unhandled,
+  DeserializeFailed,
+  UnexpectedNone,
+  QueueFull,
+  /// The request is shed because the dispatcher is overloaded. See [DispatchLoadShedding].
+  ///
+  /// [DispatchLoadShedding]: crate::prelude::DispatchLoadShedding
+  Busy,
+  Timeout,
+  Cancelled,
+  HandlerPanic,
+  Shutdown,
+  BlockingInRuntime,
+  DuplicateEvent,
+  PluginDependency,
+  GuardRejected,
+  Internal,
+  Other,
+}
+
+impl DispatchErrorCode {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      DispatchErrorCode::Handler => "handler",
+      DispatchErrorCode::Unhandled => "unhandled",
+      DispatchErrorCode::DeserializeFailed => "deserialize_failed",
+      DispatchErrorCode::UnexpectedNone => "unexpected_none",
+      DispatchErrorCode::QueueFull => "queue_full",
+      DispatchErrorCode::Busy => "busy",
+      DispatchErrorCode::Timeout => "timeout",
+      DispatchErrorCode::Cancelled => "cancelled",
+      DispatchErrorCode::HandlerPanic => "handler_panic",
+      DispatchErrorCode::Shutdown => "shutdown",
+      DispatchErrorCode::BlockingInRuntime => "blocking_in_runtime",
+      DispatchErrorCode::DuplicateEvent => "duplicate_event",
+      DispatchErrorCode::PluginDependency => "plugin_dependency",
+      DispatchErrorCode::GuardRejected => "guard_rejected",
+      DispatchErrorCode::Internal => "internal",
+      DispatchErrorCode::Other => "other",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    let code = match name {
+      "handler" => DispatchErrorCode::Handler,
+      "unhandled" => DispatchErrorCode::Unhandled,
+      "deserialize_failed" => DispatchErrorCode::DeserializeFailed,
+      "unexpected_none" => DispatchErrorCode::UnexpectedNone,
+      "queue_full" => DispatchErrorCode::QueueFull,
+      "busy" => DispatchErrorCode::Busy,
+      "timeout" => DispatchErrorCode::Timeout,
+      "cancelled" => DispatchErrorCode::Cancelled,
+      "handler_panic" => DispatchErrorCode::HandlerPanic,
+      "shutdown" => DispatchErrorCode::Shutdown,
+      "blocking_in_runtime" => DispatchErrorCode::BlockingInRuntime,
+      "duplicate_event" => DispatchErrorCode::DuplicateEvent,
+      "plugin_dependency" => DispatchErrorCode::PluginDependency,
+      "guard_rejected" => DispatchErrorCode::GuardRejected,
+      "internal" => DispatchErrorCode::Internal,
+      "other" => DispatchErrorCode::Other,
+      _ => return None,
+    };
+    Some(code)
+  }
+
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      DispatchErrorCode::Handler | DispatchErrorCode::Other => StatusCode::Err,
+      DispatchErrorCode::DeserializeFailed
+      | DispatchErrorCode::UnexpectedNone
+      | DispatchErrorCode::GuardRejected => StatusCode::InvalidParams,
+      DispatchErrorCode::Unhandled => StatusCode::NotFound,
+      DispatchErrorCode::Timeout => StatusCode::Timeout,
+      DispatchErrorCode::Cancelled => StatusCode::Cancelled,
+      DispatchErrorCode::Busy => StatusCode::Busy,
+      DispatchErrorCode::QueueFull
+      | DispatchErrorCode::HandlerPanic
+      | DispatchErrorCode::Shutdown
+      | DispatchErrorCode::BlockingInRuntime
+      | DispatchErrorCode::DuplicateEvent
+      | DispatchErrorCode::PluginDependency
+      | DispatchErrorCode::Internal => StatusCode::Internal,
+    }
+  }
+}
+
+impl fmt::Display for DispatchErrorCode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+impl AFPluginEventResponse {
+  pub fn error_code(&self) -> Option<DispatchErrorCode> {
+    self
+      .metadata(ERROR_CODE)
+      .and_then(DispatchErrorCode::from_name)
+  }
+
+  /// Returns None if the response is `Ok`.
+  pub fn error_kind(&self) -> Option<DispatchErrorKind> {
+    if self.status_code.is_ok() {
+      return None;
+    }
+    let kind = self
+      .metadata(ERROR_KIND)
+      .and_then(DispatchErrorKind::from_name);
+    match kind {
+      Some(kind) => Some(kind),
+      None if self.retryable => Some(DispatchErrorKind::Transient),
+      None => Some(DispatchErrorKind::Fatal),
+    }
+  }
+}
+
+/// Builds the response of the dispatcher's error, which carries the code and the message in the
+/// metadata.
+fn code_response<E: Error + fmt::Display>(error: &E) -> AFPluginEventResponse {
+  let code = error.code();
+  let mut response = ResponseBuilder::new(code.status_code())
+    .data(error.to_string())
+    .build();
+  response.insert_metadata(ERROR_CODE, code.as_str());
+  if let Some(message) = error.message() {
+    message.write_to(&mut response);
+  }
+  response
 }
 
 impl fmt::Display for DispatchError {
@@ -44,17 +498,21 @@ impl fmt::Display for DispatchError {
 
 impl fmt::Debug for DispatchError {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "{:?}", &self.inner)
+    write!(f, "{:?}", &self.inner)?;
+    #[cfg(feature = "backtrace")]
+    if let Some(backtrace) = self.backtrace() {
+      write!(f, "\n{}", backtrace)?;
+    }
+    Ok(())
   }
 }
 
 impl std::error::Error for DispatchError {
   fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-    None
-  }
-
-  fn cause(&self) -> Option<&dyn std::error::Error> {
-    None
+    self
+      .inner
+      .source_error()
+      .map(|err| err as &(dyn std::error::Error + 'static))
   }
 }
 
@@ -79,14 +537,27 @@ impl From<protobuf::ProtobufError> for DispatchError {
 
 impl AFPluginFromBytes for DispatchError {
   fn parse_from_bytes(bytes: Bytes) -> Result<Self, DispatchError> {
-    let s = String::from_utf8(bytes.to_vec()).unwrap();
+    let s = String::from_utf8_lossy(&bytes).into_owned();
     Ok(InternalError::DeserializeFromBytes(s).into())
   }
 }
 
 impl From<DispatchError> for AFPluginEventResponse {
   fn from(err: DispatchError) -> Self {
-    err.inner_error().as_response()
+    let mut response = err.inner_error().as_response();
+    let kind = err.kind();
+    response.retryable = kind.is_retryable();
+    if kind != DispatchErrorKind::Fatal {
+      response.insert_metadata(ERROR_KIND, kind.as_str());
+    }
+    if let Some(message) = err.message() {
+      message.write_to(&mut response);
+    }
+    #[cfg(all(feature = "backtrace", debug_assertions))]
+    if let Some(backtrace) = err.backtrace() {
+      response.insert_metadata(BACKTRACE, backtrace.to_string());
+    }
+    response
   }
 }
 #[cfg(feature = "use_serde")]
@@ -102,6 +573,143 @@ impl serde::Serialize for DispatchError {
   }
 }
 
+/// Returned by [AFPluginDispatcher::sync_send_timeout] if the response is not received in time.
+///
+/// [AFPluginDispatcher::sync_send_timeout]: crate::prelude::AFPluginDispatcher::sync_send_timeout
+#[derive(Clone, Debug)]
+pub struct DispatchTimeout {
+  pub event: AFPluginEvent,
+  pub timeout: Duration,
+}
+
+impl fmt::Display for DispatchTimeout {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "event {:?} timeout after {:?}", self.event, self.timeout)
+  }
+}
+
+impl std::error::Error for DispatchTimeout {}
+
+impl Error for DispatchTimeout {
+  fn as_response(&self) -> AFPluginEventResponse {
+    code_response(self)
+  }
+
+  fn code(&self) -> DispatchErrorCode {
+    DispatchErrorCode::Timeout
+  }
+
+  fn message(&self) -> Option<AFPluginErrorMessage> {
+    let message = AFPluginErrorMessage::new("dispatch.timeout")
+      .arg("event", self.event.as_str())
+      .arg("timeout_ms", self.timeout.as_millis());
+    Some(message)
+  }
+}
+
+/// Returned by [AFPluginDispatcher::try_async_send] with the request that isn't sent, so the
+/// caller can retry, drop or queue it by itself.
+///
+/// [AFPluginDispatcher::try_async_send]: crate::prelude::AFPluginDispatcher::try_async_send
+#[derive(Clone, Debug)]
+pub enum TrySendError {
+  /// The dispatcher has reached its capacity.
+  Full(AFPluginRequest),
+  /// The dispatcher is shutting down or stopped.
+  Closed(AFPluginRequest),
+}
+
+impl TrySendError {
+  pub fn into_request(self) -> AFPluginRequest {
+    match self {
+      TrySendError::Full(request) | TrySendError::Closed(request) => request,
+    }
+  }
+}
+
+impl fmt::Display for TrySendError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TrySendError::Full(request) => {
+        write!(
+          f,
+          "the dispatcher is full, event {:?} is not sent",
+          request.event
+        )
+      },
+      TrySendError::Closed(request) => {
+        write!(
+          f,
+          "the dispatcher is closed, event {:?} is not sent",
+          request.event
+        )
+      },
+    }
+  }
+}
+
+impl std::error::Error for TrySendError {}
+
+/// Returned if a parameter of the handler can't be extracted from the request, e.g. the payload
+/// can't be deserialized. The response is built from the extractor's error, so the frontend
+/// still receives the error it expects, while the message of the dispatcher's error and the log
+/// name the failed parameter.
+#[derive(Clone, Debug)]
+pub struct AFPluginExtractError {
+  pub event: AFPluginEvent,
+  /// The type name of the parameter.
+  pub param: &'static str,
+  pub error: DispatchError,
+}
+
+impl AFPluginExtractError {
+  pub fn new<T>(event: AFPluginEvent, error: DispatchError) -> Self {
+    Self {
+      event,
+      param: std::any::type_name::<T>(),
+      error,
+    }
+  }
+}
+
+impl fmt::Display for AFPluginExtractError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "failed to extract {} for {:?}: {}",
+      self.param, self.event, self.error
+    )
+  }
+}
+
+impl std::error::Error for AFPluginExtractError {}
+
+impl Error for AFPluginExtractError {
+  fn as_response(&self) -> AFPluginEventResponse {
+    context_response(&format!("extract {}", self.param), &self.error)
+  }
+
+  fn is_retryable(&self) -> bool {
+    self.error.is_retryable()
+  }
+
+  fn kind(&self) -> DispatchErrorKind {
+    self.error.kind()
+  }
+
+  fn code(&self) -> DispatchErrorCode {
+    self.error.code()
+  }
+
+  fn source_error(&self) -> Option<&DispatchError> {
+    Some(&self.error)
+  }
+
+  fn message(&self) -> Option<AFPluginErrorMessage> {
+    self.error.message()
+  }
+}
+
 #[derive(Clone, Debug)]
 pub(crate) enum InternalError {
   ProtobufError(String),
@@ -110,6 +718,16 @@ pub(crate) enum InternalError {
   JoinError(String),
   ServiceNotFound(String),
   HandleNotFound(String),
+  QueueFull(String),
+  Busy(String),
+  Timeout(String),
+  Cancelled(String),
+  Shutdown(String),
+  BlockingInRuntime(String),
+  DuplicateEvent(String),
+  PluginDependency(String),
+  GuardRejected(String),
+  Panic(String),
   Other(String),
 }
 
@@ -122,6 +740,16 @@ impl fmt::Display for InternalError {
       InternalError::JoinError(s) => fmt::Display::fmt(&s, f),
       InternalError::ServiceNotFound(s) => fmt::Display::fmt(&s, f),
       InternalError::HandleNotFound(s) => fmt::Display::fmt(&s, f),
+      InternalError::QueueFull(s) => fmt::Display::fmt(&s, f),
+      InternalError::Busy(s) => fmt::Display::fmt(&s, f),
+      InternalError::Timeout(s) => fmt::Display::fmt(&s, f),
+      InternalError::Cancelled(s) => fmt::Display::fmt(&s, f),
+      InternalError::Shutdown(s) => fmt::Display::fmt(&s, f),
+      InternalError::BlockingInRuntime(s) => fmt::Display::fmt(&s, f),
+      InternalError::DuplicateEvent(s) => fmt::Display::fmt(&s, f),
+      InternalError::PluginDependency(s) => fmt::Display::fmt(&s, f),
+      InternalError::GuardRejected(s) => fmt::Display::fmt(&s, f),
+      InternalError::Panic(s) => fmt::Display::fmt(&s, f),
       InternalError::Other(s) => fmt::Display::fmt(&s, f),
     }
   }
@@ -129,7 +757,47 @@ impl fmt::Display for InternalError {
 
 impl Error for InternalError {
   fn as_response(&self) -> AFPluginEventResponse {
-    ResponseBuilder::Err().data(self.to_string()).build()
+    code_response(self)
+  }
+
+  fn code(&self) -> DispatchErrorCode {
+    match self {
+      InternalError::ProtobufError(_) | InternalError::DeserializeFromBytes(_) => {
+        DispatchErrorCode::DeserializeFailed
+      },
+      InternalError::UnexpectedNone(_) => DispatchErrorCode::UnexpectedNone,
+      InternalError::JoinError(_) => DispatchErrorCode::Internal,
+      InternalError::ServiceNotFound(_) | InternalError::HandleNotFound(_) => {
+        DispatchErrorCode::Unhandled
+      },
+      InternalError::QueueFull(_) => DispatchErrorCode::QueueFull,
+      InternalError::Busy(_) => DispatchErrorCode::Busy,
+      InternalError::Timeout(_) => DispatchErrorCode::Timeout,
+      InternalError::Cancelled(_) => DispatchErrorCode::Cancelled,
+      InternalError::Shutdown(_) => DispatchErrorCode::Shutdown,
+      InternalError::BlockingInRuntime(_) => DispatchErrorCode::BlockingInRuntime,
+      InternalError::DuplicateEvent(_) => DispatchErrorCode::DuplicateEvent,
+      InternalError::PluginDependency(_) => DispatchErrorCode::PluginDependency,
+      InternalError::GuardRejected(_) => DispatchErrorCode::GuardRejected,
+      InternalError::Panic(_) => DispatchErrorCode::HandlerPanic,
+      InternalError::Other(_) => DispatchErrorCode::Other,
+    }
+  }
+
+  /// The shed request can be sent again once the burst is drained.
+  fn kind(&self) -> DispatchErrorKind {
+    match self {
+      InternalError::Busy(_) => DispatchErrorKind::Transient,
+      _ => DispatchErrorKind::Fatal,
+    }
+  }
+
+  /// Named by the code, e.g. `dispatch.handler_panic`.
+  fn message(&self) -> Option<AFPluginErrorMessage> {
+    Some(AFPluginErrorMessage::new(format!(
+      "dispatch.{}",
+      self.code().as_str()
+    )))
   }
 }
 
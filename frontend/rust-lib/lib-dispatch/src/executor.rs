@@ -0,0 +1,102 @@
+use std::future::Future;
+
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+#[cfg(feature = "trace_requests")]
+use tracing::Instrument;
+
+use crate::errors::DispatchError;
+use crate::prelude::AFBoxFuture;
+use crate::response::AFPluginEventResponse;
+use crate::service::SendHandlerFuture;
+
+/// Where the handlers of a plugin run. See [AFPlugin::executor].
+///
+/// Not available with the `local_set` feature. The handlers of the local set aren't `Send`, so
+/// they can't be moved to another runtime or to the blocking pool. Register the blocking handlers
+/// with [AFPlugin::blocking_event] instead.
+///
+/// [AFPlugin::executor]: crate::prelude::AFPlugin::executor
+/// [AFPlugin::blocking_event]: crate::prelude::AFPlugin::blocking_event
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AFPluginExecutor {
+  /// The dispatcher's runtime that is shared by all the plugins.
+  #[default]
+  Shared,
+  /// A single-threaded runtime that is owned by the plugin, so its slow handlers only delay
+  /// each other.
+  Dedicated,
+  /// The blocking pool of the dispatcher's runtime, for the handlers that block the thread, e.g.
+  /// the sqlite queries.
+  Blocking,
+}
+
+pub(crate) enum PluginExecutor {
+  Shared,
+  Dedicated(DedicatedRuntime),
+  Blocking,
+}
+
+impl PluginExecutor {
+  pub(crate) fn new(executor: AFPluginExecutor, name: &str) -> Self {
+    match executor {
+      AFPluginExecutor::Shared => PluginExecutor::Shared,
+      AFPluginExecutor::Dedicated => PluginExecutor::Dedicated(DedicatedRuntime::new(name)),
+      AFPluginExecutor::Blocking => PluginExecutor::Blocking,
+    }
+  }
+
+  /// Runs the handler's future on the executor. The handler is aborted if the returned future is
+  /// dropped, unless it's already running on the blocking pool.
+  pub(crate) fn run<F>(
+    &self,
+    fut: F,
+  ) -> AFBoxFuture<'static, Result<AFPluginEventResponse, DispatchError>>
+  where
+    F: Future<Output = Result<AFPluginEventResponse, DispatchError>> + Send + 'static,
+  {
+    #[cfg(feature = "trace_requests")]
+    let fut = fut.in_current_span();
+    match self {
+      PluginExecutor::Shared => Box::pin(fut),
+      PluginExecutor::Dedicated(runtime) => {
+        let handle = SendHandlerFuture::new(runtime.handle.spawn(fut));
+        Box::pin(async move { handle.await? })
+      },
+      PluginExecutor::Blocking => {
+        let runtime = Handle::current();
+        let handle =
+          SendHandlerFuture::new(tokio::task::spawn_blocking(move || runtime.block_on(fut)));
+        Box::pin(async move { handle.await? })
+      },
+    }
+  }
+}
+
+/// The runtime runs on its own thread until the plugin is dropped.
+pub(crate) struct DedicatedRuntime {
+  handle: Handle,
+  _shutdown: oneshot::Sender<()>,
+}
+
+impl DedicatedRuntime {
+  fn new(name: &str) -> Self {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+      .enable_all()
+      .build()
+      .expect("Failed to create the dedicated runtime of the plugin");
+    let handle = runtime.handle().clone();
+    let (shutdown, stopped) = oneshot::channel::<()>();
+    std::thread::Builder::new()
+      .name(format!("dispatch-{}", name))
+      .spawn(move || {
+        // Resolves once the sender is dropped, the runtime is dropped outside of any runtime.
+        let _ = runtime.block_on(stopped);
+      })
+      .expect("Failed to spawn the thread of the dedicated runtime");
+    Self {
+      handle,
+      _shutdown: shutdown,
+    }
+  }
+}
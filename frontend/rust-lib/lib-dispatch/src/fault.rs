@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+
+use crate::errors::{DispatchError, InternalError};
+use crate::module::AFPluginEvent;
+use crate::prelude::AFBoxFuture;
+use crate::service::{
+  AFPluginBoxService, AFPluginTransform, Service, ServiceRequest, ServiceResponse,
+};
+
+/// The faults that are injected into the requests of an event. The rates are between `0.0`,
+/// never, and `1.0`, always, and each fault is rolled independently.
+#[derive(Clone, Debug, Default)]
+pub struct FaultRule {
+  pub delay_rate: f64,
+  /// The delayed request waits for a random duration up to it.
+  pub max_delay: Duration,
+  pub fail_rate: f64,
+  pub drop_rate: f64,
+}
+
+impl FaultRule {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn delay(mut self, rate: f64, max_delay: Duration) -> Self {
+    self.delay_rate = rate;
+    self.max_delay = max_delay;
+    self
+  }
+
+  /// The failed request is resolved with a transient error without calling the handler, so the
+  /// retry policy retries it.
+  pub fn fail(mut self, rate: f64) -> Self {
+    self.fail_rate = rate;
+    self
+  }
+
+  /// The dropped request never completes, as if the backend lost it. It's resolved by its
+  /// timeout or cancellation.
+  pub fn drop(mut self, rate: f64) -> Self {
+    self.drop_rate = rate;
+    self
+  }
+}
+
+/// The middleware that randomly delays, fails or drops the requests, to check how the frontend
+/// and the retries behave when the backend misbehaves. It's meant for the tests and the debug
+/// builds:
+///
+/// ```ignore
+/// let faults = DispatchFaultInjection::new()
+///   .event(DocumentEvent::ApplyAction, FaultRule::new().fail(0.1))
+///   .all_events(FaultRule::new().delay(0.2, Duration::from_millis(500)));
+/// let plugin = document_plugin().wrap(faults);
+/// ```
+///
+/// The dropped requests take precedence over the delayed and the failed ones. Set the seed to
+/// inject the same faults in every run.
+#[derive(Clone)]
+pub struct DispatchFaultInjection {
+  rules: Arc<FaultRules>,
+}
+
+struct FaultRules {
+  events: HashMap<AFPluginEvent, FaultRule>,
+  /// The rule of the events that have no rule of their own.
+  fallback: Option<FaultRule>,
+  rng: Mutex<u64>,
+}
+
+/// The faults that are rolled for a request.
+#[derive(Debug)]
+struct Fault {
+  drop: bool,
+  delay: Option<Duration>,
+  fail: bool,
+}
+
+impl Default for DispatchFaultInjection {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl DispatchFaultInjection {
+  pub fn new() -> Self {
+    let seed = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+    Self {
+      rules: Arc::new(FaultRules {
+        events: HashMap::new(),
+        fallback: None,
+        rng: Mutex::new(non_zero(seed)),
+      }),
+    }
+  }
+
+  pub fn seed(mut self, seed: u64) -> Self {
+    *self.rules_mut().rng.get_mut() = non_zero(seed);
+    self
+  }
+
+  pub fn event<E: Into<AFPluginEvent>>(mut self, event: E, rule: FaultRule) -> Self {
+    self.rules_mut().events.insert(event.into(), rule);
+    self
+  }
+
+  /// Sets the rule of the events that have no rule of their own.
+  pub fn all_events(mut self, rule: FaultRule) -> Self {
+    self.rules_mut().fallback = Some(rule);
+    self
+  }
+
+  fn rules_mut(&mut self) -> &mut FaultRules {
+    Arc::get_mut(&mut self.rules).expect("the rules must be set before the middleware is used")
+  }
+}
+
+impl FaultRules {
+  fn roll(&self, event: &AFPluginEvent) -> Option<Fault> {
+    let rule = self.events.get(event).or(self.fallback.as_ref())?;
+    let mut rng = self.rng.lock();
+    let drop = hit(&mut rng, rule.drop_rate);
+    let delay = hit(&mut rng, rule.delay_rate).then(|| rule.max_delay.mul_f64(next_f64(&mut rng)));
+    let fail = hit(&mut rng, rule.fail_rate);
+    Some(Fault { drop, delay, fail })
+      .filter(|fault| fault.drop || fault.delay.is_some() || fault.fail)
+  }
+}
+
+impl AFPluginTransform for DispatchFaultInjection {
+  fn new_transform(&self, service: AFPluginBoxService) -> AFPluginBoxService {
+    Box::new(FaultService {
+      service,
+      rules: self.rules.clone(),
+    })
+  }
+}
+
+struct FaultService {
+  service: AFPluginBoxService,
+  rules: Arc<FaultRules>,
+}
+
+impl Service<ServiceRequest> for FaultService {
+  type Response = ServiceResponse;
+  type Error = DispatchError;
+  type Future = AFBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+  fn call(&self, req: ServiceRequest) -> Self::Future {
+    let event = req.request().event().clone();
+    let fault = match self.rules.roll(&event) {
+      None => return self.service.call(req),
+      Some(fault) => fault,
+    };
+    tracing::debug!("[dispatch]: inject {:?} into {:?}", fault, event);
+    if fault.drop {
+      return Box::pin(futures::future::pending());
+    }
+
+    let fut = (!fault.fail).then(|| self.service.call(req));
+    Box::pin(async move {
+      if let Some(delay) = fault.delay {
+        tokio::time::sleep(delay).await;
+      }
+      match fut {
+        Some(fut) => fut.await,
+        None => {
+          let msg = format!("[dispatch]: the injected fault of {:?}", event);
+          Err(DispatchError::from(InternalError::Other(msg)).transient())
+        },
+      }
+    })
+  }
+}
+
+fn non_zero(seed: u64) -> u64 {
+  seed.max(1)
+}
+
+fn hit(rng: &mut u64, rate: f64) -> bool {
+  rate > 0.0 && next_f64(rng) < rate
+}
+
+/// The xorshift64* generator, which is good enough to pick the faults.
+fn next_f64(state: &mut u64) -> f64 {
+  let mut x = *state;
+  x ^= x >> 12;
+  x ^= x << 25;
+  x ^= x >> 27;
+  *state = x;
+  let bits = x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11;
+  bits as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,271 @@
+//! The C ABI of the dispatcher, so the hosts other than Dart, e.g. Swift, Kotlin or Electron,
+//! can embed it without writing their own bridge. See `include/lib_dispatch.h`.
+//!
+//! The Rust side of the host registers the plugins with [set_plugin_factory], then the host calls
+//! `af_dispatch_init` before sending the requests.
+//!
+//! The request is framed as the event prefixed with its big-endian `u32` length, the `u8` codec
+//! (`0` for protobuf, `1` for json), then the payload. The response is framed as the `u8`
+//! [StatusCode] followed by the payload.
+//!
+//! [StatusCode]: crate::prelude::StatusCode
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+use std::os::raw::c_void;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::{Buf, Bytes};
+use parking_lot::{const_mutex, const_rwlock, Mutex, RwLock};
+
+use crate::codec::PayloadCodec;
+use crate::dispatcher::{check_blocking_on, AFPluginDispatcher};
+use crate::module::{AFPlugin, AFPluginRequest};
+use crate::request::Payload;
+use crate::response::AFPluginEventResponse;
+use crate::runtime::AFPluginRuntime;
+
+pub const AF_DISPATCH_OK: i32 = 0;
+/// `af_dispatch_init` is called before [set_plugin_factory].
+pub const AF_DISPATCH_NO_PLUGINS: i32 = -1;
+/// The runtime can't be created.
+pub const AF_DISPATCH_RUNTIME_ERROR: i32 = -2;
+/// The `on_start` hook of a plugin fails, or the dependencies of the plugins are missing.
+pub const AF_DISPATCH_START_FAILED: i32 = -3;
+/// The request is sent before `af_dispatch_init` or after `af_dispatch_shutdown`.
+pub const AF_DISPATCH_NOT_INITIALIZED: i32 = -4;
+/// The request frame is malformed.
+pub const AF_DISPATCH_INVALID_FRAME: i32 = -5;
+/// The requests are not completed before the timeout of `af_dispatch_shutdown`.
+pub const AF_DISPATCH_SHUTDOWN_TIMEOUT: i32 = -6;
+/// `af_dispatch_init` or `af_dispatch_shutdown` is called on a thread of a tokio runtime, where
+/// blocking would stall or deadlock the runtime.
+pub const AF_DISPATCH_BLOCKING_IN_RUNTIME: i32 = -7;
+/// `af_dispatch_init` is called again before `af_dispatch_shutdown`.
+pub const AF_DISPATCH_ALREADY_INITIALIZED: i32 = -8;
+/// The call panicked. The panic is caught, so it doesn't unwind into the host.
+pub const AF_DISPATCH_PANIC: i32 = -9;
+
+/// Called with the response frame of `af_dispatch_async_send` and the `context` that is passed
+/// to it. The frame is only valid during the call, so copy it if it's needed later.
+pub type AFDispatchCallback = extern "C" fn(context: *mut c_void, frame: *const u8, len: usize);
+
+type PluginFactory = Box<dyn Fn() -> Vec<AFPlugin> + Send + Sync>;
+
+struct Embedded {
+  runtime: Arc<AFPluginRuntime>,
+  dispatcher: Arc<AFPluginDispatcher>,
+}
+
+static PLUGIN_FACTORY: Mutex<Option<PluginFactory>> = const_mutex(None);
+static EMBEDDED: RwLock<Option<Embedded>> = const_rwlock(None);
+
+/// Sets the plugins that `af_dispatch_init` creates the dispatcher with. The factory is called
+/// on every init, so the host can re-init after the shutdown.
+pub fn set_plugin_factory<F>(factory: F)
+where
+  F: Fn() -> Vec<AFPlugin> + Send + Sync + 'static,
+{
+  *PLUGIN_FACTORY.lock() = Some(Box::new(factory));
+}
+
+/// Creates the runtime and the dispatcher, and runs the `on_start` hooks of the plugins. Returns
+/// [AF_DISPATCH_ALREADY_INITIALIZED] if the previous dispatcher is not shut down.
+#[no_mangle]
+pub extern "C" fn af_dispatch_init() -> i32 {
+  catch_panic("af_dispatch_init", AF_DISPATCH_PANIC, || {
+    if check_blocking_on("af_dispatch_init", "call it from a thread of the host").is_err() {
+      return AF_DISPATCH_BLOCKING_IN_RUNTIME;
+    }
+    // Holds the lock until the dispatcher is started, so the concurrent inits can't both pass.
+    let mut embedded = EMBEDDED.write();
+    if embedded.is_some() {
+      return AF_DISPATCH_ALREADY_INITIALIZED;
+    }
+    let plugins = match PLUGIN_FACTORY.lock().as_ref() {
+      None => return AF_DISPATCH_NO_PLUGINS,
+      Some(factory) => factory(),
+    };
+    let runtime = match AFPluginRuntime::new() {
+      Ok(runtime) => Arc::new(runtime),
+      Err(e) => {
+        tracing::error!("[dispatch]: create the runtime failed: {}", e);
+        return AF_DISPATCH_RUNTIME_ERROR;
+      },
+    };
+    let dispatcher = Arc::new(AFPluginDispatcher::new(runtime.clone(), plugins));
+    if let Err(e) = runtime.block_on(dispatcher.start()) {
+      tracing::error!("[dispatch]: start the plugins failed: {}", e);
+      return AF_DISPATCH_START_FAILED;
+    }
+    *embedded = Some(Embedded {
+      runtime,
+      dispatcher,
+    });
+    AF_DISPATCH_OK
+  })
+}
+
+/// Sends the request frame and calls the `callback` with the response frame on a thread of the
+/// runtime. The `context` is passed back untouched, it must be safe to use from that thread.
+#[no_mangle]
+pub extern "C" fn af_dispatch_async_send(
+  input: *const u8,
+  len: usize,
+  callback: AFDispatchCallback,
+  context: *mut c_void,
+) -> i32 {
+  catch_panic("af_dispatch_async_send", AF_DISPATCH_PANIC, || {
+    let dispatcher = match current_dispatcher() {
+      None => return AF_DISPATCH_NOT_INITIALIZED,
+      Some(dispatcher) => dispatcher,
+    };
+    let request = match decode_request(input, len) {
+      None => return AF_DISPATCH_INVALID_FRAME,
+      Some(request) => request,
+    };
+    let context = HostContext(context);
+    let _ = AFPluginDispatcher::boxed_async_send_with_callback(
+      dispatcher.as_ref(),
+      request,
+      move |response: AFPluginEventResponse| {
+        Box::pin(async move {
+          let context = context;
+          let frame = encode_response(&response);
+          callback(context.0, frame.as_ptr(), frame.len());
+        })
+      },
+    );
+    AF_DISPATCH_OK
+  })
+}
+
+/// Sends the request frame and blocks until the response. Returns the response frame and writes
+/// its length to `output_len`, or returns null if the request can't be sent or `output_len` is
+/// null. The frame must be freed with `af_dispatch_free`.
+#[no_mangle]
+pub extern "C" fn af_dispatch_sync_send(
+  input: *const u8,
+  len: usize,
+  output_len: *mut usize,
+) -> *mut u8 {
+  catch_panic("af_dispatch_sync_send", std::ptr::null_mut(), || {
+    if output_len.is_null() {
+      return std::ptr::null_mut();
+    }
+    let (dispatcher, request) = match (current_dispatcher(), decode_request(input, len)) {
+      (Some(dispatcher), Some(request)) => (dispatcher, request),
+      _ => return std::ptr::null_mut(),
+    };
+    let response = AFPluginDispatcher::sync_send(dispatcher, request);
+    let frame = encode_response(&response).into_boxed_slice();
+    unsafe { *output_len = frame.len() };
+    Box::into_raw(frame) as *mut u8
+  })
+}
+
+/// Frees the frame that is returned by `af_dispatch_sync_send`.
+#[no_mangle]
+pub extern "C" fn af_dispatch_free(frame: *mut u8, len: usize) {
+  catch_panic("af_dispatch_free", (), || {
+    if frame.is_null() {
+      return;
+    }
+    unsafe {
+      drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+        frame, len,
+      )));
+    }
+  })
+}
+
+/// Waits up to `timeout_ms` for the running requests, then drops the dispatcher and its runtime.
+/// The dispatcher is kept if it's called on a thread of a tokio runtime.
+#[no_mangle]
+pub extern "C" fn af_dispatch_shutdown(timeout_ms: u64) -> i32 {
+  catch_panic("af_dispatch_shutdown", AF_DISPATCH_PANIC, || {
+    if check_blocking_on("af_dispatch_shutdown", "call it from a thread of the host").is_err() {
+      return AF_DISPATCH_BLOCKING_IN_RUNTIME;
+    }
+    let embedded = match EMBEDDED.write().take() {
+      None => return AF_DISPATCH_NOT_INITIALIZED,
+      Some(embedded) => embedded,
+    };
+    let timeout = Duration::from_millis(timeout_ms);
+    let result = embedded
+      .runtime
+      .block_on(embedded.dispatcher.shutdown(timeout));
+    match result {
+      Ok(()) => AF_DISPATCH_OK,
+      Err(e) => {
+        tracing::warn!("[dispatch]: shut down failed: {}", e);
+        AF_DISPATCH_SHUTDOWN_TIMEOUT
+      },
+    }
+  })
+}
+
+/// A panic must not unwind across the C ABI, it's undefined behavior. Returns `on_panic` instead.
+fn catch_panic<T, F>(name: &str, on_panic: T, f: F) -> T
+where
+  F: FnOnce() -> T,
+{
+  match std::panic::catch_unwind(AssertUnwindSafe(f)) {
+    Ok(value) => value,
+    Err(_) => {
+      tracing::error!("[dispatch]: {} panicked", name);
+      on_panic
+    },
+  }
+}
+
+/// The host promises the context can be used from the threads of the runtime.
+struct HostContext(*mut c_void);
+
+unsafe impl Send for HostContext {}
+unsafe impl Sync for HostContext {}
+
+fn current_dispatcher() -> Option<Arc<AFPluginDispatcher>> {
+  EMBEDDED
+    .read()
+    .as_ref()
+    .map(|embedded| embedded.dispatcher.clone())
+}
+
+pub(crate) fn decode_request(input: *const u8, len: usize) -> Option<AFPluginRequest> {
+  if input.is_null() {
+    return None;
+  }
+  let mut frame = Bytes::copy_from_slice(unsafe { std::slice::from_raw_parts(input, len) });
+  if frame.remaining() < 4 {
+    return None;
+  }
+  let event_len = frame.get_u32() as usize;
+  if frame.remaining() < event_len + 1 {
+    return None;
+  }
+  let event = String::from_utf8(frame.split_to(event_len).to_vec()).ok()?;
+  let codec = match frame.get_u8() {
+    0 => PayloadCodec::Protobuf,
+    1 => PayloadCodec::Json,
+    _ => return None,
+  };
+
+  let mut request = AFPluginRequest::untyped(event).codec(codec);
+  if !frame.is_empty() {
+    request.payload = Payload::Bytes(frame);
+  }
+  Some(request)
+}
+
+pub(crate) fn encode_response(response: &AFPluginEventResponse) -> Vec<u8> {
+  let payload: &[u8] = match &response.payload {
+    Payload::None => &[],
+    Payload::Bytes(bytes) => bytes,
+  };
+  let mut frame = Vec::with_capacity(payload.len() + 1);
+  frame.push(response.status_code as u8);
+  frame.extend_from_slice(payload);
+  frame
+}
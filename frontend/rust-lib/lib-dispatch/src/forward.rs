@@ -0,0 +1,518 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::stream::FuturesUnordered;
+use futures::{SinkExt, StreamExt};
+use nanoid::nanoid;
+use parking_lot::Mutex;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+
+use crate::codec::PayloadCodec;
+use crate::dispatcher::AFPluginDispatcher;
+use crate::errors::{Error, InternalError};
+use crate::module::{AFPluginEvent, AFPluginRequest, DispatchRoutes};
+use crate::request::Payload;
+use crate::response::{AFPluginEventResponse, StatusCode};
+use crate::retry::DispatchRetryPolicy;
+
+/// The frames larger than it are rejected, so a corrupted length can't exhaust the memory.
+const MAX_FRAME_LEN: usize = 256 * 1024 * 1024;
+
+/// Sends the events that start with the prefix to another process that hosts a dispatcher, e.g.
+/// to run the heavy indexing in a helper process. The helper serves the connection with
+/// [serve_forwarded]. See [AFPluginDispatcherBuilder::forward].
+///
+/// The local plugin that registers the event takes it over. The forwarded requests share one
+/// connection, which is opened on the first request. The requests stay in the outbox until
+/// their responses arrive. When the connection is broken, it's reopened with the backoff of
+/// the [DispatchForwarder::reconnect] policy and the outbox is sent again. The other process
+/// skips the requests it has already handled, so a drop neither loses nor repeats a mutation.
+/// The requests in the outbox are resolved with the error if the connection can't be reopened.
+///
+/// The endpoint is the path of the unix socket, or the name of the named pipe on windows, e.g.
+/// `\\.\pipe\appflowy-indexer`.
+///
+/// [AFPluginDispatcherBuilder::forward]: crate::prelude::AFPluginDispatcherBuilder::forward
+pub struct DispatchForwarder {
+  prefix: String,
+  endpoint: PathBuf,
+  /// Identifies the forwarder to the other process, which deduplicates the requests with it.
+  client_id: String,
+  reconnect: DispatchRetryPolicy,
+  outbox: Arc<Outbox>,
+  /// Queues the ids of the new requests for the connection task.
+  queue: Mutex<Option<mpsc::UnboundedSender<u64>>>,
+  next_id: AtomicU64,
+}
+
+impl DispatchForwarder {
+  pub fn new<T: Into<String>, P: Into<PathBuf>>(prefix: T, endpoint: P) -> Self {
+    Self {
+      prefix: prefix.into(),
+      endpoint: endpoint.into(),
+      client_id: nanoid!(),
+      reconnect: DispatchRetryPolicy::new(8),
+      outbox: Arc::new(Outbox::default()),
+      queue: Mutex::new(None),
+      next_id: AtomicU64::new(0),
+    }
+  }
+
+  /// Sets how many times the connection is tried, and the backoff between the tries, before
+  /// the requests in the outbox fail. Defaults to 8 tries from 100ms to 5s apart.
+  pub fn reconnect(mut self, policy: DispatchRetryPolicy) -> Self {
+    self.reconnect = policy;
+    self
+  }
+
+  pub(crate) async fn forward(&self, request: &AFPluginRequest) -> AFPluginEventResponse {
+    let mut response = match self.send(request).await {
+      Ok(response) => response,
+      Err(e) => {
+        let msg = format!(
+          "[dispatch]: forward {:?} to {:?} failed: {}",
+          request.event, self.endpoint, e
+        );
+        tracing::error!("{}", msg);
+        InternalError::Other(msg).as_response()
+      },
+    };
+    response.correlation_id = request.correlation_id.clone();
+    response
+  }
+
+  async fn send(&self, request: &AFPluginRequest) -> io::Result<AFPluginEventResponse> {
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+    let rx = self.outbox.push(id, encode_request(id, request));
+    if self.queue().send(id).is_err() {
+      self.outbox.remove(id);
+      return Err(broken_pipe());
+    }
+    rx.await.map_err(|_| broken_pipe())
+  }
+
+  /// Spawns the connection task on the first request.
+  fn queue(&self) -> mpsc::UnboundedSender<u64> {
+    let mut queue = self.queue.lock();
+    if let Some(queue) = queue.as_ref().filter(|queue| !queue.is_closed()) {
+      return queue.clone();
+    }
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(drive_connection(
+      self.endpoint.clone(),
+      self.client_id.clone(),
+      self.reconnect.clone(),
+      self.outbox.clone(),
+      rx,
+    ));
+    queue.insert(tx).clone()
+  }
+}
+
+/// Returns the forwarder of the `event` unless a local plugin registers it.
+pub(crate) fn find_forwarder(
+  forwarders: &[Arc<DispatchForwarder>],
+  routes: &DispatchRoutes,
+  event: &AFPluginEvent,
+) -> Option<Arc<DispatchForwarder>> {
+  if routes.plugins.contains_key(event) {
+    return None;
+  }
+  forwarders
+    .iter()
+    .find(|forwarder| event.as_str().starts_with(&forwarder.prefix))
+    .cloned()
+}
+
+/// Serves the requests that are forwarded by the [DispatchForwarder] of another process. It
+/// completes when the other process closes the connection. The requests are handled
+/// concurrently.
+///
+/// The requests are deduplicated with the idempotency of the dispatcher, so the ones that are
+/// sent again after a reconnection get the responses of the first time. See
+/// [AFPluginDispatcherBuilder::idempotency_window].
+///
+/// ```ignore
+/// let listener = UnixListener::bind("/tmp/appflowy-indexer.sock")?;
+/// while let Ok((stream, _)) = listener.accept().await {
+///   let dispatcher = dispatcher.clone();
+///   tokio::spawn(async move { serve_forwarded(&dispatcher, stream).await });
+/// }
+/// ```
+///
+/// [AFPluginDispatcherBuilder::idempotency_window]: crate::prelude::AFPluginDispatcherBuilder::idempotency_window
+pub async fn serve_forwarded<S>(dispatcher: &AFPluginDispatcher, stream: S) -> io::Result<()>
+where
+  S: AsyncRead + AsyncWrite + Unpin,
+{
+  let (reader, writer) = tokio::io::split(stream);
+  let (mut reader, mut writer) = (framed_read(reader), framed_write(writer));
+  let client_id = match reader.next().await.transpose()? {
+    None => return Ok(()),
+    Some(frame) => decode_hello(frame.freeze())
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "The hello frame is malformed"))?,
+  };
+
+  let mut responses = FuturesUnordered::new();
+  loop {
+    tokio::select! {
+      frame = reader.next() => match frame.transpose()? {
+        None => return Ok(()),
+        Some(frame) => match decode_request(frame.freeze()) {
+          Some((id, mut request)) => {
+            if request.idempotency_key.is_none() {
+              request.idempotency_key = Some(format!("forward:{}:{}", client_id, id));
+            }
+            responses.push(async move {
+              let response = AFPluginDispatcher::async_send(dispatcher, request).await;
+              encode_response(id, &response)
+            })
+          },
+          None => tracing::warn!("[dispatch]: drop the malformed forwarded frame"),
+        },
+      },
+      Some(frame) = responses.next() => writer.send(frame).await?,
+    }
+  }
+}
+
+/// The requests that are waiting for their responses.
+#[derive(Default)]
+struct Outbox {
+  entries: Mutex<BTreeMap<u64, OutboxEntry>>,
+}
+
+struct OutboxEntry {
+  frame: Bytes,
+  tx: oneshot::Sender<AFPluginEventResponse>,
+  /// The connection that the request was last sent on.
+  sent_on: Option<u64>,
+}
+
+impl Outbox {
+  fn push(&self, id: u64, frame: Bytes) -> oneshot::Receiver<AFPluginEventResponse> {
+    let (tx, rx) = oneshot::channel();
+    let entry = OutboxEntry {
+      frame,
+      tx,
+      sent_on: None,
+    };
+    self.entries.lock().insert(id, entry);
+    rx
+  }
+
+  fn remove(&self, id: u64) -> Option<OutboxEntry> {
+    self.entries.lock().remove(&id)
+  }
+
+  fn contains(&self, id: u64) -> bool {
+    self.entries.lock().contains_key(&id)
+  }
+
+  fn is_empty(&self) -> bool {
+    self.entries.lock().is_empty()
+  }
+
+  /// Returns the frame of the request unless it's completed or already sent on the
+  /// `connection`.
+  fn unsent(&self, id: u64, connection: u64) -> Option<Bytes> {
+    let mut entries = self.entries.lock();
+    let entry = entries
+      .get_mut(&id)
+      .filter(|entry| entry.sent_on != Some(connection))?;
+    entry.sent_on = Some(connection);
+    Some(entry.frame.clone())
+  }
+
+  /// Returns the frames of all the requests from the oldest to the newest, and marks them as
+  /// sent on the `connection`.
+  fn resend(&self, connection: u64) -> Vec<Bytes> {
+    let mut entries = self.entries.lock();
+    entries
+      .values_mut()
+      .map(|entry| {
+        entry.sent_on = Some(connection);
+        entry.frame.clone()
+      })
+      .collect()
+  }
+
+  /// Drops the senders, so the waiting requests are resolved with the error.
+  fn fail_all(&self) {
+    self.entries.lock().clear();
+  }
+}
+
+/// Keeps the connection open while there are requests in the outbox. It exits when the
+/// forwarder is dropped.
+async fn drive_connection(
+  endpoint: PathBuf,
+  client_id: String,
+  policy: DispatchRetryPolicy,
+  outbox: Arc<Outbox>,
+  mut queue: mpsc::UnboundedReceiver<u64>,
+) {
+  let mut connection = 0;
+  loop {
+    // Connects when there's a request to send.
+    while outbox.is_empty() {
+      match queue.recv().await {
+        None => return,
+        Some(id) if outbox.contains(id) => break,
+        Some(_) => {},
+      }
+    }
+
+    let stream = match connect_with_retry(&endpoint, &policy).await {
+      Ok(stream) => stream,
+      Err(e) => {
+        tracing::error!("[dispatch]: connect to {:?} failed: {}", endpoint, e);
+        outbox.fail_all();
+        continue;
+      },
+    };
+    connection += 1;
+    match run_connection(stream, &client_id, connection, &outbox, &mut queue).await {
+      Ok(()) => return,
+      Err(e) => tracing::warn!(
+        "[dispatch]: the connection to {:?} is broken, reconnecting: {}",
+        endpoint,
+        e
+      ),
+    }
+  }
+}
+
+async fn connect_with_retry(
+  endpoint: &Path,
+  policy: &DispatchRetryPolicy,
+) -> io::Result<impl AsyncRead + AsyncWrite + Send + 'static> {
+  let mut attempt = 0;
+  loop {
+    attempt += 1;
+    match connect(endpoint).await {
+      Ok(stream) => return Ok(stream),
+      Err(e) if attempt >= policy.max_attempts => return Err(e),
+      Err(e) => {
+        let backoff = policy.backoff(attempt);
+        tracing::debug!(
+          "[dispatch]: connect to {:?} failed, retry in {:?}: {}",
+          endpoint,
+          backoff,
+          e
+        );
+        tokio::time::sleep(backoff).await;
+      },
+    }
+  }
+}
+
+/// Sends the outbox again, then the new requests, until the connection is broken. Returns
+/// `Ok` when the forwarder is dropped.
+async fn run_connection<S>(
+  stream: S,
+  client_id: &str,
+  connection: u64,
+  outbox: &Outbox,
+  queue: &mut mpsc::UnboundedReceiver<u64>,
+) -> io::Result<()>
+where
+  S: AsyncRead + AsyncWrite,
+{
+  let (reader, writer) = tokio::io::split(stream);
+  let (mut reader, mut writer) = (framed_read(reader), framed_write(writer));
+  let write = async {
+    writer.send(encode_hello(client_id)).await?;
+    for frame in outbox.resend(connection) {
+      writer.send(frame).await?;
+    }
+    while let Some(id) = queue.recv().await {
+      if let Some(frame) = outbox.unsent(id, connection) {
+        writer.send(frame).await?;
+      }
+    }
+    Ok::<_, io::Error>(())
+  };
+  let read = async {
+    while let Some(frame) = reader.next().await {
+      if let Some((id, response)) = decode_response(frame?.freeze()) {
+        if let Some(entry) = outbox.remove(id) {
+          let _ = entry.tx.send(response);
+        }
+      }
+    }
+    Err::<(), _>(broken_pipe())
+  };
+  tokio::select! {
+    result = write => result,
+    result = read => result,
+  }
+}
+
+#[cfg(unix)]
+async fn connect(endpoint: &std::path::Path) -> io::Result<tokio::net::UnixStream> {
+  tokio::net::UnixStream::connect(endpoint).await
+}
+
+#[cfg(windows)]
+async fn connect(
+  endpoint: &std::path::Path,
+) -> io::Result<tokio::net::windows::named_pipe::NamedPipeClient> {
+  tokio::net::windows::named_pipe::ClientOptions::new().open(endpoint)
+}
+
+fn broken_pipe() -> io::Error {
+  io::Error::new(io::ErrorKind::BrokenPipe, "The connection is broken")
+}
+
+/// Every frame is prefixed with its big-endian `u32` length.
+fn length_delimited() -> LengthDelimitedCodec {
+  LengthDelimitedCodec::builder()
+    .max_frame_length(MAX_FRAME_LEN)
+    .new_codec()
+}
+
+fn framed_read<R: AsyncRead>(reader: R) -> FramedRead<R, LengthDelimitedCodec> {
+  FramedRead::new(reader, length_delimited())
+}
+
+fn framed_write<W: AsyncWrite>(writer: W) -> FramedWrite<W, LengthDelimitedCodec> {
+  FramedWrite::new(writer, length_delimited())
+}
+
+/// The first frame of the connection is the id of the forwarder.
+fn encode_hello(client_id: &str) -> Bytes {
+  let mut body = BytesMut::new();
+  put_string(&mut body, client_id);
+  body.freeze()
+}
+
+fn decode_hello(mut frame: Bytes) -> Option<String> {
+  get_string(&mut frame)
+}
+
+/// The request is the `u64` id, the event, the `u8` codec, the correlation id, the
+/// idempotency key, the `u64` timeout in milliseconds, `0` for none, then the payload. The
+/// empty strings are none.
+fn encode_request(id: u64, request: &AFPluginRequest) -> Bytes {
+  let mut body = BytesMut::new();
+  body.put_u64(id);
+  put_string(&mut body, request.event.as_str());
+  body.put_u8(match request.codec {
+    PayloadCodec::Protobuf => 0,
+    PayloadCodec::Json => 1,
+  });
+  put_string(
+    &mut body,
+    request.correlation_id.as_deref().unwrap_or_default(),
+  );
+  put_string(
+    &mut body,
+    request.idempotency_key.as_deref().unwrap_or_default(),
+  );
+  body.put_u64(
+    request
+      .timeout
+      .map_or(0, |timeout| timeout.as_millis() as u64),
+  );
+  if let Payload::Bytes(bytes) = &request.payload {
+    body.put_slice(bytes);
+  }
+  body.freeze()
+}
+
+fn decode_request(mut frame: Bytes) -> Option<(u64, AFPluginRequest)> {
+  if frame.remaining() < 8 {
+    return None;
+  }
+  let id = frame.get_u64();
+  let event = get_string(&mut frame)?;
+  if frame.remaining() < 1 {
+    return None;
+  }
+  let codec = match frame.get_u8() {
+    0 => PayloadCodec::Protobuf,
+    1 => PayloadCodec::Json,
+    _ => return None,
+  };
+  let correlation_id = get_string(&mut frame)?;
+  let idempotency_key = get_string(&mut frame)?;
+  if frame.remaining() < 8 {
+    return None;
+  }
+  let timeout = frame.get_u64();
+
+  let mut request = AFPluginRequest::untyped(event).codec(codec);
+  request.correlation_id = Some(correlation_id).filter(|id| !id.is_empty());
+  request.idempotency_key = Some(idempotency_key).filter(|key| !key.is_empty());
+  if timeout > 0 {
+    request.timeout = Some(std::time::Duration::from_millis(timeout));
+  }
+  if !frame.is_empty() {
+    request.payload = Payload::Bytes(frame);
+  }
+  Some((id, request))
+}
+
+/// The response is the `u64` id of the request, the `u8` [StatusCode], then the payload.
+fn encode_response(id: u64, response: &AFPluginEventResponse) -> Bytes {
+  let mut body = BytesMut::new();
+  body.put_u64(id);
+  body.put_u8(response.status_code as u8);
+  if let Payload::Bytes(bytes) = &response.payload {
+    body.put_slice(bytes);
+  }
+  body.freeze()
+}
+
+fn decode_response(mut frame: Bytes) -> Option<(u64, AFPluginEventResponse)> {
+  if frame.remaining() < 9 {
+    return None;
+  }
+  let id = frame.get_u64();
+  let status_code = status_from_u8(frame.get_u8());
+  let payload = if frame.is_empty() {
+    Payload::None
+  } else {
+    Payload::Bytes(frame)
+  };
+  let mut response = AFPluginEventResponse::new(status_code);
+  response.payload = payload;
+  Some((id, response))
+}
+
+fn status_from_u8(status: u8) -> StatusCode {
+  match status {
+    0 => StatusCode::Ok,
+    1 => StatusCode::Err,
+    2 => StatusCode::InvalidParams,
+    3 => StatusCode::NotFound,
+    4 => StatusCode::Unauthorized,
+    5 => StatusCode::Timeout,
+    7 => StatusCode::Cancelled,
+    8 => StatusCode::Busy,
+    _ => StatusCode::Internal,
+  }
+}
+
+fn put_string(body: &mut BytesMut, value: &str) {
+  body.put_u32(value.len() as u32);
+  body.put_slice(value.as_bytes());
+}
+
+fn get_string(frame: &mut Bytes) -> Option<String> {
+  if frame.remaining() < 4 {
+    return None;
+  }
+  let len = frame.get_u32() as usize;
+  if frame.remaining() < len {
+    return None;
+  }
+  String::from_utf8(frame.split_to(len).to_vec()).ok()
+}
@@ -0,0 +1,127 @@
+//! The entry point of the fuzz targets in `fuzz/`. The arbitrary bytes are sent the way a host
+//! sends them through the C ABI: the request frame is decoded, the payload is parsed with the
+//! codec of the frame, and the request is routed to the plugins, the built-in events or the
+//! not found response. Any panic is a bug.
+//!
+//! ```sh
+//! cargo +nightly fuzz run dispatch -- -dict=fuzz/dispatch.dict
+//! ```
+use std::fmt;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use parking_lot::{const_mutex, Mutex};
+
+use crate::codec::{AFPluginDecode, AFPluginEncode, AFPluginParsed, PayloadCodec};
+use crate::dispatcher::AFPluginDispatcher;
+use crate::ffi::{decode_request, encode_response};
+use crate::module::{AFPlugin, AFPluginEventType};
+use crate::request::Payload;
+use crate::runtime::AFPluginRuntime;
+
+/// The events of the fuzz plugin, sent over the wire as `fuzz.string`, `fuzz.bytes` and
+/// `fuzz.parsed`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FuzzEvent {
+  /// Echoes the payload as a lossy UTF-8 string.
+  String,
+  /// Echoes the payload as it is.
+  Bytes,
+  /// Parses the payload with the codec of the request and encodes it back.
+  Parsed,
+}
+
+impl fmt::Display for FuzzEvent {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      FuzzEvent::String => "fuzz.string",
+      FuzzEvent::Bytes => "fuzz.bytes",
+      FuzzEvent::Parsed => "fuzz.parsed",
+    })
+  }
+}
+
+impl AFPluginEventType for FuzzEvent {}
+
+/// A message of any shape, so the fuzzer reaches the nested fields of both codecs.
+type FuzzMessage = protobuf::well_known_types::Struct;
+
+struct Fuzzer {
+  runtime: Arc<AFPluginRuntime>,
+  dispatcher: Arc<AFPluginDispatcher>,
+}
+
+/// Created by the first input and reused by the next ones, so the inputs also run against the
+/// state that the previous ones leave behind, e.g. the subscriptions.
+static FUZZER: Mutex<Option<Arc<Fuzzer>>> = const_mutex(None);
+
+/// Sends the `data` as a request frame of the C ABI and waits for its response.
+///
+/// # Panics
+///
+/// Panics if the dispatcher of the fuzzer can't be created, or if the dispatcher panics on the
+/// `data`.
+pub fn fuzz_dispatch(data: &[u8]) {
+  let request = match decode_request(data.as_ptr(), data.len()) {
+    None => return,
+    Some(request) => request,
+  };
+  if let Payload::Bytes(bytes) = &request.payload {
+    fuzz_codec(request.codec, bytes.clone());
+  }
+
+  let fuzzer = fuzzer();
+  let response = fuzzer.runtime.block_on(AFPluginDispatcher::async_send(
+    fuzzer.dispatcher.as_ref(),
+    request,
+  ));
+  let _ = encode_response(&response);
+}
+
+/// Decodes the payload with the codec like the handlers do, and encodes the decoded message
+/// back like the responders do.
+fn fuzz_codec(codec: PayloadCodec, bytes: Bytes) {
+  if let Ok(message) = FuzzMessage::decode_with(codec, bytes) {
+    let _ = message.encode_with(codec);
+  }
+}
+
+fn fuzzer() -> Arc<Fuzzer> {
+  FUZZER
+    .lock()
+    .get_or_insert_with(|| {
+      let runtime = Arc::new(AFPluginRuntime::new().expect("create the runtime of the fuzzer"));
+      let dispatcher = Arc::new(AFPluginDispatcher::new(
+        runtime.clone(),
+        vec![fuzz_plugin()],
+      ));
+      if let Err(e) = runtime.block_on(dispatcher.start()) {
+        panic!("Start the plugins of the fuzzer failed: {}", e);
+      }
+      Arc::new(Fuzzer {
+        runtime,
+        dispatcher,
+      })
+    })
+    .clone()
+}
+
+fn fuzz_plugin() -> AFPlugin {
+  AFPlugin::new()
+    .name("fuzz")
+    .event(FuzzEvent::String, echo_string)
+    .event(FuzzEvent::Bytes, echo_bytes)
+    .event(FuzzEvent::Parsed, echo_parsed)
+}
+
+async fn echo_string(data: String) -> String {
+  data
+}
+
+async fn echo_bytes(data: Bytes) -> Bytes {
+  data
+}
+
+async fn echo_parsed(data: AFPluginParsed<FuzzMessage>) -> AFPluginParsed<FuzzMessage> {
+  data
+}
@@ -0,0 +1,140 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use parking_lot::Mutex;
+
+use crate::module::AFPluginEvent;
+
+tokio::task_local! {
+  /// The event whose request is being handled on the task.
+  static CURRENT_EVENT: AFPluginEvent;
+}
+
+/// Runs the `fut` as the handling of the `event`, so the requests it dispatches are recorded as
+/// the nested events of it.
+pub(crate) async fn scope<F: std::future::Future>(event: AFPluginEvent, fut: F) -> F::Output {
+  CURRENT_EVENT.scope(event, fut).await
+}
+
+/// The event whose request is being handled on the current task.
+pub(crate) fn current_event() -> Option<AFPluginEvent> {
+  CURRENT_EVENT.try_with(|event| event.clone()).ok()
+}
+
+/// The handler of the `from` event dispatched the `to` event `count` times.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EventEdge {
+  pub from: AFPluginEvent,
+  pub to: AFPluginEvent,
+  pub count: u64,
+}
+
+/// The nested dispatches that are observed since the dispatcher was created. See
+/// [AFPluginDispatcher::event_graph].
+///
+/// Only the events that are dispatched on the task of the handler are recorded, the ones
+/// dispatched from the spawned tasks or the other runtimes, e.g. the [AFPlugin::send_event]
+/// handlers, are not.
+///
+/// [AFPluginDispatcher::event_graph]: crate::prelude::AFPluginDispatcher::event_graph
+/// [AFPlugin::send_event]: crate::prelude::AFPlugin::send_event
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EventGraph {
+  /// Sorted by the events.
+  pub edges: Vec<EventEdge>,
+}
+
+impl EventGraph {
+  /// Renders the graph in the DOT format of graphviz, the edges are labeled with the counts.
+  pub fn to_dot(&self) -> String {
+    let mut dot = String::from("digraph dispatch {\n");
+    for edge in self.edges.iter() {
+      let _ = writeln!(
+        dot,
+        "  \"{}\" -> \"{}\" [label=\"{}\"];",
+        escape(edge.from.as_str()),
+        escape(edge.to.as_str()),
+        edge.count
+      );
+    }
+    dot.push('}');
+    dot
+  }
+
+  /// Returns a cycle of the dispatch chains if there is one, e.g. `[A, B, A]` if the handler of
+  /// `A` dispatches `B` and the handler of `B` dispatches `A`.
+  pub fn find_cycle(&self) -> Option<Vec<AFPluginEvent>> {
+    let mut successors: HashMap<&AFPluginEvent, Vec<&AFPluginEvent>> = HashMap::new();
+    for edge in self.edges.iter() {
+      successors.entry(&edge.from).or_default().push(&edge.to);
+    }
+
+    let mut visited = HashSet::new();
+    for edge in self.edges.iter() {
+      let mut path = vec![];
+      if let Some(cycle) = visit(&edge.from, &successors, &mut visited, &mut path) {
+        return Some(cycle);
+      }
+    }
+    None
+  }
+}
+
+/// The depth-first search that returns the cycle once it reaches an event on the `path`.
+fn visit<'a>(
+  event: &'a AFPluginEvent,
+  successors: &HashMap<&'a AFPluginEvent, Vec<&'a AFPluginEvent>>,
+  visited: &mut HashSet<&'a AFPluginEvent>,
+  path: &mut Vec<&'a AFPluginEvent>,
+) -> Option<Vec<AFPluginEvent>> {
+  if let Some(start) = path.iter().position(|e| *e == event) {
+    let mut cycle = path[start..]
+      .iter()
+      .map(|e| (*e).clone())
+      .collect::<Vec<_>>();
+    cycle.push(event.clone());
+    return Some(cycle);
+  }
+  if !visited.insert(event) {
+    return None;
+  }
+
+  path.push(event);
+  for next in successors.get(event).into_iter().flatten() {
+    if let Some(cycle) = visit(next, successors, visited, path) {
+      return Some(cycle);
+    }
+  }
+  path.pop();
+  None
+}
+
+fn escape(value: &str) -> String {
+  value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[derive(Default)]
+pub(crate) struct DispatchEventGraph {
+  edges: Mutex<HashMap<(AFPluginEvent, AFPluginEvent), u64>>,
+}
+
+impl DispatchEventGraph {
+  pub(crate) fn record(&self, from: AFPluginEvent, to: AFPluginEvent) {
+    *self.edges.lock().entry((from, to)).or_default() += 1;
+  }
+
+  pub(crate) fn snapshot(&self) -> EventGraph {
+    let mut edges = self
+      .edges
+      .lock()
+      .iter()
+      .map(|((from, to), count)| EventEdge {
+        from: from.clone(),
+        to: to.clone(),
+        count: *count,
+      })
+      .collect::<Vec<_>>();
+    edges.sort_by(|a, b| (a.from.as_str(), a.to.as_str()).cmp(&(b.from.as_str(), b.to.as_str())));
+    EventGraph { edges }
+  }
+}
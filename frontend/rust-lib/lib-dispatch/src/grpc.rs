@@ -0,0 +1,291 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures::Stream;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{empty_body, http, Body, BoxFuture, StdError};
+use tonic::server::{Grpc, NamedService, ServerStreamingService, UnaryService};
+use tonic::Status;
+
+use crate::codec::PayloadCodec;
+use crate::dispatcher::AFPluginDispatcher;
+use crate::module::AFPluginRequest;
+use crate::notification::{
+  register_notification_sink, unregister_notification_sink, DispatchNotification, NotificationSink,
+  NotificationSinkId,
+};
+use crate::request::Payload;
+use crate::response::StatusCode;
+use crate::subscription::{
+  connect_subscriber, disconnect_subscriber, SubscriberId, SUBSCRIBE_EVENT,
+};
+
+const SERVICE_NAME: &str = "lib_dispatch.Dispatch";
+const SEND_PATH: &str = "/lib_dispatch.Dispatch/Send";
+const SUBSCRIBE_PATH: &str = "/lib_dispatch.Dispatch/Subscribe";
+
+/// The request of the `Send` RPC, see `proto/lib_dispatch.proto`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GrpcEventRequest {
+  #[prost(string, tag = "1")]
+  pub event: String,
+  #[prost(bytes = "bytes", tag = "2")]
+  pub payload: Bytes,
+  /// Sends the payload with the json codec instead of the protobuf one.
+  #[prost(bool, tag = "3")]
+  pub json: bool,
+  #[prost(string, tag = "4")]
+  pub correlation_id: String,
+  #[prost(string, tag = "5")]
+  pub idempotency_key: String,
+  #[prost(string, tag = "6")]
+  pub ordering_key: String,
+}
+
+/// The response of the `Send` RPC. The `status` is the [StatusCode] of the dispatcher, the RPC
+/// itself succeeds unless the request can't be decoded.
+///
+/// [StatusCode]: crate::prelude::StatusCode
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GrpcEventResponse {
+  #[prost(uint32, tag = "1")]
+  pub status: u32,
+  #[prost(bytes = "bytes", tag = "2")]
+  pub payload: Bytes,
+  #[prost(string, tag = "3")]
+  pub correlation_id: String,
+}
+
+/// The request of the `Subscribe` RPC. The stream receives all the notifications if the `keys`
+/// are empty, otherwise only the published changes of the keys.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GrpcSubscribeRequest {
+  #[prost(string, repeated, tag = "1")]
+  pub keys: Vec<String>,
+}
+
+/// The item of the `Subscribe` stream, the same as the [DispatchNotification].
+///
+/// [DispatchNotification]: crate::prelude::DispatchNotification
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GrpcNotification {
+  #[prost(string, tag = "1")]
+  pub source: String,
+  #[prost(int32, tag = "2")]
+  pub ty: i32,
+  #[prost(string, tag = "3")]
+  pub id: String,
+  #[prost(bytes = "bytes", tag = "4")]
+  pub payload: Bytes,
+}
+
+/// Serves the events of the dispatcher as the `lib_dispatch.Dispatch` gRPC service, so a
+/// self-hosted server can reuse the handlers of the plugins. The service is defined in
+/// `proto/lib_dispatch.proto`:
+///
+/// - The unary `Send` sends the request through the same interceptors, guards and error mapping
+///   as the FFI ones.
+/// - The server-streaming `Subscribe` streams the notifications until the client cancels it.
+///
+/// ```ignore
+/// tonic::transport::Server::builder()
+///   .add_service(DispatchGrpcService::new(dispatcher))
+///   .serve("127.0.0.1:50051".parse()?)
+///   .await?;
+/// ```
+#[derive(Clone)]
+pub struct DispatchGrpcService {
+  dispatcher: Arc<AFPluginDispatcher>,
+}
+
+impl DispatchGrpcService {
+  pub fn new(dispatcher: Arc<AFPluginDispatcher>) -> Self {
+    Self { dispatcher }
+  }
+
+  async fn send(&self, request: GrpcEventRequest) -> GrpcEventResponse {
+    let codec = if request.json {
+      PayloadCodec::Json
+    } else {
+      PayloadCodec::Protobuf
+    };
+    let mut event_request = AFPluginRequest::untyped(request.event).codec(codec);
+    if !request.payload.is_empty() {
+      event_request.payload = Payload::Bytes(request.payload);
+    }
+    event_request.correlation_id = non_empty(request.correlation_id);
+    event_request.idempotency_key = non_empty(request.idempotency_key);
+    event_request.ordering_key = non_empty(request.ordering_key);
+
+    let response = AFPluginDispatcher::async_send(self.dispatcher.as_ref(), event_request).await;
+    GrpcEventResponse {
+      status: response.status_code as u32,
+      payload: match response.payload {
+        Payload::None => Bytes::new(),
+        Payload::Bytes(bytes) => bytes,
+      },
+      correlation_id: response.correlation_id.unwrap_or_default(),
+    }
+  }
+
+  async fn subscribe(&self, request: GrpcSubscribeRequest) -> Result<NotificationStream, Status> {
+    let (notifications, receiver) = mpsc::unbounded_channel();
+    let sink = GrpcSink { notifications };
+    if request.keys.is_empty() {
+      let sink_id = register_notification_sink(sink);
+      return Ok(NotificationStream {
+        receiver,
+        registration: Registration::Sink(sink_id),
+      });
+    }
+
+    // Subscribes with the built-in event, so the plugin that takes it over sees the keys too.
+    let subscriber = connect_subscriber(sink);
+    let stream = NotificationStream {
+      receiver,
+      registration: Registration::Subscriber(subscriber),
+    };
+    for key in request.keys {
+      let mut subscribe = AFPluginRequest::untyped(SUBSCRIBE_EVENT).payload(key.into_bytes());
+      subscribe.context = subscribe.context.extension(subscriber);
+      let response = AFPluginDispatcher::async_send(self.dispatcher.as_ref(), subscribe).await;
+      if response.status_code != StatusCode::Ok {
+        return Err(Status::internal(format!(
+          "Subscribe failed: {:?}",
+          response.status_code
+        )));
+      }
+    }
+    Ok(stream)
+  }
+}
+
+impl<B> tonic::codegen::Service<http::Request<B>> for DispatchGrpcService
+where
+  B: Body + Send + 'static,
+  B::Error: Into<StdError> + Send + 'static,
+{
+  type Response = http::Response<BoxBody>;
+  type Error = std::convert::Infallible;
+  type Future = BoxFuture<Self::Response, Self::Error>;
+
+  fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn call(&mut self, request: http::Request<B>) -> Self::Future {
+    let service = self.clone();
+    match request.uri().path() {
+      SEND_PATH => Box::pin(async move {
+        let mut grpc = Grpc::new(ProstCodec::default());
+        Ok(grpc.unary(SendRpc(service), request).await)
+      }),
+      SUBSCRIBE_PATH => Box::pin(async move {
+        let mut grpc = Grpc::new(ProstCodec::default());
+        Ok(grpc.server_streaming(SubscribeRpc(service), request).await)
+      }),
+      _ => Box::pin(async move {
+        // The gRPC status 12 is UNIMPLEMENTED.
+        let response = http::Response::builder()
+          .status(200)
+          .header("grpc-status", "12")
+          .header("content-type", "application/grpc")
+          .body(empty_body())
+          .expect("the response of the unknown method is valid");
+        Ok(response)
+      }),
+    }
+  }
+}
+
+impl NamedService for DispatchGrpcService {
+  const NAME: &'static str = SERVICE_NAME;
+}
+
+struct SendRpc(DispatchGrpcService);
+
+impl UnaryService<GrpcEventRequest> for SendRpc {
+  type Response = GrpcEventResponse;
+  type Future = BoxFuture<tonic::Response<Self::Response>, Status>;
+
+  fn call(&mut self, request: tonic::Request<GrpcEventRequest>) -> Self::Future {
+    let service = self.0.clone();
+    Box::pin(async move {
+      let response = service.send(request.into_inner()).await;
+      Ok(tonic::Response::new(response))
+    })
+  }
+}
+
+struct SubscribeRpc(DispatchGrpcService);
+
+impl ServerStreamingService<GrpcSubscribeRequest> for SubscribeRpc {
+  type Response = GrpcNotification;
+  type ResponseStream = NotificationStream;
+  type Future = BoxFuture<tonic::Response<Self::ResponseStream>, Status>;
+
+  fn call(&mut self, request: tonic::Request<GrpcSubscribeRequest>) -> Self::Future {
+    let service = self.0.clone();
+    Box::pin(async move {
+      let stream = service.subscribe(request.into_inner()).await?;
+      Ok(tonic::Response::new(stream))
+    })
+  }
+}
+
+/// Queues the notifications to be streamed to the client.
+struct GrpcSink {
+  notifications: UnboundedSender<GrpcNotification>,
+}
+
+impl NotificationSink for GrpcSink {
+  fn send_notification(&self, notification: &DispatchNotification) -> Result<(), String> {
+    let notification = GrpcNotification {
+      source: notification.source.clone(),
+      ty: notification.ty,
+      id: notification.id.clone(),
+      payload: notification.payload.clone(),
+    };
+    self
+      .notifications
+      .send(notification)
+      .map_err(|_| "The grpc stream is closed".to_string())
+  }
+}
+
+enum Registration {
+  Sink(NotificationSinkId),
+  Subscriber(SubscriberId),
+}
+
+/// The stream of the `Subscribe` RPC. Its sink or subscriber is dropped with it, i.e. when the
+/// client cancels the call.
+pub struct NotificationStream {
+  receiver: UnboundedReceiver<GrpcNotification>,
+  registration: Registration,
+}
+
+impl Stream for NotificationStream {
+  type Item = Result<GrpcNotification, Status>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    self.receiver.poll_recv(cx).map(|item| item.map(Ok))
+  }
+}
+
+impl Drop for NotificationStream {
+  fn drop(&mut self) {
+    match self.registration {
+      Registration::Sink(id) => unregister_notification_sink(id),
+      Registration::Subscriber(id) => disconnect_subscriber(id),
+    }
+  }
+}
+
+fn non_empty(value: String) -> Option<String> {
+  Some(value).filter(|value| !value.is_empty())
+}
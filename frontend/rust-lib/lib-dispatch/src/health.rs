@@ -0,0 +1,125 @@
+use std::fmt;
+
+use crate::module::{AFPluginEvent, DispatchRoutes};
+use crate::prelude::AFBoxFuture;
+use crate::response::{AFPluginEventResponse, ResponseBuilder};
+
+/// The built-in event that reports the health of the plugins. See [AFPluginDispatcher::health].
+///
+/// [AFPluginDispatcher::health]: crate::prelude::AFPluginDispatcher::health
+pub const HEALTH_EVENT: &str = "system.health";
+
+#[cfg(any(target_arch = "wasm32", feature = "local_set"))]
+pub type BoxHealthCheck = Box<dyn Fn() -> AFBoxFuture<'static, AFPluginHealth> + 'static>;
+
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
+pub type BoxHealthCheck =
+  Box<dyn Fn() -> AFBoxFuture<'static, AFPluginHealth> + Send + Sync + 'static>;
+
+/// Ordered from the best to the worst.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "use_serde", derive(serde::Serialize))]
+pub enum HealthStatus {
+  #[default]
+  Healthy,
+  /// The plugin works with reduced functionality, e.g. it's offline.
+  Degraded,
+  Failed,
+}
+
+/// Returned by the health check of a plugin. See [AFPlugin::health_check].
+///
+/// [AFPlugin::health_check]: crate::prelude::AFPlugin::health_check
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "use_serde", derive(serde::Serialize))]
+pub struct AFPluginHealth {
+  pub status: HealthStatus,
+  pub message: Option<String>,
+}
+
+impl AFPluginHealth {
+  pub fn healthy() -> Self {
+    Self::default()
+  }
+
+  pub fn degraded<T: Into<String>>(message: T) -> Self {
+    Self {
+      status: HealthStatus::Degraded,
+      message: Some(message.into()),
+    }
+  }
+
+  pub fn failed<T: Into<String>>(message: T) -> Self {
+    Self {
+      status: HealthStatus::Failed,
+      message: Some(message.into()),
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "use_serde", derive(serde::Serialize))]
+pub struct PluginHealthReport {
+  pub plugin: String,
+  pub health: AFPluginHealth,
+}
+
+/// The health of all the plugins that have the health check. The status is the worst of them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "use_serde", derive(serde::Serialize))]
+pub struct DispatchHealth {
+  pub status: HealthStatus,
+  pub plugins: Vec<PluginHealthReport>,
+}
+
+impl fmt::Display for DispatchHealth {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{:?}", self.status)?;
+    for report in self.plugins.iter() {
+      write!(f, "\n{}: {:?}", report.plugin, report.health.status)?;
+      if let Some(message) = &report.health.message {
+        write!(f, " {}", message)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+pub(crate) fn is_health_event(routes: &DispatchRoutes, event: &AFPluginEvent) -> bool {
+  // The plugin that registers the event takes it over.
+  *event == AFPluginEvent::untyped(HEALTH_EVENT) && !routes.plugins.contains_key(event)
+}
+
+/// Runs the health checks of the plugins concurrently.
+pub(crate) async fn check_health(routes: &DispatchRoutes) -> DispatchHealth {
+  let mut plugins = routes.unique_plugins();
+  plugins.sort_by(|a, b| a.name.cmp(&b.name));
+  let checks = plugins.iter().filter_map(|plugin| {
+    let check = plugin.health_check_future()?;
+    let name = plugin.name.clone();
+    Some(async move {
+      PluginHealthReport {
+        plugin: name,
+        health: check.await,
+      }
+    })
+  });
+
+  let plugins = futures::future::join_all(checks).await;
+  let status = plugins
+    .iter()
+    .map(|report| report.health.status)
+    .max()
+    .unwrap_or_default();
+  DispatchHealth { status, plugins }
+}
+
+/// The payload is the json of the [DispatchHealth] if the `use_serde` feature is enabled,
+/// otherwise it's the printed text.
+pub(crate) fn health_response(health: &DispatchHealth) -> AFPluginEventResponse {
+  #[cfg(feature = "use_serde")]
+  let payload = serde_json::to_vec(health).unwrap_or_default();
+  #[cfg(not(feature = "use_serde"))]
+  let payload = health.to_string();
+  ResponseBuilder::Ok().data(payload).build()
+}
@@ -0,0 +1,77 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use crate::module::{AFPluginEvent, AFPluginRequest};
+use crate::request::Payload;
+use crate::response::AFPluginEventResponse;
+
+/// A dispatched request and its response. See [AFPluginDispatcher::history].
+///
+/// [AFPluginDispatcher::history]: crate::prelude::AFPluginDispatcher::history
+#[derive(Clone, Debug)]
+pub struct DispatchRecord {
+  pub id: String,
+  pub event: AFPluginEvent,
+  pub correlation_id: Option<String>,
+  pub payload: Payload,
+  pub response: AFPluginEventResponse,
+  /// The time it takes to handle the request, excluding the time waiting in the queue.
+  pub elapsed: Duration,
+}
+
+impl fmt::Display for DispatchRecord {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{}:{:?} {:?} in {:?}",
+      self.id, self.event, self.response.status_code, self.elapsed
+    )
+  }
+}
+
+/// Keeps the last `capacity` records. The oldest one is dropped when it's full.
+pub(crate) struct DispatchHistory {
+  capacity: usize,
+  records: Mutex<VecDeque<DispatchRecord>>,
+}
+
+impl DispatchHistory {
+  pub(crate) fn new(capacity: usize) -> Self {
+    Self {
+      capacity,
+      records: Mutex::new(VecDeque::with_capacity(capacity)),
+    }
+  }
+
+  pub(crate) fn record(
+    &self,
+    request: &AFPluginRequest,
+    response: &AFPluginEventResponse,
+    elapsed: Duration,
+  ) {
+    if self.capacity == 0 {
+      return;
+    }
+
+    let record = DispatchRecord {
+      id: request.id.clone(),
+      event: request.event.clone(),
+      correlation_id: request.correlation_id.clone(),
+      payload: request.payload.clone(),
+      response: response.clone(),
+      elapsed,
+    };
+    let mut records = self.records.lock();
+    if records.len() == self.capacity {
+      records.pop_front();
+    }
+    records.push_back(record);
+  }
+
+  pub(crate) fn records(&self) -> Vec<DispatchRecord> {
+    self.records.lock().iter().cloned().collect()
+  }
+}
@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode as HttpStatus};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Router;
+
+use crate::codec::PayloadCodec;
+use crate::dispatcher::AFPluginDispatcher;
+use crate::module::AFPluginRequest;
+use crate::request::Payload;
+use crate::response::{AFPluginEventResponse, StatusCode};
+
+const CORRELATION_ID_HEADER: &str = "x-correlation-id";
+const IDEMPOTENCY_KEY_HEADER: &str = "x-idempotency-key";
+const ORDERING_KEY_HEADER: &str = "x-ordering-key";
+
+/// Builds the routes that expose the events of the dispatcher over HTTP, so the handlers can be
+/// exercised with curl or driven by a client other than Flutter:
+///
+/// - `POST /event/{name}` sends the body as the payload of the event and responds with the
+///   payload of the response. The `application/json` body is sent with the json codec, any
+///   other one with the protobuf codec.
+/// - `GET /events` lists the registered events, one per line.
+///
+/// The requests go through the same interceptors, guards and error mapping as the FFI ones.
+/// The `x-correlation-id`, `x-idempotency-key` and `x-ordering-key` headers are copied to the
+/// request, and the correlation id is returned in the header of the response.
+///
+/// ```ignore
+/// axum::Server::bind(&"127.0.0.1:8080".parse()?)
+///   .serve(http_router(dispatcher).into_make_service())
+///   .await?;
+/// ```
+pub fn http_router(dispatcher: Arc<AFPluginDispatcher>) -> Router {
+  Router::new()
+    .route("/event/:name", post(send_event))
+    .route("/events", get(list_events))
+    .with_state(dispatcher)
+}
+
+async fn send_event(
+  State(dispatcher): State<Arc<AFPluginDispatcher>>,
+  Path(name): Path<String>,
+  headers: HeaderMap,
+  body: Bytes,
+) -> Response {
+  let codec = if is_json(&headers) {
+    PayloadCodec::Json
+  } else {
+    PayloadCodec::Protobuf
+  };
+  let mut request = AFPluginRequest::untyped(name).codec(codec);
+  if !body.is_empty() {
+    request.payload = Payload::Bytes(body);
+  }
+  request.correlation_id = header_value(&headers, CORRELATION_ID_HEADER);
+  request.idempotency_key = header_value(&headers, IDEMPOTENCY_KEY_HEADER);
+  request.ordering_key = header_value(&headers, ORDERING_KEY_HEADER);
+
+  let response = AFPluginDispatcher::async_send(dispatcher.as_ref(), request).await;
+  into_http_response(response, codec)
+}
+
+async fn list_events(State(dispatcher): State<Arc<AFPluginDispatcher>>) -> String {
+  let mut events = dispatcher
+    .events()
+    .into_iter()
+    .map(|info| info.event.as_str().to_string())
+    .collect::<Vec<_>>();
+  events.sort();
+  events.join("\n")
+}
+
+fn into_http_response(response: AFPluginEventResponse, codec: PayloadCodec) -> Response {
+  let content_type = match codec {
+    PayloadCodec::Json => "application/json",
+    PayloadCodec::Protobuf => "application/octet-stream",
+  };
+  let body = match response.payload {
+    Payload::None => Bytes::new(),
+    Payload::Bytes(bytes) => bytes,
+  };
+  let mut http_response = (http_status(response.status_code), body).into_response();
+  let headers = http_response.headers_mut();
+  headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+  if let Some(value) = response
+    .correlation_id
+    .and_then(|id| HeaderValue::from_str(&id).ok())
+  {
+    headers.insert(CORRELATION_ID_HEADER, value);
+  }
+  http_response
+}
+
+fn http_status(status: StatusCode) -> HttpStatus {
+  match status {
+    StatusCode::Ok => HttpStatus::OK,
+    StatusCode::Err => HttpStatus::BAD_REQUEST,
+    StatusCode::InvalidParams => HttpStatus::UNPROCESSABLE_ENTITY,
+    StatusCode::NotFound => HttpStatus::NOT_FOUND,
+    StatusCode::Unauthorized => HttpStatus::UNAUTHORIZED,
+    StatusCode::Timeout => HttpStatus::GATEWAY_TIMEOUT,
+    StatusCode::Internal => HttpStatus::INTERNAL_SERVER_ERROR,
+    // The non-standard status of nginx for the requests that the client gives up.
+    StatusCode::Cancelled => HttpStatus::from_u16(499).unwrap_or(HttpStatus::BAD_REQUEST),
+    StatusCode::Busy => HttpStatus::SERVICE_UNAVAILABLE,
+  }
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+  headers
+    .get(header::CONTENT_TYPE)
+    .and_then(|value| value.to_str().ok())
+    .map_or(false, |value| value.starts_with("application/json"))
+}
+
+fn header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+  headers
+    .get(name)
+    .and_then(|value| value.to_str().ok())
+    .map(|value| value.to_string())
+}
@@ -0,0 +1,103 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tokio::time::Instant;
+
+use crate::errors::DispatchErrorKind;
+use crate::module::AFPluginEvent;
+use crate::response::{AFPluginEventResponse, StatusCode};
+
+pub const DEFAULT_IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(10 * 60);
+pub const DEFAULT_IDEMPOTENCY_CAPACITY: usize = 1024;
+
+type IdempotencyKey = (AFPluginEvent, String);
+
+/// Remembers the responses of the recently completed requests that carry the idempotency key.
+/// See [AFPluginRequest::idempotency_key].
+///
+/// [AFPluginRequest::idempotency_key]: crate::prelude::AFPluginRequest::idempotency_key
+pub(crate) struct DispatchIdempotency {
+  window: Duration,
+  capacity: usize,
+  state: Mutex<IdempotencyState>,
+}
+
+#[derive(Default)]
+struct IdempotencyState {
+  responses: HashMap<IdempotencyKey, (Instant, AFPluginEventResponse)>,
+  /// The keys from the oldest to the newest.
+  order: VecDeque<IdempotencyKey>,
+}
+
+impl Default for DispatchIdempotency {
+  fn default() -> Self {
+    Self::new(DEFAULT_IDEMPOTENCY_WINDOW, DEFAULT_IDEMPOTENCY_CAPACITY)
+  }
+}
+
+impl DispatchIdempotency {
+  pub(crate) fn new(window: Duration, capacity: usize) -> Self {
+    Self {
+      window,
+      capacity,
+      state: Mutex::new(IdempotencyState::default()),
+    }
+  }
+
+  pub(crate) fn get(&self, event: &AFPluginEvent, key: &str) -> Option<AFPluginEventResponse> {
+    let mut state = self.state.lock();
+    self.evict_expired(&mut state);
+    let (_, response) = state.responses.get(&(event.clone(), key.to_owned()))?;
+    tracing::debug!("[dispatch]: replay the response of {:?} {}", event, key);
+    Some(response.clone())
+  }
+
+  /// The cancelled, timed out, retryable and unavailable responses are not remembered, as the
+  /// request didn't complete and re-sending it should run the handler again.
+  pub(crate) fn complete(
+    &self,
+    event: &AFPluginEvent,
+    key: String,
+    response: &AFPluginEventResponse,
+  ) {
+    if self.capacity == 0
+      || response.is_retryable()
+      || response.error_kind() == Some(DispatchErrorKind::Unavailable)
+      || matches!(
+        response.status_code,
+        StatusCode::Cancelled | StatusCode::Timeout
+      )
+    {
+      return;
+    }
+
+    let mut state = self.state.lock();
+    let key = (event.clone(), key);
+    if state.responses.contains_key(&key) {
+      return;
+    }
+    if state.order.len() >= self.capacity {
+      if let Some(oldest) = state.order.pop_front() {
+        state.responses.remove(&oldest);
+      }
+    }
+    state.order.push_back(key.clone());
+    state
+      .responses
+      .insert(key, (Instant::now(), response.clone()));
+  }
+
+  fn evict_expired(&self, state: &mut IdempotencyState) {
+    let now = Instant::now();
+    while let Some(oldest) = state.order.front() {
+      match state.responses.get(oldest) {
+        Some((completed_at, _)) if now.duration_since(*completed_at) < self.window => break,
+        _ => {
+          let oldest = state.order.pop_front().unwrap();
+          state.responses.remove(&oldest);
+        },
+      }
+    }
+  }
+}
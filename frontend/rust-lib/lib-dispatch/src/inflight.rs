@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tokio::time::Instant;
+
+use crate::module::{AFPluginEvent, AFPluginRequest};
+
+/// A request that is being handled. See [AFPluginDispatcher::in_flight].
+///
+/// [AFPluginDispatcher::in_flight]: crate::prelude::AFPluginDispatcher::in_flight
+#[derive(Clone, Debug)]
+pub struct InFlightRequest {
+  pub id: String,
+  pub event: AFPluginEvent,
+  pub correlation_id: Option<String>,
+  pub ordering_key: Option<String>,
+  /// When the request started running, excluding the time waiting in the queue.
+  pub started_at: Instant,
+  pub elapsed: Duration,
+}
+
+struct InFlightEntry {
+  event: AFPluginEvent,
+  correlation_id: Option<String>,
+  ordering_key: Option<String>,
+  started_at: Instant,
+}
+
+/// The requests that are being handled, keyed by the request id.
+#[derive(Default)]
+pub(crate) struct DispatchInFlight {
+  requests: Mutex<HashMap<String, InFlightEntry>>,
+}
+
+impl DispatchInFlight {
+  /// The request is removed when the returned guard is dropped.
+  pub(crate) fn enter(self: &Arc<Self>, request: &AFPluginRequest) -> InFlightGuard {
+    let entry = InFlightEntry {
+      event: request.event.clone(),
+      correlation_id: request.correlation_id.clone(),
+      ordering_key: request.ordering_key.clone(),
+      started_at: Instant::now(),
+    };
+    self.requests.lock().insert(request.id.clone(), entry);
+    InFlightGuard {
+      in_flight: self.clone(),
+      id: request.id.clone(),
+    }
+  }
+
+  /// Sorted from the longest running to the latest.
+  pub(crate) fn snapshot(&self) -> Vec<InFlightRequest> {
+    let now = Instant::now();
+    let mut requests = self
+      .requests
+      .lock()
+      .iter()
+      .map(|(id, entry)| InFlightRequest {
+        id: id.clone(),
+        event: entry.event.clone(),
+        correlation_id: entry.correlation_id.clone(),
+        ordering_key: entry.ordering_key.clone(),
+        started_at: entry.started_at,
+        elapsed: now.saturating_duration_since(entry.started_at),
+      })
+      .collect::<Vec<_>>();
+    requests.sort_by_key(|request| request.started_at);
+    requests
+  }
+}
+
+pub(crate) struct InFlightGuard {
+  in_flight: Arc<DispatchInFlight>,
+  id: String,
+}
+
+impl Drop for InFlightGuard {
+  fn drop(&mut self) {
+    self.in_flight.requests.lock().remove(&self.id);
+  }
+}
@@ -0,0 +1,22 @@
+use crate::module::AFPluginRequest;
+use crate::prelude::AFConcurrent;
+use crate::response::AFPluginEventResponse;
+
+/// Inspects the requests before they are routed to the plugins, e.g. to route a share of the
+/// requests to the cloud-backed handler for the A/B testing.
+///
+/// The interceptor can rewrite the request, including its event to redirect it to another
+/// plugin, or return the response that resolves the request without calling any handler. The
+/// interceptors are called in the order they're added until one of them returns a response.
+pub trait DispatchInterceptor: AFConcurrent {
+  fn intercept(&self, request: &mut AFPluginRequest) -> Option<AFPluginEventResponse>;
+}
+
+impl<F> DispatchInterceptor for F
+where
+  F: Fn(&mut AFPluginRequest) -> Option<AFPluginEventResponse> + AFConcurrent,
+{
+  fn intercept(&self, request: &mut AFPluginRequest) -> Option<AFPluginEventResponse> {
+    (self)(request)
+  }
+}
@@ -0,0 +1,259 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::{File, OpenOptions};
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+
+#[cfg(not(target_arch = "wasm32"))]
+use bytes::Bytes;
+#[cfg(not(target_arch = "wasm32"))]
+use parking_lot::Mutex;
+
+use crate::codec::PayloadCodec;
+use crate::module::{AFPluginEvent, AFPluginRequest};
+use crate::prelude::AFConcurrent;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::replay::{codec_from_name, codec_name, decode_hex, encode_hex, escape, unescape};
+use crate::request::Payload;
+
+/// A request of a mutating event that is kept in the [DispatchJournal] until it's completed.
+/// See [AFPlugin::mutating].
+///
+/// [AFPlugin::mutating]: crate::prelude::AFPlugin::mutating
+#[derive(Clone, Debug)]
+pub struct JournalEntry {
+  pub request_id: String,
+  pub event: AFPluginEvent,
+  pub codec: PayloadCodec,
+  pub payload: Payload,
+  pub payload_version: Option<u32>,
+  pub ordering_key: Option<String>,
+  pub correlation_id: Option<String>,
+  pub idempotency_key: Option<String>,
+}
+
+impl JournalEntry {
+  pub(crate) fn new(request: &AFPluginRequest) -> Self {
+    Self {
+      request_id: request.id.clone(),
+      event: request.event.clone(),
+      codec: request.codec,
+      payload: request.payload.clone(),
+      payload_version: request.payload_version,
+      ordering_key: request.ordering_key.clone(),
+      correlation_id: request.correlation_id.clone(),
+      idempotency_key: request.idempotency_key.clone(),
+    }
+  }
+
+  /// Builds the request to dispatch again. It keeps the id of the journaled request, so the
+  /// journal completes the same entry.
+  pub fn to_request(&self) -> AFPluginRequest {
+    let mut request = AFPluginRequest::new(self.event.clone())
+      .payload(self.payload.clone())
+      .codec(self.codec);
+    request.id = self.request_id.clone();
+    request.payload_version = self.payload_version;
+    request.ordering_key = self.ordering_key.clone();
+    request.correlation_id = self.correlation_id.clone();
+    request.idempotency_key = self.idempotency_key.clone();
+    request
+  }
+}
+
+/// Persists the requests of the mutating events from the time they are queued until they are
+/// completed, so the requests that are lost with the app, e.g. by a crash, are dispatched again
+/// on the next launch. See [AFPluginDispatcherBuilder::journal].
+///
+/// The journal is implemented for the storage of the app, e.g. a table of sqlite. The
+/// [FileJournal] is the one without dependencies.
+///
+/// [AFPluginDispatcherBuilder::journal]: crate::prelude::AFPluginDispatcherBuilder::journal
+pub trait DispatchJournal: AFConcurrent {
+  /// Called before the request waits for the capacity of the dispatcher or is queued. The entry
+  /// whose request id is already pending is ignored, e.g. it's the request that is dispatched
+  /// again, or the one that is queued after it waited for the capacity.
+  fn append(&self, entry: &JournalEntry);
+
+  /// Called once the request is completed, including the failed, timed out and cancelled ones.
+  /// The requests that are rejected by the shutdown are not completed, they are dispatched again
+  /// on the next launch.
+  fn complete(&self, request_id: &str);
+
+  /// The entries that are not completed, in the order they are appended.
+  fn pending(&self) -> Vec<JournalEntry>;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const JOURNAL_HEADER: &str = "# dispatch journal v1";
+
+/// Appends a line per appended and per completed request to the file. The pending entries are
+/// kept in memory, and the file is compacted to them when it's opened.
+///
+/// The appended line is the tab-separated `+`, request id, event, codec, payload version,
+/// ordering key, correlation id, idempotency key and the hex of the payload. The completed
+/// line is `-` and the request id. The last line that is cut off by a crash is skipped.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct FileJournal {
+  inner: Mutex<FileJournalInner>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct FileJournalInner {
+  file: File,
+  pending: Vec<JournalEntry>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileJournal {
+  /// Creates the file if it doesn't exist, otherwise loads its pending entries.
+  pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    let path = path.as_ref();
+    let pending = if path.exists() { load(path)? } else { vec![] };
+
+    // Rewrites the pending entries only, so the completed ones don't pile up.
+    let mut compacted = path.as_os_str().to_owned();
+    compacted.push(".tmp");
+    let compacted = PathBuf::from(compacted);
+    {
+      let mut file = File::create(&compacted)?;
+      writeln!(file, "{}", JOURNAL_HEADER)?;
+      for entry in pending.iter() {
+        writeln!(file, "{}", appended_line(entry))?;
+      }
+      file.sync_all()?;
+    }
+    std::fs::rename(&compacted, path)?;
+
+    let file = OpenOptions::new().append(true).open(path)?;
+    Ok(Self {
+      inner: Mutex::new(FileJournalInner { file, pending }),
+    })
+  }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DispatchJournal for FileJournal {
+  fn append(&self, entry: &JournalEntry) {
+    let mut inner = self.inner.lock();
+    if inner
+      .pending
+      .iter()
+      .any(|pending| pending.request_id == entry.request_id)
+    {
+      return;
+    }
+    if let Err(e) = writeln!(inner.file, "{}", appended_line(entry)) {
+      tracing::error!(
+        "[dispatch]: journal the request of {:?} failed: {}",
+        entry.event,
+        e
+      );
+    }
+    inner.pending.push(entry.clone());
+  }
+
+  fn complete(&self, request_id: &str) {
+    let mut inner = self.inner.lock();
+    let position = match inner
+      .pending
+      .iter()
+      .position(|pending| pending.request_id == request_id)
+    {
+      None => return,
+      Some(position) => position,
+    };
+    inner.pending.remove(position);
+    if let Err(e) = writeln!(inner.file, "-\t{}", escape(request_id)) {
+      tracing::error!(
+        "[dispatch]: complete the journaled request {} failed: {}",
+        request_id,
+        e
+      );
+    }
+  }
+
+  fn pending(&self) -> Vec<JournalEntry> {
+    self.inner.lock().pending.clone()
+  }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn load(path: &Path) -> io::Result<Vec<JournalEntry>> {
+  let reader = BufReader::new(File::open(path)?);
+  let mut pending: Vec<JournalEntry> = vec![];
+  for (index, line) in reader.lines().enumerate() {
+    let line = line?;
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    match line.split_once('\t') {
+      Some(("+", fields)) => match parse_entry(fields) {
+        Some(entry) => pending.push(entry),
+        None => tracing::warn!(
+          "[dispatch]: skip the malformed journal entry at line {}",
+          index + 1
+        ),
+      },
+      Some(("-", request_id)) => {
+        let request_id = unescape(request_id);
+        pending.retain(|entry| entry.request_id != request_id);
+      },
+      _ => tracing::warn!("[dispatch]: skip the malformed journal line {}", index + 1),
+    }
+  }
+  Ok(pending)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn appended_line(entry: &JournalEntry) -> String {
+  let payload = match &entry.payload {
+    Payload::None => "-".to_string(),
+    Payload::Bytes(bytes) => encode_hex(bytes),
+  };
+  let payload_version = entry
+    .payload_version
+    .map_or_else(|| "-".to_string(), |version| version.to_string());
+  format!(
+    "+\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+    escape(&entry.request_id),
+    escape(entry.event.as_str()),
+    codec_name(entry.codec),
+    payload_version,
+    escape(entry.ordering_key.as_deref().unwrap_or_default()),
+    escape(entry.correlation_id.as_deref().unwrap_or_default()),
+    escape(entry.idempotency_key.as_deref().unwrap_or_default()),
+    payload
+  )
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_entry(fields: &str) -> Option<JournalEntry> {
+  let mut fields = fields.split('\t');
+  let request_id = unescape(fields.next()?);
+  let event = unescape(fields.next()?);
+  let codec = codec_from_name(fields.next()?)?;
+  let payload_version = match fields.next()? {
+    "-" => None,
+    version => Some(version.parse().ok()?),
+  };
+  let ordering_key = unescape(fields.next()?);
+  let correlation_id = unescape(fields.next()?);
+  let idempotency_key = unescape(fields.next()?);
+  let payload = match fields.next()? {
+    "-" => Payload::None,
+    hex => Payload::Bytes(Bytes::from(decode_hex(hex)?)),
+  };
+
+  Some(JournalEntry {
+    request_id,
+    event: AFPluginEvent::untyped(event),
+    codec,
+    payload,
+    payload_version,
+    ordering_key: Some(ordering_key).filter(|key| !key.is_empty()),
+    correlation_id: Some(correlation_id).filter(|id| !id.is_empty()),
+    idempotency_key: Some(idempotency_key).filter(|key| !key.is_empty()),
+  })
+}
@@ -5,9 +5,77 @@ mod response;
 mod service;
 pub mod util;
 
+mod audit;
+mod builder;
 mod byte_trait;
+mod cache;
+mod coalesce;
+mod codec;
+mod compression;
+mod config;
 mod data;
+mod dead_letter;
 mod dispatcher;
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
+mod executor;
+mod fault;
+#[cfg(all(
+  feature = "c_abi",
+  not(target_arch = "wasm32"),
+  not(feature = "local_set")
+))]
+pub mod ffi;
+#[cfg(all(feature = "forward", not(target_arch = "wasm32")))]
+mod forward;
+#[cfg(all(
+  feature = "fuzz",
+  not(target_arch = "wasm32"),
+  not(feature = "local_set")
+))]
+pub mod fuzz;
+mod graph;
+#[cfg(all(
+  feature = "grpc",
+  not(target_arch = "wasm32"),
+  not(feature = "local_set")
+))]
+mod grpc;
+mod health;
+mod history;
+#[cfg(all(
+  feature = "http",
+  not(target_arch = "wasm32"),
+  not(feature = "local_set")
+))]
+mod http;
+mod idempotency;
+mod inflight;
+mod interceptor;
+mod journal;
+mod lifecycle;
+mod localize;
+mod logger;
+mod metrics;
+#[cfg(feature = "test_helper")]
+mod mock;
+mod notification;
+mod observer;
+#[cfg(feature = "prometheus")]
+mod prometheus;
+#[cfg(not(target_arch = "wasm32"))]
+mod replay;
+mod retry;
+mod scheduler;
+mod shared_buffer;
+mod slow_poll;
+mod stats;
+mod subscription;
+mod supervisor;
+#[cfg(feature = "test_helper")]
+pub mod test;
+mod watchdog;
+#[cfg(all(feature = "websocket", not(target_arch = "wasm32")))]
+mod websocket;
 
 #[macro_use]
 pub mod macros;
@@ -17,6 +85,73 @@ pub use errors::Error;
 
 pub mod prelude {
   pub use crate::{
-    byte_trait::*, data::*, dispatcher::*, errors::*, module::*, request::*, response::*,
+    audit::*,
+    builder::*,
+    byte_trait::*,
+    cache::DispatchCache,
+    codec::*,
+    compression::*,
+    data::*,
+    dead_letter::*,
+    dispatcher::*,
+    errors::*,
+    fault::{DispatchFaultInjection, FaultRule},
+    graph::{EventEdge, EventGraph},
+    health::*,
+    history::DispatchRecord,
+    idempotency::{DEFAULT_IDEMPOTENCY_CAPACITY, DEFAULT_IDEMPOTENCY_WINDOW},
+    inflight::InFlightRequest,
+    interceptor::*,
+    journal::*,
+    lifecycle::*,
+    localize::*,
+    logger::DispatchLogger,
+    metrics::*,
+    module::*,
+    notification::*,
+    observer::*,
+    request::*,
+    response::*,
+    retry::*,
+    runtime::AFPluginSpawner,
+    scheduler::{DispatchLoadShedding, DispatchPriority},
+    service::{AFPluginBoxService, AFPluginTransform, Service, ServiceRequest, ServiceResponse},
+    shared_buffer::*,
+    stats::{EventStats, STATS_EVENT},
+    subscription::*,
+    supervisor::{DispatchCrash, DispatchCrashHook, DispatchSupervisor},
+    watchdog::{DispatchWatchdog, StuckHandler, StuckHandlerObserver},
+  };
+
+  #[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
+  pub use crate::executor::AFPluginExecutor;
+
+  #[cfg(feature = "test_helper")]
+  pub use crate::mock::{MockHandler, MockRecorder, MockResponse};
+
+  #[cfg(not(target_arch = "wasm32"))]
+  pub use crate::replay::{DispatchRecorder, DispatchReplay, RecordedRequest, ReplayTiming};
+
+  #[cfg(all(feature = "websocket", not(target_arch = "wasm32")))]
+  pub use crate::websocket::serve_websocket;
+
+  #[cfg(all(feature = "forward", not(target_arch = "wasm32")))]
+  pub use crate::forward::{serve_forwarded, DispatchForwarder};
+
+  #[cfg(all(
+    feature = "http",
+    not(target_arch = "wasm32"),
+    not(feature = "local_set")
+  ))]
+  pub use crate::http::http_router;
+
+  #[cfg(all(
+    feature = "grpc",
+    not(target_arch = "wasm32"),
+    not(feature = "local_set")
+  ))]
+  pub use crate::grpc::{
+    DispatchGrpcService, GrpcEventRequest, GrpcEventResponse, GrpcNotification,
+    GrpcSubscribeRequest, NotificationStream,
   };
 }
@@ -0,0 +1,75 @@
+use tokio::sync::broadcast;
+
+use crate::errors::DispatchError;
+use crate::request::{AFPluginEventRequest, FromAFPluginRequest, Payload};
+use crate::util::ready::{ready, Ready};
+
+/// The number of the lifecycle events that a slow subscriber can lag behind.
+pub const DEFAULT_LIFECYCLE_CAPACITY: usize = 64;
+
+/// The transitions of the dispatcher, published by the [DispatchLifecycleChannel].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DispatchLifecycle {
+  /// The `on_start` hooks of the plugins are completed.
+  Started,
+  /// The name of the plugin that is registered after the dispatcher was created.
+  PluginRegistered(String),
+  PluginUnregistered(String),
+  /// There is no running or pending request.
+  QueueIdle,
+  /// The dispatcher stops accepting new requests and drains the running ones.
+  ShuttingDown,
+  Stopped,
+}
+
+/// Publishes the [DispatchLifecycle] events, so the plugins and the host react to the
+/// transitions without polling. It's returned by [AFPluginDispatcher::lifecycle] and extracted
+/// by the handlers:
+///
+/// ```ignore
+/// async fn init_handler(lifecycle: DispatchLifecycleChannel) {
+///   let mut rx = lifecycle.subscribe();
+///   ..
+/// }
+/// ```
+///
+/// The subscriber that lags more than [DEFAULT_LIFECYCLE_CAPACITY] events misses the oldest ones.
+///
+/// [AFPluginDispatcher::lifecycle]: crate::prelude::AFPluginDispatcher::lifecycle
+#[derive(Clone, Debug)]
+pub struct DispatchLifecycleChannel {
+  sender: broadcast::Sender<DispatchLifecycle>,
+}
+
+impl Default for DispatchLifecycleChannel {
+  fn default() -> Self {
+    let (sender, _) = broadcast::channel(DEFAULT_LIFECYCLE_CAPACITY);
+    Self { sender }
+  }
+}
+
+impl DispatchLifecycleChannel {
+  pub fn subscribe(&self) -> broadcast::Receiver<DispatchLifecycle> {
+    self.sender.subscribe()
+  }
+
+  pub(crate) fn publish(&self, lifecycle: DispatchLifecycle) {
+    tracing::trace!("[dispatch]: {:?}", lifecycle);
+    // It fails if there is no subscriber.
+    let _ = self.sender.send(lifecycle);
+  }
+}
+
+#[doc(hidden)]
+impl FromAFPluginRequest for DispatchLifecycleChannel {
+  type Error = DispatchError;
+  type Future = Ready<Result<Self, Self::Error>>;
+
+  fn from_request(req: &AFPluginEventRequest, _payload: &mut Payload) -> Self::Future {
+    ready(Ok(
+      req
+        .get_state::<DispatchLifecycleChannel>()
+        .unwrap_or_default(),
+    ))
+  }
+}
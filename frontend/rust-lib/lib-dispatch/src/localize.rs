@@ -0,0 +1,113 @@
+use crate::prelude::AFConcurrent;
+use crate::response::AFPluginEventResponse;
+
+/// The metadata key of the response that names the message of the error.
+pub const ERROR_MESSAGE_KEY: &str = "error-message-key";
+
+/// The prefix of the metadata keys that carry the arguments of the message, e.g.
+/// `error-message-arg.event`.
+pub const ERROR_MESSAGE_ARG_PREFIX: &str = "error-message-arg.";
+
+/// The metadata key of the response that carries the message translated by the
+/// [DispatchLocalizer].
+pub const ERROR_MESSAGE: &str = "error-message";
+
+/// The message of the error that can be translated, e.g. `dispatch.timeout` with the `event`
+/// argument. The frontend renders it with its own translations if the dispatcher has no
+/// [DispatchLocalizer], while the payload keeps the raw message of the error for the logs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AFPluginErrorMessage {
+  pub key: String,
+  pub args: Vec<(String, String)>,
+}
+
+impl AFPluginErrorMessage {
+  pub fn new<T: Into<String>>(key: T) -> Self {
+    Self {
+      key: key.into(),
+      args: vec![],
+    }
+  }
+
+  pub fn arg<K: Into<String>, V: ToString>(mut self, name: K, value: V) -> Self {
+    self.args.push((name.into(), value.to_string()));
+    self
+  }
+
+  pub fn get_arg(&self, name: &str) -> Option<&str> {
+    self
+      .args
+      .iter()
+      .find(|(arg, _)| arg == name)
+      .map(|(_, value)| value.as_str())
+  }
+
+  pub(crate) fn write_to(&self, response: &mut AFPluginEventResponse) {
+    response.insert_metadata(ERROR_MESSAGE_KEY, self.key.as_str());
+    for (name, value) in self.args.iter() {
+      response.insert_metadata(
+        format!("{}{}", ERROR_MESSAGE_ARG_PREFIX, name),
+        value.as_str(),
+      );
+    }
+  }
+}
+
+impl AFPluginEventResponse {
+  pub fn error_message(&self) -> Option<AFPluginErrorMessage> {
+    let key = self.metadata(ERROR_MESSAGE_KEY)?;
+    let mut args = self
+      .metadata
+      .iter()
+      .filter_map(|(name, value)| {
+        let name = name.strip_prefix(ERROR_MESSAGE_ARG_PREFIX)?;
+        Some((name.to_string(), value.clone()))
+      })
+      .collect::<Vec<_>>();
+    args.sort();
+    Some(AFPluginErrorMessage {
+      key: key.to_string(),
+      args,
+    })
+  }
+
+  /// Returns the message translated by the [DispatchLocalizer].
+  pub fn localized_message(&self) -> Option<&str> {
+    self.metadata(ERROR_MESSAGE)
+  }
+}
+
+/// Translates the messages of the errors into the locale of the request, see
+/// [AFPluginContext::locale]. It's registered by [AFPluginDispatcherBuilder::localizer].
+///
+/// [AFPluginContext::locale]: crate::prelude::AFPluginContext::locale
+/// [AFPluginDispatcherBuilder::localizer]: crate::prelude::AFPluginDispatcherBuilder::localizer
+pub trait DispatchLocalizer: AFConcurrent {
+  /// Returns None if the message has no translation.
+  fn localize(&self, message: &AFPluginErrorMessage, locale: Option<&str>) -> Option<String>;
+}
+
+impl<F> DispatchLocalizer for F
+where
+  F: Fn(&AFPluginErrorMessage, Option<&str>) -> Option<String> + AFConcurrent,
+{
+  fn localize(&self, message: &AFPluginErrorMessage, locale: Option<&str>) -> Option<String> {
+    (self)(message, locale)
+  }
+}
+
+pub(crate) fn localize_response(
+  localizer: &dyn DispatchLocalizer,
+  response: &mut AFPluginEventResponse,
+  locale: Option<&str>,
+) {
+  if response.status_code.is_ok() {
+    return;
+  }
+  let localized = response
+    .error_message()
+    .and_then(|message| localizer.localize(&message, locale));
+  if let Some(localized) = localized {
+    response.insert_metadata(ERROR_MESSAGE, localized);
+  }
+}
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tracing::Level;
+
+use crate::module::AFPluginEvent;
+use crate::response::AFPluginEventResponse;
+
+/// Logs the start and the finish of every request, with its duration and status, at the level
+/// of its plugin or event. See [AFPluginDispatcherBuilder::log_requests].
+///
+/// ```ignore
+/// let logger = DispatchLogger::new(Level::DEBUG)
+///   // The cursor events are too chatty.
+///   .plugin("Cursor", None)
+///   .plugin("Sync", Some(Level::INFO))
+///   .event(SyncEvent::Ping, Some(Level::TRACE));
+/// ```
+///
+/// [AFPluginDispatcherBuilder::log_requests]: crate::prelude::AFPluginDispatcherBuilder::log_requests
+#[derive(Clone, Debug)]
+pub struct DispatchLogger {
+  level: Option<Level>,
+  plugins: HashMap<String, Option<Level>>,
+  events: HashMap<AFPluginEvent, Option<Level>>,
+}
+
+impl DispatchLogger {
+  /// Logs the requests at the `level` unless their plugins or events set their own.
+  pub fn new(level: Level) -> Self {
+    Self {
+      level: Some(level),
+      plugins: HashMap::new(),
+      events: HashMap::new(),
+    }
+  }
+
+  /// Logs only the plugins and the events that set their levels.
+  pub fn silent() -> Self {
+    Self {
+      level: None,
+      plugins: HashMap::new(),
+      events: HashMap::new(),
+    }
+  }
+
+  /// Logs the requests of the plugin at the `level`. `None` silences them.
+  pub fn plugin<T: Into<String>>(mut self, plugin: T, level: Option<Level>) -> Self {
+    self.plugins.insert(plugin.into(), level);
+    self
+  }
+
+  /// Logs the requests of the event at the `level`, it overrides the level of the plugin. `None`
+  /// silences them.
+  pub fn event<E: Into<AFPluginEvent>>(mut self, event: E, level: Option<Level>) -> Self {
+    self.events.insert(event.into(), level);
+    self
+  }
+
+  pub(crate) fn level_of(&self, plugin: &str, event: &AFPluginEvent) -> Option<Level> {
+    match self.events.get(event) {
+      Some(level) => *level,
+      None => self.plugins.get(plugin).copied().unwrap_or(self.level),
+    }
+  }
+}
+
+macro_rules! log_at {
+  ($level:expr, $($arg:tt)+) => {
+    match $level {
+      Level::ERROR => tracing::error!($($arg)+),
+      Level::WARN => tracing::warn!($($arg)+),
+      Level::INFO => tracing::info!($($arg)+),
+      Level::DEBUG => tracing::debug!($($arg)+),
+      _ => tracing::trace!($($arg)+),
+    }
+  };
+}
+
+pub(crate) fn log_start(level: Level, plugin: &str, event: &AFPluginEvent, request_id: &str) {
+  log_at!(
+    level,
+    plugin,
+    event = event.as_str(),
+    request_id,
+    "[dispatch]: start {}",
+    event.as_str()
+  );
+}
+
+pub(crate) fn log_finish(
+  level: Level,
+  plugin: &str,
+  event: &AFPluginEvent,
+  request_id: &str,
+  response: &AFPluginEventResponse,
+  elapsed: Duration,
+) {
+  log_at!(
+    level,
+    plugin,
+    event = event.as_str(),
+    request_id,
+    status = ?response.status_code,
+    elapsed_ms = elapsed.as_millis() as u64,
+    "[dispatch]: finish {} with {:?} in {:?}",
+    event.as_str(),
+    response.status_code,
+    elapsed
+  );
+}
@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use crate::prelude::AFConcurrent;
+
+/// A snapshot of the dispatcher's queue. See [AFPluginDispatcher::metrics].
+///
+/// [AFPluginDispatcher::metrics]: crate::prelude::AFPluginDispatcher::metrics
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DispatchMetrics {
+  /// The number of requests that wait to be started, including the ones that wait for the
+  /// capacity of the dispatcher.
+  pub queued: usize,
+  /// The number of requests that are being handled.
+  pub running: usize,
+  /// The time between sending and starting the last started request, including its wait for the
+  /// capacity.
+  pub last_queue_latency: Duration,
+  /// The maximum send-to-start time since the dispatcher was created.
+  pub max_queue_latency: Duration,
+  /// The number of requests that are rejected by the load shedding since the dispatcher was
+  /// created. See [DispatchLoadShedding].
+  ///
+  /// [DispatchLoadShedding]: crate::prelude::DispatchLoadShedding
+  pub shed: u64,
+}
+
+/// Reported when the number of queued requests crosses the high-water mark.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HighWater {
+  /// The queue grows above the mark, the dispatcher falls behind.
+  Exceeded { queued: usize },
+  /// The queue drains back to the mark.
+  Recovered { queued: usize },
+}
+
+pub trait HighWaterListener: AFConcurrent {
+  fn on_high_water(&self, high_water: HighWater);
+}
+
+impl<F> HighWaterListener for F
+where
+  F: Fn(HighWater) + AFConcurrent,
+{
+  fn on_high_water(&self, high_water: HighWater) {
+    (self)(high_water)
+  }
+}
+
+pub(crate) struct HighWaterMark {
+  pub(crate) mark: usize,
+  pub(crate) listener: Box<dyn HighWaterListener>,
+}
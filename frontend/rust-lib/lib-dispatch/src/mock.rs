@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use parking_lot::{Mutex, RwLock};
+
+use crate::byte_trait::ToBytes;
+use crate::module::{AFPluginEvent, AFPluginRequest};
+use crate::prelude::AFConcurrent;
+use crate::request::Payload;
+use crate::response::{AFPluginEventResponse, StatusCode};
+
+/// Replaces the handler of an event in the tests, so a plugin can be tested without the plugins
+/// it dispatches into. See [AFPluginDispatcher::mock_event].
+///
+/// [AFPluginDispatcher::mock_event]: crate::prelude::AFPluginDispatcher::mock_event
+pub trait MockHandler: AFConcurrent {
+  fn handle(&self, request: &AFPluginRequest) -> AFPluginEventResponse;
+}
+
+impl<F> MockHandler for F
+where
+  F: Fn(&AFPluginRequest) -> AFPluginEventResponse + AFConcurrent,
+{
+  fn handle(&self, request: &AFPluginRequest) -> AFPluginEventResponse {
+    (self)(request)
+  }
+}
+
+/// Responds to every request with the same response.
+#[derive(Clone)]
+pub struct MockResponse(AFPluginEventResponse);
+
+impl MockResponse {
+  pub fn new(response: AFPluginEventResponse) -> Self {
+    Self(response)
+  }
+
+  /// The successful response with the `data`.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the `data` can't be serialized.
+  pub fn ok<T: ToBytes>(data: T) -> Self {
+    Self::with_status(StatusCode::Ok, data)
+  }
+
+  /// The failed response with the `error`, e.g. a `FlowyError`.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the `error` can't be serialized.
+  pub fn err<E: ToBytes>(error: E) -> Self {
+    Self::with_status(StatusCode::Err, error)
+  }
+
+  fn with_status<T: ToBytes>(status_code: StatusCode, data: T) -> Self {
+    let bytes = match data.into_bytes() {
+      Ok(bytes) => bytes,
+      Err(e) => panic!("Serialize the mock response failed: {:?}", e),
+    };
+    let mut response = AFPluginEventResponse::new(status_code);
+    response.payload = Payload::Bytes(bytes);
+    Self(response)
+  }
+}
+
+impl MockHandler for MockResponse {
+  fn handle(&self, _request: &AFPluginRequest) -> AFPluginEventResponse {
+    self.0.clone()
+  }
+}
+
+/// Records the requests that are handled by the wrapped mock. The clones share the records, so
+/// the test keeps one to check the calls:
+///
+/// ```ignore
+/// let recorder = MockRecorder::new(MockResponse::ok(workspace));
+/// dispatcher.mock_event(UserEvent::GetWorkspace, recorder.clone());
+/// // ...
+/// assert_eq!(recorder.call_count(), 1);
+/// ```
+#[derive(Clone)]
+pub struct MockRecorder {
+  inner: Arc<dyn MockHandler>,
+  calls: Arc<Mutex<Vec<AFPluginRequest>>>,
+}
+
+impl MockRecorder {
+  pub fn new<M: MockHandler + 'static>(mock: M) -> Self {
+    Self {
+      inner: Arc::new(mock),
+      calls: Arc::new(Mutex::new(vec![])),
+    }
+  }
+
+  /// The recorded requests from the oldest to the newest.
+  pub fn calls(&self) -> Vec<AFPluginRequest> {
+    self.calls.lock().clone()
+  }
+
+  pub fn call_count(&self) -> usize {
+    self.calls.lock().len()
+  }
+
+  pub fn clear(&self) {
+    self.calls.lock().clear();
+  }
+}
+
+impl MockHandler for MockRecorder {
+  fn handle(&self, request: &AFPluginRequest) -> AFPluginEventResponse {
+    self.calls.lock().push(request.clone());
+    self.inner.handle(request)
+  }
+}
+
+/// The mocks of the dispatcher. They can be changed while the dispatcher is used.
+#[derive(Default)]
+pub(crate) struct DispatchMocks {
+  handlers: RwLock<HashMap<AFPluginEvent, Arc<dyn MockHandler>>>,
+}
+
+impl DispatchMocks {
+  pub(crate) fn insert(&self, event: AFPluginEvent, mock: Arc<dyn MockHandler>) {
+    self.handlers.write().insert(event, mock);
+  }
+
+  pub(crate) fn remove(&self, event: &AFPluginEvent) {
+    self.handlers.write().remove(event);
+  }
+
+  pub(crate) fn clear(&self) {
+    self.handlers.write().clear();
+  }
+
+  pub(crate) fn get(&self, event: &AFPluginEvent) -> Option<Arc<dyn MockHandler>> {
+    self.handlers.read().get(event).cloned()
+  }
+}
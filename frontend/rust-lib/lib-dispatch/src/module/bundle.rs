@@ -0,0 +1,70 @@
+use std::sync::Arc;
+
+use crate::dispatcher::AFConcurrent;
+use crate::module::{AFPlugin, AFPluginState};
+use crate::service::AFPluginTransform;
+
+type PluginSetup = Box<dyn Fn(AFPlugin) -> AFPlugin>;
+
+/// Groups the plugins of a feature, e.g. the grid's event handlers, notifications and background
+/// jobs, with the states and the middlewares that they share, so the feature is registered as a
+/// single unit.
+///
+/// ```ignore
+/// let bundle = AFPluginBundle::new("grid")
+///   .plugin(grid_plugin)
+///   .plugin(grid_sync_plugin)
+///   .state(database_manager)
+///   .wrap(logging);
+/// let dispatcher = AFPluginDispatcher::new(runtime, bundle.into_plugins());
+/// ```
+pub struct AFPluginBundle {
+  pub name: String,
+  plugins: Vec<AFPlugin>,
+  setups: Vec<PluginSetup>,
+}
+
+impl AFPluginBundle {
+  pub fn new(name: &str) -> Self {
+    Self {
+      name: name.to_owned(),
+      plugins: vec![],
+      setups: vec![],
+    }
+  }
+
+  pub fn plugin(mut self, plugin: AFPlugin) -> Self {
+    self.plugins.push(plugin);
+    self
+  }
+
+  /// Adds the state that is shared by all the plugins of the bundle. The plugins read the same
+  /// instance instead of their own copies.
+  pub fn state<D: AFConcurrent + 'static>(mut self, data: D) -> Self {
+    let state = AFPluginState::new(data);
+    self
+      .setups
+      .push(Box::new(move |plugin| plugin.shared_state(state.clone())));
+    self
+  }
+
+  /// Wraps the handlers of all the plugins of the bundle with the middleware. It's applied after
+  /// the plugins' own middlewares, so it's the innermost one.
+  pub fn wrap<T: AFPluginTransform>(mut self, transform: T) -> Self {
+    let transform: Arc<dyn AFPluginTransform> = Arc::new(transform);
+    self.setups.push(Box::new(move |plugin| {
+      plugin.wrap_shared(transform.clone())
+    }));
+    self
+  }
+
+  /// Returns the plugins with the bundle's states and middlewares applied.
+  pub fn into_plugins(self) -> Vec<AFPlugin> {
+    let setups = self.setups;
+    self
+      .plugins
+      .into_iter()
+      .map(|plugin| setups.iter().fold(plugin, |plugin, setup| setup(plugin)))
+      .collect()
+  }
+}
@@ -0,0 +1,26 @@
+use crate::dispatcher::AFConcurrent;
+use crate::errors::DispatchError;
+use crate::request::{AFPluginEventRequest, Payload};
+use crate::service::{BoxServiceFactory, ServiceRequest, ServiceResponse};
+
+/// A predicate that decides whether a handler of the event handles the request, e.g. by the
+/// version of the payload or a feature flag in the plugin's states. See [AFPlugin::guarded_event].
+///
+/// [AFPlugin::guarded_event]: crate::prelude::AFPlugin::guarded_event
+pub trait AFPluginGuard: AFConcurrent + 'static {
+  fn check(&self, request: &AFPluginEventRequest, payload: &Payload) -> bool;
+}
+
+impl<F> AFPluginGuard for F
+where
+  F: Fn(&AFPluginEventRequest, &Payload) -> bool + AFConcurrent + 'static,
+{
+  fn check(&self, request: &AFPluginEventRequest, payload: &Payload) -> bool {
+    (self)(request, payload)
+  }
+}
+
+pub(crate) struct GuardedService {
+  pub(crate) guard: Box<dyn AFPluginGuard>,
+  pub(crate) factory: BoxServiceFactory<(), ServiceRequest, ServiceResponse, DispatchError>,
+}
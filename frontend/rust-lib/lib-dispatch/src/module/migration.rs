@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
+
+use crate::dispatcher::AFConcurrent;
+use crate::errors::{DispatchError, InternalError};
+use crate::module::AFPluginEvent;
+
+/// Declares the schema version of the payload type. Bump it when the payload changes in the way
+/// that the older clients can't follow, and register the migration from the previous version by
+/// [AFPlugin::migrate].
+///
+/// [AFPlugin::migrate]: crate::prelude::AFPlugin::migrate
+pub trait AFPluginVersioned {
+  const PAYLOAD_VERSION: u32;
+}
+
+/// Converts the payload of the version `n` to the version `n + 1`.
+pub trait AFPluginMigration: AFConcurrent + 'static {
+  fn migrate(&self, payload: Bytes) -> Result<Bytes, DispatchError>;
+}
+
+impl<F> AFPluginMigration for F
+where
+  F: Fn(Bytes) -> Result<Bytes, DispatchError> + AFConcurrent + 'static,
+{
+  fn migrate(&self, payload: Bytes) -> Result<Bytes, DispatchError> {
+    (self)(payload)
+  }
+}
+
+#[derive(Default)]
+pub(crate) struct PayloadMigrations {
+  /// The version that the handler expects.
+  pub(crate) version: u32,
+  /// Keyed by the version that the migration converts from.
+  pub(crate) steps: BTreeMap<u32, Box<dyn AFPluginMigration>>,
+}
+
+impl PayloadMigrations {
+  /// Runs the migrations one by one until the payload is of the current version.
+  pub(crate) fn migrate(
+    &self,
+    event: &AFPluginEvent,
+    from: u32,
+    mut payload: Bytes,
+  ) -> Result<Bytes, DispatchError> {
+    if from > self.version {
+      return Err(
+        InternalError::DeserializeFromBytes(format!(
+          "[dispatch]: the payload version {} of {:?} is newer than {}",
+          from, event, self.version
+        ))
+        .into(),
+      );
+    }
+
+    for version in from..self.version {
+      let step = self.steps.get(&version).ok_or_else(|| {
+        InternalError::DeserializeFromBytes(format!(
+          "[dispatch]: no migration of {:?} from the payload version {}",
+          event, version
+        ))
+      })?;
+      payload = step.migrate(payload)?;
+    }
+    if from < self.version {
+      tracing::debug!(
+        "[dispatch]: migrated the payload of {:?} from version {} to {}",
+        event,
+        from,
+        self.version
+      );
+    }
+    Ok(payload)
+  }
+}
@@ -1,9 +1,17 @@
 #![allow(clippy::module_inception)]
 
+pub use bundle::*;
 pub use container::*;
 pub use data::*;
+pub use guard::*;
+pub use migration::{AFPluginMigration, AFPluginVersioned};
 pub use module::*;
+pub use validate::AFPluginValidationError;
 
+mod bundle;
 mod container;
 mod data;
+mod guard;
+mod migration;
 mod module;
+mod validate;
@@ -1,57 +1,410 @@
 use std::sync::Arc;
 use std::{
-  collections::HashMap,
+  collections::{HashMap, HashSet},
   fmt,
   fmt::{Debug, Display},
   future::Future,
   hash::Hash,
   pin::Pin,
   task::{Context, Poll},
+  time::Duration,
 };
 
 use futures_core::ready;
 use nanoid::nanoid;
+use parking_lot::RwLock;
 use pin_project::pin_project;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{Notify, OnceCell, OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
 
+use crate::byte_trait::AFPluginFromBytes;
+use crate::codec::{parse_with_codec, AFPluginDecode, PayloadCodec};
+use crate::compression::PayloadCompression;
 use crate::dispatcher::AFConcurrent;
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
+use crate::executor::{AFPluginExecutor, PluginExecutor};
+use crate::health::{AFPluginHealth, BoxHealthCheck};
+use crate::module::migration::{AFPluginMigration, AFPluginVersioned, PayloadMigrations};
+use crate::module::validate::{PayloadValidator, TypedValidator};
+use crate::module::{AFPluginGuard, GuardedService};
 use crate::prelude::{AFBoxFuture, AFStateMap};
+use crate::scheduler::DispatchPriority;
 use crate::service::AFPluginHandler;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::service::{AFPluginBlockingHandler, AFPluginSendHandler};
 use crate::{
   errors::{DispatchError, InternalError},
-  request::{payload::Payload, AFPluginEventRequest, FromAFPluginRequest},
+  request::{payload::Payload, AFPluginContext, AFPluginEventRequest, FromAFPluginRequest},
   response::{AFPluginEventResponse, AFPluginResponder},
   service::{
-    factory, AFPluginHandlerService, AFPluginServiceFactory, BoxService, BoxServiceFactory,
-    Service, ServiceRequest, ServiceResponse,
+    factory, AFPluginHandlerService, AFPluginServiceFactory, AFPluginTransform, BoxService,
+    BoxServiceFactory, Service, ServiceRequest, ServiceResponse,
   },
 };
 
 pub type AFPluginMap = Arc<HashMap<AFPluginEvent, Arc<AFPlugin>>>;
-pub(crate) fn plugin_map_or_crash(plugins: Vec<AFPlugin>) -> AFPluginMap {
+
+/// Maps the legacy events to the events that are registered in the [AFPluginMap].
+pub type AFPluginAliases = Arc<HashMap<AFPluginEvent, AFPluginEvent>>;
+
+/// The routing table of the dispatcher.
+#[derive(Clone, Default)]
+pub(crate) struct DispatchRoutes {
+  pub(crate) plugins: AFPluginMap,
+  pub(crate) aliases: AFPluginAliases,
+  /// The renamed events that are added by `AFPluginDispatcherBuilder::alias`. They take
+  /// precedence over the legacy events of the namespaced plugins.
+  pub(crate) renames: AFPluginAliases,
+  /// The plugin that handles the events that no other plugin handles. See [AFPlugin::fallback].
+  pub(crate) fallback: Option<Arc<AFPlugin>>,
+}
+
+impl DispatchRoutes {
+  pub(crate) fn new(plugins: AFPluginMap, fallback: Option<Arc<AFPlugin>>) -> Self {
+    let aliases = Arc::new(legacy_aliases(&plugins));
+    Self {
+      plugins,
+      aliases,
+      renames: AFPluginAliases::default(),
+      fallback,
+    }
+  }
+
+  /// Returns the routes of the plugins that keep the renamed events of `self`.
+  pub(crate) fn replace(&self, plugins: AFPluginMap, fallback: Option<Arc<AFPlugin>>) -> Self {
+    let mut routes = Self::new(plugins, fallback);
+    routes.renames = self.renames.clone();
+    routes
+  }
+
+  /// Returns the plugin that handles the `event`, or the fallback plugin if the event is not
+  /// registered.
+  pub(crate) fn lookup(&self, event: &AFPluginEvent) -> Option<&Arc<AFPlugin>> {
+    self.plugins.get(event).or(self.fallback.as_ref())
+  }
+
+  /// Returns the registered events with the names of their plugins, sorted by the events.
+  pub(crate) fn events(&self) -> Vec<DispatchEventInfo> {
+    let mut events = self
+      .plugins
+      .iter()
+      .map(|(event, plugin)| DispatchEventInfo {
+        event: event.clone(),
+        plugin: plugin.name.clone(),
+      })
+      .collect::<Vec<_>>();
+    events.sort_by(|a, b| a.event.0.cmp(&b.event.0));
+    events
+  }
+
+  /// Returns each registered plugin once.
+  pub(crate) fn unique_plugins(&self) -> Vec<Arc<AFPlugin>> {
+    let mut plugins: Vec<Arc<AFPlugin>> = vec![];
+    for plugin in self.plugins.values().chain(self.fallback.iter()) {
+      if !plugins.iter().any(|p| Arc::ptr_eq(p, plugin)) {
+        plugins.push(plugin.clone());
+      }
+    }
+    plugins
+  }
+
+  /// Returns the registered plugins including the ones whose events are chained by the plugins
+  /// that are registered later. See [DuplicatePolicy].
+  fn reachable_plugins(&self) -> Vec<Arc<AFPlugin>> {
+    let mut pending = self.unique_plugins();
+    let mut plugins: Vec<Arc<AFPlugin>> = vec![];
+    while let Some(plugin) = pending.pop() {
+      if plugins.iter().any(|p| Arc::ptr_eq(p, &plugin)) {
+        continue;
+      }
+      pending.extend(plugin.chained_plugins());
+      plugins.push(plugin);
+    }
+    plugins
+  }
+
+  /// Returns the plugin with the `name`, including the chained ones.
+  pub(crate) fn find_plugin(&self, name: &str) -> Option<Arc<AFPlugin>> {
+    self
+      .reachable_plugins()
+      .into_iter()
+      .find(|plugin| plugin.name == name)
+  }
+
+  /// Returns true if the `plugin` still handles any event, by itself or chained.
+  pub(crate) fn contains_plugin(&self, plugin: &Arc<AFPlugin>) -> bool {
+    self
+      .reachable_plugins()
+      .iter()
+      .any(|p| Arc::ptr_eq(p, plugin))
+  }
+
+  /// Returns the plugins sorted by their dependencies, the dependencies come first. Fails if a
+  /// dependency is not registered or the dependencies are circular.
+  pub(crate) fn plugins_in_dependency_order(&self) -> Result<Vec<Arc<AFPlugin>>, InternalError> {
+    let mut plugins = self.unique_plugins();
+    // Sorts by name first, so the order is stable between the runs.
+    plugins.sort_by(|a, b| a.name.cmp(&b.name));
+    let by_name = plugins
+      .iter()
+      .map(|plugin| (plugin.name.as_str(), plugin))
+      .collect::<HashMap<_, _>>();
+
+    fn visit<'a>(
+      plugin: &'a Arc<AFPlugin>,
+      by_name: &HashMap<&str, &'a Arc<AFPlugin>>,
+      visiting: &mut Vec<&'a str>,
+      visited: &mut HashSet<&'a str>,
+      sorted: &mut Vec<Arc<AFPlugin>>,
+    ) -> Result<(), InternalError> {
+      if visited.contains(plugin.name.as_str()) {
+        return Ok(());
+      }
+      if visiting.contains(&plugin.name.as_str()) {
+        visiting.push(&plugin.name);
+        return Err(InternalError::PluginDependency(format!(
+          "[dispatch]: circular plugin dependencies: {}",
+          visiting.join(" -> ")
+        )));
+      }
+
+      visiting.push(&plugin.name);
+      for dependency in plugin.dependencies.iter() {
+        let dependency: &'a Arc<AFPlugin> = *by_name.get(dependency.as_str()).ok_or_else(|| {
+          InternalError::PluginDependency(format!(
+            "[dispatch]: {} depends on {} that is not registered",
+            plugin.name, dependency
+          ))
+        })?;
+        visit(dependency, by_name, visiting, visited, sorted)?;
+      }
+      visiting.pop();
+      visited.insert(&plugin.name);
+      sorted.push(plugin.clone());
+      Ok(())
+    }
+
+    let mut sorted = Vec::with_capacity(plugins.len());
+    let mut visited = HashSet::new();
+    for plugin in plugins.iter() {
+      visit(plugin, &by_name, &mut vec![], &mut visited, &mut sorted)?;
+    }
+    Ok(sorted)
+  }
+
+  /// Returns the plugins in the order they are registered.
+  pub(crate) fn plugins_in_registration_order(&self) -> Vec<Arc<AFPlugin>> {
+    let mut plugins = self.unique_plugins();
+    plugins.sort_by_key(|plugin| plugin.registration);
+    plugins
+  }
+
+  /// Returns the registered event that the `event` refers to.
+  pub(crate) fn resolve(&self, event: &AFPluginEvent) -> Option<&AFPluginEvent> {
+    if self.plugins.contains_key(event) {
+      return None;
+    }
+    self.renames.get(event).or_else(|| self.aliases.get(event))
+  }
+}
+
+/// A registered event and the plugin that handles it. See [AFPluginDispatcher::events].
+///
+/// [AFPluginDispatcher::events]: crate::prelude::AFPluginDispatcher::events
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DispatchEventInfo {
+  pub event: AFPluginEvent,
+  pub plugin: String,
+}
+
+/// The unqualified events of the namespaced plugins are kept as the aliases, so the legacy
+/// senders still work. An unqualified event is dropped if it's ambiguous.
+fn legacy_aliases(
+  plugins: &HashMap<AFPluginEvent, Arc<AFPlugin>>,
+) -> HashMap<AFPluginEvent, AFPluginEvent> {
+  let mut aliases = HashMap::new();
+  let mut ambiguous = HashSet::new();
+  for (event, plugin) in plugins.iter() {
+    let legacy = match plugin.legacy_event(event) {
+      None => continue,
+      Some(legacy) => legacy,
+    };
+    if plugins.contains_key(&legacy) || ambiguous.contains(&legacy) {
+      continue;
+    }
+    if aliases.insert(legacy.clone(), event.clone()).is_some() {
+      tracing::warn!("[dispatch]: legacy event {:?} is ambiguous", legacy);
+      aliases.remove(&legacy);
+      ambiguous.insert(legacy);
+    }
+  }
+  aliases
+}
+
+#[cfg(any(target_arch = "wasm32", feature = "local_set"))]
+pub type BoxPluginHook = Box<dyn Fn() -> AFBoxFuture<'static, ()> + 'static>;
+
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
+pub type BoxPluginHook = Box<dyn Fn() -> AFBoxFuture<'static, ()> + Send + Sync + 'static>;
+
+/// Builds a plugin asynchronously. See `AFPluginDispatcher::new_async`.
+pub type AFPluginFactory = AFBoxFuture<'static, Result<AFPlugin, DispatchError>>;
+
+#[cfg(any(target_arch = "wasm32", feature = "local_set"))]
+type BoxPluginFactory = Box<dyn Fn() -> AFPluginFactory + 'static>;
+
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
+type BoxPluginFactory = Box<dyn Fn() -> AFPluginFactory + Send + Sync + 'static>;
+
+/// The plugin that is built when the first of its events arrives. See [AFPlugin::lazy].
+struct LazyPlugin {
+  events: HashSet<AFPluginEvent>,
+  factory: BoxPluginFactory,
+  plugin: OnceCell<Arc<AFPlugin>>,
+}
+
+/// Builds the routing table of the plugins. The events that they share are handled according to
+/// their own duplicate policy, or the dispatcher's `policy` if they don't set one. Fails if the
+/// events conflict, see [DuplicatePolicy::Error].
+pub(crate) fn plugin_routes(
+  plugins: Vec<AFPlugin>,
+  policy: DuplicatePolicy,
+) -> Result<DispatchRoutes, InternalError> {
   let mut plugin_map: HashMap<AFPluginEvent, Arc<AFPlugin>> = HashMap::new();
-  plugins.into_iter().for_each(|m| {
-    let events = m.events();
-    let plugins = Arc::new(m);
-    events.into_iter().for_each(|e| {
-      if plugin_map.contains_key(&e) {
-        let plugin_name = plugin_map.get(&e).map(|p| &p.name);
-        panic!("⚠️⚠️⚠️Error: {:?} is already defined in {:?}", &e, plugin_name,);
+  let mut fallback: Option<Arc<AFPlugin>> = None;
+  for plugin in plugins {
+    add_plugin(&mut plugin_map, &mut fallback, plugin, policy)?;
+  }
+  Ok(DispatchRoutes::new(Arc::new(plugin_map), fallback))
+}
+
+/// Same as [plugin_routes], but the plugins whose events conflict are left out of the routing
+/// table and logged instead, for the constructors of the dispatcher that can't fail.
+pub(crate) fn plugin_routes_lossy(
+  plugins: Vec<AFPlugin>,
+  policy: DuplicatePolicy,
+) -> DispatchRoutes {
+  let mut plugin_map: HashMap<AFPluginEvent, Arc<AFPlugin>> = HashMap::new();
+  let mut fallback: Option<Arc<AFPlugin>> = None;
+  for plugin in plugins {
+    let name = plugin.name.clone();
+    if let Err(err) = add_plugin(&mut plugin_map, &mut fallback, plugin, policy) {
+      tracing::error!("{}, the plugin {:?} is not registered", err, name);
+    }
+  }
+  DispatchRoutes::new(Arc::new(plugin_map), fallback)
+}
+
+/// Adds the plugin to the routing table only if none of its events conflict.
+fn add_plugin(
+  plugin_map: &mut HashMap<AFPluginEvent, Arc<AFPlugin>>,
+  fallback: &mut Option<Arc<AFPlugin>>,
+  mut m: AFPlugin,
+  policy: DuplicatePolicy,
+) -> Result<(), InternalError> {
+  m.check_namespace()?;
+  m.mark_registered();
+  let events = m.events();
+  let policy = m.own_duplicate_policy().unwrap_or(policy);
+  for e in events.iter() {
+    if let Some(existing) = plugin_map.get(e) {
+      m.merge_duplicate(policy, e, existing)?;
+    }
+  }
+  if m.has_fallback() {
+    if let Some(existing) = fallback.as_ref() {
+      if policy == DuplicatePolicy::Error {
+        return Err(InternalError::DuplicateEvent(format!(
+          "[dispatch]: the fallback handler is already defined in {:?}",
+          existing.name
+        )));
       }
-      plugin_map.insert(e, plugins.clone());
-    });
-  });
-  Arc::new(plugin_map)
+      tracing::warn!(
+        "[dispatch]: the fallback handler of {:?} is overridden by {:?}",
+        existing.name,
+        m.name
+      );
+    }
+  }
+
+  let plugin = Arc::new(m);
+  if plugin.has_fallback() {
+    *fallback = Some(plugin.clone());
+  }
+  for e in events {
+    plugin_map.insert(e, plugin.clone());
+  }
+  Ok(())
 }
 
+/// The event that a plugin registers. It's usually an enum that derives `Flowy_Event`, so a
+/// misspelled event is a compile error. The event is sent over the wire as its [Display] output.
+pub trait AFPluginEventType: Eq + Hash + Debug + Clone + Display + 'static {}
+
+/// The untyped events of the tests. The other code registers and sends the typed events, or the
+/// untyped ones received over the wire through [AFPluginEvent::untyped].
+#[cfg(feature = "test_helper")]
+impl AFPluginEventType for &'static str {}
+
+#[cfg(feature = "test_helper")]
+impl AFPluginEventType for String {}
+
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub struct AFPluginEvent(String);
 
-impl<T: Display + Eq + Hash + Debug + Clone> std::convert::From<T> for AFPluginEvent {
-  fn from(t: T) -> Self {
-    AFPluginEvent(format!("{}", t))
+impl AFPluginEvent {
+  /// Creates the event from its wire representation, e.g. the event that the host sends over the
+  /// wire. The rust code uses the typed events instead, so a misspelled event is a compile error.
+  pub fn untyped<T: Into<String>>(event: T) -> Self {
+    AFPluginEvent(event.into())
+  }
+
+  /// Returns the wire representation of the event.
+  pub fn as_str(&self) -> &str {
+    &self.0
+  }
+
+  /// Returns the event in the form of `namespace.event`.
+  pub fn qualified(&self, namespace: &str) -> AFPluginEvent {
+    AFPluginEvent(format!("{}.{}", namespace, self.0))
   }
 }
 
+impl<E: AFPluginEventType> std::convert::From<E> for AFPluginEvent {
+  fn from(event: E) -> Self {
+    AFPluginEvent(event.to_string())
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum PluginHook {
+  Start,
+  Stop,
+  Idle,
+  Teardown,
+}
+
+/// What to do when an event is registered by more than one plugin.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+  /// The registration fails.
+  #[default]
+  Error,
+  /// The new plugin replaces the existing one for the event.
+  Override,
+  /// Both handlers are called in the registration order. The new handler isn't called if the
+  /// existing one returns an error, and the response of the last called handler is returned.
+  Chain,
+  /// The handlers are called in the registration order until one of them succeeds. The response
+  /// of the last called handler is returned.
+  FirstSuccess,
+  /// The new handler observes the event, e.g. for the analytics. It receives a copy of the
+  /// request after the existing handler is completed, and its response is discarded.
+  Observe,
+}
+
 /// A plugin is used to handle the events that the plugin can handle.
 ///
 /// When an event is a dispatched by the `AFPluginDispatcher`, the dispatcher will
@@ -61,6 +414,12 @@ impl<T: Display + Eq + Hash + Debug + Clone> std::convert::From<T> for AFPluginE
 pub struct AFPlugin {
   pub name: String,
 
+  /// If set, the plugin's events are registered as `namespace.event`.
+  namespace: Option<String>,
+
+  /// The namespace that is set after the first one. The plugin fails to register with it.
+  conflicting_namespace: Option<String>,
+
   /// a list of `AFPluginState` that the plugin registers. The state can be read by the plugin's handler.
   states: AFStateMap,
 
@@ -70,14 +429,98 @@ pub struct AFPlugin {
   event_service_factory: Arc<
     HashMap<AFPluginEvent, BoxServiceFactory<(), ServiceRequest, ServiceResponse, DispatchError>>,
   >,
+
+  /// The handlers that only handle the requests that pass their guards, in the registration
+  /// order. See [AFPlugin::guarded_event].
+  guarded: Arc<HashMap<AFPluginEvent, Vec<GuardedService>>>,
+
+  /// Validates the payloads of the events before the handlers run. See [AFPlugin::validate].
+  validators: Arc<HashMap<AFPluginEvent, Box<dyn PayloadValidator>>>,
+
+  /// Upgrades the payloads of the older versions before the handlers run. See
+  /// [AFPlugin::migrate].
+  migrations: Arc<HashMap<AFPluginEvent, PayloadMigrations>>,
+
+  /// Handles the events that are not registered by any plugin. See [AFPlugin::fallback].
+  fallback: Option<Arc<BoxServiceFactory<(), ServiceRequest, ServiceResponse, DispatchError>>>,
+
+  /// The middlewares that wrap the services of the handlers, the first one is the outermost.
+  transforms: Arc<Vec<Arc<dyn AFPluginTransform>>>,
+
+  /// The events whose identical in-flight requests are coalesced.
+  coalesced_events: HashSet<AFPluginEvent>,
+
+  /// The events whose successful responses are cached, and how long they are cached for.
+  cached_events: HashMap<AFPluginEvent, Duration>,
+
+  /// The events whose handlers are not aborted once started.
+  must_complete_events: HashSet<AFPluginEvent>,
+
+  /// The events that change the data, they are recorded by the audit sink.
+  mutating_events: HashSet<AFPluginEvent>,
+
+  /// Limits the number of the plugin's handlers that run concurrently.
+  concurrency: Option<Arc<Semaphore>>,
+
+  /// The async hooks that are called at the points of the plugin's lifecycle.
+  hooks: HashMap<PluginHook, BoxPluginHook>,
+
+  /// The plugins whose handlers of the events run before this plugin's, and the policies that
+  /// combine them. See [DuplicatePolicy]. It's changed when a chained plugin is unregistered.
+  chained: RwLock<HashMap<AFPluginEvent, (DuplicatePolicy, Arc<AFPlugin>)>>,
+
+  /// Overrides the dispatcher's [DuplicatePolicy] for the events of this plugin.
+  duplicate_policy: Option<DuplicatePolicy>,
+
+  /// Runs the plugin's handlers. See [AFPlugin::executor].
+  #[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
+  executor: PluginExecutor,
+
+  /// Builds the plugin that handles the lazy events. See [AFPlugin::lazy].
+  lazy: Option<LazyPlugin>,
+
+  /// Reports the health of the plugin. See [AFPlugin::health_check].
+  health_check: Option<BoxHealthCheck>,
+
+  /// The names of the plugins that must be started before this plugin.
+  dependencies: Vec<String>,
+
+  /// Increases with every registered plugin. See [DispatchRoutes::plugins_in_registration_order].
+  registration: u64,
+
+  inflight: AtomicUsize,
+  idle: Notify,
 }
 
 impl std::default::Default for AFPlugin {
   fn default() -> Self {
     Self {
       name: "".to_owned(),
+      namespace: None,
+      conflicting_namespace: None,
       states: Default::default(),
       event_service_factory: Arc::new(HashMap::new()),
+      guarded: Arc::new(HashMap::new()),
+      validators: Arc::new(HashMap::new()),
+      migrations: Arc::new(HashMap::new()),
+      fallback: None,
+      transforms: Arc::new(vec![]),
+      coalesced_events: HashSet::new(),
+      cached_events: HashMap::new(),
+      must_complete_events: HashSet::new(),
+      mutating_events: HashSet::new(),
+      concurrency: None,
+      hooks: HashMap::new(),
+      chained: RwLock::new(HashMap::new()),
+      duplicate_policy: None,
+      #[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
+      executor: PluginExecutor::Shared,
+      lazy: None,
+      health_check: None,
+      dependencies: vec![],
+      registration: 0,
+      inflight: AtomicUsize::new(0),
+      idle: Notify::new(),
     }
   }
 }
@@ -92,10 +535,94 @@ impl AFPlugin {
     self
   }
 
-  pub fn state<D: AFConcurrent + 'static>(mut self, data: D) -> Self {
-    Arc::get_mut(&mut self.states)
-      .unwrap()
-      .insert(crate::module::AFPluginState::new(data));
+  /// Registers the plugin's events as `namespace.event` to avoid the collisions with other
+  /// plugins. The unqualified events are still routed to the plugin if they are not ambiguous.
+  /// The namespace can only be set once, the plugin fails to register otherwise.
+  pub fn namespace(mut self, namespace: &str) -> Self {
+    if self.namespace.is_some() {
+      self.conflicting_namespace = Some(namespace.to_owned());
+      return self;
+    }
+
+    let factories = Arc::get_mut(&mut self.event_service_factory).unwrap();
+    let registered = factories.drain().collect::<Vec<_>>();
+    factories.extend(
+      registered
+        .into_iter()
+        .map(|(event, factory)| (event.qualified(namespace), factory)),
+    );
+    let guarded = Arc::get_mut(&mut self.guarded).unwrap();
+    let registered = guarded.drain().collect::<Vec<_>>();
+    guarded.extend(
+      registered
+        .into_iter()
+        .map(|(event, services)| (event.qualified(namespace), services)),
+    );
+    let validators = Arc::get_mut(&mut self.validators).unwrap();
+    let registered = validators.drain().collect::<Vec<_>>();
+    validators.extend(
+      registered
+        .into_iter()
+        .map(|(event, validator)| (event.qualified(namespace), validator)),
+    );
+    let migrations = Arc::get_mut(&mut self.migrations).unwrap();
+    let registered = migrations.drain().collect::<Vec<_>>();
+    migrations.extend(
+      registered
+        .into_iter()
+        .map(|(event, migrations)| (event.qualified(namespace), migrations)),
+    );
+    self.coalesced_events = qualify_all(&self.coalesced_events, namespace);
+    self.cached_events = self
+      .cached_events
+      .drain()
+      .map(|(event, ttl)| (event.qualified(namespace), ttl))
+      .collect();
+    self.must_complete_events = qualify_all(&self.must_complete_events, namespace);
+    self.mutating_events = qualify_all(&self.mutating_events, namespace);
+    if let Some(lazy) = self.lazy.as_mut() {
+      lazy.events = qualify_all(&lazy.events, namespace);
+    }
+    self.namespace = Some(namespace.to_owned());
+    self
+  }
+
+  pub(crate) fn check_namespace(&self) -> Result<(), InternalError> {
+    match (&self.namespace, &self.conflicting_namespace) {
+      (Some(namespace), Some(conflicting)) => Err(InternalError::DuplicateEvent(format!(
+        "[dispatch]: the namespace of {:?} is already set to {:?}, can't set it to {:?}",
+        self.name, namespace, conflicting
+      ))),
+      _ => Ok(()),
+    }
+  }
+
+  /// Returns the key of the `event` in the routing table.
+  fn event_key<E: Into<AFPluginEvent>>(&self, event: E) -> AFPluginEvent {
+    let event = event.into();
+    match &self.namespace {
+      None => event,
+      Some(namespace) => event.qualified(namespace),
+    }
+  }
+
+  /// Returns the unqualified event if the plugin is namespaced.
+  pub(crate) fn legacy_event(&self, event: &AFPluginEvent) -> Option<AFPluginEvent> {
+    let namespace = self.namespace.as_ref()?;
+    let legacy = event.0.strip_prefix(namespace)?.strip_prefix('.')?;
+    Some(AFPluginEvent(legacy.to_owned()))
+  }
+
+  pub fn state<D: AFConcurrent + 'static>(self, data: D) -> Self {
+    self.shared_state(crate::module::AFPluginState::new(data))
+  }
+
+  /// Adds the state that may be shared with other plugins.
+  pub(crate) fn shared_state<D: AFConcurrent + 'static>(
+    mut self,
+    state: crate::module::AFPluginState<D>,
+  ) -> Self {
+    Arc::get_mut(&mut self.states).unwrap().insert(state);
     self
   }
 
@@ -107,9 +634,9 @@ impl AFPlugin {
     <T as FromAFPluginRequest>::Future: AFConcurrent,
     R: Future + AFConcurrent + 'static,
     R::Output: AFPluginResponder + 'static,
-    E: Eq + Hash + Debug + Clone + Display,
+    E: AFPluginEventType,
   {
-    let event: AFPluginEvent = event.into();
+    let event = self.event_key(event);
     if self.event_service_factory.contains_key(&event) {
       panic!("Register duplicate Event: {:?}", &event);
     } else {
@@ -120,15 +647,538 @@ impl AFPlugin {
     self
   }
 
+  /// Wraps the services of all the plugin's handlers with the middleware. The middleware that is
+  /// added first is the outermost one, so it sees the request first and the response last.
+  pub fn wrap<T: AFPluginTransform>(self, transform: T) -> Self {
+    self.wrap_shared(Arc::new(transform))
+  }
+
+  pub(crate) fn wrap_shared(mut self, transform: Arc<dyn AFPluginTransform>) -> Self {
+    Arc::get_mut(&mut self.transforms).unwrap().push(transform);
+    self
+  }
+
+  /// Applies the `register` function that is generated by the `#[event_handler(event)]`
+  /// attribute, which registers the annotated handler with its event.
+  pub fn handler<F>(self, register: F) -> Self
+  where
+    F: FnOnce(AFPlugin) -> AFPlugin,
+  {
+    register(self)
+  }
+
+  /// Runs the plugin's handlers on the `executor` instead of the shared runtime, e.g. on the
+  /// blocking pool for the database plugin, so its handlers don't stall the others. It's not
+  /// available with the `local_set` feature, see [AFPluginExecutor].
+  #[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
+  pub fn executor(mut self, executor: AFPluginExecutor) -> Self {
+    self.executor = PluginExecutor::new(executor, &self.name);
+    self
+  }
+
+  #[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
+  pub(crate) fn plugin_executor(&self) -> &PluginExecutor {
+    &self.executor
+  }
+
+  /// Registers the `events` whose handlers are provided by the plugin that the `factory` builds
+  /// when the first of the events arrives, e.g. the rarely used import plugin. The concurrent
+  /// first requests wait for the same build, and a failed build is retried by the next request.
+  ///
+  /// The built plugin must register the same events, including the namespace. Only its handlers,
+  /// states and middlewares are used, its hooks and other settings are ignored. Set them on this
+  /// plugin instead.
+  pub fn lazy<E, F>(mut self, events: Vec<E>, factory: F) -> Self
+  where
+    E: AFPluginEventType,
+    F: Fn() -> AFPluginFactory + AFConcurrent + 'static,
+  {
+    let events = events
+      .into_iter()
+      .map(|event| self.event_key(event))
+      .collect();
+    self.lazy = Some(LazyPlugin {
+      events,
+      factory: Box::new(factory),
+      plugin: OnceCell::new(),
+    });
+    self
+  }
+
+  /// Returns the built plugin if the `event` is one of the lazy events.
+  pub(crate) async fn lazy_plugin(
+    &self,
+    event: &AFPluginEvent,
+  ) -> Result<Option<Arc<AFPlugin>>, DispatchError> {
+    let lazy = match &self.lazy {
+      Some(lazy) if lazy.events.contains(event) => lazy,
+      _ => return Ok(None),
+    };
+    let plugin = lazy
+      .plugin
+      .get_or_try_init(|| async {
+        tracing::info!("[dispatch]: build the lazy plugin {}", self.name);
+        (lazy.factory)().await.map(Arc::new)
+      })
+      .await?;
+    Ok(Some(plugin.clone()))
+  }
+
+  /// Registers the `handler` that receives the events that are not registered by any plugin,
+  /// e.g. to forward them to a remote backend or to respond that they are unsupported in this
+  /// version. Only one plugin of the dispatcher can have the fallback handler.
+  ///
+  /// The handler can read the unknown event with [AFPluginEventRequest::event].
+  pub fn fallback<H, T, R>(mut self, handler: H) -> Self
+  where
+    H: AFPluginHandler<T, R>,
+    T: FromAFPluginRequest + 'static + AFConcurrent,
+    <T as FromAFPluginRequest>::Future: AFConcurrent,
+    R: Future + AFConcurrent + 'static,
+    R::Output: AFPluginResponder + 'static,
+  {
+    self.fallback = Some(Arc::new(factory(AFPluginHandlerService::new(handler))));
+    self
+  }
+
+  pub(crate) fn has_fallback(&self) -> bool {
+    self.fallback.is_some()
+  }
+
+  /// Registers the `handler` that only handles the requests of the `event` that pass the
+  /// `guard`, e.g. the requests with the new version of the payload. The guarded handlers are
+  /// checked in the registration order, and the handler registered by [AFPlugin::event] handles
+  /// the requests that none of them accepts. The request fails if there is no such handler.
+  pub fn guarded_event<E, G, H, T, R>(mut self, event: E, guard: G, handler: H) -> Self
+  where
+    G: AFPluginGuard,
+    H: AFPluginHandler<T, R>,
+    T: FromAFPluginRequest + 'static + AFConcurrent,
+    <T as FromAFPluginRequest>::Future: AFConcurrent,
+    R: Future + AFConcurrent + 'static,
+    R::Output: AFPluginResponder + 'static,
+    E: AFPluginEventType,
+  {
+    let event = self.event_key(event);
+    Arc::get_mut(&mut self.guarded)
+      .unwrap()
+      .entry(event)
+      .or_default()
+      .push(GuardedService {
+        guard: Box::new(guard),
+        factory: factory(AFPluginHandlerService::new(handler)),
+      });
+    self
+  }
+
+  /// Validates the payload of the `event` as `T` before the handler runs, so the handlers don't
+  /// repeat the validation. The request is responded with the [AFPluginValidationError] that
+  /// lists the failed fields if the validation fails.
+  ///
+  /// ```ignore
+  /// AFPlugin::new()
+  ///   .event(DatabaseEvent::CreateRow, create_row_handler)
+  ///   .validate::<_, CreateRowPayloadPB>(DatabaseEvent::CreateRow)
+  /// ```
+  ///
+  /// [AFPluginValidationError]: crate::prelude::AFPluginValidationError
+  pub fn validate<E, T>(mut self, event: E) -> Self
+  where
+    E: AFPluginEventType,
+    T: AFPluginFromBytes + validator::Validate + 'static,
+  {
+    let event = self.event_key(event);
+    Arc::get_mut(&mut self.validators)
+      .unwrap()
+      .insert(event, Box::new(TypedValidator::<T>::new()));
+    self
+  }
+
+  /// Declares that the handler of the `event` expects the payload of `T::PAYLOAD_VERSION`. The
+  /// requests that declare an older version by [AFPluginRequest::payload_version] are upgraded by
+  /// the migrations before the handler runs, so the newer backend still accepts the events of the
+  /// older frontend during the staged rollouts. The requests without the version are passed
+  /// through.
+  ///
+  /// ```ignore
+  /// AFPlugin::new()
+  ///   .event(DocumentEvent::CreateDocument, create_document_handler)
+  ///   .versioned::<_, CreateDocumentPayloadPB>(DocumentEvent::CreateDocument)
+  ///   .migrate(DocumentEvent::CreateDocument, 1, migrate_create_document_v1)
+  /// ```
+  pub fn versioned<E, T>(mut self, event: E) -> Self
+  where
+    E: AFPluginEventType,
+    T: AFPluginVersioned,
+  {
+    let event = self.event_key(event);
+    Arc::get_mut(&mut self.migrations)
+      .unwrap()
+      .entry(event)
+      .or_default()
+      .version = T::PAYLOAD_VERSION;
+    self
+  }
+
+  /// Registers the `migration` that converts the payload of the `event` from `from_version` to
+  /// `from_version + 1`. See [AFPlugin::versioned].
+  pub fn migrate<E, M>(mut self, event: E, from_version: u32, migration: M) -> Self
+  where
+    E: AFPluginEventType,
+    M: AFPluginMigration,
+  {
+    let event = self.event_key(event);
+    Arc::get_mut(&mut self.migrations)
+      .unwrap()
+      .entry(event)
+      .or_default()
+      .steps
+      .insert(from_version, Box::new(migration));
+    self
+  }
+
+  /// Registers the `handler` that runs on the multi-threaded runtime, so a slow handler doesn't
+  /// block the other events of the single-threaded dispatcher. The handler, its parameters and
+  /// its output must be `Send`. The plugin's states are still extracted on the dispatcher's
+  /// runtime.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[track_caller]
+  pub fn send_event<E, H, T, R>(self, event: E, handler: H) -> Self
+  where
+    H: AFPluginHandler<T, R> + Send + Sync,
+    T: FromAFPluginRequest + Send + 'static + AFConcurrent,
+    <T as FromAFPluginRequest>::Future: AFConcurrent,
+    R: Future + Send + AFConcurrent + 'static,
+    R::Output: AFPluginResponder + Send + 'static,
+    E: AFPluginEventType,
+  {
+    self.event(event, AFPluginSendHandler::new(handler))
+  }
+
+  /// Registers the `handler` that runs on the blocking pool, e.g. the handler that queries sqlite
+  /// or reads the files. The pool is shared by all the dispatchers and its size is set by
+  /// [set_blocking_threads]. The bounds are the same as [AFPlugin::send_event].
+  ///
+  /// A started handler keeps running even if its request is cancelled or timed out. Unlike the
+  /// executor of the plugin, it's also available with the `local_set` feature.
+  ///
+  /// [set_blocking_threads]: crate::runtime::set_blocking_threads
+  #[cfg(not(target_arch = "wasm32"))]
+  #[track_caller]
+  pub fn blocking_event<E, H, T, R>(self, event: E, handler: H) -> Self
+  where
+    H: AFPluginHandler<T, R> + Send + Sync,
+    T: FromAFPluginRequest + Send + 'static + AFConcurrent,
+    <T as FromAFPluginRequest>::Future: AFConcurrent,
+    R: Future + Send + AFConcurrent + 'static,
+    R::Output: AFPluginResponder + Send + 'static,
+    E: AFPluginEventType,
+  {
+    self.event(event, AFPluginBlockingHandler::new(handler))
+  }
+
+  /// Coalesces the identical requests of the `event`, the requests with the same payload.
+  ///
+  /// While a request is being handled, the identical requests don't run the handler again but
+  /// receive the same response. Only use it for the read-style events that have no side effects.
+  pub fn coalesce<E>(mut self, event: E) -> Self
+  where
+    E: AFPluginEventType,
+  {
+    let event = self.event_key(event);
+    self.coalesced_events.insert(event);
+    self
+  }
+
+  pub(crate) fn is_coalesced(&self, event: &AFPluginEvent) -> bool {
+    self.coalesced_events.contains(event)
+  }
+
+  /// Caches the successful responses of the `event` for the `ttl`. The requests with the same
+  /// payload receive the cached response without running the handler. The handlers that change
+  /// the data invalidate the cache by the [DispatchCache] extractor. Only use it for the
+  /// read-style events.
+  ///
+  /// [DispatchCache]: crate::prelude::DispatchCache
+  pub fn cache<E>(mut self, event: E, ttl: Duration) -> Self
+  where
+    E: AFPluginEventType,
+  {
+    let event = self.event_key(event);
+    self.cached_events.insert(event, ttl);
+    self
+  }
+
+  pub(crate) fn cache_ttl(&self, event: &AFPluginEvent) -> Option<Duration> {
+    self.cached_events.get(event).copied()
+  }
+
+  /// Keeps the handler of the `event` running until it's completed, even if the request is
+  /// cancelled or the caller drops the response. Use it for the side-effectful handlers that
+  /// must not be interrupted halfway, e.g. the writes to the database.
+  pub fn must_complete<E>(mut self, event: E) -> Self
+  where
+    E: AFPluginEventType,
+  {
+    let event = self.event_key(event);
+    self.must_complete_events.insert(event);
+    self
+  }
+
+  pub(crate) fn is_must_complete(&self, event: &AFPluginEvent) -> bool {
+    self.must_complete_events.contains(event)
+  }
+
+  /// Marks the `event` as the one that changes the data, its requests are recorded by the
+  /// [AuditSink] of the dispatcher.
+  ///
+  /// [AuditSink]: crate::prelude::AuditSink
+  pub fn mutating<E>(mut self, event: E) -> Self
+  where
+    E: AFPluginEventType,
+  {
+    let event = self.event_key(event);
+    self.mutating_events.insert(event);
+    self
+  }
+
+  pub(crate) fn is_mutating(&self, event: &AFPluginEvent) -> bool {
+    self.mutating_events.contains(event)
+  }
+
+  /// Caps the number of the plugin's handlers that run concurrently. The exceeding requests wait
+  /// until one of the running handlers is completed, so a heavy plugin can't starve the others.
+  pub fn max_concurrent(mut self, max_concurrent: usize) -> Self {
+    self.concurrency = Some(Arc::new(Semaphore::new(max_concurrent)));
+    self
+  }
+
+  /// Waits for a free slot if the plugin's concurrency is limited. The handler should hold the
+  /// returned permit until it's completed.
+  pub(crate) async fn acquire_concurrency(&self) -> Option<OwnedSemaphorePermit> {
+    match &self.concurrency {
+      None => None,
+      // The semaphore is never closed.
+      Some(semaphore) => semaphore.clone().acquire_owned().await.ok(),
+    }
+  }
+
+  /// Sets the hook that releases the plugin's resources when it's unregistered by
+  /// `AFPluginDispatcher::unregister_plugin`.
+  pub fn teardown<F>(self, teardown: F) -> Self
+  where
+    F: Fn() -> AFBoxFuture<'static, ()> + AFConcurrent + 'static,
+  {
+    self.hook(PluginHook::Teardown, teardown)
+  }
+
+  /// Sets the hook that is called by `AFPluginDispatcher::start`, e.g. to open the database.
+  pub fn on_start<F>(self, on_start: F) -> Self
+  where
+    F: Fn() -> AFBoxFuture<'static, ()> + AFConcurrent + 'static,
+  {
+    self.hook(PluginHook::Start, on_start)
+  }
+
+  /// Sets the hook that is called after the dispatcher is shut down, e.g. to flush the buffers.
+  pub fn on_stop<F>(self, on_stop: F) -> Self
+  where
+    F: Fn() -> AFBoxFuture<'static, ()> + AFConcurrent + 'static,
+  {
+    self.hook(PluginHook::Stop, on_stop)
+  }
+
+  /// Sets the hook that is called every time the dispatcher has no running or pending request.
+  pub fn on_idle<F>(self, on_idle: F) -> Self
+  where
+    F: Fn() -> AFBoxFuture<'static, ()> + AFConcurrent + 'static,
+  {
+    self.hook(PluginHook::Idle, on_idle)
+  }
+
+  /// Declares that the plugin depends on the plugin with the `name`, e.g. the document plugin
+  /// depends on the user plugin. The `on_start` hook of the dependency is called first and its
+  /// `on_stop` hook is called last.
+  pub fn depends_on(mut self, name: &str) -> Self {
+    self.dependencies.push(name.to_owned());
+    self
+  }
+
+  /// Sets the check that is run by the built-in `system.health` event, e.g. to report that the
+  /// database is unavailable or the sync is offline.
+  pub fn health_check<F>(mut self, health_check: F) -> Self
+  where
+    F: Fn() -> AFBoxFuture<'static, AFPluginHealth> + AFConcurrent + 'static,
+  {
+    self.health_check = Some(Box::new(health_check));
+    self
+  }
+
+  pub(crate) fn health_check_future(&self) -> Option<AFBoxFuture<'static, AFPluginHealth>> {
+    self.health_check.as_ref().map(|check| check())
+  }
+
+  fn hook<F>(mut self, kind: PluginHook, hook: F) -> Self
+  where
+    F: Fn() -> AFBoxFuture<'static, ()> + AFConcurrent + 'static,
+  {
+    self.hooks.insert(kind, Box::new(hook));
+    self
+  }
+
+  pub(crate) fn has_hook(&self, kind: PluginHook) -> bool {
+    self.hooks.contains_key(&kind)
+  }
+
+  pub(crate) async fn run_hook(&self, kind: PluginHook) {
+    if let Some(hook) = self.hooks.get(&kind) {
+      tracing::trace!("[dispatch]: run {:?} hook of {}", kind, self.name);
+      hook().await;
+    }
+  }
+
+  /// Sets how the plugin's events are combined with the same events of the plugins that are
+  /// registered earlier, overriding the dispatcher's policy. The plugins passed to
+  /// `AFPluginDispatcher::new` can only share the events if they set the policy, the others are
+  /// left out of the dispatcher.
+  pub fn duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+    self.duplicate_policy = Some(policy);
+    self
+  }
+
+  pub(crate) fn own_duplicate_policy(&self) -> Option<DuplicatePolicy> {
+    self.duplicate_policy
+  }
+
+  pub(crate) fn mark_registered(&mut self) {
+    static NEXT_REGISTRATION: AtomicU64 = AtomicU64::new(1);
+    self.registration = NEXT_REGISTRATION.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// Combines the plugin's `event` with the `existing` plugin that handles it.
+  pub(crate) fn merge_duplicate(
+    &mut self,
+    policy: DuplicatePolicy,
+    event: &AFPluginEvent,
+    existing: &Arc<AFPlugin>,
+  ) -> Result<(), InternalError> {
+    match policy {
+      DuplicatePolicy::Error => Err(InternalError::DuplicateEvent(format!(
+        "[dispatch]: {:?} is already defined in {:?}",
+        event, existing.name
+      ))),
+      DuplicatePolicy::Override => {
+        tracing::warn!(
+          "[dispatch]: {:?} of {:?} is overridden by {:?}",
+          event,
+          existing.name,
+          self.name
+        );
+        Ok(())
+      },
+      DuplicatePolicy::Chain | DuplicatePolicy::FirstSuccess | DuplicatePolicy::Observe => {
+        self
+          .chained
+          .get_mut()
+          .insert(event.clone(), (policy, existing.clone()));
+        Ok(())
+      },
+    }
+  }
+
+  pub(crate) fn chained(&self, event: &AFPluginEvent) -> Option<(DuplicatePolicy, Arc<AFPlugin>)> {
+    self.chained.read().get(event).cloned()
+  }
+
+  fn chained_plugins(&self) -> Vec<Arc<AFPlugin>> {
+    let chained = self.chained.read();
+    chained.values().map(|(_, plugin)| plugin.clone()).collect()
+  }
+
+  /// Removes the `plugin` from the chain of the `event`. The plugin that it chains onto takes its
+  /// place.
+  pub(crate) fn unchain(&self, event: &AFPluginEvent, plugin: &Arc<AFPlugin>) {
+    let mut chained = self.chained.write();
+    let (policy, previous) = match chained.get(event) {
+      None => return,
+      Some((policy, previous)) => (*policy, previous.clone()),
+    };
+    if !Arc::ptr_eq(&previous, plugin) {
+      drop(chained);
+      previous.unchain(event, plugin);
+      return;
+    }
+    match plugin.chained(event) {
+      Some((_, before)) => chained.insert(event.clone(), (policy, before)),
+      None => chained.remove(event),
+    };
+  }
+
+  /// Marks a handler of the plugin as running until the returned guard is dropped.
+  pub(crate) fn enter(self: &Arc<Self>) -> InflightGuard {
+    self.inflight.fetch_add(1, Ordering::SeqCst);
+    InflightGuard {
+      plugin: self.clone(),
+    }
+  }
+
+  /// Resolves when none of the plugin's handlers is running.
+  pub(crate) async fn wait_idle(&self) {
+    loop {
+      let notified = self.idle.notified();
+      if self.inflight.load(Ordering::SeqCst) == 0 {
+        return;
+      }
+      notified.await;
+    }
+  }
+
   pub fn events(&self) -> Vec<AFPluginEvent> {
+    let lazy_events = self.lazy.iter().flat_map(|lazy| lazy.events.iter());
+    let guarded_events = self
+      .guarded
+      .keys()
+      .filter(|event| !self.event_service_factory.contains_key(event));
     self
       .event_service_factory
       .keys()
+      .chain(guarded_events)
+      .chain(lazy_events)
       .cloned()
       .collect::<Vec<_>>()
   }
 }
 
+/// Whether the sender of the request waits for its response.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DispatchMode {
+  /// The response is delivered to the sender.
+  #[default]
+  RequestReply,
+  /// The response is discarded. The sender is resolved with an empty response right away and
+  /// the callback is not called.
+  FireAndForget,
+}
+
+fn qualify_all(events: &HashSet<AFPluginEvent>, namespace: &str) -> HashSet<AFPluginEvent> {
+  events
+    .iter()
+    .map(|event| event.qualified(namespace))
+    .collect()
+}
+
+pub(crate) struct InflightGuard {
+  plugin: Arc<AFPlugin>,
+}
+
+impl Drop for InflightGuard {
+  fn drop(&mut self) {
+    if self.plugin.inflight.fetch_sub(1, Ordering::SeqCst) == 1 {
+      self.plugin.idle.notify_waiters();
+    }
+  }
+}
+
 /// A request that will be passed to the corresponding plugin.
 ///
 /// Each request can carry the payload that will be deserialized into the corresponding data struct.
@@ -138,6 +1188,39 @@ pub struct AFPluginRequest {
   pub id: String,
   pub event: AFPluginEvent,
   pub(crate) payload: Payload,
+  pub priority: DispatchPriority,
+  pub mode: DispatchMode,
+  /// The handler of the request will be aborted if it runs longer than the timeout. It's not
+  /// enforced on wasm32, whose runtime has no timers.
+  pub timeout: Option<Duration>,
+  /// The requests that share the same ordering key are executed one by one in FIFO order, e.g.
+  /// the edits to the same document.
+  pub ordering_key: Option<String>,
+  /// Identifies the user action that the request belongs to. It's generated when the request is
+  /// dispatched if absent, and is attached to the response. Pass the handler's [CorrelationId] to
+  /// the nested requests to trace them together.
+  ///
+  /// [CorrelationId]: crate::prelude::CorrelationId
+  pub correlation_id: Option<String>,
+  /// How the payload is encoded. See [AFPluginRequest::parse].
+  pub codec: PayloadCodec,
+  /// The response payload is compressed with it if the payload is large. See
+  /// [AFPluginDispatcherBuilder::compression_threshold].
+  ///
+  /// [AFPluginDispatcherBuilder::compression_threshold]: crate::prelude::AFPluginDispatcherBuilder::compression_threshold
+  pub accept_compression: Option<PayloadCompression>,
+  /// Identifies the mutation, so re-sending it, e.g. after the FFI call fails, doesn't apply it
+  /// twice. The dispatcher returns the remembered response of the completed request with the same
+  /// event and key instead of running the handler again.
+  pub idempotency_key: Option<String>,
+  /// The schema version of the payload. See [AFPlugin::versioned].
+  pub payload_version: Option<u32>,
+  pub context: AFPluginContext,
+  pub(crate) cancel_token: CancellationToken,
+  /// The states of the dispatcher. They are set when the request is dispatched.
+  pub(crate) shared_states: AFStateMap,
+  /// Receives the partial responses that are reported by the handler.
+  pub(crate) progress: Option<UnboundedSender<AFPluginEventResponse>>,
 }
 
 impl AFPluginRequest {
@@ -149,9 +1232,28 @@ impl AFPluginRequest {
       id: nanoid!(6),
       event: event.into(),
       payload: Payload::None,
+      priority: DispatchPriority::default(),
+      mode: DispatchMode::default(),
+      timeout: None,
+      ordering_key: None,
+      correlation_id: None,
+      codec: PayloadCodec::default(),
+      accept_compression: None,
+      idempotency_key: None,
+      payload_version: None,
+      context: AFPluginContext::default(),
+      cancel_token: CancellationToken::new(),
+      shared_states: AFStateMap::default(),
+      progress: None,
     }
   }
 
+  /// Creates the request of the event that is not typed, e.g. the event that the host sends over
+  /// the wire. See [AFPluginEvent::untyped].
+  pub fn untyped<T: Into<String>>(event: T) -> Self {
+    Self::new(AFPluginEvent::untyped(event))
+  }
+
   pub fn payload<P>(mut self, payload: P) -> Self
   where
     P: Into<Payload>,
@@ -159,6 +1261,71 @@ impl AFPluginRequest {
     self.payload = payload.into();
     self
   }
+
+  pub fn priority(mut self, priority: DispatchPriority) -> Self {
+    self.priority = priority;
+    self
+  }
+
+  pub fn mode(mut self, mode: DispatchMode) -> Self {
+    self.mode = mode;
+    self
+  }
+
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = Some(timeout);
+    self
+  }
+
+  pub fn ordering_key<K: Into<String>>(mut self, key: K) -> Self {
+    self.ordering_key = Some(key.into());
+    self
+  }
+
+  pub fn correlation_id<T: Into<String>>(mut self, correlation_id: T) -> Self {
+    self.correlation_id = Some(correlation_id.into());
+    self
+  }
+
+  pub fn codec(mut self, codec: PayloadCodec) -> Self {
+    self.codec = codec;
+    self
+  }
+
+  pub fn accept_compression(mut self, compression: PayloadCompression) -> Self {
+    self.accept_compression = Some(compression);
+    self
+  }
+
+  pub fn idempotency_key<K: Into<String>>(mut self, key: K) -> Self {
+    self.idempotency_key = Some(key.into());
+    self
+  }
+
+  pub fn payload_version(mut self, version: u32) -> Self {
+    self.payload_version = Some(version);
+    self
+  }
+
+  /// Passes the `context` to the handler. See [AFPluginContext].
+  ///
+  /// [AFPluginContext]: crate::prelude::AFPluginContext
+  pub fn context(mut self, context: AFPluginContext) -> Self {
+    self.context = context;
+    self
+  }
+
+  /// Parses the payload with the codec of the request.
+  ///
+  /// ```ignore
+  /// let params = request.parse::<CreateDocParams>()?;
+  /// ```
+  pub fn parse<T>(&self) -> Result<T, DispatchError>
+  where
+    T: AFPluginDecode,
+  {
+    parse_with_codec(self.codec, &self.payload)
+  }
 }
 
 impl std::fmt::Display for AFPluginRequest {
@@ -176,9 +1343,22 @@ impl AFPluginServiceFactory<AFPluginRequest> for AFPlugin {
 
   fn new_service(&self, _cfg: Self::Context) -> Self::Future {
     let services = self.event_service_factory.clone();
+    let guarded = self.guarded.clone();
+    let validators = self.validators.clone();
+    let migrations = self.migrations.clone();
+    let fallback = self.fallback.clone();
+    let transforms = self.transforms.clone();
     let states = self.states.clone();
     Box::pin(async move {
-      let service = AFPluginService { services, states };
+      let service = AFPluginService {
+        services,
+        guarded,
+        validators,
+        migrations,
+        fallback,
+        transforms,
+        states,
+      };
       Ok(Box::new(service) as Self::Service)
     })
   }
@@ -188,9 +1368,51 @@ pub struct AFPluginService {
   services: Arc<
     HashMap<AFPluginEvent, BoxServiceFactory<(), ServiceRequest, ServiceResponse, DispatchError>>,
   >,
+  guarded: Arc<HashMap<AFPluginEvent, Vec<GuardedService>>>,
+  validators: Arc<HashMap<AFPluginEvent, Box<dyn PayloadValidator>>>,
+  migrations: Arc<HashMap<AFPluginEvent, PayloadMigrations>>,
+  fallback: Option<Arc<BoxServiceFactory<(), ServiceRequest, ServiceResponse, DispatchError>>>,
+  transforms: Arc<Vec<Arc<dyn AFPluginTransform>>>,
   states: AFStateMap,
 }
 
+impl AFPluginService {
+  /// Returns the factory of the handler that handles the request.
+  fn select(
+    &self,
+    request: &AFPluginEventRequest,
+    payload: &Payload,
+  ) -> Result<&BoxServiceFactory<(), ServiceRequest, ServiceResponse, DispatchError>, InternalError>
+  {
+    let event = &request.event;
+    if let Some(guarded) = self.guarded.get(event) {
+      if let Some(service) = guarded
+        .iter()
+        .find(|service| service.guard.check(request, payload))
+      {
+        return Ok(&service.factory);
+      }
+      return self.services.get(event).ok_or_else(|| {
+        InternalError::GuardRejected(format!(
+          "[dispatch]: none of the handlers of {:?} accepts the request",
+          event
+        ))
+      });
+    }
+
+    self
+      .services
+      .get(event)
+      .or(self.fallback.as_deref())
+      .ok_or_else(|| {
+        InternalError::ServiceNotFound(format!(
+          "Can not find service factory for event: {:?}",
+          event
+        ))
+      })
+  }
+}
+
 impl Service<AFPluginRequest> for AFPluginService {
   type Response = AFPluginEventResponse;
   type Error = DispatchError;
@@ -198,28 +1420,73 @@ impl Service<AFPluginRequest> for AFPluginService {
   type Future = AFBoxFuture<'static, Result<Self::Response, Self::Error>>;
 
   fn call(&self, request: AFPluginRequest) -> Self::Future {
-    let AFPluginRequest { id, event, payload } = request;
+    let AFPluginRequest {
+      id,
+      event,
+      mut payload,
+      payload_version,
+      cancel_token,
+      progress,
+      correlation_id,
+      codec,
+      context,
+      shared_states,
+      ..
+    } = request;
     let states = self.states.clone();
-    let request = AFPluginEventRequest::new(id, event, states);
+    let mut request = AFPluginEventRequest::new(id, event, states);
+    request.shared_states = shared_states;
+    request.cancel_token = cancel_token;
+    request.progress = progress;
+    request.correlation_id = correlation_id.unwrap_or_default();
+    request.codec = codec;
+    request.context = context;
+
+    let migrated = match (
+      payload_version,
+      self.migrations.get(&request.event),
+      &payload,
+    ) {
+      (Some(version), Some(migrations), Payload::Bytes(bytes)) => {
+        Some(migrations.migrate(&request.event, version, bytes.clone()))
+      },
+      _ => None,
+    };
+    match migrated {
+      None => {},
+      Some(Ok(bytes)) => payload = Payload::Bytes(bytes),
+      Some(Err(err)) => {
+        tracing::warn!("{}", err);
+        return Box::pin(async { Err(err) });
+      },
+    }
 
-    match self.services.get(&request.event) {
-      Some(factory) => {
+    if let Some(validator) = self.validators.get(&request.event) {
+      if let Err(err) = validator.validate(&request.event, &payload) {
+        tracing::warn!("[dispatch]: {}", err);
+        return Box::pin(async { Err(err) });
+      }
+    }
+
+    match self.select(&request, &payload) {
+      Ok(factory) => {
         let service_fut = factory.new_service(());
+        let transforms = self.transforms.clone();
         let fut = AFPluginServiceFuture {
-          fut: Box::pin(async {
-            let service = service_fut.await?;
+          fut: Box::pin(async move {
+            let mut service = service_fut.await?;
+            for transform in transforms.iter().rev() {
+              service = transform.new_transform(service);
+            }
             let service_req = ServiceRequest::new(request, payload);
             service.call(service_req).await
           }),
         };
         Box::pin(async move { Ok(fut.await.unwrap_or_else(|e| e.into())) })
       },
-      None => {
-        let msg = format!(
-          "Can not find service factory for event: {:?}",
-          request.event
-        );
-        Box::pin(async { Err(InternalError::ServiceNotFound(msg).into()) })
+      Err(err) => {
+        tracing::warn!("{}", err);
+        Box::pin(async { Err(err.into()) })
       },
     }
   }
@@ -0,0 +1,82 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use validator::{Validate, ValidationErrors};
+
+use crate::byte_trait::AFPluginFromBytes;
+use crate::dispatcher::AFConcurrent;
+use crate::errors::{DispatchError, Error, InternalError};
+use crate::module::AFPluginEvent;
+use crate::request::Payload;
+use crate::response::{AFPluginEventResponse, ResponseBuilder};
+
+/// Returned if the payload of the event fails the validation. See [AFPlugin::validate].
+///
+/// [AFPlugin::validate]: crate::prelude::AFPlugin::validate
+#[derive(Clone, Debug)]
+pub struct AFPluginValidationError {
+  pub event: AFPluginEvent,
+  pub errors: ValidationErrors,
+}
+
+impl fmt::Display for AFPluginValidationError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "invalid payload of {:?}", self.event)?;
+    let mut fields = self.errors.field_errors().into_iter().collect::<Vec<_>>();
+    fields.sort_by_key(|(field, _)| *field);
+    for (field, errors) in fields {
+      for error in errors {
+        write!(f, "\n{}: {}", field, error.code)?;
+        if let Some(message) = &error.message {
+          write!(f, " {}", message)?;
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+impl std::error::Error for AFPluginValidationError {}
+
+impl Error for AFPluginValidationError {
+  /// The payload lists the failed fields, one per line.
+  fn as_response(&self) -> AFPluginEventResponse {
+    ResponseBuilder::InvalidParams()
+      .data(self.to_string())
+      .build()
+  }
+}
+
+pub(crate) trait PayloadValidator: AFConcurrent + 'static {
+  fn validate(&self, event: &AFPluginEvent, payload: &Payload) -> Result<(), DispatchError>;
+}
+
+pub(crate) struct TypedValidator<T>(PhantomData<fn() -> T>);
+
+impl<T> TypedValidator<T> {
+  pub(crate) fn new() -> Self {
+    Self(PhantomData)
+  }
+}
+
+impl<T> PayloadValidator for TypedValidator<T>
+where
+  T: AFPluginFromBytes + Validate + 'static,
+{
+  fn validate(&self, event: &AFPluginEvent, payload: &Payload) -> Result<(), DispatchError> {
+    let bytes = match payload {
+      // The handler reports the missing payload.
+      Payload::None => return Ok(()),
+      Payload::Bytes(bytes) => bytes.clone(),
+    };
+    let data = T::parse_from_bytes(bytes)
+      .map_err(|e| InternalError::DeserializeFromBytes(format!("{}", e)))?;
+    data.validate().map_err(|errors| {
+      AFPluginValidationError {
+        event: event.clone(),
+        errors,
+      }
+      .into()
+    })
+  }
+}
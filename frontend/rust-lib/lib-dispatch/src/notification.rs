@@ -0,0 +1,163 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use parking_lot::{const_rwlock, Mutex, RwLock};
+use tokio::sync::broadcast;
+
+use crate::byte_trait::ToBytes;
+
+/// A change that the Rust side pushes to the observers, e.g. the Dart side, without being
+/// requested. See [send_notification].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DispatchNotification {
+  /// The module that sends the notification, e.g. the folder.
+  pub source: String,
+  /// The kind of the change, usually an enum of the source.
+  pub ty: i32,
+  /// The id of the observed object, e.g. the view's id. Empty if the notification isn't about a
+  /// particular object.
+  pub id: String,
+  pub payload: Bytes,
+}
+
+impl DispatchNotification {
+  pub fn new<S: Into<String>, T: Into<i32>>(source: S, ty: T, payload: Bytes) -> Self {
+    Self {
+      source: source.into(),
+      ty: ty.into(),
+      id: String::new(),
+      payload,
+    }
+  }
+
+  pub fn id<T: Into<String>>(mut self, id: T) -> Self {
+    self.id = id.into();
+    self
+  }
+
+  /// Delivers the notification to all the registered sinks.
+  pub fn send(self) {
+    let sinks = NOTIFICATION_SINKS.read();
+    for (_, sink) in sinks.iter() {
+      if let Err(e) = sink.send_notification(&self) {
+        tracing::error!(
+          "[dispatch]: deliver the notification of {} failed: {}",
+          self.source,
+          e
+        );
+      }
+    }
+  }
+}
+
+/// Receives the notifications, e.g. posts them to the Dart port. The sinks are global, so they
+/// must be `Send` and `Sync` even if the dispatcher runs on a local set.
+pub trait NotificationSink: Send + Sync + 'static {
+  fn send_notification(&self, notification: &DispatchNotification) -> Result<(), String>;
+}
+
+/// Identifies a registered sink, see [unregister_notification_sink].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NotificationSinkId(u64);
+
+static NOTIFICATION_SINKS: RwLock<Vec<(NotificationSinkId, Box<dyn NotificationSink>)>> =
+  const_rwlock(Vec::new());
+static NEXT_SINK_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Registers the sink until it's unregistered or the process exits.
+pub fn register_notification_sink<T: NotificationSink>(sink: T) -> NotificationSinkId {
+  let id = NotificationSinkId(NEXT_SINK_ID.fetch_add(1, Ordering::Relaxed));
+  NOTIFICATION_SINKS.write().push((id, Box::new(sink)));
+  id
+}
+
+pub fn unregister_notification_sink(id: NotificationSinkId) {
+  NOTIFICATION_SINKS
+    .write()
+    .retain(|(sink_id, _)| *sink_id != id);
+}
+
+/// The hot reload of Flutter registers the sinks again, so the old ones should be removed first.
+pub fn unregister_all_notification_sinks() {
+  NOTIFICATION_SINKS.write().clear();
+}
+
+/// Pushes a notification of the `source` to all the registered sinks. The payload that can't be
+/// serialized is logged and dropped.
+///
+/// ```ignore
+/// send_notification("Folder", FolderNotification::DidUpdateView, view_pb);
+/// ```
+pub fn send_notification<S, T, P>(source: S, ty: T, payload: P)
+where
+  S: Into<String>,
+  T: Into<i32>,
+  P: ToBytes,
+{
+  let source = source.into();
+  match payload.into_bytes() {
+    Ok(payload) => DispatchNotification::new(source, ty, payload).send(),
+    Err(e) => tracing::error!(
+      "[dispatch]: serialize the notification of {} failed: {:?}",
+      source,
+      e
+    ),
+  }
+}
+
+/// Forwards the notifications to a broadcast channel, so the Rust side can observe them too.
+/// The receivers that lag behind the capacity miss the oldest notifications.
+#[derive(Clone)]
+pub struct ChannelNotificationSink {
+  sender: broadcast::Sender<DispatchNotification>,
+}
+
+impl ChannelNotificationSink {
+  pub fn new(capacity: usize) -> Self {
+    let (sender, _) = broadcast::channel(capacity);
+    Self { sender }
+  }
+
+  pub fn subscribe(&self) -> broadcast::Receiver<DispatchNotification> {
+    self.sender.subscribe()
+  }
+}
+
+impl NotificationSink for ChannelNotificationSink {
+  fn send_notification(&self, notification: &DispatchNotification) -> Result<(), String> {
+    // No receiver isn't an error, the notifications are dropped until one subscribes.
+    let _ = self.sender.send(notification.clone());
+    Ok(())
+  }
+}
+
+/// Keeps the notifications in memory, so the tests can assert on what's sent. The clones share
+/// the notifications, so keep one to inspect after registering the other.
+#[derive(Clone, Default)]
+pub struct CollectNotificationSink {
+  notifications: Arc<Mutex<Vec<DispatchNotification>>>,
+}
+
+impl CollectNotificationSink {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// The notifications in the order they're sent.
+  pub fn notifications(&self) -> Vec<DispatchNotification> {
+    self.notifications.lock().clone()
+  }
+
+  /// Returns the collected notifications and clears them.
+  pub fn take(&self) -> Vec<DispatchNotification> {
+    std::mem::take(&mut *self.notifications.lock())
+  }
+}
+
+impl NotificationSink for CollectNotificationSink {
+  fn send_notification(&self, notification: &DispatchNotification) -> Result<(), String> {
+    self.notifications.lock().push(notification.clone());
+    Ok(())
+  }
+}
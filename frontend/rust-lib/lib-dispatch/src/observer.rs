@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+use crate::errors::DispatchError;
+use crate::module::AFPluginEvent;
+use crate::prelude::AFConcurrent;
+use crate::request::AFPluginContext;
+use crate::response::AFPluginEventResponse;
+
+/// The failed request and its response, which is handed over to the [DispatchErrorObserver].
+#[derive(Debug)]
+pub struct DispatchErrorReport<'a> {
+  pub event: &'a AFPluginEvent,
+  pub correlation_id: Option<&'a str>,
+  pub context: &'a AFPluginContext,
+  /// The response whose status code is not `Ok`, e.g. the error returned by the handler.
+  pub response: &'a AFPluginEventResponse,
+  /// Set if the error is reported by the dispatcher, e.g. the event has no handler.
+  pub error: Option<&'a DispatchError>,
+  pub elapsed: Duration,
+}
+
+/// Observes the errors of all the requests, e.g. to pipe them to the crash reporting. It's
+/// registered by [AFPluginDispatcherBuilder::on_error].
+///
+/// It's called before the response is sent back to the caller, so it should return quickly. The
+/// replayed responses of the idempotent requests are not reported again.
+///
+/// [AFPluginDispatcherBuilder::on_error]: crate::prelude::AFPluginDispatcherBuilder::on_error
+pub trait DispatchErrorObserver: AFConcurrent {
+  fn on_error(&self, report: &DispatchErrorReport<'_>);
+}
+
+impl<F> DispatchErrorObserver for F
+where
+  F: Fn(&DispatchErrorReport<'_>) + AFConcurrent,
+{
+  fn on_error(&self, report: &DispatchErrorReport<'_>) {
+    (self)(report)
+  }
+}
@@ -0,0 +1,114 @@
+use std::fmt::Write;
+
+use crate::dispatcher::AFPluginDispatcher;
+use crate::stats::LATENCY_BUCKETS;
+
+impl AFPluginDispatcher {
+  /// Renders the metrics of the dispatcher in the Prometheus text exposition format. The host
+  /// decides how to expose them, e.g. serving them on a `/metrics` endpoint:
+  ///
+  /// - `dispatch_queued_requests` and `dispatch_running_requests`, the queue depth.
+  /// - `dispatch_shed_requests_total`, see [DispatchLoadShedding].
+  /// - `dispatch_requests_total` and `dispatch_errors_total` of every event.
+  /// - `dispatch_handler_latency_seconds`, the latency histogram of every event.
+  ///
+  /// Only the events that have run their handlers are reported, see [AFPluginDispatcher::stats].
+  ///
+  /// [DispatchLoadShedding]: crate::prelude::DispatchLoadShedding
+  pub fn prometheus_metrics(&self) -> String {
+    let metrics = self.metrics();
+    let stats = self.stats();
+    let histograms = self.scheduler.stats.histograms();
+    let mut out = String::new();
+
+    let _ = writeln!(
+      out,
+      "# HELP dispatch_queued_requests The number of requests that wait to be started."
+    );
+    let _ = writeln!(out, "# TYPE dispatch_queued_requests gauge");
+    let _ = writeln!(out, "dispatch_queued_requests {}", metrics.queued);
+    let _ = writeln!(
+      out,
+      "# HELP dispatch_running_requests The number of requests that are being handled."
+    );
+    let _ = writeln!(out, "# TYPE dispatch_running_requests gauge");
+    let _ = writeln!(out, "dispatch_running_requests {}", metrics.running);
+    let _ = writeln!(
+      out,
+      "# HELP dispatch_shed_requests_total The number of requests rejected by the load shedding."
+    );
+    let _ = writeln!(out, "# TYPE dispatch_shed_requests_total counter");
+    let _ = writeln!(out, "dispatch_shed_requests_total {}", metrics.shed);
+
+    let _ = writeln!(
+      out,
+      "# HELP dispatch_requests_total The number of requests handled by the event."
+    );
+    let _ = writeln!(out, "# TYPE dispatch_requests_total counter");
+    for stats in stats.iter() {
+      let _ = writeln!(
+        out,
+        "dispatch_requests_total{{event=\"{}\"}} {}",
+        escape_label(&stats.event),
+        stats.calls
+      );
+    }
+    let _ = writeln!(
+      out,
+      "# HELP dispatch_errors_total The number of error responses of the event."
+    );
+    let _ = writeln!(out, "# TYPE dispatch_errors_total counter");
+    for stats in stats.iter() {
+      let _ = writeln!(
+        out,
+        "dispatch_errors_total{{event=\"{}\"}} {}",
+        escape_label(&stats.event),
+        stats.errors
+      );
+    }
+
+    let _ = writeln!(
+      out,
+      "# HELP dispatch_handler_latency_seconds The time it takes to handle the request."
+    );
+    let _ = writeln!(out, "# TYPE dispatch_handler_latency_seconds histogram");
+    for histogram in histograms.iter() {
+      let event = escape_label(&histogram.event);
+      let mut cumulative = 0;
+      for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets.iter()) {
+        cumulative += count;
+        let _ = writeln!(
+          out,
+          "dispatch_handler_latency_seconds_bucket{{event=\"{}\",le=\"{}\"}} {}",
+          event,
+          bound.as_secs_f64(),
+          cumulative
+        );
+      }
+      let _ = writeln!(
+        out,
+        "dispatch_handler_latency_seconds_bucket{{event=\"{}\",le=\"+Inf\"}} {}",
+        event, histogram.count
+      );
+      let _ = writeln!(
+        out,
+        "dispatch_handler_latency_seconds_sum{{event=\"{}\"}} {}",
+        event,
+        histogram.sum.as_secs_f64()
+      );
+      let _ = writeln!(
+        out,
+        "dispatch_handler_latency_seconds_count{{event=\"{}\"}} {}",
+        event, histogram.count
+      );
+    }
+    out
+  }
+}
+
+fn escape_label(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace('"', "\\\"")
+    .replace('\n', "\\n")
+}
@@ -0,0 +1,224 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use crate::codec::PayloadCodec;
+use crate::dispatcher::AFPluginDispatcher;
+use crate::module::{AFPluginEvent, AFPluginRequest};
+use crate::request::Payload;
+use crate::response::AFPluginEventResponse;
+
+const RECORDING_HEADER: &str = "# dispatch recording v1";
+
+/// Writes every request that is sent to the dispatcher to a file, with the time since the
+/// recording started, so the user can export it with the bug report. See
+/// [AFPluginDispatcherBuilder::record_to] and [DispatchReplay].
+///
+/// Each request is a line of the tab-separated offset in microseconds, event, codec, ordering
+/// key, correlation id and the hex of the payload. The line is written before the request is
+/// queued, so the recording keeps the order the requests arrive in even if the app crashes.
+///
+/// [AFPluginDispatcherBuilder::record_to]: crate::prelude::AFPluginDispatcherBuilder::record_to
+pub struct DispatchRecorder {
+  started_at: Instant,
+  file: Mutex<File>,
+}
+
+impl DispatchRecorder {
+  /// Truncates the file if it exists.
+  pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    let mut file = File::create(path)?;
+    writeln!(file, "{}", RECORDING_HEADER)?;
+    Ok(Self {
+      started_at: Instant::now(),
+      file: Mutex::new(file),
+    })
+  }
+
+  pub(crate) fn record(&self, request: &AFPluginRequest) {
+    let payload = match &request.payload {
+      Payload::None => "-".to_string(),
+      Payload::Bytes(bytes) => encode_hex(bytes),
+    };
+    let line = format!(
+      "{}\t{}\t{}\t{}\t{}\t{}",
+      self.started_at.elapsed().as_micros(),
+      escape(request.event.as_str()),
+      codec_name(request.codec),
+      escape(request.ordering_key.as_deref().unwrap_or_default()),
+      escape(request.correlation_id.as_deref().unwrap_or_default()),
+      payload
+    );
+    if let Err(e) = writeln!(self.file.lock(), "{}", line) {
+      tracing::error!("[dispatch]: record the request failed: {}", e);
+    }
+  }
+}
+
+/// A request of the recording and when it was sent.
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+  /// The time since the recording started.
+  pub offset: Duration,
+  pub event: AFPluginEvent,
+  pub codec: PayloadCodec,
+  pub ordering_key: Option<String>,
+  pub correlation_id: Option<String>,
+  pub payload: Payload,
+}
+
+impl RecordedRequest {
+  /// Builds the request to send again, it has a new id.
+  pub fn to_request(&self) -> AFPluginRequest {
+    let mut request = AFPluginRequest::new(self.event.clone())
+      .payload(self.payload.clone())
+      .codec(self.codec);
+    request.ordering_key = self.ordering_key.clone();
+    request.correlation_id = self.correlation_id.clone();
+    request
+  }
+}
+
+/// How fast [DispatchReplay::replay] sends the requests.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReplayTiming {
+  /// Sends the next request as soon as the previous one is responded.
+  #[default]
+  AsFastAsPossible,
+  /// Waits until the recorded offset of the request, unless the previous one is responded
+  /// later than that.
+  Original,
+}
+
+/// Feeds the requests of a recording of the [DispatchRecorder] back through the dispatcher, to
+/// reproduce the state that the user ended up with.
+///
+/// The requests are sent one by one in the recorded order, and each one waits for the response
+/// of the previous one, so the replay is deterministic even if the original requests ran
+/// concurrently.
+pub struct DispatchReplay {
+  requests: Vec<RecordedRequest>,
+}
+
+impl DispatchReplay {
+  pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut requests = vec![];
+    for (index, line) in reader.lines().enumerate() {
+      let line = line?;
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let request = parse_line(&line).ok_or_else(|| {
+        io::Error::new(
+          io::ErrorKind::InvalidData,
+          format!("Malformed recording at line {}", index + 1),
+        )
+      })?;
+      requests.push(request);
+    }
+    Ok(Self { requests })
+  }
+
+  pub fn requests(&self) -> &[RecordedRequest] {
+    &self.requests
+  }
+
+  /// Returns the responses in the order of the requests.
+  pub async fn replay(
+    &self,
+    dispatcher: &AFPluginDispatcher,
+    timing: ReplayTiming,
+  ) -> Vec<AFPluginEventResponse> {
+    let started_at = tokio::time::Instant::now();
+    let mut responses = Vec::with_capacity(self.requests.len());
+    for recorded in self.requests.iter() {
+      if timing == ReplayTiming::Original {
+        tokio::time::sleep_until(started_at + recorded.offset).await;
+      }
+      let request = recorded.to_request();
+      responses.push(AFPluginDispatcher::async_send(dispatcher, request).await);
+    }
+    responses
+  }
+}
+
+fn parse_line(line: &str) -> Option<RecordedRequest> {
+  let mut fields = line.split('\t');
+  let offset = Duration::from_micros(fields.next()?.parse().ok()?);
+  let event = unescape(fields.next()?);
+  let codec = codec_from_name(fields.next()?)?;
+  let ordering_key = unescape(fields.next()?);
+  let correlation_id = unescape(fields.next()?);
+  let payload = match fields.next()? {
+    "-" => Payload::None,
+    hex => Payload::Bytes(Bytes::from(decode_hex(hex)?)),
+  };
+
+  Some(RecordedRequest {
+    offset,
+    event: AFPluginEvent::untyped(event),
+    codec,
+    ordering_key: Some(ordering_key).filter(|key| !key.is_empty()),
+    correlation_id: Some(correlation_id).filter(|id| !id.is_empty()),
+    payload,
+  })
+}
+
+pub(crate) fn codec_name(codec: PayloadCodec) -> &'static str {
+  match codec {
+    PayloadCodec::Protobuf => "protobuf",
+    PayloadCodec::Json => "json",
+  }
+}
+
+pub(crate) fn codec_from_name(name: &str) -> Option<PayloadCodec> {
+  match name {
+    "protobuf" => Some(PayloadCodec::Protobuf),
+    "json" => Some(PayloadCodec::Json),
+    _ => None,
+  }
+}
+
+pub(crate) fn escape(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace('\t', "\\t")
+    .replace('\n', "\\n")
+}
+
+pub(crate) fn unescape(value: &str) -> String {
+  let mut unescaped = String::with_capacity(value.len());
+  let mut chars = value.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      unescaped.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some('t') => unescaped.push('\t'),
+      Some('n') => unescaped.push('\n'),
+      Some(c) => unescaped.push(c),
+      None => unescaped.push('\\'),
+    }
+  }
+  unescaped
+}
+
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+  if hex.len() % 2 != 0 {
+    return None;
+  }
+  (0..hex.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+    .collect()
+}
@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use crate::byte_trait::ToBytes;
+use crate::codec::{AFPluginCodec, PayloadCodec};
+use crate::compression::PayloadCompression;
+use crate::dispatcher::{
+  into_request, AFBoxFuture, AFConcurrent, AFPluginDispatcher, BoxFutureCallback,
+};
+use crate::errors::DispatchError;
+use crate::module::{AFPluginEvent, AFPluginRequest, DispatchMode};
+use crate::request::{AFPluginContext, Payload};
+use crate::response::AFPluginEventResponse;
+use crate::scheduler::DispatchPriority;
+
+/// Builds the request fluently and sends it by the dispatcher.
+///
+/// ```ignore
+/// let response = dispatcher
+///   .request(DocumentEvent::CreateDocument)
+///   .data(params)
+///   .priority(DispatchPriority::High)
+///   .send()
+///   .await;
+/// ```
+pub struct DispatchRequestBuilder<'a> {
+  dispatch: &'a AFPluginDispatcher,
+  request: Result<AFPluginRequest, DispatchError>,
+  callback: Option<BoxFutureCallback>,
+}
+
+impl<'a> DispatchRequestBuilder<'a> {
+  pub(crate) fn new<E>(dispatch: &'a AFPluginDispatcher, event: E) -> Self
+  where
+    E: Into<AFPluginEvent>,
+  {
+    Self {
+      dispatch,
+      request: Ok(AFPluginRequest::new(event)),
+      callback: None,
+    }
+  }
+
+  pub fn event<E>(self, event: E) -> Self
+  where
+    E: Into<AFPluginEvent>,
+  {
+    self.map(|mut request| {
+      request.event = event.into();
+      request
+    })
+  }
+
+  pub fn payload<P>(self, payload: P) -> Self
+  where
+    P: Into<Payload>,
+  {
+    self.map(|request| request.payload(payload))
+  }
+
+  /// Serializes the `data` into the payload, e.g. the protobuf struct. The serialization error is
+  /// returned as the error response when the request is sent.
+  pub fn data<T>(mut self, data: T) -> Self
+  where
+    T: ToBytes,
+  {
+    self.request = match self.request {
+      Ok(request) => data.into_bytes().map(|bytes| request.payload(bytes)),
+      Err(e) => Err(e),
+    };
+    self
+  }
+
+  /// Encodes the `data` with the codec `C` and declares the codec on the request.
+  ///
+  /// ```ignore
+  /// dispatcher.request(event).encode::<JsonCodec, _>(&params, PayloadCodec::Json)
+  /// ```
+  pub fn encode<C, T>(mut self, data: &T, codec: PayloadCodec) -> Self
+  where
+    C: AFPluginCodec<T>,
+  {
+    self.request = match self.request {
+      Ok(request) => C::encode(data).map(|bytes| request.payload(bytes).codec(codec)),
+      Err(e) => Err(e),
+    };
+    self
+  }
+
+  pub fn codec(self, codec: PayloadCodec) -> Self {
+    self.map(|request| request.codec(codec))
+  }
+
+  pub fn accept_compression(self, compression: PayloadCompression) -> Self {
+    self.map(|request| request.accept_compression(compression))
+  }
+
+  pub fn idempotency_key<K: Into<String>>(self, key: K) -> Self {
+    self.map(|request| request.idempotency_key(key))
+  }
+
+  pub fn payload_version(self, version: u32) -> Self {
+    self.map(|request| request.payload_version(version))
+  }
+
+  pub fn context(self, context: AFPluginContext) -> Self {
+    self.map(|request| request.context(context))
+  }
+
+  pub fn priority(self, priority: DispatchPriority) -> Self {
+    self.map(|request| request.priority(priority))
+  }
+
+  pub fn mode(self, mode: DispatchMode) -> Self {
+    self.map(|request| request.mode(mode))
+  }
+
+  pub fn timeout(self, timeout: Duration) -> Self {
+    self.map(|request| request.timeout(timeout))
+  }
+
+  pub fn ordering_key<K: Into<String>>(self, key: K) -> Self {
+    self.map(|request| request.ordering_key(key))
+  }
+
+  pub fn correlation_id<T: Into<String>>(self, correlation_id: T) -> Self {
+    self.map(|request| request.correlation_id(correlation_id))
+  }
+
+  pub fn callback<Callback>(mut self, callback: Callback) -> Self
+  where
+    Callback: FnOnce(AFPluginEventResponse) -> AFBoxFuture<'static, ()> + AFConcurrent + 'static,
+  {
+    self.callback = Some(Box::new(callback));
+    self
+  }
+
+  /// Returns the request without sending it.
+  pub fn build(self) -> Result<AFPluginRequest, DispatchError> {
+    self.request
+  }
+
+  pub async fn send(self) -> AFPluginEventResponse {
+    let DispatchRequestBuilder {
+      dispatch,
+      request,
+      callback,
+    } = self;
+    match request {
+      Ok(request) => {
+        let request = into_request(request);
+        tracing::trace!("[dispatch]: Async event: {:?}", &request.event);
+        dispatch.send_on_runtime(request, callback).await
+      },
+      Err(e) => {
+        let response: AFPluginEventResponse = e.into();
+        if let Some(callback) = callback {
+          callback(response.clone()).await;
+        }
+        response
+      },
+    }
+  }
+
+  fn map<F>(mut self, f: F) -> Self
+  where
+    F: FnOnce(AFPluginRequest) -> AFPluginRequest,
+  {
+    self.request = self.request.map(f);
+    self
+  }
+}
@@ -0,0 +1,90 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::dispatcher::AFConcurrent;
+use crate::errors::DispatchError;
+use crate::module::AFPluginStateMap;
+use crate::request::{AFPluginEventRequest, FromAFPluginRequest, Payload};
+use crate::util::ready::{ready, Ready};
+
+/// Tells the handler who or what triggers the request. It's set by [AFPluginRequest::context] and
+/// extracted by the handler:
+///
+/// ```ignore
+/// async fn open_document_handler(data: AFPluginData<OpenDocumentPB>, ctx: AFPluginContext) {
+///   let locale = ctx.locale.as_deref().unwrap_or("en");
+///   ..
+/// }
+/// ```
+///
+/// The handler receives the default context if the request doesn't set it.
+///
+/// [AFPluginRequest::context]: crate::prelude::AFPluginRequest::context
+#[derive(Clone, Debug, Default)]
+pub struct AFPluginContext {
+  /// The request is timed out at the deadline. See [AFPluginRequest::timeout].
+  ///
+  /// [AFPluginRequest::timeout]: crate::prelude::AFPluginRequest::timeout
+  pub deadline: Option<Instant>,
+  pub user_id: Option<String>,
+  pub device_id: Option<String>,
+  pub locale: Option<String>,
+  extensions: Arc<AFPluginStateMap>,
+}
+
+impl AFPluginContext {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn deadline(mut self, deadline: Instant) -> Self {
+    self.deadline = Some(deadline);
+    self
+  }
+
+  pub fn user_id<T: Into<String>>(mut self, user_id: T) -> Self {
+    self.user_id = Some(user_id.into());
+    self
+  }
+
+  pub fn device_id<T: Into<String>>(mut self, device_id: T) -> Self {
+    self.device_id = Some(device_id.into());
+    self
+  }
+
+  pub fn locale<T: Into<String>>(mut self, locale: T) -> Self {
+    self.locale = Some(locale.into());
+    self
+  }
+
+  /// Attaches the value that the built-in fields don't cover, one value per type.
+  pub fn extension<T: AFConcurrent + 'static>(mut self, value: T) -> Self {
+    Arc::get_mut(&mut self.extensions)
+      .expect("extension must be set before the context is shared")
+      .insert(value);
+    self
+  }
+
+  pub fn get_extension<T: 'static>(&self) -> Option<&T> {
+    self.extensions.get::<T>()
+  }
+
+  /// Returns the time left until the deadline, `Duration::ZERO` if it has passed.
+  pub fn remaining(&self) -> Option<Duration> {
+    self
+      .deadline
+      .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+  }
+}
+
+#[doc(hidden)]
+impl FromAFPluginRequest for AFPluginContext {
+  type Error = DispatchError;
+  type Future = Ready<Result<Self, Self::Error>>;
+
+  fn from_request(req: &AFPluginEventRequest, _payload: &mut Payload) -> Self::Future {
+    ready(Ok(req.context.clone()))
+  }
+}
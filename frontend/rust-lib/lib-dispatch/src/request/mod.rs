@@ -1,6 +1,12 @@
 #![allow(clippy::module_inception)]
+mod builder;
+mod context;
 pub mod payload;
+mod progress;
 mod request;
 
+pub use builder::*;
+pub use context::*;
 pub use payload::*;
+pub use progress::*;
 pub use request::*;
@@ -10,10 +10,19 @@ pub enum Payload {
 }
 
 impl Payload {
+  /// Reuses the buffer of the bytes if it's not shared, otherwise copies it.
   pub fn to_vec(self) -> Vec<u8> {
     match self {
       Payload::None => vec![],
-      Payload::Bytes(bytes) => bytes.to_vec(),
+      Payload::Bytes(bytes) => Vec::from(bytes),
+    }
+  }
+
+  /// Returns the bytes without copying.
+  pub fn into_bytes(self) -> Bytes {
+    match self {
+      Payload::None => Bytes::new(),
+      Payload::Bytes(bytes) => bytes,
     }
   }
 }
@@ -0,0 +1,52 @@
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::byte_trait::ToBytes;
+use crate::errors::DispatchError;
+use crate::request::{AFPluginEventRequest, FromAFPluginRequest, Payload};
+use crate::response::{AFPluginEventResponse, ResponseBuilder};
+use crate::util::ready::{ready, Ready};
+
+/// Reports the progress or the partial results of a long-running handler, e.g. search, import or
+/// sync, to the caller before the final response.
+///
+/// The caller receives them only if the request is sent by
+/// `AFPluginDispatcher::async_send_with_progress`. Otherwise, sending the progress is a no-op.
+#[derive(Clone, Default)]
+pub struct AFPluginProgress {
+  sender: Option<UnboundedSender<AFPluginEventResponse>>,
+}
+
+impl AFPluginProgress {
+  pub fn is_subscribed(&self) -> bool {
+    self.sender.is_some()
+  }
+
+  pub fn send<D>(&self, data: D) -> Result<(), DispatchError>
+  where
+    D: ToBytes,
+  {
+    if self.is_subscribed() {
+      let response = ResponseBuilder::Ok().data(data.into_bytes()?).build();
+      self.send_response(response);
+    }
+    Ok(())
+  }
+
+  pub fn send_response(&self, response: AFPluginEventResponse) {
+    if let Some(sender) = &self.sender {
+      // The caller may stop listening, e.g. the request is cancelled.
+      let _ = sender.send(response);
+    }
+  }
+}
+
+impl FromAFPluginRequest for AFPluginProgress {
+  type Error = DispatchError;
+  type Future = Ready<Result<Self, Self::Error>>;
+
+  fn from_request(req: &AFPluginEventRequest, _payload: &mut Payload) -> Self::Future {
+    ready(Ok(AFPluginProgress {
+      sender: req.progress.clone(),
+    }))
+  }
+}
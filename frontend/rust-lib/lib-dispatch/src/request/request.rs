@@ -5,14 +5,19 @@ use std::{
   task::{Context, Poll},
 };
 
+use bytes::Bytes;
 use derivative::*;
 use futures_core::ready;
+use tokio::sync::mpsc::UnboundedSender;
+pub use tokio_util::sync::CancellationToken;
 
 use crate::prelude::{AFConcurrent, AFStateMap};
 use crate::{
+  codec::PayloadCodec,
   errors::{DispatchError, InternalError},
   module::AFPluginEvent,
-  request::payload::Payload,
+  request::{payload::Payload, AFPluginContext},
+  response::AFPluginEventResponse,
   util::ready::{ready, Ready},
 };
 
@@ -23,6 +28,15 @@ pub struct AFPluginEventRequest {
   pub(crate) event: AFPluginEvent,
   #[derivative(Debug = "ignore")]
   pub(crate) states: AFStateMap,
+  #[derivative(Debug = "ignore")]
+  pub(crate) shared_states: AFStateMap,
+  pub(crate) correlation_id: String,
+  pub(crate) codec: PayloadCodec,
+  pub(crate) context: AFPluginContext,
+  #[derivative(Debug = "ignore")]
+  pub(crate) cancel_token: CancellationToken,
+  #[derivative(Debug = "ignore")]
+  pub(crate) progress: Option<UnboundedSender<AFPluginEventResponse>>,
 }
 
 impl AFPluginEventRequest {
@@ -34,9 +48,32 @@ impl AFPluginEventRequest {
       id,
       event: event.into(),
       states,
+      shared_states: AFStateMap::default(),
+      correlation_id: String::new(),
+      codec: PayloadCodec::default(),
+      context: AFPluginContext::default(),
+      cancel_token: CancellationToken::new(),
+      progress: None,
     }
   }
 
+  pub fn correlation_id(&self) -> &str {
+    &self.correlation_id
+  }
+
+  pub fn event(&self) -> &AFPluginEvent {
+    &self.event
+  }
+
+  pub fn context(&self) -> &AFPluginContext {
+    &self.context
+  }
+
+  /// The codec of the payload that is declared by the caller.
+  pub fn codec(&self) -> PayloadCodec {
+    self.codec
+  }
+
   pub fn get_state<T>(&self) -> Option<T>
   where
     T: AFConcurrent + 'static + Clone,
@@ -45,7 +82,7 @@ impl AFPluginEventRequest {
       return Some(data.clone());
     }
 
-    None
+    self.shared_states.get::<T>().cloned()
   }
 }
 
@@ -79,6 +116,72 @@ impl FromAFPluginRequest for String {
   }
 }
 
+/// Shares the buffer of the payload instead of copying it, which matters for the large payloads,
+/// e.g. the documents.
+#[doc(hidden)]
+impl FromAFPluginRequest for Bytes {
+  type Error = DispatchError;
+  type Future = Ready<Result<Self, Self::Error>>;
+
+  fn from_request(req: &AFPluginEventRequest, payload: &mut Payload) -> Self::Future {
+    match &payload {
+      Payload::None => ready(Err(unexpected_none_payload(req))),
+      Payload::Bytes(buf) => ready(Ok(buf.clone())),
+    }
+  }
+}
+
+/// The token is cancelled when the request is cancelled by the caller. Long-running handlers can
+/// check it to stop cooperatively.
+#[doc(hidden)]
+impl FromAFPluginRequest for CancellationToken {
+  type Error = DispatchError;
+  type Future = Ready<Result<Self, Self::Error>>;
+
+  fn from_request(req: &AFPluginEventRequest, _payload: &mut Payload) -> Self::Future {
+    ready(Ok(req.cancel_token.clone()))
+  }
+}
+
+/// The correlation id of the request. See [AFPluginRequest::correlation_id].
+///
+/// [AFPluginRequest::correlation_id]: crate::prelude::AFPluginRequest::correlation_id
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CorrelationId(pub String);
+
+impl std::fmt::Display for CorrelationId {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+impl std::convert::From<CorrelationId> for String {
+  fn from(id: CorrelationId) -> Self {
+    id.0
+  }
+}
+
+#[doc(hidden)]
+impl FromAFPluginRequest for CorrelationId {
+  type Error = DispatchError;
+  type Future = Ready<Result<Self, Self::Error>>;
+
+  fn from_request(req: &AFPluginEventRequest, _payload: &mut Payload) -> Self::Future {
+    ready(Ok(CorrelationId(req.correlation_id.clone())))
+  }
+}
+
+/// The event of the request. Useful for the fallback handler that receives different events.
+#[doc(hidden)]
+impl FromAFPluginRequest for AFPluginEvent {
+  type Error = DispatchError;
+  type Future = Ready<Result<Self, Self::Error>>;
+
+  fn from_request(req: &AFPluginEventRequest, _payload: &mut Payload) -> Self::Future {
+    ready(Ok(req.event.clone()))
+  }
+}
+
 pub fn unexpected_none_payload(request: &AFPluginEventRequest) -> DispatchError {
   tracing::warn!("{:?} expected payload", &request.event);
   InternalError::UnexpectedNone("Expected payload".to_string()).into()
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use crate::{
   request::Payload,
   response::{AFPluginEventResponse, StatusCode},
@@ -15,6 +17,7 @@ macro_rules! static_response {
 pub struct ResponseBuilder<T = Payload> {
   pub payload: T,
   pub status: StatusCode,
+  pub metadata: HashMap<String, String>,
 }
 
 impl ResponseBuilder {
@@ -22,6 +25,7 @@ impl ResponseBuilder {
     ResponseBuilder {
       payload: Payload::None,
       status,
+      metadata: HashMap::new(),
     }
   }
 
@@ -30,13 +34,28 @@ impl ResponseBuilder {
     self
   }
 
+  pub fn metadata<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+    self.metadata.insert(key.into(), value.into());
+    self
+  }
+
   pub fn build(self) -> AFPluginEventResponse {
     AFPluginEventResponse {
       payload: self.payload,
       status_code: self.status,
+      retryable: false,
+      correlation_id: None,
+      metadata: self.metadata,
     }
   }
 
   static_response!(Ok, StatusCode::Ok);
   static_response!(Err, StatusCode::Err);
+  static_response!(InvalidParams, StatusCode::InvalidParams);
+  static_response!(NotFound, StatusCode::NotFound);
+  static_response!(Unauthorized, StatusCode::Unauthorized);
+  static_response!(Timeout, StatusCode::Timeout);
+  static_response!(Internal, StatusCode::Internal);
+  static_response!(Cancelled, StatusCode::Cancelled);
+  static_response!(Busy, StatusCode::Busy);
 }
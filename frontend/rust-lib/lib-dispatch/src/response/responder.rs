@@ -1,8 +1,8 @@
 #[allow(unused_imports)]
 use crate::errors::{DispatchError, InternalError};
 use crate::{
-  request::AFPluginEventRequest,
-  response::{AFPluginEventResponse, ResponseBuilder},
+  request::{AFPluginEventRequest, Payload},
+  response::{AFPluginEventResponse, ResponseBuilder, StatusCode},
 };
 use bytes::Bytes;
 
@@ -26,6 +26,7 @@ impl_responder!(&'_ String);
 impl_responder!(Bytes);
 impl_responder!(());
 impl_responder!(Vec<u8>);
+impl_responder!(Payload);
 
 impl<T, E> AFPluginResponder for Result<T, E>
 where
@@ -39,3 +40,22 @@ where
     }
   }
 }
+
+impl AFPluginResponder for DispatchError {
+  fn respond_to(self, _: &AFPluginEventRequest) -> AFPluginEventResponse {
+    self.into()
+  }
+}
+
+/// Overrides the status code of the response, e.g. `(StatusCode::Unauthorized, "sign in first")`.
+impl<T> AFPluginResponder for (StatusCode, T)
+where
+  T: AFPluginResponder,
+{
+  fn respond_to(self, request: &AFPluginEventRequest) -> AFPluginEventResponse {
+    let (status_code, responder) = self;
+    let mut response = responder.respond_to(request);
+    response.status_code = status_code;
+    response
+  }
+}
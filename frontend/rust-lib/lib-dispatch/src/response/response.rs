@@ -1,19 +1,37 @@
 use crate::{
   byte_trait::AFPluginFromBytes,
   data::AFPluginData,
-  errors::DispatchError,
+  errors::{DispatchError, InternalError},
   request::{AFPluginEventRequest, Payload},
   response::AFPluginResponder,
 };
 use derivative::*;
-use std::{convert::TryFrom, fmt, fmt::Formatter};
+use std::{collections::HashMap, convert::TryFrom, fmt, fmt::Formatter};
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+/// `Err` is the error returned by the handler, its payload is the error of the handler. The others
+/// are reported by the dispatcher, and their payloads are the error messages.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
 #[cfg_attr(feature = "use_serde", derive(serde_repr::Serialize_repr))]
 #[repr(u8)]
 pub enum StatusCode {
   Ok = 0,
   Err = 1,
+  /// The payload can't be parsed or is rejected by the guards.
+  InvalidParams = 2,
+  /// No handler handles the event.
+  NotFound = 3,
+  Unauthorized = 4,
+  Timeout = 5,
+  Internal = 6,
+  Cancelled = 7,
+  /// The dispatcher is overloaded and sheds the request.
+  Busy = 8,
+}
+
+impl StatusCode {
+  pub fn is_ok(&self) -> bool {
+    *self == StatusCode::Ok
+  }
 }
 
 // serde user guide: https://serde.rs/field-attrs.html
@@ -23,6 +41,18 @@ pub struct AFPluginEventResponse {
   #[derivative(Debug = "ignore")]
   pub payload: Payload,
   pub status_code: StatusCode,
+  /// Set if the response is built from a retryable error.
+  #[cfg_attr(feature = "use_serde", serde(skip))]
+  pub(crate) retryable: bool,
+  /// The correlation id of the request that the response belongs to.
+  #[cfg_attr(feature = "use_serde", serde(skip_serializing_if = "Option::is_none"))]
+  pub correlation_id: Option<String>,
+  /// The extra information of the response, e.g. the version of the entity.
+  #[cfg_attr(
+    feature = "use_serde",
+    serde(skip_serializing_if = "HashMap::is_empty")
+  )]
+  pub metadata: HashMap<String, String>,
 }
 
 impl AFPluginEventResponse {
@@ -30,9 +60,24 @@ impl AFPluginEventResponse {
     AFPluginEventResponse {
       payload: Payload::None,
       status_code,
+      retryable: false,
+      correlation_id: None,
+      metadata: HashMap::new(),
     }
   }
 
+  pub fn is_retryable(&self) -> bool {
+    self.retryable
+  }
+
+  pub fn metadata(&self, key: &str) -> Option<&str> {
+    self.metadata.get(key).map(|value| value.as_str())
+  }
+
+  pub fn insert_metadata<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+    self.metadata.insert(key.into(), value.into());
+  }
+
   pub fn parse<T, E>(self) -> Result<Result<T, E>, DispatchError>
   where
     T: AFPluginFromBytes,
@@ -47,6 +92,9 @@ impl AFPluginEventResponse {
         let err = <AFPluginData<E>>::try_from(self.payload)?;
         Ok(Err(err.into_inner()))
       },
+      _ => Err(
+        InternalError::Other(String::from_utf8_lossy(self.payload.as_ref()).into_owned()).into(),
+      ),
     }
   }
 }
@@ -0,0 +1,81 @@
+use bytes::Bytes;
+use futures::StreamExt;
+
+use crate::dispatcher::af_spawn;
+use crate::errors::{DispatchError, InternalError};
+use crate::request::AFPluginEventRequest;
+use crate::response::{AFPluginEventResponse, AFPluginResponder, ResponseBuilder};
+
+#[cfg(any(target_arch = "wasm32", feature = "local_set"))]
+pub type AFBoxStream<'a, T> = futures::stream::LocalBoxStream<'a, T>;
+
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
+pub type AFBoxStream<'a, T> = futures::stream::BoxStream<'a, T>;
+
+/// Returned by the handler that produces a large result, e.g. exporting the workspace, so the
+/// result is delivered chunk by chunk instead of being buffered in memory.
+///
+/// Every chunk is delivered as a partial response to the caller of
+/// `AFPluginDispatcher::async_send_with_stream`, and the final response is empty. The stream
+/// stops at the first error, which is delivered as the last chunk.
+pub struct AFPluginResponseStream {
+  stream: AFBoxStream<'static, Result<Bytes, DispatchError>>,
+}
+
+impl AFPluginResponseStream {
+  #[cfg(any(target_arch = "wasm32", feature = "local_set"))]
+  pub fn new<S>(stream: S) -> Self
+  where
+    S: futures::Stream<Item = Result<Bytes, DispatchError>> + 'static,
+  {
+    Self {
+      stream: stream.boxed_local(),
+    }
+  }
+
+  #[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
+  pub fn new<S>(stream: S) -> Self
+  where
+    S: futures::Stream<Item = Result<Bytes, DispatchError>> + Send + 'static,
+  {
+    Self {
+      stream: stream.boxed(),
+    }
+  }
+}
+
+impl AFPluginResponder for AFPluginResponseStream {
+  fn respond_to(self, request: &AFPluginEventRequest) -> AFPluginEventResponse {
+    let sender = match request.progress.clone() {
+      Some(sender) => sender,
+      None => {
+        return InternalError::Other(format!(
+          "[dispatch]: the stream response of {:?} requires async_send_with_stream",
+          request.event
+        ))
+        .into()
+      },
+    };
+
+    let cancel_token = request.cancel_token.clone();
+    let mut stream = self.stream;
+    af_spawn(async move {
+      loop {
+        let chunk = tokio::select! {
+          _ = cancel_token.cancelled() => break,
+          chunk = stream.next() => chunk,
+        };
+        let (response, is_err) = match chunk {
+          None => break,
+          Some(Ok(bytes)) => (ResponseBuilder::Ok().data(bytes).build(), false),
+          Some(Err(err)) => (err.into(), true),
+        };
+        // Stop producing the chunks if the caller stops listening.
+        if sender.send(response).is_err() || is_err {
+          break;
+        }
+      }
+    });
+    ResponseBuilder::Ok().build()
+  }
+}
@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+/// Retries the request whose handler returns a retryable error.
+///
+/// An error is retryable if its [Error::kind] is [DispatchErrorKind::Transient], e.g. the
+/// database is locked. The handler marks its error by [DispatchError::transient]. The request is
+/// dispatched again after the backoff, which is doubled after each attempt until it reaches the
+/// `max_backoff`.
+///
+/// [Error::kind]: crate::Error::kind
+/// [DispatchErrorKind::Transient]: crate::prelude::DispatchErrorKind::Transient
+/// [DispatchError::transient]: crate::prelude::DispatchError::transient
+#[derive(Clone, Debug)]
+pub struct DispatchRetryPolicy {
+  /// The number of attempts including the first one.
+  pub max_attempts: usize,
+  pub initial_backoff: Duration,
+  pub max_backoff: Duration,
+}
+
+impl DispatchRetryPolicy {
+  pub fn new(max_attempts: usize) -> Self {
+    Self {
+      max_attempts,
+      initial_backoff: Duration::from_millis(100),
+      max_backoff: Duration::from_secs(5),
+    }
+  }
+
+  pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+    self.initial_backoff = backoff;
+    self
+  }
+
+  pub fn max_backoff(mut self, backoff: Duration) -> Self {
+    self.max_backoff = backoff;
+    self
+  }
+
+  /// Returns the backoff before the next attempt. The `attempt` starts from 1.
+  pub(crate) fn backoff(&self, attempt: usize) -> Duration {
+    let exp = attempt.saturating_sub(1).min(u32::MAX as usize) as u32;
+    let factor = 2u32.saturating_pow(exp);
+    self
+      .initial_backoff
+      .saturating_mul(factor)
+      .min(self.max_backoff)
+  }
+}
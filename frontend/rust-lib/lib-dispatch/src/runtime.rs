@@ -1,17 +1,30 @@
 use std::fmt::{Display, Formatter};
 use std::future::Future;
 use std::io;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use tokio::runtime;
-use tokio::runtime::Runtime;
+use tokio::runtime::{Handle, Runtime};
 use tokio::task::JoinHandle;
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::errors::{DispatchError, InternalError};
+use crate::prelude::{AFBoxFuture, AFConcurrent};
+
 pub struct AFPluginRuntime {
-  inner: Runtime,
+  inner: RuntimeInner,
   #[cfg(any(target_arch = "wasm32", feature = "local_set"))]
   local: tokio::task::LocalSet,
 }
 
+/// The runtime is either created by the dispatcher or shared by the host application.
+enum RuntimeInner {
+  Owned(Runtime),
+  Shared(Handle),
+}
+
 impl Display for AFPluginRuntime {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
     if cfg!(any(target_arch = "wasm32", feature = "local_set")) {
@@ -24,12 +37,57 @@ impl Display for AFPluginRuntime {
 
 impl AFPluginRuntime {
   pub fn new() -> io::Result<Self> {
-    let inner = default_tokio_runtime()?;
-    Ok(Self {
-      inner,
+    Ok(Self::with_inner(default_tokio_runtime()?))
+  }
+
+  /// Creates the runtime with `worker_threads` threads. The local set mode always runs on a
+  /// single thread, so the `worker_threads` is ignored.
+  pub fn with_worker_threads(worker_threads: usize) -> io::Result<Self> {
+    #[cfg(any(target_arch = "wasm32", feature = "local_set"))]
+    let inner = {
+      let _ = worker_threads;
+      default_tokio_runtime()?
+    };
+    #[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
+    let inner = multi_thread_runtime(Some(worker_threads))?;
+    Ok(Self::with_inner(inner))
+  }
+
+  /// Runs the dispatcher on the runtime of the host application instead of creating one, so they
+  /// don't compete for the threads. The host keeps the runtime alive as long as the dispatcher.
+  ///
+  /// In the local set mode, the handlers run on the dispatcher's own local set, which is driven by
+  /// the shared runtime.
+  pub fn from_handle(handle: Handle) -> Self {
+    Self {
+      inner: RuntimeInner::Shared(handle),
       #[cfg(any(target_arch = "wasm32", feature = "local_set"))]
       local: tokio::task::LocalSet::new(),
-    })
+    }
+  }
+
+  /// Same as [AFPluginRuntime::from_handle] with the runtime that the caller runs on.
+  ///
+  /// # Panics
+  ///
+  /// Panics if it's not called inside a tokio runtime.
+  pub fn current() -> Self {
+    Self::from_handle(Handle::current())
+  }
+
+  pub fn handle(&self) -> &Handle {
+    match &self.inner {
+      RuntimeInner::Owned(runtime) => runtime.handle(),
+      RuntimeInner::Shared(handle) => handle,
+    }
+  }
+
+  fn with_inner(inner: Runtime) -> Self {
+    Self {
+      inner: RuntimeInner::Owned(inner),
+      #[cfg(any(target_arch = "wasm32", feature = "local_set"))]
+      local: tokio::task::LocalSet::new(),
+    }
   }
 
   #[cfg(any(target_arch = "wasm32", feature = "local_set"))]
@@ -48,7 +106,7 @@ impl AFPluginRuntime {
     F: Future + Send + 'static,
     <F as Future>::Output: Send + 'static,
   {
-    self.inner.spawn(future)
+    self.handle().spawn(future)
   }
 
   #[cfg(any(target_arch = "wasm32", feature = "local_set"))]
@@ -73,7 +131,10 @@ impl AFPluginRuntime {
   where
     F: Future,
   {
-    self.local.block_on(&self.inner, f)
+    match &self.inner {
+      RuntimeInner::Owned(runtime) => self.local.block_on(runtime, f),
+      RuntimeInner::Shared(handle) => handle.block_on(self.local.run_until(f)),
+    }
   }
 
   #[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
@@ -82,20 +143,156 @@ impl AFPluginRuntime {
   where
     F: Future,
   {
-    self.inner.block_on(f)
+    match &self.inner {
+      RuntimeInner::Owned(runtime) => runtime.block_on(f),
+      RuntimeInner::Shared(handle) => handle.block_on(f),
+    }
+  }
+}
+
+/// Runs the detached tasks of the dispatcher, e.g. the dispatch of the requests and the
+/// callbacks, so the dispatcher isn't tied to the spawn of tokio. [AFPluginRuntime] spawns them
+/// on tokio and [WasmSpawner] on the event loop of the browser. See
+/// [AFPluginDispatcherBuilder::spawner].
+///
+/// The channels of the dispatcher are the runtime-agnostic ones of `tokio::sync`, so they work
+/// with any spawner.
+///
+/// [AFPluginDispatcherBuilder::spawner]: crate::prelude::AFPluginDispatcherBuilder::spawner
+pub trait AFPluginSpawner: AFConcurrent {
+  fn spawn_detached(&self, future: AFBoxFuture<'static, ()>);
+}
+
+impl AFPluginSpawner for AFPluginRuntime {
+  fn spawn_detached(&self, future: AFBoxFuture<'static, ()>) {
+    let _ = self.spawn(future);
+  }
+}
+
+/// Spawns the tasks with `wasm_bindgen_futures::spawn_local`, so they are driven by the event
+/// loop of the browser instead of a blocking tokio runtime. It's the default spawner on wasm32.
+#[cfg(target_arch = "wasm32")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WasmSpawner;
+
+#[cfg(target_arch = "wasm32")]
+impl AFPluginSpawner for WasmSpawner {
+  fn spawn_detached(&self, future: AFBoxFuture<'static, ()>) {
+    wasm_bindgen_futures::spawn_local(future);
+  }
+}
+
+/// The spawner of the dispatcher that is created with the `runtime`.
+pub(crate) fn default_spawner(runtime: Arc<AFPluginRuntime>) -> Arc<dyn AFPluginSpawner> {
+  #[cfg(target_arch = "wasm32")]
+  {
+    let _ = runtime;
+    Arc::new(WasmSpawner)
+  }
+  #[cfg(not(target_arch = "wasm32"))]
+  {
+    runtime
+  }
+}
+
+/// Returns the multi-threaded runtime that runs the handlers registered by
+/// [AFPlugin::send_event]. It's the dispatcher's runtime in the multi-thread mode.
+///
+/// [AFPlugin::send_event]: crate::prelude::AFPlugin::send_event
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
+pub(crate) fn send_runtime_handle() -> tokio::runtime::Handle {
+  tokio::runtime::Handle::current()
+}
+
+/// Returns the multi-threaded runtime that runs the handlers registered by
+/// [AFPlugin::send_event]. It's created on first use in the local set mode.
+///
+/// [AFPlugin::send_event]: crate::prelude::AFPlugin::send_event
+#[cfg(all(not(target_arch = "wasm32"), feature = "local_set"))]
+pub(crate) fn send_runtime_handle() -> tokio::runtime::Handle {
+  static SEND_RUNTIME: std::sync::OnceLock<Runtime> = std::sync::OnceLock::new();
+  SEND_RUNTIME
+    .get_or_init(|| {
+      runtime::Builder::new_multi_thread()
+        .thread_name("dispatch-rt-send")
+        .enable_all()
+        .build()
+        .expect("Failed to create the multi-thread runtime of the send handlers")
+    })
+    .handle()
+    .clone()
+}
+
+/// The default max number of the threads of the blocking pool. See [set_blocking_threads].
+pub const DEFAULT_BLOCKING_THREADS: usize = 16;
+
+#[cfg(not(target_arch = "wasm32"))]
+static BLOCKING_THREADS: AtomicUsize = AtomicUsize::new(DEFAULT_BLOCKING_THREADS);
+
+#[cfg(not(target_arch = "wasm32"))]
+static BLOCKING_RUNTIME: std::sync::OnceLock<Runtime> = std::sync::OnceLock::new();
+
+/// Sets the max number of the threads that run the handlers registered by
+/// [AFPlugin::blocking_event]. Fails once the first blocking handler has run, the pool is
+/// already created with its size.
+///
+/// [AFPlugin::blocking_event]: crate::prelude::AFPlugin::blocking_event
+#[cfg(not(target_arch = "wasm32"))]
+pub fn set_blocking_threads(threads: usize) -> Result<(), DispatchError> {
+  if BLOCKING_RUNTIME.get().is_some() {
+    let msg = format!(
+      "[dispatch]: set {} blocking threads failed, the blocking pool is already created with {}",
+      threads,
+      BLOCKING_THREADS.load(Ordering::SeqCst)
+    );
+    return Err(InternalError::Other(msg).into());
   }
+  BLOCKING_THREADS.store(threads.max(1), Ordering::SeqCst);
+  Ok(())
+}
+
+/// Returns the runtime whose blocking pool runs the handlers registered by
+/// [AFPlugin::blocking_event]. It's created on first use and has no worker threads, so it
+/// doesn't compete with the runtimes that run the async handlers.
+///
+/// [AFPlugin::blocking_event]: crate::prelude::AFPlugin::blocking_event
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn blocking_runtime_handle() -> tokio::runtime::Handle {
+  BLOCKING_RUNTIME
+    .get_or_init(|| {
+      runtime::Builder::new_current_thread()
+        .thread_name("dispatch-rt-blocking")
+        .max_blocking_threads(BLOCKING_THREADS.load(Ordering::SeqCst))
+        .enable_all()
+        .build()
+        .expect("Failed to create the blocking runtime of the blocking handlers")
+    })
+    .handle()
+    .clone()
 }
 
 #[cfg(any(target_arch = "wasm32", feature = "local_set"))]
 pub fn default_tokio_runtime() -> io::Result<Runtime> {
-  runtime::Builder::new_current_thread()
-    .thread_name("dispatch-rt-st")
-    .build()
+  let mut builder = runtime::Builder::new_current_thread();
+  builder.thread_name("dispatch-rt-st");
+  // The timer is required by the request timeout. It's not available on wasm.
+  #[cfg(not(target_arch = "wasm32"))]
+  builder.enable_time();
+  builder.build()
 }
 
 #[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
 pub fn default_tokio_runtime() -> io::Result<Runtime> {
-  runtime::Builder::new_multi_thread()
+  multi_thread_runtime(None)
+}
+
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "local_set")))]
+fn multi_thread_runtime(worker_threads: Option<usize>) -> io::Result<Runtime> {
+  let mut builder = runtime::Builder::new_multi_thread();
+  if let Some(worker_threads) = worker_threads {
+    builder.worker_threads(worker_threads);
+  }
+  builder
     .thread_name("dispatch-rt-mt")
     .enable_io()
     .enable_time()
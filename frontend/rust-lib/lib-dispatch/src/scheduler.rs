@@ -0,0 +1,902 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::{oneshot, Notify, OwnedSemaphorePermit};
+use tokio::time::Instant;
+
+use crate::audit::AuditSink;
+use crate::builder::DispatchPanicPolicy;
+use crate::cache::DispatchCache;
+use crate::coalesce::DispatchCoalescer;
+use crate::config::DispatchConfig;
+use crate::dead_letter::DeadLetterSink;
+use crate::dispatcher::{AFStateMap, DispatchContext, DispatchService};
+use crate::errors::{Error, InternalError};
+#[cfg(all(feature = "forward", not(target_arch = "wasm32")))]
+use crate::forward::DispatchForwarder;
+use crate::graph::{current_event, DispatchEventGraph};
+use crate::history::DispatchHistory;
+use crate::idempotency::DispatchIdempotency;
+use crate::inflight::DispatchInFlight;
+use crate::interceptor::DispatchInterceptor;
+use crate::journal::{DispatchJournal, JournalEntry};
+use crate::lifecycle::{DispatchLifecycle, DispatchLifecycleChannel};
+use crate::localize::DispatchLocalizer;
+use crate::logger::DispatchLogger;
+use crate::metrics::{DispatchMetrics, HighWater, HighWaterMark};
+#[cfg(feature = "test_helper")]
+use crate::mock::DispatchMocks;
+use crate::module::{AFPlugin, AFPluginRequest, DispatchRoutes, DuplicatePolicy, PluginHook};
+use crate::observer::DispatchErrorObserver;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::replay::DispatchRecorder;
+use crate::response::AFPluginEventResponse;
+use crate::retry::DispatchRetryPolicy;
+use crate::runtime::{default_spawner, AFPluginRuntime, AFPluginSpawner};
+use crate::stats::DispatchStats;
+use crate::supervisor::{supervise, DispatchSupervisor};
+use crate::watchdog::DispatchWatchdog;
+
+/// The priority of a request.
+///
+/// When the dispatcher reaches its concurrency limit, the pending requests with higher priority
+/// are started before the lower ones. Requests with the same priority are started in FIFO order.
+/// The priority has no effect without [AFPluginDispatcherBuilder::max_concurrent], every request
+/// is started right away.
+///
+/// [AFPluginDispatcherBuilder::max_concurrent]: crate::prelude::AFPluginDispatcherBuilder::max_concurrent
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DispatchPriority {
+  /// User-facing events, e.g. the keystrokes in the editor.
+  High = 0,
+  #[default]
+  Normal = 1,
+  /// Events that can be delayed, e.g. the background syncing.
+  Background = 2,
+}
+
+impl DispatchPriority {
+  const COUNT: usize = 3;
+
+  fn lane(&self) -> usize {
+    *self as usize
+  }
+}
+
+/// Decides what happens to the requests that exceed the concurrency limit. It only takes effect
+/// with [AFPluginDispatcherBuilder::max_concurrent], the dispatcher without the limit never sheds
+/// the requests.
+///
+/// The shed requests are responded with the [DispatchErrorCode::Busy] error, which is
+/// [DispatchErrorKind::Transient], so the callers can send them again after a while.
+///
+/// [AFPluginDispatcherBuilder::max_concurrent]: crate::prelude::AFPluginDispatcherBuilder::max_concurrent
+/// [DispatchErrorCode::Busy]: crate::prelude::DispatchErrorCode::Busy
+/// [DispatchErrorKind::Transient]: crate::prelude::DispatchErrorKind::Transient
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DispatchLoadShedding {
+  /// The exceeding requests wait in the queue, however long it grows.
+  #[default]
+  Queue,
+  /// Rejects the requests right away if all the running slots are taken.
+  Reject,
+  /// Allows at most the given number of requests to wait in the queue, the exceeding ones are
+  /// rejected.
+  BoundedQueue(usize),
+}
+
+impl DispatchLoadShedding {
+  /// The max number of the running and the queued requests.
+  fn bound(&self, max_concurrent: usize) -> Option<usize> {
+    match self {
+      DispatchLoadShedding::Queue => None,
+      DispatchLoadShedding::Reject => Some(max_concurrent),
+      DispatchLoadShedding::BoundedQueue(max_queued) => {
+        Some(max_concurrent.saturating_add(*max_queued))
+      },
+    }
+  }
+}
+
+pub(crate) struct DispatchTask {
+  pub(crate) ctx: DispatchContext,
+  /// The capacity permit of the dispatcher. It's released after the task is completed.
+  pub(crate) permit: Option<OwnedSemaphorePermit>,
+  /// `None` if the request is fire-and-forget, nobody waits for its response.
+  pub(crate) ret: Option<oneshot::Sender<AFPluginEventResponse>>,
+  pub(crate) enqueued_at: Instant,
+  /// Set if the request is journaled, it's completed in the journal with the response.
+  journal: Option<Arc<dyn DispatchJournal>>,
+}
+
+impl DispatchTask {
+  /// The `enqueued_at` is the time the request is sent, including the time it waits for the
+  /// capacity of the dispatcher.
+  pub(crate) fn new(
+    ctx: DispatchContext,
+    permit: Option<OwnedSemaphorePermit>,
+    ret: Option<oneshot::Sender<AFPluginEventResponse>>,
+    enqueued_at: Instant,
+  ) -> Self {
+    Self {
+      ctx,
+      permit,
+      ret,
+      enqueued_at,
+      journal: None,
+    }
+  }
+
+  fn is_cancelled(&self) -> bool {
+    self.ctx.request.cancel_token.is_cancelled()
+  }
+
+  fn lane(&self) -> usize {
+    self.ctx.request.priority.lane()
+  }
+
+  fn ordering_key(&self) -> Option<String> {
+    self.ctx.request.ordering_key.clone()
+  }
+}
+
+/// Starts the dispatched requests with the spawner.
+///
+/// Without the concurrency limit, every request is spawned right away. Otherwise, the requests
+/// that exceed the limit wait in the lane of their [DispatchPriority] until one of the running
+/// requests is completed.
+///
+/// The requests that share the same ordering key are executed one by one in FIFO order. Only the
+/// first one is put into the lane, the others wait until their predecessor is completed.
+///
+/// While paused, the new tasks are buffered in the lanes and started after resuming.
+pub(crate) struct DispatchScheduler {
+  /// The routing table. It's replaced as a whole when the plugins are changed, so the running
+  /// requests keep using the snapshot they started with.
+  routes: RwLock<DispatchRoutes>,
+  pub(crate) spawner: Arc<dyn AFPluginSpawner>,
+  pub(crate) max_concurrent: Option<usize>,
+  pub(crate) load_shedding: DispatchLoadShedding,
+  pub(crate) duplicate_policy: DuplicatePolicy,
+  pub(crate) retry_policy: Option<DispatchRetryPolicy>,
+  pub(crate) default_timeout: Option<Duration>,
+  pub(crate) panic_policy: DispatchPanicPolicy,
+  pub(crate) watchdog: Option<Arc<DispatchWatchdog>>,
+  pub(crate) slow_poll_budget: Option<Duration>,
+  pub(crate) supervisor: Option<Arc<DispatchSupervisor>>,
+  pub(crate) dead_letter: Option<Arc<dyn DeadLetterSink>>,
+  pub(crate) error_observer: Option<Arc<dyn DispatchErrorObserver>>,
+  pub(crate) localizer: Option<Arc<dyn DispatchLocalizer>>,
+  pub(crate) high_water: Option<HighWaterMark>,
+  pub(crate) history: Option<Arc<DispatchHistory>>,
+  pub(crate) stats: Arc<DispatchStats>,
+  pub(crate) logger: Option<Arc<DispatchLogger>>,
+  pub(crate) audit: Option<Arc<dyn AuditSink>>,
+  pub(crate) journal: Option<Arc<dyn DispatchJournal>>,
+  pub(crate) in_flight: Arc<DispatchInFlight>,
+  pub(crate) event_graph: DispatchEventGraph,
+  #[cfg(not(target_arch = "wasm32"))]
+  pub(crate) recorder: Option<Arc<DispatchRecorder>>,
+  #[cfg(all(feature = "forward", not(target_arch = "wasm32")))]
+  pub(crate) forwarders: Arc<Vec<Arc<DispatchForwarder>>>,
+  #[cfg(feature = "test_helper")]
+  pub(crate) mocks: Arc<DispatchMocks>,
+  /// The states that are shared by all the plugins. See [AFPluginDispatcherBuilder::state].
+  pub(crate) states: AFStateMap,
+  pub(crate) interceptors: Arc<Vec<Box<dyn DispatchInterceptor>>>,
+  pub(crate) compression_threshold: usize,
+  cache: DispatchCache,
+  pub(crate) idempotency: Arc<DispatchIdempotency>,
+  coalescer: Arc<DispatchCoalescer>,
+  state: Mutex<SchedulerState>,
+  /// Once closed, the new tasks are rejected with the shutdown error.
+  closed: AtomicBool,
+  paused: AtomicBool,
+  /// Notified when there is no running or pending task.
+  idle: Notify,
+  pub(crate) lifecycle: DispatchLifecycleChannel,
+  /// Set once the shutdown is completed.
+  stopped: AtomicBool,
+  on_stopped: Notify,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+  running: usize,
+  lanes: [VecDeque<DispatchTask>; DispatchPriority::COUNT],
+  /// The key exists if one of its tasks is pending in the lane or running. The value is the
+  /// tasks that wait for it.
+  ordered: HashMap<String, VecDeque<DispatchTask>>,
+  /// The number of the requests that wait for the capacity of the dispatcher, they are not
+  /// scheduled yet.
+  waiting_capacity: usize,
+  last_queue_latency: Duration,
+  max_queue_latency: Duration,
+  /// The number of the requests that are rejected by the [DispatchLoadShedding].
+  shed: u64,
+  above_high_water: bool,
+}
+
+impl SchedulerState {
+  fn is_idle(&self) -> bool {
+    self.running == 0 && self.lanes.iter().all(|lane| lane.is_empty()) && self.ordered.is_empty()
+  }
+
+  fn enqueue(&mut self, task: DispatchTask) {
+    match task.ordering_key() {
+      // Waits for the predecessor that has the same key.
+      Some(key) if self.ordered.contains_key(&key) => {
+        self.ordered.entry(key).or_default().push_back(task);
+      },
+      key => {
+        if let Some(key) = key {
+          self.ordered.insert(key, VecDeque::new());
+        }
+        let lane = task.lane();
+        self.lanes[lane].push_back(task);
+      },
+    }
+  }
+
+  /// The number of the scheduled tasks that wait to be started.
+  fn pending(&self) -> usize {
+    let pending: usize = self.lanes.iter().map(|lane| lane.len()).sum();
+    let waiting: usize = self.ordered.values().map(|tasks| tasks.len()).sum();
+    pending + waiting
+  }
+
+  /// Same as [SchedulerState::pending] including the requests that wait for the capacity.
+  fn queued(&self) -> usize {
+    self.pending() + self.waiting_capacity
+  }
+
+  /// Returns the next task of the key. The key is released if there is no waiting task.
+  fn next_ordered(&mut self, key: &str) -> Option<DispatchTask> {
+    let next = self.ordered.get_mut(key)?.pop_front();
+    if next.is_none() {
+      self.ordered.remove(key);
+    }
+    next
+  }
+}
+
+impl DispatchScheduler {
+  pub(crate) fn new(
+    routes: DispatchRoutes,
+    runtime: Arc<AFPluginRuntime>,
+    config: DispatchConfig,
+  ) -> Self {
+    let mut routes = routes;
+    routes.renames = Arc::new(config.renames);
+    // The handlers extract the cache from the shared states to invalidate it.
+    let cache = DispatchCache::default();
+    let mut states = config.states;
+    states.insert(cache.clone());
+    let lifecycle = DispatchLifecycleChannel::default();
+    states.insert(lifecycle.clone());
+    Self {
+      routes: RwLock::new(routes),
+      spawner: config.spawner.unwrap_or_else(|| default_spawner(runtime)),
+      max_concurrent: config.max_concurrent,
+      load_shedding: config.load_shedding,
+      duplicate_policy: config.duplicate_policy,
+      retry_policy: config.retry_policy,
+      default_timeout: config.default_timeout,
+      panic_policy: config.panic_policy,
+      watchdog: config.watchdog,
+      slow_poll_budget: config.slow_poll_budget,
+      supervisor: config.supervisor,
+      dead_letter: config.dead_letter,
+      error_observer: config.error_observer,
+      localizer: config.localizer,
+      high_water: config.high_water,
+      history: config.history,
+      stats: Arc::new(DispatchStats::default()),
+      logger: config.logger,
+      audit: config.audit,
+      journal: config.journal,
+      in_flight: Arc::new(DispatchInFlight::default()),
+      event_graph: DispatchEventGraph::default(),
+      #[cfg(not(target_arch = "wasm32"))]
+      recorder: config.recorder,
+      #[cfg(all(feature = "forward", not(target_arch = "wasm32")))]
+      forwarders: Arc::new(config.forwarders),
+      #[cfg(feature = "test_helper")]
+      mocks: Arc::new(DispatchMocks::default()),
+      states: Arc::new(states),
+      interceptors: Arc::new(config.interceptors),
+      compression_threshold: config.compression_threshold,
+      cache,
+      idempotency: Arc::new(config.idempotency),
+      coalescer: Arc::new(DispatchCoalescer::default()),
+      state: Mutex::new(SchedulerState::default()),
+      closed: AtomicBool::new(false),
+      paused: AtomicBool::new(false),
+      idle: Notify::new(),
+      lifecycle,
+      stopped: AtomicBool::new(false),
+      on_stopped: Notify::new(),
+    }
+  }
+
+  pub(crate) fn schedule(self: &Arc<Self>, task: DispatchTask) {
+    self.schedule_batch(vec![task]);
+  }
+
+  /// Enqueues the tasks under a single lock, then starts as many of them as allowed.
+  pub(crate) fn schedule_batch(self: &Arc<Self>, mut tasks: Vec<DispatchTask>) {
+    if let Some(parent) = current_event() {
+      for task in tasks.iter() {
+        self
+          .event_graph
+          .record(parent.clone(), task.ctx.request.event.clone());
+      }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(recorder) = &self.recorder {
+      for task in tasks.iter() {
+        recorder.record(&task.ctx.request);
+      }
+    }
+    self.journal_tasks(&mut tasks);
+    if self.is_closed() {
+      for mut task in tasks {
+        let msg = format!(
+          "[dispatch]: reject event {:?}, the dispatcher is shut down",
+          task.ctx.request.event
+        );
+        tracing::warn!("{}", msg);
+        // Stays pending in the journal, e.g. the nested request of the handler that is still
+        // running during the shutdown, so it's dispatched again on the next launch.
+        task.journal = None;
+        self.reject_task(task, InternalError::Shutdown(msg));
+      }
+      return;
+    }
+
+    let bound = self
+      .max_concurrent
+      .and_then(|max_concurrent| self.load_shedding.bound(max_concurrent));
+    let shed = {
+      let mut state = self.state.lock();
+      let mut shed = vec![];
+      for task in tasks {
+        match bound {
+          Some(bound) if state.running + state.pending() >= bound => shed.push(task),
+          _ => state.enqueue(task),
+        }
+      }
+      state.shed += shed.len() as u64;
+      shed
+    };
+    for task in shed {
+      let msg = format!(
+        "[dispatch]: shed event {:?}, the dispatcher is overloaded",
+        task.ctx.request.event
+      );
+      tracing::warn!("{}", msg);
+      self.reject_task(task, InternalError::Busy(msg));
+    }
+    self.run_pending();
+    self.check_high_water();
+  }
+
+  /// Counts the `count` requests as queued until they get the capacity of the dispatcher. See
+  /// [CapacityWaiters::leave].
+  pub(crate) fn wait_capacity(self: &Arc<Self>, count: usize) -> CapacityWaiters {
+    self.state.lock().waiting_capacity += count;
+    self.check_high_water();
+    CapacityWaiters {
+      scheduler: self.clone(),
+      count,
+    }
+  }
+
+  /// Journals the tasks of the mutating events before they are queued, so they outlive the
+  /// queue if the app is killed.
+  fn journal_tasks(&self, tasks: &mut [DispatchTask]) {
+    if self.journal.is_none() {
+      return;
+    }
+    for task in tasks.iter_mut() {
+      task.journal = self.journal_request(&task.ctx.request);
+    }
+  }
+
+  /// Journals the request if its event is mutating, and returns the journal that completes it.
+  /// The request that is already journaled, e.g. while it waited for the capacity, is kept as is.
+  pub(crate) fn journal_request(
+    &self,
+    request: &AFPluginRequest,
+  ) -> Option<Arc<dyn DispatchJournal>> {
+    let journal = self.journal.as_ref()?;
+    let event = &request.event;
+    let routes = self.routes();
+    let event = routes.resolve(event).unwrap_or(event);
+    if !routes.lookup(event).map_or(false, |p| p.is_mutating(event)) {
+      return None;
+    }
+    journal.append(&JournalEntry::new(request));
+    Some(journal.clone())
+  }
+
+  fn run_pending(self: &Arc<Self>) {
+    loop {
+      let task = {
+        let mut state = self.state.lock();
+        if self.is_paused() {
+          return;
+        }
+        if let Some(max_concurrent) = self.max_concurrent {
+          if state.running >= max_concurrent {
+            return;
+          }
+        }
+
+        // The lanes are ordered from the highest priority to the lowest.
+        match state.lanes.iter_mut().find_map(|lane| lane.pop_front()) {
+          None => return,
+          Some(task) => {
+            state.running += 1;
+            task
+          },
+        }
+      };
+      self.spawn_running_task(task);
+    }
+  }
+
+  /// Spawns the task that takes one of the running slots.
+  fn spawn_running_task(self: &Arc<Self>, task: DispatchTask) {
+    {
+      let latency = task.enqueued_at.elapsed();
+      let mut state = self.state.lock();
+      state.last_queue_latency = latency;
+      state.max_queue_latency = state.max_queue_latency.max(latency);
+    }
+
+    // The guard starts the next pending task when it gets dropped, even if the handler panics.
+    let guard = RunningGuard {
+      scheduler: self.clone(),
+      ordering_key: task.ordering_key(),
+    };
+    self.spawn_task(task, Some(guard));
+  }
+
+  pub(crate) fn routes(&self) -> DispatchRoutes {
+    self.routes.read().clone()
+  }
+
+  /// Adds the plugin to the routing table. The events that are already registered are handled
+  /// according to the [DuplicatePolicy]. A second fallback handler is rejected by
+  /// [DuplicatePolicy::Error] and replaces the existing one otherwise.
+  /// Returns the plugins that are overridden by the `plugin` and don't handle any event anymore.
+  pub(crate) fn register(&self, mut plugin: AFPlugin) -> Result<Vec<Arc<AFPlugin>>, InternalError> {
+    plugin.check_namespace()?;
+    plugin.mark_registered();
+    let mut routes = self.routes.write();
+    let plugins = &routes.plugins;
+    let mut fallback = routes.fallback.clone();
+    if plugin.has_fallback() {
+      if let Some(existing) = &fallback {
+        if self.duplicate_policy == DuplicatePolicy::Error {
+          return Err(InternalError::DuplicateEvent(format!(
+            "[dispatch]: the fallback handler is already defined in {:?}",
+            existing.name
+          )));
+        }
+        tracing::warn!(
+          "[dispatch]: the fallback handler of {:?} is overridden by {:?}",
+          existing.name,
+          plugin.name
+        );
+      }
+    }
+
+    let events = plugin.events();
+    let policy = plugin
+      .own_duplicate_policy()
+      .unwrap_or(self.duplicate_policy);
+    let mut replaced: Vec<Arc<AFPlugin>> = vec![];
+    for event in events.iter() {
+      if let Some(existing) = plugins.get(event) {
+        plugin.merge_duplicate(policy, event, existing)?;
+        replaced.push(existing.clone());
+      }
+    }
+    if plugin.has_fallback() {
+      replaced.extend(fallback.clone());
+    }
+
+    let mut plugin_map = plugins.as_ref().clone();
+    let plugin = Arc::new(plugin);
+    for event in events {
+      plugin_map.insert(event, plugin.clone());
+    }
+    if plugin.has_fallback() {
+      fallback = Some(plugin);
+    }
+    *routes = routes.replace(Arc::new(plugin_map), fallback);
+
+    let mut overridden: Vec<Arc<AFPlugin>> = vec![];
+    for existing in replaced {
+      if !routes.contains_plugin(&existing) && !overridden.iter().any(|p| Arc::ptr_eq(p, &existing))
+      {
+        overridden.push(existing);
+      }
+    }
+    Ok(overridden)
+  }
+
+  /// Removes all the events of the plugin from the routing table, including the ones that are
+  /// chained by the plugins registered later. The events that the plugin chains onto the plugins
+  /// registered earlier are routed to them again. The plugins that it overrode are already torn
+  /// down, so their events are gone with it.
+  pub(crate) fn unregister(&self, name: &str) -> Option<Arc<AFPlugin>> {
+    let mut routes = self.routes.write();
+    let plugin = routes.find_plugin(name)?;
+    let mut plugin_map = HashMap::new();
+    for (event, head) in routes.plugins.iter() {
+      if Arc::ptr_eq(head, &plugin) {
+        if let Some((_, previous)) = plugin.chained(event) {
+          plugin_map.insert(event.clone(), previous);
+        }
+      } else {
+        head.unchain(event, &plugin);
+        plugin_map.insert(event.clone(), head.clone());
+      }
+    }
+    let fallback = routes.fallback.clone().filter(|p| !Arc::ptr_eq(p, &plugin));
+    *routes = routes.replace(Arc::new(plugin_map), fallback);
+    Some(plugin)
+  }
+
+  /// Removes the cancelled tasks from the lanes. They are resolved with the cancelled response
+  /// right away instead of waiting for the free slot.
+  pub(crate) fn remove_cancelled(&self) {
+    let cancelled = {
+      let mut state = self.state.lock();
+      let mut cancelled = vec![];
+      for lane in state.lanes.iter_mut() {
+        cancelled.extend(drain_cancelled(lane));
+      }
+
+      let mut successors_cancelled = vec![];
+      for successors in state.ordered.values_mut() {
+        successors_cancelled.extend(drain_cancelled(successors));
+      }
+
+      // The cancelled tasks in the lanes hand over their ordering keys to the successors.
+      for task in cancelled.iter() {
+        if let Some(key) = task.ordering_key() {
+          if let Some(next) = state.next_ordered(&key) {
+            let lane = next.lane();
+            state.lanes[lane].push_front(next);
+          }
+        }
+      }
+      cancelled.extend(successors_cancelled);
+      cancelled
+    };
+
+    for task in cancelled {
+      let msg = format!(
+        "[dispatch]: event {:?} is cancelled",
+        task.ctx.request.event
+      );
+      tracing::debug!("{}", msg);
+      self.reject_task(task, InternalError::Cancelled(msg));
+    }
+    self.check_high_water();
+  }
+
+  pub(crate) fn metrics(&self) -> DispatchMetrics {
+    let state = self.state.lock();
+    DispatchMetrics {
+      queued: state.queued(),
+      running: state.running,
+      last_queue_latency: state.last_queue_latency,
+      max_queue_latency: state.max_queue_latency,
+      shed: state.shed,
+    }
+  }
+
+  /// Notifies the listener if the queue depth crosses the high-water mark.
+  fn check_high_water(&self) {
+    let high_water = match &self.high_water {
+      None => return,
+      Some(high_water) => high_water,
+    };
+
+    let crossed = {
+      let mut state = self.state.lock();
+      let queued = state.queued();
+      let above = queued > high_water.mark;
+      if above == state.above_high_water {
+        None
+      } else {
+        state.above_high_water = above;
+        if above {
+          Some(HighWater::Exceeded { queued })
+        } else {
+          Some(HighWater::Recovered { queued })
+        }
+      }
+    };
+
+    if let Some(crossed) = crossed {
+      tracing::debug!("[dispatch]: {:?}", crossed);
+      high_water.listener.on_high_water(crossed);
+    }
+  }
+
+  /// Stops accepting new tasks. The running and pending tasks are still executed.
+  pub(crate) fn close(&self) {
+    self.closed.store(true, Ordering::SeqCst);
+  }
+
+  pub(crate) fn is_closed(&self) -> bool {
+    self.closed.load(Ordering::SeqCst)
+  }
+
+  /// Stops starting the pending tasks. The running tasks are not affected.
+  pub(crate) fn pause(&self) {
+    self.paused.store(true, Ordering::SeqCst);
+  }
+
+  /// Starts the tasks that are buffered while paused.
+  pub(crate) fn resume(self: &Arc<Self>) {
+    self.paused.store(false, Ordering::SeqCst);
+    self.run_pending();
+    self.check_high_water();
+  }
+
+  pub(crate) fn is_paused(&self) -> bool {
+    self.paused.load(Ordering::SeqCst)
+  }
+
+  pub(crate) fn mark_stopped(&self) {
+    self.stopped.store(true, Ordering::SeqCst);
+    self.on_stopped.notify_waiters();
+    self.lifecycle.publish(DispatchLifecycle::Stopped);
+  }
+
+  pub(crate) fn is_stopped(&self) -> bool {
+    self.stopped.load(Ordering::SeqCst)
+  }
+
+  /// Resolves when the shutdown is completed.
+  pub(crate) async fn wait_stopped(&self) {
+    loop {
+      let notified = self.on_stopped.notified();
+      if self.is_stopped() {
+        return;
+      }
+      notified.await;
+    }
+  }
+
+  /// Resolves when all the running and pending tasks are completed.
+  pub(crate) async fn wait_idle(&self) {
+    loop {
+      // Registers the waiter before checking the state, so the notification can't be missed.
+      let notified = self.idle.notified();
+      if self.state.lock().is_idle() {
+        return;
+      }
+      notified.await;
+    }
+  }
+
+  fn reject_task(&self, task: DispatchTask, error: InternalError) {
+    let DispatchTask {
+      ctx, ret, journal, ..
+    } = task;
+    if let Some(journal) = journal {
+      journal.complete(&ctx.request.id);
+    }
+    let (request, callback) = ctx.into_parts();
+    let ret = match ret {
+      None => return,
+      Some(ret) => ret,
+    };
+    let mut response = error.as_response();
+    response.correlation_id = request.correlation_id;
+    self.spawner.spawn_detached(Box::pin(async move {
+      if let Some(callback) = callback {
+        callback(response.clone()).await;
+      }
+      let _ = ret.send(response);
+    }));
+  }
+
+  fn spawn_task(&self, task: DispatchTask, guard: Option<RunningGuard>) {
+    let DispatchTask {
+      ctx,
+      permit,
+      ret,
+      journal,
+      ..
+    } = task;
+    let routes = self.routes();
+    // Entered as soon as the plugin is resolved from the snapshot of the routes, so the plugin
+    // that is unregistered meanwhile waits for the request, including its lazy initialization
+    // and the wait for the concurrency permit.
+    let plugin = {
+      let event = &ctx.request.event;
+      let event = routes.resolve(event).unwrap_or(event);
+      routes.lookup(event).map(|plugin| plugin.enter())
+    };
+    let service = DispatchService {
+      routes,
+      retry_policy: self.retry_policy.clone(),
+      default_timeout: self.default_timeout,
+      panic_policy: self.panic_policy,
+      watchdog: self.watchdog.clone(),
+      slow_poll_budget: self.slow_poll_budget,
+      dead_letter: self.dead_letter.clone(),
+      error_observer: self.error_observer.clone(),
+      localizer: self.localizer.clone(),
+      history: self.history.clone(),
+      stats: self.stats.clone(),
+      logger: self.logger.clone(),
+      audit: self.audit.clone(),
+      states: self.states.clone(),
+      interceptors: self.interceptors.clone(),
+      compression_threshold: self.compression_threshold,
+      cache: self.cache.clone(),
+      idempotency: self.idempotency.clone(),
+      coalescer: self.coalescer.clone(),
+      #[cfg(all(feature = "forward", not(target_arch = "wasm32")))]
+      forwarders: self.forwarders.clone(),
+      #[cfg(feature = "test_helper")]
+      mocks: self.mocks.clone(),
+    };
+
+    let event = ctx.request.event.clone();
+    let request_id = ctx.request.id.clone();
+    let cancel_token = ctx.request.cancel_token.clone();
+    let supervisor = self.supervisor.clone();
+    let in_flight = self.in_flight.enter(&ctx.request);
+    self.spawner.spawn_detached(Box::pin(async move {
+      let response = supervise(supervisor, &service, ctx).await;
+      drop(in_flight);
+      if let Some(journal) = journal {
+        journal.complete(&request_id);
+      }
+      if let Some(ret) = ret {
+        if ret.send(response).is_err() && !cancel_token.is_cancelled() {
+          tracing::warn!(
+            "[dispatch]: the response of {:?} is dropped, the caller is gone",
+            event
+          );
+        }
+      }
+      drop(plugin);
+      drop(permit);
+      drop(guard);
+    }));
+  }
+
+  fn complete(self: &Arc<Self>, ordering_key: Option<String>) {
+    let next = {
+      let mut state = self.state.lock();
+      match ordering_key.and_then(|key| state.next_ordered(&key)) {
+        Some(task) if !self.is_paused() => Some(task),
+        next => {
+          // The successor waits in the lane while paused.
+          if let Some(task) = next {
+            let lane = task.lane();
+            state.lanes[lane].push_front(task);
+          }
+          state.running -= 1;
+          None
+        },
+      }
+    };
+
+    match next {
+      // The successor takes over the running slot of the completed task.
+      Some(task) => self.spawn_running_task(task),
+      None => {
+        self.run_pending();
+        if self.state.lock().is_idle() {
+          self.idle.notify_waiters();
+          self.lifecycle.publish(DispatchLifecycle::QueueIdle);
+          self.run_idle_hooks();
+        }
+      },
+    }
+    self.check_high_water();
+  }
+
+  fn run_idle_hooks(&self) {
+    let plugins = self
+      .routes()
+      .unique_plugins()
+      .into_iter()
+      .filter(|plugin| plugin.has_hook(PluginHook::Idle))
+      .collect::<Vec<_>>();
+    if plugins.is_empty() {
+      return;
+    }
+
+    self.spawner.spawn_detached(Box::pin(async move {
+      for plugin in plugins {
+        plugin.run_hook(PluginHook::Idle).await;
+      }
+    }));
+  }
+
+  /// Runs the hook of every plugin one by one. The `on_stop` hooks are called in the reverse
+  /// dependency order.
+  /// The plugins stop in the reverse order of the start. If their dependencies can't be resolved,
+  /// e.g. a dependency is unregistered, they still stop in the reverse order of the
+  /// registration, so their resources are released.
+  pub(crate) async fn run_hooks(&self, kind: PluginHook) -> Result<(), InternalError> {
+    let routes = self.routes();
+    let mut plugins = match routes.plugins_in_dependency_order() {
+      Ok(plugins) => plugins,
+      Err(e) if kind == PluginHook::Stop => {
+        tracing::warn!("{}, stop the plugins in the order of the registration", e);
+        routes.plugins_in_registration_order()
+      },
+      Err(e) => return Err(e),
+    };
+    if kind == PluginHook::Stop {
+      plugins.reverse();
+    }
+    for plugin in plugins {
+      plugin.run_hook(kind).await;
+    }
+    Ok(())
+  }
+}
+
+fn drain_cancelled(tasks: &mut VecDeque<DispatchTask>) -> VecDeque<DispatchTask> {
+  let (cancelled, pending): (VecDeque<_>, VecDeque<_>) =
+    tasks.drain(..).partition(|task| task.is_cancelled());
+  *tasks = pending;
+  cancelled
+}
+
+/// The requests that wait for the capacity of the dispatcher. The ones that are left are not
+/// counted anymore when it gets dropped.
+pub(crate) struct CapacityWaiters {
+  scheduler: Arc<DispatchScheduler>,
+  count: usize,
+}
+
+impl CapacityWaiters {
+  /// Called when one of the requests gets the capacity or gives up on it.
+  pub(crate) fn leave(&mut self) {
+    if self.count > 0 {
+      self.count -= 1;
+      self.scheduler.state.lock().waiting_capacity -= 1;
+      self.scheduler.check_high_water();
+    }
+  }
+}
+
+impl Drop for CapacityWaiters {
+  fn drop(&mut self) {
+    if self.count > 0 {
+      self.scheduler.state.lock().waiting_capacity -= self.count;
+      self.scheduler.check_high_water();
+    }
+  }
+}
+
+struct RunningGuard {
+  scheduler: Arc<DispatchScheduler>,
+  ordering_key: Option<String>,
+}
+
+impl Drop for RunningGuard {
+  fn drop(&mut self) {
+    self.scheduler.complete(self.ordering_key.take());
+  }
+}
@@ -0,0 +1,51 @@
+use std::{future::Future, marker::PhantomData};
+
+#[cfg(feature = "trace_requests")]
+use tracing::Instrument;
+
+use crate::{
+  response::AFPluginResponder,
+  runtime::{blocking_runtime_handle, send_runtime_handle},
+  service::{AFPluginHandler, SendHandlerFuture},
+};
+
+/// Runs the handler on the blocking pool, so the blocking calls of the handler, e.g. sqlite and
+/// file IO, don't freeze the other events. See [AFPlugin::blocking_event].
+///
+/// [AFPlugin::blocking_event]: crate::prelude::AFPlugin::blocking_event
+pub struct AFPluginBlockingHandler<H, R> {
+  handler: H,
+  _phantom: PhantomData<fn() -> R>,
+}
+
+impl<H, R> AFPluginBlockingHandler<H, R> {
+  pub fn new(handler: H) -> Self {
+    Self {
+      handler,
+      _phantom: PhantomData,
+    }
+  }
+}
+
+impl<H: Clone, R> Clone for AFPluginBlockingHandler<H, R> {
+  fn clone(&self) -> Self {
+    Self::new(self.handler.clone())
+  }
+}
+
+impl<H, T, R> AFPluginHandler<T, SendHandlerFuture<R::Output>> for AFPluginBlockingHandler<H, R>
+where
+  H: AFPluginHandler<T, R> + Send + Sync,
+  R: Future + Send + 'static,
+  R::Output: AFPluginResponder + Send + 'static,
+{
+  fn call(&self, param: T) -> SendHandlerFuture<R::Output> {
+    let fut = self.handler.call(param);
+    #[cfg(feature = "trace_requests")]
+    let fut = fut.in_current_span();
+    // The timers and IO of the handler are driven by the multi-threaded runtime.
+    let runtime = send_runtime_handle();
+    let handle = blocking_runtime_handle().spawn_blocking(move || runtime.block_on(fut));
+    SendHandlerFuture::new(handle)
+  }
+}
@@ -10,7 +10,8 @@ use pin_project::pin_project;
 
 use crate::dispatcher::AFConcurrent;
 use crate::{
-  errors::DispatchError,
+  errors::{AFPluginExtractError, DispatchError, InternalError},
+  module::AFPluginEvent,
   request::{AFPluginEventRequest, FromAFPluginRequest},
   response::{AFPluginEventResponse, AFPluginResponder},
   service::{AFPluginServiceFactory, Service, ServiceRequest, ServiceResponse},
@@ -99,11 +100,13 @@ where
   fn call(&self, req: ServiceRequest) -> Self::Future {
     let (req, mut payload) = req.into_parts();
     let fut = T::from_request(&req, &mut payload);
-    HandlerServiceFuture::Extract(fut, Some(req), self.handler.clone())
+    HandlerServiceFuture::Extract(fut, req, self.handler.clone())
   }
 }
 
-#[pin_project(project = HandlerServiceProj)]
+/// Every state owns the request, which is moved to the next state by replacing the current one
+/// with `Done`.
+#[pin_project(project = HandlerServiceProj, project_replace = HandlerServiceProjOwn)]
 pub enum HandlerServiceFuture<H, T, R>
 where
   H: AFPluginHandler<T, R>,
@@ -111,8 +114,9 @@ where
   R: Future + AFConcurrent,
   R::Output: AFPluginResponder,
 {
-  Extract(#[pin] T::Future, Option<AFPluginEventRequest>, H),
-  Handle(#[pin] R, Option<AFPluginEventRequest>),
+  Extract(#[pin] T::Future, AFPluginEventRequest, H),
+  Handle(#[pin] R, AFPluginEventRequest),
+  Done,
 }
 
 impl<F, T, R> Future for HandlerServiceFuture<F, T, R>
@@ -127,26 +131,36 @@ where
   fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
     loop {
       match self.as_mut().project() {
-        HandlerServiceProj::Extract(fut, req, handle) => {
-          match ready!(fut.poll(cx)) {
-            Ok(params) => {
-              let fut = handle.call(params);
-              let state = HandlerServiceFuture::Handle(fut, req.take());
-              self.as_mut().set(state);
-            },
-            Err(err) => {
-              let req = req.take().unwrap();
-              let system_err: DispatchError = err.into();
-              let res: AFPluginEventResponse = system_err.into();
-              return Poll::Ready(Ok(ServiceResponse::new(req, res)));
-            },
-          };
+        HandlerServiceProj::Extract(fut, _, _) => {
+          let result = ready!(fut.poll(cx));
+          if let HandlerServiceProjOwn::Extract(_, req, handle) =
+            self.as_mut().project_replace(HandlerServiceFuture::Done)
+          {
+            match result {
+              Ok(params) => {
+                let fut = handle.call(params);
+                self.as_mut().set(HandlerServiceFuture::Handle(fut, req));
+              },
+              Err(err) => {
+                let system_err: DispatchError = err.into();
+                let res: AFPluginEventResponse = system_err.into();
+                return Poll::Ready(Ok(ServiceResponse::new(req, res)));
+              },
+            }
+          }
         },
-        HandlerServiceProj::Handle(fut, req) => {
+        HandlerServiceProj::Handle(fut, _) => {
           let result = ready!(fut.poll(cx));
-          let req = req.take().unwrap();
-          let resp = result.respond_to(&req);
-          return Poll::Ready(Ok(ServiceResponse::new(req, resp)));
+          if let HandlerServiceProjOwn::Handle(_, req) =
+            self.as_mut().project_replace(HandlerServiceFuture::Done)
+          {
+            let resp = result.respond_to(&req);
+            return Poll::Ready(Ok(ServiceResponse::new(req, resp)));
+          }
+        },
+        HandlerServiceProj::Done => {
+          let msg = "[dispatch]: the handler is polled after completion".to_string();
+          return Poll::Ready(Err(InternalError::Other(msg).into()));
         },
       }
     }
@@ -184,6 +198,7 @@ macro_rules! tuple_from_req ({$tuple_type:ident, $(($n:tt, $T:ident)),+} => {
 
             fn from_request(req: &AFPluginEventRequest, payload: &mut crate::prelude::Payload) -> Self::Future {
                 $tuple_type {
+                    event: req.event.clone(),
                     items: <($(Option<$T>,)+)>::default(),
                     futs: FromRequestFutures($($T::from_request(req, payload),)+),
                 }
@@ -193,6 +208,7 @@ macro_rules! tuple_from_req ({$tuple_type:ident, $(($n:tt, $T:ident)),+} => {
         #[doc(hidden)]
         #[pin_project::pin_project]
         pub struct $tuple_type<$($T: FromAFPluginRequest),+> {
+            event: AFPluginEvent,
             items: ($(Option<$T>,)+),
             #[pin]
             futs: FromRequestFutures<$($T,)+>,
@@ -210,17 +226,24 @@ macro_rules! tuple_from_req ({$tuple_type:ident, $(($n:tt, $T:ident)),+} => {
                         match this.futs.as_mut().project().$n.poll(cx) {
                             Poll::Ready(Ok(item)) => this.items.$n = Some(item),
                             Poll::Pending => ready = false,
-                            Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                            Poll::Ready(Err(e)) => {
+                                let error = AFPluginExtractError::new::<$T>(this.event.clone(), e.into());
+                                tracing::warn!("[dispatch]: {}", error);
+                                return Poll::Ready(Err(error.into()));
+                            },
                         }
                     }
                 )+
 
-                if ready {
-                    Poll::Ready(Ok(
-                        ($(this.items.$n.take().unwrap(),)+)
-                    ))
-                } else {
-                    Poll::Pending
+                if !ready {
+                    return Poll::Pending;
+                }
+                match ($(this.items.$n.take(),)+) {
+                    ($(Some($T),)+) => Poll::Ready(Ok(($($T,)+))),
+                    _ => {
+                        let msg = format!("[dispatch]: the parameters of {:?} are polled after completion", this.event);
+                        Poll::Ready(Err(InternalError::Other(msg).into()))
+                    },
                 }
             }
         }
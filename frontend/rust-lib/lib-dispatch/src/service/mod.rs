@@ -1,8 +1,18 @@
 #![allow(clippy::module_inception)]
+#[cfg(not(target_arch = "wasm32"))]
+mod blocking;
 mod boxed;
 mod handler;
+#[cfg(not(target_arch = "wasm32"))]
+mod send;
 mod service;
+mod transform;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub use blocking::*;
 pub use boxed::*;
 pub use handler::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use send::*;
 pub use service::*;
+pub use transform::*;
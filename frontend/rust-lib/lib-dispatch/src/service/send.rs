@@ -0,0 +1,90 @@
+use std::{
+  future::Future,
+  marker::PhantomData,
+  pin::Pin,
+  task::{Context, Poll},
+};
+
+use futures_core::ready;
+use tokio::task::JoinHandle;
+#[cfg(feature = "trace_requests")]
+use tracing::Instrument;
+
+use crate::{
+  errors::{DispatchError, InternalError},
+  response::AFPluginResponder,
+  runtime::send_runtime_handle,
+  service::AFPluginHandler,
+};
+
+/// Runs the handler on the multi-threaded runtime instead of the dispatcher's runtime, so a slow
+/// handler doesn't block the other events. See [AFPlugin::send_event].
+///
+/// [AFPlugin::send_event]: crate::prelude::AFPlugin::send_event
+pub struct AFPluginSendHandler<H, R> {
+  handler: H,
+  _phantom: PhantomData<fn() -> R>,
+}
+
+impl<H, R> AFPluginSendHandler<H, R> {
+  pub fn new(handler: H) -> Self {
+    Self {
+      handler,
+      _phantom: PhantomData,
+    }
+  }
+}
+
+impl<H: Clone, R> Clone for AFPluginSendHandler<H, R> {
+  fn clone(&self) -> Self {
+    Self::new(self.handler.clone())
+  }
+}
+
+impl<H, T, R> AFPluginHandler<T, SendHandlerFuture<R::Output>> for AFPluginSendHandler<H, R>
+where
+  H: AFPluginHandler<T, R> + Send + Sync,
+  R: Future + Send + 'static,
+  R::Output: AFPluginResponder + Send + 'static,
+{
+  fn call(&self, param: T) -> SendHandlerFuture<R::Output> {
+    let fut = self.handler.call(param);
+    #[cfg(feature = "trace_requests")]
+    let fut = fut.in_current_span();
+    SendHandlerFuture {
+      handle: send_runtime_handle().spawn(fut),
+    }
+  }
+}
+
+/// Resolves with the output of the handler that runs on the multi-threaded runtime. The handler
+/// is aborted if the future is dropped, e.g. the request is cancelled or timed out, unless it
+/// has started on the blocking pool.
+pub struct SendHandlerFuture<O> {
+  handle: JoinHandle<O>,
+}
+
+impl<O> SendHandlerFuture<O> {
+  pub(crate) fn new(handle: JoinHandle<O>) -> Self {
+    Self { handle }
+  }
+}
+
+impl<O> Future for SendHandlerFuture<O> {
+  type Output = Result<O, DispatchError>;
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let result = ready!(Pin::new(&mut self.handle).poll(cx));
+    Poll::Ready(result.map_err(|e| {
+      let msg = format!("[dispatch]: send handler join error: {}", e);
+      tracing::error!("{}", msg);
+      InternalError::JoinError(msg).into()
+    }))
+  }
+}
+
+impl<O> Drop for SendHandlerFuture<O> {
+  fn drop(&mut self) {
+    self.handle.abort();
+  }
+}
@@ -25,7 +25,7 @@ pub trait AFPluginServiceFactory<Request> {
   fn new_service(&self, cfg: Self::Context) -> Self::Future;
 }
 
-pub(crate) struct ServiceRequest {
+pub struct ServiceRequest {
   event_state: AFPluginEventRequest,
   payload: Payload,
 }
@@ -39,9 +39,17 @@ impl ServiceRequest {
   }
 
   #[inline]
-  pub(crate) fn into_parts(self) -> (AFPluginEventRequest, Payload) {
+  pub fn into_parts(self) -> (AFPluginEventRequest, Payload) {
     (self.event_state, self.payload)
   }
+
+  pub fn request(&self) -> &AFPluginEventRequest {
+    &self.event_state
+  }
+
+  pub fn payload(&self) -> &Payload {
+    &self.payload
+  }
 }
 
 pub struct ServiceResponse {
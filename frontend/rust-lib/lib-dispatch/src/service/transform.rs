@@ -0,0 +1,26 @@
+use crate::dispatcher::AFConcurrent;
+use crate::errors::DispatchError;
+use crate::service::{BoxService, ServiceRequest, ServiceResponse};
+
+/// The boxed service of a plugin's handler.
+pub type AFPluginBoxService = BoxService<ServiceRequest, ServiceResponse, DispatchError>;
+
+/// A middleware that wraps the service of every handler of the plugin, e.g. to log, authorize or
+/// validate the requests in one place. See [AFPlugin::wrap].
+///
+/// The returned service usually holds the passed-in `service` and calls it after or before its
+/// own logic. It can also respond without calling it, e.g. if the request is unauthorized.
+///
+/// [AFPlugin::wrap]: crate::prelude::AFPlugin::wrap
+pub trait AFPluginTransform: AFConcurrent + 'static {
+  fn new_transform(&self, service: AFPluginBoxService) -> AFPluginBoxService;
+}
+
+impl<F> AFPluginTransform for F
+where
+  F: Fn(AFPluginBoxService) -> AFPluginBoxService + AFConcurrent + 'static,
+{
+  fn new_transform(&self, service: AFPluginBoxService) -> AFPluginBoxService {
+    (self)(service)
+  }
+}
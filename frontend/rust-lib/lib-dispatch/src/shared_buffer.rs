@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::Bytes;
+use parking_lot::{const_mutex, Mutex};
+
+use crate::errors::{DispatchError, InternalError};
+use crate::module::{AFPluginEvent, AFPluginRequest, DispatchRoutes};
+use crate::request::Payload;
+use crate::response::{AFPluginEventResponse, ResponseBuilder};
+
+/// The built-in event that releases the shared buffer of the handle, e.g. after Dart reads the
+/// document snapshot of a response. The handle is the UTF-8 decimal payload.
+pub const RELEASE_BUFFER_EVENT: &str = "system.release_buffer";
+
+/// Identifies a buffer that is shared with the host instead of being copied across the FFI
+/// boundary. The buffer stays at the same address until it's taken or released. Zero is never
+/// a handle, so the host can use it for none.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SharedBufferHandle(u64);
+
+impl SharedBufferHandle {
+  pub fn from_raw(handle: u64) -> Self {
+    Self(handle)
+  }
+
+  pub fn as_raw(&self) -> u64 {
+    self.0
+  }
+}
+
+/// The address and the length of a shared buffer, which the host reads or writes in place. The
+/// buffers of [alloc_shared_buffer] are writable, the ones of [share_buffer] are read-only.
+#[derive(Clone, Copy, Debug)]
+pub struct SharedBuffer {
+  pub handle: SharedBufferHandle,
+  pub ptr: *const u8,
+  pub len: usize,
+}
+
+enum Buffer {
+  /// Allocated for the host to write the payload of a request. It's owned by the host until the
+  /// request that carries its handle takes it over, so it can't be released before.
+  Writable(Vec<u8>),
+  /// The payload of a response for the host to read.
+  Readable(Bytes),
+}
+
+static SHARED_BUFFERS: Mutex<Option<HashMap<SharedBufferHandle, Buffer>>> = const_mutex(None);
+static NEXT_BUFFER_HANDLE: AtomicU64 = AtomicU64::new(1);
+
+/// The writable buffers that the host allocates but never sends, e.g. when the isolate restarts
+/// halfway, can't be freed because the host may still write into them. They are bounded instead:
+/// [alloc_shared_buffer] fails once the writable buffers hold this many bytes.
+pub const MAX_WRITABLE_BUFFER_BYTES: usize = 256 * 1024 * 1024;
+
+/// Allocates a zeroed buffer of `len` bytes for the host to write a large payload into. The
+/// request carries the handle instead of the payload, and the handler side takes it over with
+/// [take_shared_buffer]. Returns `None` if the writable buffers would exceed the
+/// [MAX_WRITABLE_BUFFER_BYTES], the host sends the payload inline then.
+pub fn alloc_shared_buffer(len: usize) -> Option<SharedBuffer> {
+  let mut buffers = SHARED_BUFFERS.lock();
+  let buffers = buffers.get_or_insert_with(HashMap::new);
+  let writable: usize = buffers
+    .values()
+    .map(|buffer| match buffer {
+      Buffer::Writable(buffer) => buffer.len(),
+      Buffer::Readable(_) => 0,
+    })
+    .sum();
+  if writable.saturating_add(len) > MAX_WRITABLE_BUFFER_BYTES {
+    tracing::warn!(
+      "[dispatch]: can not allocate the shared buffer of {} bytes, {} bytes are not sent yet",
+      len,
+      writable
+    );
+    return None;
+  }
+
+  let mut buffer = vec![0; len];
+  // Moving the vec into the map doesn't move its allocation.
+  let ptr = buffer.as_mut_ptr() as *const u8;
+  let handle = next_handle();
+  buffers.insert(handle, Buffer::Writable(buffer));
+  Some(SharedBuffer { handle, ptr, len })
+}
+
+/// Keeps the `bytes` alive until the host releases them with the [RELEASE_BUFFER_EVENT], so
+/// the host can read them in place.
+pub fn share_buffer(bytes: Bytes) -> SharedBuffer {
+  let (ptr, len) = (bytes.as_ptr(), bytes.len());
+  let handle = insert(Buffer::Readable(bytes));
+  SharedBuffer { handle, ptr, len }
+}
+
+/// Takes the buffer out of the registry without copying it. It's called once the request that
+/// carries the handle arrives, the host doesn't write into the buffer once it sends the request.
+/// Returns `None` if the handle is unknown or already taken.
+pub fn take_shared_buffer(handle: SharedBufferHandle) -> Option<Bytes> {
+  let buffer = SHARED_BUFFERS.lock().as_mut()?.remove(&handle)?;
+  match buffer {
+    Buffer::Writable(buffer) => Some(Bytes::from(buffer)),
+    Buffer::Readable(bytes) => Some(bytes),
+  }
+}
+
+/// Frees the buffer that the host has read. Returns `false` if the handle is unknown, already
+/// released, or of a writable buffer, which only the request that carries it takes over.
+pub fn release_shared_buffer(handle: SharedBufferHandle) -> bool {
+  let buffer = {
+    let mut buffers = SHARED_BUFFERS.lock();
+    let buffers = match buffers.as_mut() {
+      None => return false,
+      Some(buffers) => buffers,
+    };
+    match buffers.get(&handle) {
+      Some(Buffer::Readable(_)) => buffers.remove(&handle),
+      _ => None,
+    }
+  };
+  buffer.is_some()
+}
+
+/// The number of the buffers that are not released yet, e.g. to find the leaks in the tests.
+pub fn shared_buffer_count() -> usize {
+  SHARED_BUFFERS
+    .lock()
+    .as_ref()
+    .map_or(0, |buffers| buffers.len())
+}
+
+fn next_handle() -> SharedBufferHandle {
+  SharedBufferHandle(NEXT_BUFFER_HANDLE.fetch_add(1, Ordering::Relaxed))
+}
+
+fn insert(buffer: Buffer) -> SharedBufferHandle {
+  let handle = next_handle();
+  SHARED_BUFFERS
+    .lock()
+    .get_or_insert_with(HashMap::new)
+    .insert(handle, buffer);
+  handle
+}
+
+pub(crate) fn is_release_buffer_event(routes: &DispatchRoutes, event: &AFPluginEvent) -> bool {
+  // The plugin that registers the event takes it over.
+  *event == AFPluginEvent::untyped(RELEASE_BUFFER_EVENT) && !routes.plugins.contains_key(event)
+}
+
+pub(crate) fn release_buffer_response(request: &AFPluginRequest) -> AFPluginEventResponse {
+  match handle_release_buffer(request) {
+    Ok(()) => ResponseBuilder::Ok().build(),
+    Err(e) => e.into(),
+  }
+}
+
+fn handle_release_buffer(request: &AFPluginRequest) -> Result<(), DispatchError> {
+  let handle = match &request.payload {
+    Payload::Bytes(bytes) => std::str::from_utf8(bytes)
+      .ok()
+      .and_then(|handle| handle.trim().parse::<u64>().ok())
+      .ok_or_else(|| InternalError::DeserializeFromBytes("Invalid buffer handle".to_string()))?,
+    Payload::None => {
+      return Err(
+        InternalError::UnexpectedNone("The request has no buffer handle".to_string()).into(),
+      )
+    },
+  };
+  if !release_shared_buffer(SharedBufferHandle(handle)) {
+    tracing::warn!(
+      "[dispatch]: the shared buffer {} is unknown, already released or not sent yet",
+      handle
+    );
+  }
+  Ok(())
+}
@@ -0,0 +1,53 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use pin_project::pin_project;
+
+use crate::module::AFPluginEvent;
+
+/// Warns if a single poll of the future takes longer than the `budget`. A slow poll means the
+/// handler runs the blocking code, e.g. the synchronous IO, on the async thread, and all the other
+/// events on the thread wait for it. See [AFPluginDispatcherBuilder::slow_poll_budget].
+///
+/// [AFPluginDispatcherBuilder::slow_poll_budget]: crate::prelude::AFPluginDispatcherBuilder::slow_poll_budget
+#[pin_project]
+pub(crate) struct SlowPoll<F> {
+  #[pin]
+  fut: F,
+  budget: Option<Duration>,
+  event: AFPluginEvent,
+}
+
+impl<F> SlowPoll<F> {
+  pub(crate) fn new(fut: F, budget: Option<Duration>, event: AFPluginEvent) -> Self {
+    Self { fut, budget, event }
+  }
+}
+
+impl<F: Future> Future for SlowPoll<F> {
+  type Output = F::Output;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.project();
+    let budget = match this.budget {
+      None => return this.fut.poll(cx),
+      Some(budget) => *budget,
+    };
+
+    let started_at = Instant::now();
+    let poll = this.fut.poll(cx);
+    let elapsed = started_at.elapsed();
+    if elapsed > budget {
+      tracing::warn!(
+        "[dispatch]: a poll of {:?} took {:?}, over the budget of {:?}. The handler may block \
+         the async thread",
+        this.event,
+        elapsed,
+        budget
+      );
+    }
+    poll
+  }
+}
@@ -0,0 +1,191 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use crate::errors::DispatchError;
+use crate::module::{AFPluginEvent, DispatchRoutes};
+use crate::response::{AFPluginEventResponse, ResponseBuilder};
+
+/// The built-in event that reports the [EventStats] of all the events. See
+/// [AFPluginDispatcher::stats].
+///
+/// [AFPluginDispatcher::stats]: crate::prelude::AFPluginDispatcher::stats
+pub const STATS_EVENT: &str = "system.stats";
+
+/// The latency percentiles are computed from the latest samples of the event.
+const LATENCY_SAMPLES: usize = 256;
+
+/// The upper bounds of the latency histogram buckets.
+pub(crate) const LATENCY_BUCKETS: [Duration; 12] = [
+  Duration::from_millis(1),
+  Duration::from_millis(5),
+  Duration::from_millis(10),
+  Duration::from_millis(25),
+  Duration::from_millis(50),
+  Duration::from_millis(100),
+  Duration::from_millis(250),
+  Duration::from_millis(500),
+  Duration::from_secs(1),
+  Duration::from_millis(2500),
+  Duration::from_secs(5),
+  Duration::from_secs(10),
+];
+
+/// The execution statistics of an event since the dispatcher was created.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "use_serde", derive(serde::Serialize))]
+pub struct EventStats {
+  pub event: String,
+  pub calls: u64,
+  /// The number of the responses that are not [StatusCode::Ok].
+  ///
+  /// [StatusCode::Ok]: crate::prelude::StatusCode::Ok
+  pub errors: u64,
+  pub p50_latency: Duration,
+  pub p95_latency: Duration,
+  pub max_latency: Duration,
+  pub last_error: Option<String>,
+}
+
+impl fmt::Display for EventStats {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{}: {} calls, {} errors, p50 {:?}, p95 {:?}, max {:?}",
+      self.event, self.calls, self.errors, self.p50_latency, self.p95_latency, self.max_latency
+    )
+  }
+}
+
+/// The latency histogram of an event since the dispatcher was created.
+#[cfg(feature = "prometheus")]
+pub(crate) struct LatencyHistogram {
+  pub(crate) event: String,
+  /// The number of the calls that complete within each of the [LATENCY_BUCKETS], not
+  /// cumulative.
+  pub(crate) buckets: [u64; LATENCY_BUCKETS.len()],
+  pub(crate) count: u64,
+  pub(crate) sum: Duration,
+}
+
+#[derive(Default)]
+struct EventStatsState {
+  calls: u64,
+  errors: u64,
+  samples: VecDeque<Duration>,
+  max_latency: Duration,
+  last_error: Option<String>,
+  buckets: [u64; LATENCY_BUCKETS.len()],
+  latency_sum: Duration,
+}
+
+impl EventStatsState {
+  fn snapshot(&self, event: &AFPluginEvent) -> EventStats {
+    let mut samples = self.samples.iter().copied().collect::<Vec<_>>();
+    samples.sort_unstable();
+    EventStats {
+      event: event.as_str().to_string(),
+      calls: self.calls,
+      errors: self.errors,
+      p50_latency: percentile(&samples, 50),
+      p95_latency: percentile(&samples, 95),
+      max_latency: self.max_latency,
+      last_error: self.last_error.clone(),
+    }
+  }
+}
+
+/// Returns the nearest-rank percentile of the sorted samples.
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+  if sorted.is_empty() {
+    return Duration::ZERO;
+  }
+  let rank = (sorted.len() * percent + 99) / 100;
+  sorted[rank.max(1) - 1]
+}
+
+#[derive(Default)]
+pub(crate) struct DispatchStats {
+  events: Mutex<HashMap<AFPluginEvent, EventStatsState>>,
+}
+
+impl DispatchStats {
+  pub(crate) fn record(
+    &self,
+    event: &AFPluginEvent,
+    response: &AFPluginEventResponse,
+    error: Option<&DispatchError>,
+    elapsed: Duration,
+  ) {
+    let mut events = self.events.lock();
+    let stats = events.entry(event.clone()).or_default();
+    stats.calls += 1;
+    if !response.status_code.is_ok() {
+      stats.errors += 1;
+      stats.last_error = Some(match error {
+        Some(error) => error.to_string(),
+        None => response.to_string(),
+      });
+    }
+    if stats.samples.len() == LATENCY_SAMPLES {
+      stats.samples.pop_front();
+    }
+    stats.samples.push_back(elapsed);
+    stats.max_latency = stats.max_latency.max(elapsed);
+    stats.latency_sum += elapsed;
+    if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| elapsed <= *bound) {
+      stats.buckets[bucket] += 1;
+    }
+  }
+
+  /// Sorted by the event.
+  pub(crate) fn snapshot(&self) -> Vec<EventStats> {
+    let mut stats = self
+      .events
+      .lock()
+      .iter()
+      .map(|(event, state)| state.snapshot(event))
+      .collect::<Vec<_>>();
+    stats.sort_by(|a, b| a.event.cmp(&b.event));
+    stats
+  }
+
+  /// Sorted by the event.
+  #[cfg(feature = "prometheus")]
+  pub(crate) fn histograms(&self) -> Vec<LatencyHistogram> {
+    let mut histograms = self
+      .events
+      .lock()
+      .iter()
+      .map(|(event, state)| LatencyHistogram {
+        event: event.as_str().to_string(),
+        buckets: state.buckets,
+        count: state.calls,
+        sum: state.latency_sum,
+      })
+      .collect::<Vec<_>>();
+    histograms.sort_by(|a, b| a.event.cmp(&b.event));
+    histograms
+  }
+}
+
+pub(crate) fn is_stats_event(routes: &DispatchRoutes, event: &AFPluginEvent) -> bool {
+  // The plugin that registers the event takes it over.
+  *event == AFPluginEvent::untyped(STATS_EVENT) && !routes.plugins.contains_key(event)
+}
+
+/// The payload is the json of the [EventStats] list if the `use_serde` feature is enabled,
+/// otherwise it's the printed text with one event per line.
+pub(crate) fn stats_response(stats: &[EventStats]) -> AFPluginEventResponse {
+  #[cfg(feature = "use_serde")]
+  let payload = serde_json::to_vec(stats).unwrap_or_default();
+  #[cfg(not(feature = "use_serde"))]
+  let payload = stats
+    .iter()
+    .map(|stats| stats.to_string())
+    .collect::<Vec<_>>()
+    .join("\n");
+  ResponseBuilder::Ok().data(payload).build()
+}
@@ -0,0 +1,192 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use parking_lot::{const_rwlock, RwLock};
+
+use crate::byte_trait::ToBytes;
+use crate::errors::{DispatchError, InternalError};
+use crate::module::{AFPluginEvent, AFPluginRequest, DispatchRoutes};
+use crate::notification::{DispatchNotification, NotificationSink};
+use crate::request::Payload;
+use crate::response::{AFPluginEventResponse, ResponseBuilder};
+
+/// The built-in event that subscribes the [SubscriberId] of the request context to the key of
+/// the payload, e.g. a workspace id or a document id. The key is the UTF-8 payload.
+pub const SUBSCRIBE_EVENT: &str = "system.subscribe";
+
+/// The built-in event that undoes the [SUBSCRIBE_EVENT] of the same key.
+pub const UNSUBSCRIBE_EVENT: &str = "system.unsubscribe";
+
+/// Identifies a connected subscriber, e.g. the Dart side. The requests of the subscriber carry
+/// it as the extension of their context:
+///
+/// ```ignore
+/// let subscriber = connect_subscriber(DartNotificationSender::new(port));
+/// let request = AFPluginRequest::untyped(SUBSCRIBE_EVENT)
+///   .payload(doc_id.as_bytes().to_vec())
+///   .context(AFPluginContext::new().extension(subscriber));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SubscriberId(u64);
+
+struct Subscriber {
+  sink: Box<dyn NotificationSink>,
+  keys: HashSet<String>,
+}
+
+#[derive(Default)]
+struct Subscriptions {
+  subscribers: HashMap<SubscriberId, Subscriber>,
+  /// The subscribers of each key.
+  keys: HashMap<String, HashSet<SubscriberId>>,
+}
+
+static SUBSCRIPTIONS: RwLock<Option<Subscriptions>> = const_rwlock(None);
+static NEXT_SUBSCRIBER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Connects a subscriber whose published changes are delivered to the `sink`. It has no
+/// subscriptions until it sends the [SUBSCRIBE_EVENT].
+pub fn connect_subscriber<T: NotificationSink>(sink: T) -> SubscriberId {
+  let id = SubscriberId(NEXT_SUBSCRIBER_ID.fetch_add(1, Ordering::Relaxed));
+  let subscriber = Subscriber {
+    sink: Box::new(sink),
+    keys: HashSet::new(),
+  };
+  SUBSCRIPTIONS
+    .write()
+    .get_or_insert_with(Subscriptions::default)
+    .subscribers
+    .insert(id, subscriber);
+  id
+}
+
+/// Drops the subscriber and all of its subscriptions, e.g. when the Dart isolate is restarted.
+pub fn disconnect_subscriber(id: SubscriberId) {
+  let mut guard = SUBSCRIPTIONS.write();
+  let subscriptions = match guard.as_mut() {
+    None => return,
+    Some(subscriptions) => subscriptions,
+  };
+  if let Some(subscriber) = subscriptions.subscribers.remove(&id) {
+    for key in subscriber.keys.iter() {
+      subscriptions.unsubscribe_key(key, id);
+    }
+  }
+}
+
+/// Delivers the change of the `key` to the subscribers of it. The notification's id is the key.
+/// The payload that can't be serialized is logged and dropped.
+pub fn publish<S, T, K, P>(source: S, ty: T, key: K, payload: P)
+where
+  S: Into<String>,
+  T: Into<i32>,
+  K: Into<String>,
+  P: ToBytes,
+{
+  let source = source.into();
+  let payload = match payload.into_bytes() {
+    Ok(payload) => payload,
+    Err(e) => {
+      tracing::error!(
+        "[dispatch]: serialize the change of {} failed: {:?}",
+        source,
+        e
+      );
+      return;
+    },
+  };
+  let notification = DispatchNotification::new(source, ty, payload).id(key);
+
+  let guard = SUBSCRIPTIONS.read();
+  let subscriptions = match guard.as_ref() {
+    None => return,
+    Some(subscriptions) => subscriptions,
+  };
+  let subscribers = subscriptions
+    .keys
+    .get(&notification.id)
+    .into_iter()
+    .flatten();
+  for subscriber in subscribers.filter_map(|id| subscriptions.subscribers.get(id)) {
+    if let Err(e) = subscriber.sink.send_notification(&notification) {
+      tracing::error!(
+        "[dispatch]: deliver the change of {} failed: {}",
+        notification.id,
+        e
+      );
+    }
+  }
+}
+
+/// The number of the subscribers of the `key`.
+pub fn subscriber_count(key: &str) -> usize {
+  SUBSCRIPTIONS
+    .read()
+    .as_ref()
+    .and_then(|subscriptions| subscriptions.keys.get(key))
+    .map_or(0, |subscribers| subscribers.len())
+}
+
+impl Subscriptions {
+  fn subscribe(&mut self, id: SubscriberId, key: String) -> Result<(), DispatchError> {
+    let subscriber = self.subscribers.get_mut(&id).ok_or_else(|| {
+      InternalError::UnexpectedNone(format!("The subscriber {:?} is disconnected", id))
+    })?;
+    subscriber.keys.insert(key.clone());
+    self.keys.entry(key).or_default().insert(id);
+    Ok(())
+  }
+
+  fn unsubscribe(&mut self, id: SubscriberId, key: &str) {
+    if let Some(subscriber) = self.subscribers.get_mut(&id) {
+      subscriber.keys.remove(key);
+    }
+    self.unsubscribe_key(key, id);
+  }
+
+  fn unsubscribe_key(&mut self, key: &str, id: SubscriberId) {
+    if let Some(subscribers) = self.keys.get_mut(key) {
+      subscribers.remove(&id);
+      if subscribers.is_empty() {
+        self.keys.remove(key);
+      }
+    }
+  }
+}
+
+pub(crate) fn is_subscription_event(routes: &DispatchRoutes, event: &AFPluginEvent) -> bool {
+  // The plugin that registers the event takes it over.
+  (*event == AFPluginEvent::untyped(SUBSCRIBE_EVENT)
+    || *event == AFPluginEvent::untyped(UNSUBSCRIBE_EVENT))
+    && !routes.plugins.contains_key(event)
+}
+
+pub(crate) fn subscription_response(request: &AFPluginRequest) -> AFPluginEventResponse {
+  match handle_subscription(request) {
+    Ok(()) => ResponseBuilder::Ok().build(),
+    Err(e) => e.into(),
+  }
+}
+
+fn handle_subscription(request: &AFPluginRequest) -> Result<(), DispatchError> {
+  let id = *request
+    .context
+    .get_extension::<SubscriberId>()
+    .ok_or_else(|| InternalError::UnexpectedNone("The request has no subscriber".to_string()))?;
+  let key = match &request.payload {
+    Payload::Bytes(bytes) => String::from_utf8(bytes.to_vec())
+      .map_err(|e| InternalError::DeserializeFromBytes(format!("Invalid key: {}", e)))?,
+    Payload::None => {
+      return Err(InternalError::UnexpectedNone("The request has no key".to_string()).into())
+    },
+  };
+
+  let mut guard = SUBSCRIPTIONS.write();
+  let subscriptions = guard.get_or_insert_with(Subscriptions::default);
+  if request.event == AFPluginEvent::untyped(SUBSCRIBE_EVENT) {
+    subscriptions.subscribe(id, key)
+  } else {
+    subscriptions.unsubscribe(id, &key);
+    Ok(())
+  }
+}
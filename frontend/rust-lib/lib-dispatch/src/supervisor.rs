@@ -0,0 +1,144 @@
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+
+use futures::FutureExt;
+use parking_lot::Mutex;
+
+use crate::dispatcher::{panic_reason, BoxFutureCallback, DispatchContext, DispatchService};
+use crate::errors::{Error, InternalError};
+use crate::module::AFPluginEvent;
+use crate::prelude::AFConcurrent;
+use crate::response::AFPluginEventResponse;
+use crate::service::Service;
+
+/// The request whose dispatch task crashed outside the handler, e.g. in an interceptor, an
+/// observer or the callback. The panics of the handlers are responded with the
+/// `HandlerPanic` error, they don't crash the task.
+#[derive(Clone, Debug)]
+pub struct DispatchCrash {
+  pub event: AFPluginEvent,
+  pub request_id: String,
+  pub correlation_id: Option<String>,
+  pub reason: String,
+  /// The number of the restarts before this crash.
+  pub restarts: usize,
+}
+
+pub trait DispatchCrashHook: AFConcurrent {
+  fn on_crash(&self, crash: &DispatchCrash);
+}
+
+impl<F> DispatchCrashHook for F
+where
+  F: Fn(&DispatchCrash) + AFConcurrent,
+{
+  fn on_crash(&self, crash: &DispatchCrash) {
+    (self)(crash)
+  }
+}
+
+/// Supervises the tasks that dispatch the requests. Whether or not it's set, the crashed task
+/// is resolved with the `HandlerPanic` error instead of dropping the response, and its running
+/// slot is released.
+///
+/// The supervisor reports every crash to the hook, and respawns the request up to
+/// `max_restarts` times with the same caller waiting for the response. The respawned request
+/// runs through the handler again, so only enable the restarts if the handlers are idempotent.
+/// The callback of the request is called once at most.
+#[derive(Default)]
+pub struct DispatchSupervisor {
+  pub max_restarts: usize,
+  hook: Option<Box<dyn DispatchCrashHook>>,
+}
+
+impl DispatchSupervisor {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn max_restarts(mut self, max_restarts: usize) -> Self {
+    self.max_restarts = max_restarts;
+    self
+  }
+
+  pub fn on_crash<H>(mut self, hook: H) -> Self
+  where
+    H: DispatchCrashHook + 'static,
+  {
+    self.hook = Some(Box::new(hook));
+    self
+  }
+}
+
+/// Calls the `service` and catches the crash of the call.
+pub(crate) async fn supervise(
+  supervisor: Option<Arc<DispatchSupervisor>>,
+  service: &DispatchService,
+  ctx: DispatchContext,
+) -> AFPluginEventResponse {
+  let (request, callback) = ctx.into_parts();
+  let max_restarts = supervisor.as_ref().map_or(0, |s| s.max_restarts);
+  let callback = Arc::new(Mutex::new(callback));
+  let mut restarts = 0;
+  loop {
+    let ctx = DispatchContext {
+      request: request.clone(),
+      callback: shared_callback(&callback),
+    };
+    let result = AssertUnwindSafe(service.call(ctx)).catch_unwind().await;
+    let panic = match result {
+      Ok(Ok(response)) => return response,
+      Ok(Err(e)) => {
+        tracing::error!("[dispatch]: runtime error: {:?}", e);
+        return InternalError::Other(format!("{:?}", e)).as_response();
+      },
+      Err(panic) => panic,
+    };
+
+    let crash = DispatchCrash {
+      event: request.event.clone(),
+      request_id: request.id.clone(),
+      correlation_id: request.correlation_id.clone(),
+      reason: panic_reason(panic.as_ref()),
+      restarts,
+    };
+    tracing::error!(
+      "[dispatch]: the task of {:?} crashed after {} restarts: {}",
+      crash.event,
+      restarts,
+      crash.reason
+    );
+    if let Some(hook) = supervisor.as_ref().and_then(|s| s.hook.as_ref()) {
+      hook.on_crash(&crash);
+    }
+    if restarts >= max_restarts {
+      let msg = format!(
+        "[dispatch]: the task of {:?} crashed: {}",
+        crash.event, crash.reason
+      );
+      let mut response = InternalError::Panic(msg).as_response();
+      response.correlation_id = crash.correlation_id;
+      let callback = callback.lock().take();
+      if let Some(callback) = callback {
+        callback(response.clone()).await;
+      }
+      return response;
+    }
+    restarts += 1;
+  }
+}
+
+/// Hands the callback to the attempt that completes first, the others don't call it.
+fn shared_callback(callback: &Arc<Mutex<Option<BoxFutureCallback>>>) -> Option<BoxFutureCallback> {
+  if callback.lock().is_none() {
+    return None;
+  }
+  let callback = callback.clone();
+  Some(Box::new(move |response| {
+    let callback = callback.lock().take();
+    match callback {
+      Some(callback) => callback(response),
+      None => Box::pin(async {}),
+    }
+  }))
+}
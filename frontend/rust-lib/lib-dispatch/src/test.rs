@@ -0,0 +1,272 @@
+//! The harness to unit-test the handlers of a plugin without the rest of the application.
+//!
+//! ```ignore
+//! #[tokio::test]
+//! async fn create_document_test() {
+//!   let tester = EventTester::new(vec![document_plugin()]).await;
+//!   let document = tester
+//!     .event(DocumentEvent::CreateDocument)
+//!     .payload(CreateDocumentPayloadPB { .. })
+//!     .async_send()
+//!     .await
+//!     .parse::<DocumentDataPB, FlowyError>();
+//!   tester.teardown().await;
+//! }
+//! ```
+//!
+//! The timeouts, retries and TTLs can be tested deterministically with the virtual time of
+//! tokio, which needs the current thread runtime of `#[tokio::test]`:
+//!
+//! ```ignore
+//! let tester = EventTester::new(vec![plugin()]).await;
+//! tester.pause_time();
+//! let pending = tester.event(SlowEvent).request(|r| r.timeout(Duration::from_secs(1))).send();
+//! tester.advance(Duration::from_secs(1)).await;
+//! pending.await.assert_status(StatusCode::Timeout);
+//! ```
+//!
+//! Both run as the tests of `tests/api/tester.rs`.
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::byte_trait::{AFPluginFromBytes, ToBytes};
+use crate::dispatcher::AFPluginDispatcher;
+use crate::mock::MockHandler;
+use crate::module::{AFPlugin, AFPluginEvent, AFPluginRequest};
+use crate::response::{AFPluginEventResponse, StatusCode};
+use crate::runtime::AFPluginRuntime;
+
+/// The requests that are still running after it are reported by [EventTester::teardown].
+const TEARDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The times [EventTester::advance] yields to the woken tasks. A woken request passes a few
+/// tasks, e.g. the scheduler, the executor and the response, before it completes.
+const ADVANCE_YIELDS: usize = 32;
+
+/// A dispatcher with the chosen plugins that runs on the runtime of the test, so dropping it
+/// inside the test doesn't drop a runtime.
+pub struct EventTester {
+  dispatcher: Arc<AFPluginDispatcher>,
+}
+
+impl EventTester {
+  /// Creates the dispatcher with the `plugins` and runs their `on_start` hooks.
+  ///
+  /// # Panics
+  ///
+  /// Panics if it's not called inside a tokio runtime, or a hook fails.
+  pub async fn new(plugins: Vec<AFPlugin>) -> Self {
+    let runtime = Arc::new(AFPluginRuntime::current());
+    Self::with_dispatcher(AFPluginDispatcher::new(runtime, plugins)).await
+  }
+
+  /// Same as [EventTester::new] with the dispatcher that the test configures, e.g. with the
+  /// timeout or the interceptors. Create it with [AFPluginRuntime::current].
+  pub async fn with_dispatcher(dispatcher: AFPluginDispatcher) -> Self {
+    if let Err(e) = dispatcher.start().await {
+      panic!("Start the plugins failed: {}", e);
+    }
+    Self {
+      dispatcher: Arc::new(dispatcher),
+    }
+  }
+
+  pub fn dispatcher(&self) -> &Arc<AFPluginDispatcher> {
+    &self.dispatcher
+  }
+
+  /// Same as [AFPluginDispatcher::mock_event].
+  pub fn mock_event<E, M>(&self, event: E, mock: M)
+  where
+    E: Into<AFPluginEvent>,
+    M: MockHandler + 'static,
+  {
+    self.dispatcher.mock_event(event, mock);
+  }
+
+  pub fn event<E: Into<AFPluginEvent>>(&self, event: E) -> EventTest<'_> {
+    EventTest {
+      dispatcher: &self.dispatcher,
+      request: AFPluginRequest::new(event),
+    }
+  }
+
+  /// Freezes the time of the runtime, so the time only moves forward with [EventTester::advance],
+  /// or when all the tasks are idle and one of them waits for a timer. See
+  /// [tokio::time::pause].
+  ///
+  /// # Panics
+  ///
+  /// Panics if the runtime of the test is not the current thread one, or the time is already
+  /// paused.
+  pub fn pause_time(&self) {
+    tokio::time::pause();
+  }
+
+  /// # Panics
+  ///
+  /// Panics if the time is not paused.
+  pub fn resume_time(&self) {
+    tokio::time::resume();
+  }
+
+  /// Moves the paused time forward by the `duration`, then lets the woken tasks run, e.g. the
+  /// requests whose timeout is reached, or the retried ones whose backoff elapsed. The tasks
+  /// that they wake in turn run too, e.g. the handler that the retry runs again.
+  pub async fn advance(&self, duration: Duration) {
+    tokio::time::advance(duration).await;
+    // The tasks of the local set only run within its `run_until`.
+    self
+      .dispatcher
+      .runtime
+      .run_until(async {
+        for _ in 0..ADVANCE_YIELDS {
+          tokio::task::yield_now().await;
+        }
+      })
+      .await;
+  }
+
+  /// Shuts down the dispatcher and runs the `on_stop` hooks of the plugins.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the requests are not completed within 5 seconds, e.g. the test leaks a request
+  /// that never completes.
+  pub async fn teardown(self) {
+    if let Err(e) = self.dispatcher.shutdown(TEARDOWN_TIMEOUT).await {
+      panic!("Tear down failed: {}", e);
+    }
+  }
+}
+
+/// A request that is built by [EventTester::event].
+pub struct EventTest<'a> {
+  dispatcher: &'a AFPluginDispatcher,
+  request: AFPluginRequest,
+}
+
+impl<'a> EventTest<'a> {
+  /// # Panics
+  ///
+  /// Panics if the payload can't be serialized.
+  pub fn payload<P: ToBytes>(mut self, payload: P) -> Self {
+    match payload.into_bytes() {
+      Ok(bytes) => self.request = self.request.payload(bytes),
+      Err(e) => panic!("Serialize the payload failed: {:?}", e),
+    }
+    self
+  }
+
+  /// Changes the request before it's sent, e.g. to set its timeout or context.
+  pub fn request<F: FnOnce(AFPluginRequest) -> AFPluginRequest>(mut self, f: F) -> Self {
+    self.request = f(self.request);
+    self
+  }
+
+  pub async fn async_send(self) -> EventTestResponse {
+    let response = AFPluginDispatcher::async_send(self.dispatcher, self.request).await;
+    EventTestResponse { response }
+  }
+
+  /// Dispatches the request right away and returns its pending response, so the test can
+  /// [EventTester::advance] the time before awaiting it.
+  pub fn send(self) -> PendingEventTest {
+    let fut = AFPluginDispatcher::async_send_with_response(self.dispatcher, self.request);
+    PendingEventTest { fut: Box::pin(fut) }
+  }
+}
+
+/// The response of [EventTest::send], which resolves once the request is completed.
+pub struct PendingEventTest {
+  fut: Pin<Box<dyn Future<Output = AFPluginEventResponse>>>,
+}
+
+impl Future for PendingEventTest {
+  type Output = EventTestResponse;
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    self
+      .fut
+      .as_mut()
+      .poll(cx)
+      .map(|response| EventTestResponse { response })
+  }
+}
+
+/// The response of an [EventTest], with the assertions that panic with the response in the
+/// message.
+#[derive(Debug)]
+pub struct EventTestResponse {
+  response: AFPluginEventResponse,
+}
+
+impl EventTestResponse {
+  pub fn response(&self) -> &AFPluginEventResponse {
+    &self.response
+  }
+
+  pub fn into_response(self) -> AFPluginEventResponse {
+    self.response
+  }
+
+  pub fn assert_status(self, status_code: StatusCode) -> Self {
+    assert_eq!(
+      self.response.status_code, status_code,
+      "Unexpected response: {}",
+      self.response
+    );
+    self
+  }
+
+  pub fn assert_ok(self) -> Self {
+    self.assert_status(StatusCode::Ok)
+  }
+
+  /// Parses the payload of the successful response.
+  ///
+  /// # Panics
+  ///
+  /// Panics with the error `E` if the handler fails, or if the payload can't be parsed.
+  pub fn parse<T, E>(self) -> T
+  where
+    T: AFPluginFromBytes,
+    E: AFPluginFromBytes + Debug,
+  {
+    match self.response.parse::<T, E>() {
+      Ok(Ok(data)) => data,
+      Ok(Err(e)) => panic!("The handler failed: {:?}", e),
+      Err(e) => panic!("Parse {} failed: {}", std::any::type_name::<T>(), e),
+    }
+  }
+
+  /// Parses the error of the failed response.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the handler succeeds, or if the error can't be parsed.
+  pub fn parse_error<T, E>(self) -> E
+  where
+    T: AFPluginFromBytes + Debug,
+    E: AFPluginFromBytes,
+  {
+    match self.response.parse::<T, E>() {
+      Ok(Err(e)) => e,
+      Ok(Ok(data)) => panic!("The handler succeeded with {:?}", data),
+      Err(e) => panic!("Parse {} failed: {}", std::any::type_name::<E>(), e),
+    }
+  }
+
+  /// Asserts the payload of the successful response equals the `expected`.
+  pub fn assert_payload<T, E>(self, expected: T)
+  where
+    T: AFPluginFromBytes + PartialEq + Debug,
+    E: AFPluginFromBytes + Debug,
+  {
+    assert_eq!(self.parse::<T, E>(), expected);
+  }
+}
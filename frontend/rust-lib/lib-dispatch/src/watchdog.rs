@@ -0,0 +1,92 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::module::AFPluginEvent;
+use crate::prelude::AFConcurrent;
+
+/// The request whose handler runs past the threshold of the [DispatchWatchdog].
+#[derive(Clone, Debug)]
+pub struct StuckHandler {
+  pub event: AFPluginEvent,
+  pub request_id: String,
+  pub correlation_id: Option<String>,
+  /// The time since the request started running.
+  pub elapsed: Duration,
+}
+
+pub trait StuckHandlerObserver: AFConcurrent {
+  fn on_stuck(&self, handler: &StuckHandler);
+}
+
+impl<F> StuckHandlerObserver for F
+where
+  F: Fn(&StuckHandler) + AFConcurrent,
+{
+  fn on_stuck(&self, handler: &StuckHandler) {
+    (self)(handler)
+  }
+}
+
+/// Reports the handlers that run past the `threshold`, e.g. the migration that never completes.
+/// The event, the elapsed time and the request id are logged every time the handler runs
+/// another `threshold`, and passed to the observer if it's set. The handler itself is not
+/// affected, see [AFPluginRequest::timeout] to abort it.
+///
+/// [AFPluginRequest::timeout]: crate::prelude::AFPluginRequest::timeout
+pub struct DispatchWatchdog {
+  pub threshold: Duration,
+  observer: Option<Box<dyn StuckHandlerObserver>>,
+}
+
+impl DispatchWatchdog {
+  pub fn new(threshold: Duration) -> Self {
+    Self {
+      threshold,
+      observer: None,
+    }
+  }
+
+  pub fn observer<O>(mut self, observer: O) -> Self
+  where
+    O: StuckHandlerObserver + 'static,
+  {
+    self.observer = Some(Box::new(observer));
+    self
+  }
+}
+
+/// Runs the `fut` under the watchdog if it's set.
+pub(crate) async fn watch<F: Future>(
+  watchdog: Option<Arc<DispatchWatchdog>>,
+  mut handler: StuckHandler,
+  fut: F,
+) -> F::Output {
+  let watchdog = match watchdog {
+    None => return fut.await,
+    Some(watchdog) => watchdog,
+  };
+
+  tokio::pin!(fut);
+  let started_at = Instant::now();
+  loop {
+    tokio::select! {
+      biased;
+      output = &mut fut => return output,
+      _ = tokio::time::sleep(watchdog.threshold) => {
+        handler.elapsed = started_at.elapsed();
+        tracing::warn!(
+          "[dispatch]: event {:?} of request {} is still running after {:?}",
+          handler.event,
+          handler.request_id,
+          handler.elapsed
+        );
+        if let Some(observer) = &watchdog.observer {
+          observer.on_stuck(&handler);
+        }
+      },
+    }
+  }
+}
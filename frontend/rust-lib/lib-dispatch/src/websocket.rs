@@ -0,0 +1,188 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::stream::FuturesUnordered;
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::Uri;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::dispatcher::AFPluginDispatcher;
+use crate::errors::{DispatchError, InternalError};
+use crate::module::AFPluginRequest;
+use crate::notification::{
+  register_notification_sink, unregister_notification_sink, DispatchNotification, NotificationSink,
+};
+use crate::request::Payload;
+use crate::response::AFPluginEventResponse;
+use crate::subscription::{connect_subscriber, disconnect_subscriber};
+
+const RESPONSE_FRAME: u8 = 0;
+const NOTIFICATION_FRAME: u8 = 1;
+
+/// Serves the requests of a WebSocket client with the same handlers as the FFI path, e.g. for
+/// the web app or a remote debugger. It completes when the client closes the connection.
+///
+/// All the frames are binary, and the strings in them are prefixed with their big-endian `u32`
+/// length:
+/// - The request frame is the id, which is echoed in the response, the event, then the payload.
+/// - The response frame is `0u8`, the id of the request, the `u8` [StatusCode], then the
+///   payload.
+/// - The notification frame is `1u8`, the source, the big-endian `i32` type, the id, then the
+///   payload.
+///
+/// The client receives all the notifications, and the changes of the keys it subscribes to
+/// with the [SUBSCRIBE_EVENT]. Its subscriptions are dropped when it disconnects. The requests
+/// are handled concurrently, so the responses may be written in a different order.
+///
+/// The client that connects with a stable id, e.g. `ws://127.0.0.1:9001/?client_id=desktop-1`,
+/// can send the requests that are not responded again after it reconnects. The ones with the
+/// same request id are deduplicated with the idempotency of the dispatcher, so they get the
+/// responses of the first time instead of being handled twice. See
+/// [AFPluginDispatcherBuilder::idempotency_window].
+///
+/// ```ignore
+/// let listener = TcpListener::bind("127.0.0.1:9001").await?;
+/// while let Ok((stream, _)) = listener.accept().await {
+///   let dispatcher = dispatcher.clone();
+///   tokio::spawn(async move { serve_websocket(&dispatcher, stream).await });
+/// }
+/// ```
+///
+/// [StatusCode]: crate::prelude::StatusCode
+/// [SUBSCRIBE_EVENT]: crate::prelude::SUBSCRIBE_EVENT
+/// [AFPluginDispatcherBuilder::idempotency_window]: crate::prelude::AFPluginDispatcherBuilder::idempotency_window
+pub async fn serve_websocket<S>(
+  dispatcher: &AFPluginDispatcher,
+  stream: S,
+) -> Result<(), DispatchError>
+where
+  S: AsyncRead + AsyncWrite + Unpin,
+{
+  let mut client_id = None;
+  let handshake = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+    client_id = query_client_id(request.uri());
+    Ok(response)
+  };
+  let mut socket = tokio_tungstenite::accept_hdr_async(stream, handshake)
+    .await
+    .map_err(websocket_error)?;
+  let (frames, mut outgoing) = mpsc::unbounded_channel();
+  let sink = WebSocketSink { frames };
+  let sink_id = register_notification_sink(sink.clone());
+  let subscriber = connect_subscriber(sink);
+
+  let mut responses = FuturesUnordered::new();
+  let result = loop {
+    tokio::select! {
+      message = socket.next() => match message {
+        None | Some(Ok(Message::Close(_))) => break Ok(()),
+        Some(Err(e)) => break Err(websocket_error(e)),
+        Some(Ok(Message::Binary(frame))) => match decode_request(Bytes::from(frame)) {
+          Some((id, mut request)) => {
+            if let Some(client_id) = client_id.as_ref() {
+              request.idempotency_key = Some(format!("websocket:{}:{}", client_id, id));
+            }
+            request.context = request.context.extension(subscriber);
+            responses.push(async move {
+              let response = AFPluginDispatcher::async_send(dispatcher, request).await;
+              encode_response(&id, &response)
+            });
+          },
+          None => tracing::warn!("[dispatch]: drop the malformed websocket frame"),
+        },
+        // The pings are answered by the socket.
+        Some(Ok(_)) => {},
+      },
+      Some(frame) = responses.next() => {
+        if let Err(e) = socket.send(Message::Binary(frame)).await {
+          break Err(websocket_error(e));
+        }
+      },
+      Some(frame) = outgoing.recv() => {
+        if let Err(e) = socket.send(Message::Binary(frame)).await {
+          break Err(websocket_error(e));
+        }
+      },
+    }
+  };
+
+  unregister_notification_sink(sink_id);
+  disconnect_subscriber(subscriber);
+  result
+}
+
+/// Queues the notifications to be written by the connection.
+#[derive(Clone)]
+struct WebSocketSink {
+  frames: UnboundedSender<Vec<u8>>,
+}
+
+impl NotificationSink for WebSocketSink {
+  fn send_notification(&self, notification: &DispatchNotification) -> Result<(), String> {
+    self
+      .frames
+      .send(encode_notification(notification))
+      .map_err(|_| "The websocket is closed".to_string())
+  }
+}
+
+fn websocket_error(e: tokio_tungstenite::tungstenite::Error) -> DispatchError {
+  InternalError::Other(format!("[dispatch]: websocket error: {}", e)).into()
+}
+
+fn query_client_id(uri: &Uri) -> Option<String> {
+  uri
+    .query()?
+    .split('&')
+    .find_map(|pair| pair.strip_prefix("client_id="))
+    .filter(|id| !id.is_empty())
+    .map(|id| id.to_string())
+}
+
+fn decode_request(mut frame: Bytes) -> Option<(String, AFPluginRequest)> {
+  let id = get_string(&mut frame)?;
+  let event = get_string(&mut frame)?;
+  let mut request = AFPluginRequest::untyped(event);
+  if !frame.is_empty() {
+    request.payload = Payload::Bytes(frame);
+  }
+  Some((id, request))
+}
+
+fn encode_response(id: &str, response: &AFPluginEventResponse) -> Vec<u8> {
+  let mut frame = BytesMut::new();
+  frame.put_u8(RESPONSE_FRAME);
+  put_string(&mut frame, id);
+  frame.put_u8(response.status_code as u8);
+  if let Payload::Bytes(bytes) = &response.payload {
+    frame.put_slice(bytes);
+  }
+  frame.to_vec()
+}
+
+fn encode_notification(notification: &DispatchNotification) -> Vec<u8> {
+  let mut frame = BytesMut::new();
+  frame.put_u8(NOTIFICATION_FRAME);
+  put_string(&mut frame, &notification.source);
+  frame.put_i32(notification.ty);
+  put_string(&mut frame, &notification.id);
+  frame.put_slice(&notification.payload);
+  frame.to_vec()
+}
+
+fn put_string(frame: &mut BytesMut, value: &str) {
+  frame.put_u32(value.len() as u32);
+  frame.put_slice(value.as_bytes());
+}
+
+fn get_string(frame: &mut Bytes) -> Option<String> {
+  if frame.remaining() < 4 {
+    return None;
+  }
+  let len = frame.get_u32() as usize;
+  if frame.remaining() < len {
+    return None;
+  }
+  String::from_utf8(frame.split_to(len).to_vec()).ok()
+}
@@ -0,0 +1,49 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+
+static READ_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+async fn read() -> String {
+  READ_CALLS.fetch_add(1, Ordering::SeqCst);
+  "document".to_string()
+}
+
+async fn update(cache: DispatchCache) -> String {
+  cache.invalidate("read");
+  "updated".to_string()
+}
+
+#[tokio::test]
+async fn cache_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new()
+      .name("document")
+      .event("read", read)
+      .event("update", update)
+      .cache("read", Duration::from_millis(200))],
+  ));
+  let dispatcher = dispatch.as_ref();
+  let send_read = move || AFPluginDispatcher::async_send(dispatcher, AFPluginRequest::new("read"));
+  send_read().await;
+  let resp = send_read().await;
+  assert_eq!(resp.payload.as_ref(), b"document");
+  assert_eq!(READ_CALLS.load(Ordering::SeqCst), 1);
+
+  // The handler that changes the data invalidates the cached response.
+  AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("update")).await;
+  send_read().await;
+  assert_eq!(READ_CALLS.load(Ordering::SeqCst), 2);
+
+  // The expired response is not used.
+  tokio::time::sleep(Duration::from_millis(300)).await;
+  send_read().await;
+  assert_eq!(READ_CALLS.load(Ordering::SeqCst), 3);
+
+  std::mem::forget(dispatch);
+}
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use protobuf::well_known_types::StringValue;
+use protobuf::Message;
+
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+
+async fn shout(text: AFPluginParsed<StringValue>) -> String {
+  text.into_inner().value.to_uppercase()
+}
+
+fn string_value(value: &str) -> StringValue {
+  let mut string_value = StringValue::new();
+  string_value.value = value.to_string();
+  string_value
+}
+
+#[tokio::test]
+async fn codec_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().name("text").event("shout", shout)],
+  ));
+
+  let payload = string_value("hello").write_to_bytes().unwrap();
+  let request = AFPluginRequest::new("shout").payload(payload);
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.payload.as_ref(), b"HELLO");
+
+  // The same handler accepts the json payload.
+  let payload = protobuf::json::print_to_string(&string_value("hello")).unwrap();
+  let request = AFPluginRequest::new("shout")
+    .payload(payload)
+    .codec(PayloadCodec::Json);
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.payload.as_ref(), b"HELLO");
+
+  // The malformed payload is rejected before the handler runs.
+  let request = AFPluginRequest::new("shout")
+    .payload("{")
+    .codec(PayloadCodec::Json);
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_ne!(resp.status_code, StatusCode::Ok);
+
+  std::mem::forget(dispatch);
+}
+
+async fn echo_message(text: AFPluginParsed<StringValue>) -> AFPluginParsed<StringValue> {
+  let mut shouted = StringValue::new();
+  shouted.value = text.value.to_uppercase();
+  AFPluginParsed(shouted)
+}
+
+async fn read_message(text: AFPluginData<StringValue>) -> String {
+  text.into_inner().value
+}
+
+#[tokio::test]
+async fn protobuf_message_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new()
+      .name("text")
+      .event("echo", echo_message)
+      .event("read", read_message)],
+  ));
+
+  let payload = string_value("hello").write_to_bytes().unwrap();
+  let request = AFPluginRequest::new("echo").payload(payload);
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  let shouted = StringValue::parse_from_bytes(resp.payload.as_ref()).unwrap();
+  assert_eq!(shouted.value, "HELLO");
+
+  // The response is encoded with the codec of the request.
+  let payload = protobuf::json::print_to_string(&string_value("hello")).unwrap();
+  let request = AFPluginRequest::new("echo")
+    .payload(payload)
+    .codec(PayloadCodec::Json);
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  let json = std::str::from_utf8(resp.payload.as_ref()).unwrap();
+  let shouted = protobuf::json::parse_from_str::<StringValue>(json).unwrap();
+  assert_eq!(shouted.value, "HELLO");
+
+  // The malformed payload is responded with the error instead of panicking.
+  let request = AFPluginRequest::new("read").payload(vec![0xff, 0xff, 0xff]);
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.status_code, StatusCode::InvalidParams);
+
+  std::mem::forget(dispatch);
+}
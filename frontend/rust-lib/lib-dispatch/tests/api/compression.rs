@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+
+async fn export(size: String) -> String {
+  "a".repeat(size.parse().unwrap())
+}
+
+#[tokio::test]
+async fn compression_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![AFPlugin::new()
+        .name("document")
+        .event("export", export)])
+      .compression_threshold(1024)
+      .build()
+      .unwrap(),
+  );
+  let dispatcher = dispatch.as_ref();
+  let send = move |size: &'static str| {
+    let request = AFPluginRequest::new("export")
+      .payload(size)
+      .accept_compression(PayloadCompression::Lz4);
+    AFPluginDispatcher::async_send(dispatcher, request)
+  };
+
+  let resp = send("4096").await;
+  assert_eq!(resp.metadata(CONTENT_ENCODING), Some("lz4"));
+  assert!(resp.payload.as_ref().len() < 4096);
+  let resp = resp.decompress().unwrap();
+  assert_eq!(resp.payload.as_ref(), "a".repeat(4096).as_bytes());
+
+  // The payloads below the threshold are not compressed.
+  let resp = send("16").await;
+  assert!(resp.metadata(CONTENT_ENCODING).is_none());
+  assert_eq!(resp.payload.as_ref(), b"aaaaaaaaaaaaaaaa");
+
+  std::mem::forget(dispatch);
+}
@@ -0,0 +1,618 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+use tokio::sync::oneshot;
+
+pub async fn hello() -> String {
+  "say hello".to_string()
+}
+
+static RELEASE: Mutex<Option<oneshot::Receiver<()>>> = Mutex::new(None);
+
+pub async fn wait_release() -> String {
+  let release = RELEASE.lock().unwrap().take().unwrap();
+  let _ = release.await;
+  "released".to_string()
+}
+
+#[tokio::test]
+async fn capacity_test() {
+  let (release, rx) = oneshot::channel();
+  *RELEASE.lock().unwrap() = Some(rx);
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::with_capacity(
+    runtime,
+    vec![AFPlugin::new()
+      .event("hello", hello)
+      .event("wait", wait_release)],
+    1,
+  ));
+  let pending =
+    AFPluginDispatcher::try_async_send(dispatch.as_ref(), AFPluginRequest::new("wait")).unwrap();
+
+  // The running request takes the only slot.
+  let result = AFPluginDispatcher::try_async_send(dispatch.as_ref(), AFPluginRequest::new("hello"));
+  assert!(matches!(result, Err(TrySendError::Full(_))));
+
+  release.send(()).unwrap();
+  let resp = pending.await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(resp.payload.as_ref(), b"released");
+
+  // The slot is released with the response.
+  let resp = AFPluginDispatcher::try_async_send(dispatch.as_ref(), AFPluginRequest::new("hello"))
+    .unwrap()
+    .await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn builder_test() {
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .worker_threads(2)
+      .default_timeout(Duration::from_millis(20))
+      .plugin(
+        AFPlugin::new()
+          .event("hello", hello)
+          .event("write", write)
+          .event("stuck", stuck),
+      )
+      .build()
+      .unwrap(),
+  );
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("hello")).await;
+  assert_eq!(resp.payload.as_ref(), b"say hello");
+
+  // The requests without their own timeout are timed out by the default one.
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("stuck")).await;
+  assert_eq!(resp.status_code, StatusCode::Timeout);
+  let request = AFPluginRequest::new("write")
+    .payload("saved")
+    .timeout(Duration::from_secs(5));
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.payload.as_ref(), b"saved");
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn builder_duplicate_event_test() {
+  let result = AFPluginDispatcher::builder()
+    .runtime(Arc::new(AFPluginRuntime::current()))
+    .plugin(AFPlugin::new().name("a").event("hello", hello))
+    .plugin(AFPlugin::new().name("b").event("hello", hello))
+    .build();
+  let err = result.err().unwrap();
+  assert_eq!(err.code(), DispatchErrorCode::DuplicateEvent);
+}
+
+#[tokio::test]
+async fn runtime_handle_test() {
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime_handle(tokio::runtime::Handle::current())
+      .plugin(AFPlugin::new().event("hello", hello))
+      .build()
+      .unwrap(),
+  );
+
+  // The handler runs on the runtime of the test.
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("hello")).await;
+  assert_eq!(resp.payload.as_ref(), b"say hello");
+}
+
+static RECORDED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub async fn record(content: String) -> String {
+  RECORDED.lock().unwrap().push(content.clone());
+  content
+}
+
+#[tokio::test]
+async fn send_with_response_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("record", record)],
+  ));
+  let request = AFPluginRequest::new("record").payload("first");
+  let pending = AFPluginDispatcher::async_send_with_response(dispatch.as_ref(), request);
+
+  // The request is dispatched before its response is awaited.
+  tokio::time::timeout(Duration::from_secs(5), async {
+    while RECORDED.lock().unwrap().is_empty() {
+      tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+  })
+  .await
+  .unwrap();
+  let resp = pending.await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(resp.payload.as_ref(), b"first");
+
+  std::mem::forget(dispatch);
+}
+
+pub async fn write(content: String) -> String {
+  tokio::time::sleep(Duration::from_millis(50)).await;
+  content
+}
+
+pub async fn stuck() -> String {
+  tokio::time::sleep(Duration::from_secs(10)).await;
+  "done".to_string()
+}
+
+#[tokio::test]
+async fn shutdown_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("write", write)],
+  ));
+  let request = AFPluginRequest::new("write").payload("saved");
+  let pending = AFPluginDispatcher::async_send_with_response(dispatch.as_ref(), request);
+
+  // The running request is completed before the shutdown returns.
+  dispatch.shutdown(Duration::from_secs(5)).await.unwrap();
+  let resp = pending.await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(resp.payload.as_ref(), b"saved");
+
+  // The requests sent afterwards are rejected.
+  let request = AFPluginRequest::new("write").payload("lost");
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.status_code, StatusCode::Internal);
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn shutdown_timeout_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("stuck", stuck)],
+  ));
+  let _pending =
+    AFPluginDispatcher::async_send_with_response(dispatch.as_ref(), AFPluginRequest::new("stuck"));
+  assert!(dispatch.shutdown(Duration::from_millis(50)).await.is_err());
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn stop_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("write", write)],
+  ));
+  let request = AFPluginRequest::new("write").payload("saved");
+  let pending = AFPluginDispatcher::async_send_with_response(dispatch.as_ref(), request);
+  let (tx, rx) = oneshot::channel();
+  AFPluginDispatcher::stop(dispatch.clone(), Duration::from_secs(5), move |result| {
+    let _ = tx.send(result.is_ok());
+  });
+
+  // The signal is sent once the running request is completed.
+  dispatch.stopped().await;
+  assert!(dispatch.is_stopped());
+  assert!(rx.await.unwrap());
+  assert_eq!(pending.await.payload.as_ref(), b"saved");
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn dead_letter_test() {
+  let (sink, mut dead_letters) = tokio::sync::mpsc::unbounded_channel();
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![AFPlugin::new().event("hello", hello)])
+      .dead_letter_sink(sink)
+      .build()
+      .unwrap(),
+  );
+  let resp =
+    AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("unknown")).await;
+  assert_eq!(resp.status_code, StatusCode::NotFound);
+  assert_eq!(resp.error_code(), Some(DispatchErrorCode::Unhandled));
+
+  // The request without a handler is handed over to the sink.
+  let letter = dead_letters.recv().await.unwrap();
+  assert_eq!(letter.request.event, AFPluginEvent::from("unknown"));
+
+  // The handled requests are not.
+  AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("hello")).await;
+  assert!(dead_letters.try_recv().is_err());
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn sync_send_in_runtime_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("hello", hello)],
+  ));
+
+  // Blocking inside the runtime is refused instead of stalling the worker thread.
+  let result = AFPluginDispatcher::try_sync_send(dispatch.as_ref(), AFPluginRequest::new("hello"));
+  assert!(result.is_err());
+  let resp = AFPluginDispatcher::sync_send(dispatch.clone(), AFPluginRequest::new("hello"));
+  assert_eq!(resp.status_code, StatusCode::Internal);
+
+  std::mem::forget(dispatch);
+}
+
+#[test]
+fn sync_send_timeout_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("hello", hello).event("stuck", stuck)],
+  ));
+  let resp = AFPluginDispatcher::sync_send_timeout(
+    dispatch.as_ref(),
+    AFPluginRequest::new("hello"),
+    Duration::from_secs(5),
+  )
+  .unwrap();
+  assert_eq!(resp.payload.as_ref(), b"say hello");
+
+  // The caller's thread is released after the timeout.
+  let timeout = AFPluginDispatcher::sync_send_timeout(
+    dispatch.as_ref(),
+    AFPluginRequest::new("stuck"),
+    Duration::from_millis(50),
+  )
+  .unwrap_err();
+  assert_eq!(timeout.timeout, Duration::from_millis(50));
+
+  std::mem::forget(dispatch);
+}
+
+pub async fn echo(content: String) -> String {
+  content
+}
+
+#[tokio::test]
+async fn record_history_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![AFPlugin::new().event("echo", echo)])
+      .record_history(2)
+      .build()
+      .unwrap(),
+  );
+  for content in ["first", "second", "third"] {
+    let request = AFPluginRequest::new("echo").payload(content);
+    AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  }
+
+  // Only the last two dispatches are kept, from the oldest to the newest.
+  let history = dispatch.history();
+  assert_eq!(history.len(), 2);
+  assert_eq!(history[0].response.payload.as_ref(), b"second");
+  assert_eq!(history[1].response.payload.as_ref(), b"third");
+
+  std::mem::forget(dispatch);
+}
+
+static LOGGED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub async fn log(content: String) -> String {
+  LOGGED.lock().unwrap().push(content.clone());
+  content
+}
+
+#[tokio::test]
+async fn fire_and_forget_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("log", log)],
+  ));
+  let request = AFPluginRequest::new("log").payload("opened");
+  AFPluginDispatcher::fire_and_forget(dispatch.as_ref(), request);
+
+  // The handler still runs although nobody waits for the response.
+  tokio::time::timeout(Duration::from_secs(5), async {
+    while LOGGED.lock().unwrap().is_empty() {
+      tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+  })
+  .await
+  .unwrap();
+  assert_eq!(*LOGGED.lock().unwrap(), vec!["opened"]);
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn alias_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![AFPlugin::new().event("greet", hello)])
+      .alias("hello", "greet")
+      .build()
+      .unwrap(),
+  );
+
+  // The renamed event is still routed by its legacy name.
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("hello")).await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(resp.payload.as_ref(), b"say hello");
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn stats_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("hello", hello)],
+  ));
+  for _ in 0..2 {
+    AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("hello")).await;
+  }
+  AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("unknown")).await;
+
+  // The requests that don't run a handler are not counted.
+  let stats = dispatch.stats();
+  assert_eq!(stats.len(), 1);
+  assert_eq!(stats[0].event, "hello");
+  assert_eq!(stats[0].calls, 2);
+  assert_eq!(stats[0].errors, 0);
+  assert!(stats[0].max_latency >= stats[0].p50_latency);
+
+  let resp =
+    AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new(STATS_EVENT)).await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+
+  std::mem::forget(dispatch);
+}
+
+static IN_FLIGHT_RELEASE: Mutex<Option<oneshot::Receiver<()>>> = Mutex::new(None);
+
+async fn save() -> String {
+  let release = IN_FLIGHT_RELEASE.lock().unwrap().take().unwrap();
+  let _ = release.await;
+  "saved".to_string()
+}
+
+#[tokio::test]
+async fn in_flight_test() {
+  let (release, rx) = oneshot::channel();
+  *IN_FLIGHT_RELEASE.lock().unwrap() = Some(rx);
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("save", save)],
+  ));
+  let request = AFPluginRequest::new("save").correlation_id("stuck");
+  let pending = AFPluginDispatcher::try_async_send(dispatch.as_ref(), request).unwrap();
+
+  let in_flight = dispatch.in_flight();
+  assert_eq!(in_flight.len(), 1);
+  assert_eq!(in_flight[0].event, AFPluginEvent::from("save"));
+  assert_eq!(in_flight[0].correlation_id.as_deref(), Some("stuck"));
+
+  // The request is removed once it's handled.
+  release.send(()).unwrap();
+  assert_eq!(pending.await.payload.as_ref(), b"saved");
+  assert!(dispatch.in_flight().is_empty());
+
+  std::mem::forget(dispatch);
+}
+
+static GRAPH_DISPATCHER: Mutex<Option<Arc<AFPluginDispatcher>>> = Mutex::new(None);
+
+async fn open_workspace() -> String {
+  let dispatch = GRAPH_DISPATCHER.lock().unwrap().clone().unwrap();
+  for _ in 0..2 {
+    AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("load_views")).await;
+  }
+  "opened".to_string()
+}
+
+#[tokio::test]
+async fn event_graph_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new()
+      .event("open_workspace", open_workspace)
+      .event("load_views", hello)],
+  ));
+  *GRAPH_DISPATCHER.lock().unwrap() = Some(dispatch.clone());
+  AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("open_workspace")).await;
+
+  let graph = dispatch.event_graph();
+  assert_eq!(
+    graph.edges,
+    vec![EventEdge {
+      from: AFPluginEvent::from("open_workspace"),
+      to: AFPluginEvent::from("load_views"),
+      count: 2,
+    }]
+  );
+  assert!(graph.find_cycle().is_none());
+  assert_eq!(
+    graph.to_dot(),
+    "digraph dispatch {\n  \"open_workspace\" -> \"load_views\" [label=\"2\"];\n}"
+  );
+
+  std::mem::forget(dispatch);
+}
+
+static SPAWNED: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingSpawner;
+
+impl AFPluginSpawner for CountingSpawner {
+  fn spawn_detached(&self, future: AFBoxFuture<'static, ()>) {
+    SPAWNED.fetch_add(1, Ordering::SeqCst);
+    tokio::spawn(future);
+  }
+}
+
+#[tokio::test]
+async fn spawner_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![AFPlugin::new().event("hello", hello)])
+      .spawner(CountingSpawner)
+      .build()
+      .unwrap(),
+  );
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("hello")).await;
+  assert_eq!(resp.payload.as_ref(), b"say hello");
+
+  // The request is dispatched on the task of the spawner instead of the runtime.
+  assert_eq!(SPAWNED.load(Ordering::SeqCst), 1);
+
+  std::mem::forget(dispatch);
+}
+
+static REPORTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn report_error(report: &DispatchErrorReport<'_>) {
+  let entry = format!(
+    "{} {:?} {}",
+    report.event.as_str(),
+    report.response.status_code,
+    report.correlation_id.unwrap_or_default()
+  );
+  REPORTED.lock().unwrap().push(entry);
+}
+
+#[tokio::test]
+async fn error_observer_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![AFPlugin::new().event("hello", hello)])
+      .on_error(report_error)
+      .build()
+      .unwrap(),
+  );
+  let request = AFPluginRequest::new("hello").correlation_id("ok");
+  AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  let request = AFPluginRequest::new("unknown").correlation_id("failed");
+  AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+
+  // Only the failed requests are reported.
+  assert_eq!(*REPORTED.lock().unwrap(), vec!["unknown NotFound failed"]);
+
+  std::mem::forget(dispatch);
+}
+
+static STUCK: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[tokio::test]
+async fn watchdog_test() {
+  let watchdog =
+    DispatchWatchdog::new(Duration::from_millis(20)).observer(|stuck: &StuckHandler| {
+      STUCK.lock().unwrap().push(stuck.event.as_str().to_string());
+    });
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugin(AFPlugin::new().event("hello", hello).event("write", write))
+      .watchdog(watchdog)
+      .build()
+      .unwrap(),
+  );
+  AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("hello")).await;
+  let request = AFPluginRequest::new("write").payload("saved");
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+
+  // The slow handler is reported but still completes.
+  assert_eq!(resp.payload.as_ref(), b"saved");
+  let stuck = STUCK.lock().unwrap().clone();
+  assert!(!stuck.is_empty());
+  assert!(stuck.iter().all(|event| event == "write"));
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn lifecycle_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().name("greeting").event("hello", hello)],
+  ));
+  let mut lifecycle = dispatch.lifecycle().subscribe();
+  let plugin = AFPlugin::new().name("echo").event("echo", echo);
+  dispatch.register_plugin(plugin).unwrap();
+  assert_eq!(
+    lifecycle.recv().await.unwrap(),
+    DispatchLifecycle::PluginRegistered("echo".to_string())
+  );
+
+  AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("hello")).await;
+  assert_eq!(
+    lifecycle.recv().await.unwrap(),
+    DispatchLifecycle::QueueIdle
+  );
+
+  dispatch.shutdown(Duration::from_secs(5)).await.unwrap();
+  let mut transitions = vec![];
+  while let Ok(transition) = lifecycle.try_recv() {
+    transitions.push(transition);
+  }
+  assert_eq!(
+    transitions,
+    vec![DispatchLifecycle::ShuttingDown, DispatchLifecycle::Stopped]
+  );
+
+  std::mem::forget(dispatch);
+}
+
+static DRAINED: AtomicUsize = AtomicUsize::new(0);
+
+async fn count_drained() {
+  tokio::time::sleep(Duration::from_millis(10)).await;
+  DRAINED.fetch_add(1, Ordering::SeqCst);
+}
+
+#[tokio::test]
+async fn wait_drained_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("count", count_drained)],
+  ));
+  for _ in 0..8 {
+    AFPluginDispatcher::fire_and_forget(dispatch.as_ref(), AFPluginRequest::new("count"));
+  }
+  dispatch.wait_drained().await;
+  assert_eq!(DRAINED.load(Ordering::SeqCst), 8);
+
+  // The dispatcher keeps accepting the requests.
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("count")).await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+
+  std::mem::forget(dispatch);
+}
@@ -0,0 +1,29 @@
+use lib_dispatch::prelude::*;
+
+#[test]
+fn error_context_test() {
+  let result: Result<(), DispatchError> = Err(DispatchError::from("disk full".to_string()));
+  let err = result
+    .context("write the snapshot")
+    .with_context(|| format!("save the document {}", 1))
+    .unwrap_err();
+  assert!(err.to_string().starts_with("save the document 1"));
+
+  // The chain goes from the outermost context to the root cause.
+  assert_eq!(err.chain().count(), 3);
+  assert!(err.root_cause().to_string().contains("disk full"));
+  assert_eq!(err.code(), err.root_cause().code());
+}
+
+#[cfg(feature = "backtrace")]
+#[test]
+fn backtrace_test() {
+  let err = DispatchError::from("disk full".to_string());
+  let captured = err.backtrace().unwrap().to_string();
+
+  // The context shares the backtrace of the root cause instead of capturing another one.
+  let err = err.context("write the snapshot");
+  assert_eq!(err.backtrace().unwrap().to_string(), captured);
+  let resp = AFPluginEventResponse::from(err);
+  assert_eq!(resp.metadata(BACKTRACE), Some(captured.as_str()));
+}
@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use lib_dispatch::prelude::*;
+use lib_dispatch::test::EventTester;
+
+static SAVE_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+async fn save() -> String {
+  SAVE_CALLS.fetch_add(1, Ordering::SeqCst);
+  "saved".to_string()
+}
+
+async fn read() -> String {
+  "document".to_string()
+}
+
+#[tokio::test]
+async fn fault_injection_test() {
+  let faults = DispatchFaultInjection::new()
+    .seed(7)
+    .event("save", FaultRule::new().fail(1.0))
+    .event("read", FaultRule::new().drop(1.0));
+  let tester = EventTester::new(vec![AFPlugin::new()
+    .name("document")
+    .wrap(faults)
+    .event("save", save)
+    .event("read", read)])
+  .await;
+
+  // The failed request doesn't reach the handler.
+  let resp = tester
+    .event("save")
+    .async_send()
+    .await
+    .assert_status(StatusCode::Err);
+  assert_eq!(resp.response().error_code(), Some(DispatchErrorCode::Other));
+  assert_eq!(SAVE_CALLS.load(Ordering::SeqCst), 0);
+
+  // The dropped request is resolved by its timeout.
+  tester.pause_time();
+  let pending = tester
+    .event("read")
+    .request(|request| request.timeout(Duration::from_secs(1)))
+    .send();
+  tester.advance(Duration::from_secs(1)).await;
+  pending.await.assert_status(StatusCode::Timeout);
+
+  tester.teardown().await;
+}
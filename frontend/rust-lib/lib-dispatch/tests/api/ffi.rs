@@ -0,0 +1,92 @@
+use std::os::raw::c_void;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lib_dispatch::ffi::*;
+use lib_dispatch::prelude::*;
+
+async fn echo(content: String) -> String {
+  content
+}
+
+fn request_frame(event: &str, codec: u8, payload: &[u8]) -> Vec<u8> {
+  let mut frame = (event.len() as u32).to_be_bytes().to_vec();
+  frame.extend_from_slice(event.as_bytes());
+  frame.push(codec);
+  frame.extend_from_slice(payload);
+  frame
+}
+
+fn sync_send(frame: &[u8]) -> Option<Vec<u8>> {
+  let mut len = 0;
+  let output = af_dispatch_sync_send(frame.as_ptr(), frame.len(), &mut len);
+  if output.is_null() {
+    return None;
+  }
+  let response = unsafe { std::slice::from_raw_parts(output, len) }.to_vec();
+  af_dispatch_free(output, len);
+  Some(response)
+}
+
+static ASYNC_RESPONSES: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+extern "C" fn on_response(_context: *mut c_void, frame: *const u8, len: usize) {
+  let frame = unsafe { std::slice::from_raw_parts(frame, len) }.to_vec();
+  ASYNC_RESPONSES.lock().unwrap().push(frame);
+}
+
+// The embedded dispatcher is global, so the whole lifecycle is a single test. It blocks on the
+// runtime of the dispatcher, which is not allowed on a thread of a tokio runtime.
+#[test]
+fn c_abi_test() {
+  let frame = request_frame("echo", 0, b"hello");
+  assert_eq!(af_dispatch_init(), AF_DISPATCH_NO_PLUGINS);
+  assert!(sync_send(&frame).is_none());
+
+  set_plugin_factory(|| vec![AFPlugin::new().name("echo").event("echo", echo)]);
+  assert_eq!(af_dispatch_init(), AF_DISPATCH_OK);
+  assert_eq!(af_dispatch_init(), AF_DISPATCH_ALREADY_INITIALIZED);
+
+  // The response frame is the status code followed by the payload.
+  let response = sync_send(&frame).unwrap();
+  assert_eq!(response[0], StatusCode::Ok as u8);
+  assert_eq!(&response[1..], b"hello");
+  let response = sync_send(&request_frame("missing", 1, b"")).unwrap();
+  assert_eq!(response[0], StatusCode::NotFound as u8);
+
+  // The frames that are cut off or have an unknown codec are rejected.
+  assert!(sync_send(&frame[..6]).is_none());
+  assert!(sync_send(&request_frame("echo", 2, b"hello")).is_none());
+  let invalid = &frame[..2];
+  assert_eq!(
+    af_dispatch_async_send(
+      invalid.as_ptr(),
+      invalid.len(),
+      on_response,
+      std::ptr::null_mut()
+    ),
+    AF_DISPATCH_INVALID_FRAME
+  );
+
+  assert_eq!(
+    af_dispatch_async_send(
+      frame.as_ptr(),
+      frame.len(),
+      on_response,
+      std::ptr::null_mut()
+    ),
+    AF_DISPATCH_OK
+  );
+  let started_at = Instant::now();
+  while ASYNC_RESPONSES.lock().unwrap().is_empty() {
+    assert!(started_at.elapsed() < Duration::from_secs(5));
+    std::thread::sleep(Duration::from_millis(10));
+  }
+  let mut response = vec![StatusCode::Ok as u8];
+  response.extend_from_slice(b"hello");
+  assert_eq!(*ASYNC_RESPONSES.lock().unwrap(), vec![response]);
+
+  assert_eq!(af_dispatch_shutdown(1000), AF_DISPATCH_OK);
+  assert!(sync_send(&frame).is_none());
+  assert_eq!(af_dispatch_shutdown(1000), AF_DISPATCH_NOT_INITIALIZED);
+}
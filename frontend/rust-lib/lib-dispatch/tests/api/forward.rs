@@ -0,0 +1,136 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::UnixListener;
+
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+
+static BUILD_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+async fn build(name: String) -> String {
+  BUILD_CALLS.fetch_add(1, Ordering::SeqCst);
+  format!("indexed {}", name)
+}
+
+async fn lookup(word: String) -> String {
+  format!("found {}", word)
+}
+
+async fn read_frame<S: AsyncRead + Unpin>(stream: &mut S) -> Vec<u8> {
+  let len = stream.read_u32().await.unwrap() as usize;
+  let mut frame = vec![0; len];
+  stream.read_exact(&mut frame).await.unwrap();
+  frame
+}
+
+async fn write_frame<S: AsyncWrite + Unpin>(stream: &mut S, frame: &[u8]) {
+  stream.write_u32(frame.len() as u32).await.unwrap();
+  stream.write_all(frame).await.unwrap();
+}
+
+#[tokio::test]
+async fn forward_test() {
+  let path = std::env::temp_dir().join(format!(
+    "dispatch-forward-lookup-{}.sock",
+    std::process::id()
+  ));
+  let _ = std::fs::remove_file(&path);
+  let listener = UnixListener::bind(&path).unwrap();
+  let helper = Arc::new(AFPluginDispatcher::new(
+    Arc::new(AFPluginRuntime::new().unwrap()),
+    vec![AFPlugin::new()
+      .name("indexer")
+      .event("index.lookup", lookup)],
+  ));
+  let app = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(Arc::new(AFPluginRuntime::new().unwrap()))
+      .forward(DispatchForwarder::new("index.", &path))
+      .build()
+      .unwrap(),
+  );
+
+  let serve = async {
+    let (stream, _) = listener.accept().await.unwrap();
+    serve_forwarded(helper.as_ref(), stream).await.unwrap();
+  };
+  let request = AFPluginRequest::new("index.lookup").payload("notes");
+  let send = AFPluginDispatcher::async_send(app.as_ref(), request);
+  let resp = tokio::select! {
+    resp = send => resp,
+    _ = serve => panic!("The helper stopped serving"),
+  };
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(resp.payload.as_ref(), b"found notes");
+
+  // The events without the prefix are not forwarded.
+  let resp = AFPluginDispatcher::async_send(app.as_ref(), AFPluginRequest::new("lookup")).await;
+  assert_eq!(resp.status_code, StatusCode::NotFound);
+
+  std::mem::forget(app);
+  std::mem::forget(helper);
+  std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn forward_outbox_test() {
+  let path = std::env::temp_dir().join(format!(
+    "dispatch-forward-build-{}.sock",
+    std::process::id()
+  ));
+  let _ = std::fs::remove_file(&path);
+  let listener = UnixListener::bind(&path).unwrap();
+  let helper = Arc::new(AFPluginDispatcher::new(
+    Arc::new(AFPluginRuntime::new().unwrap()),
+    vec![AFPlugin::new().name("indexer").event("index.build", build)],
+  ));
+  let app = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(Arc::new(AFPluginRuntime::new().unwrap()))
+      .forward(
+        DispatchForwarder::new("index.", &path)
+          .reconnect(DispatchRetryPolicy::new(8).initial_backoff(Duration::from_millis(10))),
+      )
+      .build()
+      .unwrap(),
+  );
+
+  let serve = async {
+    // The helper handles the request of the first connection, but the connection is broken
+    // before the response is sent back.
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let hello = read_frame(&mut stream).await;
+    let request = read_frame(&mut stream).await;
+    let (mut local, remote) = tokio::io::duplex(64 * 1024);
+    let lose_response = async move {
+      write_frame(&mut local, &hello).await;
+      write_frame(&mut local, &request).await;
+      read_frame(&mut local).await;
+    };
+    let (served, _) = tokio::join!(serve_forwarded(helper.as_ref(), remote), lose_response);
+    served.unwrap();
+    drop(stream);
+
+    // The forwarder reconnects and sends the outbox again.
+    let (stream, _) = listener.accept().await.unwrap();
+    serve_forwarded(helper.as_ref(), stream).await.unwrap();
+  };
+  let request = AFPluginRequest::new("index.build").payload("notes");
+  let send = AFPluginDispatcher::async_send(app.as_ref(), request);
+  let resp = tokio::select! {
+    resp = send => resp,
+    _ = serve => panic!("The helper stopped serving"),
+  };
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(resp.payload.as_ref(), b"indexed notes");
+
+  // The request that is sent again is deduplicated by the helper.
+  assert_eq!(BUILD_CALLS.load(Ordering::SeqCst), 1);
+
+  std::mem::forget(app);
+  std::mem::forget(helper);
+  std::fs::remove_file(&path).unwrap();
+}
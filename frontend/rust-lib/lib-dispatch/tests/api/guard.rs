@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+
+async fn open_v1() -> String {
+  "opened v1".to_string()
+}
+
+async fn open_v2() -> String {
+  "opened v2".to_string()
+}
+
+fn is_v2(_request: &AFPluginEventRequest, payload: &Payload) -> bool {
+  payload.as_ref() == b"v2"
+}
+
+#[tokio::test]
+async fn guarded_event_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new()
+      .name("document")
+      .guarded_event("open", is_v2, open_v2)
+      .event("open", open_v1)],
+  ));
+  let request = AFPluginRequest::new("open").payload("v2");
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.payload.as_ref(), b"opened v2");
+
+  // The requests that the guard rejects are handled by the unguarded handler.
+  let request = AFPluginRequest::new("open").payload("v1");
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.payload.as_ref(), b"opened v1");
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn guarded_event_without_fallback_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new()
+      .name("document")
+      .guarded_event("open", is_v2, open_v2)],
+  ));
+  let request = AFPluginRequest::new("open").payload("v2");
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+
+  // The request fails if none of the guards accepts it.
+  let request = AFPluginRequest::new("open").payload("v1");
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.status_code, StatusCode::InvalidParams);
+
+  std::mem::forget(dispatch);
+}
@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+
+async fn local_search() -> String {
+  "local results".to_string()
+}
+
+async fn cloud_search() -> String {
+  "cloud results".to_string()
+}
+
+fn redirect_to_cloud(request: &mut AFPluginRequest) -> Option<AFPluginEventResponse> {
+  if request.event == AFPluginEvent::from("search")
+    && request.correlation_id.as_deref() == Some("cloud")
+  {
+    request.event = AFPluginEvent::from("cloud_search");
+  }
+  None
+}
+
+fn block_offline(request: &mut AFPluginRequest) -> Option<AFPluginEventResponse> {
+  if request.correlation_id.as_deref() == Some("offline") {
+    return Some(ResponseBuilder::Ok().data("offline").build());
+  }
+  None
+}
+
+#[tokio::test]
+async fn interceptor_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![AFPlugin::new()
+        .name("search")
+        .event("search", local_search)
+        .event("cloud_search", cloud_search)])
+      .interceptor(block_offline)
+      .interceptor(redirect_to_cloud)
+      .build()
+      .unwrap(),
+  );
+  let dispatcher = dispatch.as_ref();
+  let send = move |correlation_id: &'static str| {
+    let request = AFPluginRequest::new("search").correlation_id(correlation_id);
+    AFPluginDispatcher::async_send(dispatcher, request)
+  };
+
+  let resp = send("local").await;
+  assert_eq!(resp.payload.as_ref(), b"local results");
+
+  // The rewritten event is routed to the other handler.
+  let resp = send("cloud").await;
+  assert_eq!(resp.payload.as_ref(), b"cloud results");
+
+  // The response of the interceptor resolves the request without calling any handler.
+  let resp = send("offline").await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(resp.payload.as_ref(), b"offline");
+
+  std::mem::forget(dispatch);
+}
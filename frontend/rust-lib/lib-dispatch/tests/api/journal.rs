@@ -0,0 +1,81 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+use lib_dispatch::test::EventTester;
+
+static SAVED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+async fn save(content: String) -> String {
+  SAVED.lock().unwrap().push(content);
+  "saved".to_string()
+}
+
+fn entry(request_id: &str, content: &str) -> JournalEntry {
+  JournalEntry {
+    request_id: request_id.to_string(),
+    event: AFPluginEvent::from("save"),
+    codec: PayloadCodec::default(),
+    payload: Payload::from(content),
+    payload_version: None,
+    ordering_key: None,
+    correlation_id: None,
+    idempotency_key: None,
+  }
+}
+
+#[tokio::test]
+async fn journal_redispatch_test() {
+  let path = std::env::temp_dir().join(format!("dispatch-journal-{}.log", std::process::id()));
+  let _ = std::fs::remove_file(&path);
+  {
+    let journal = FileJournal::open(&path).unwrap();
+    journal.append(&entry("save-1", "first"));
+    journal.append(&entry("save-2", "second"));
+    journal.append(&entry("save-3", "third"));
+    journal.complete("save-2");
+  }
+  // The app crashes while it writes the line of the next request.
+  let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+  write!(file, "+\tsave-4\tsave\tproto").unwrap();
+  drop(file);
+
+  let journal = FileJournal::open(&path).unwrap();
+  let pending = journal
+    .pending()
+    .into_iter()
+    .map(|entry| entry.request_id)
+    .collect::<Vec<_>>();
+  assert_eq!(pending, vec!["save-1", "save-3"]);
+
+  // Opening the journal compacts the file to the header and the pending entries.
+  let content = std::fs::read_to_string(&path).unwrap();
+  assert_eq!(content.lines().count(), 3);
+  assert!(!content.contains("save-2"));
+  assert!(!content.contains("save-4"));
+
+  let dispatcher = AFPluginDispatcher::builder()
+    .runtime(Arc::new(AFPluginRuntime::current()))
+    .plugin(
+      AFPlugin::new()
+        .name("document")
+        .event("save", save)
+        .mutating("save"),
+    )
+    .journal(journal)
+    .build()
+    .unwrap();
+  let tester = EventTester::with_dispatcher(dispatcher).await;
+  assert_eq!(tester.dispatcher().redispatch_journaled(), 2);
+  tester.teardown().await;
+
+  let mut saved = SAVED.lock().unwrap().clone();
+  saved.sort();
+  assert_eq!(saved, vec!["first", "third"]);
+
+  // The redispatched requests are completed, so the next launch doesn't send them again.
+  assert!(FileJournal::open(&path).unwrap().pending().is_empty());
+  std::fs::remove_file(&path).unwrap();
+}
@@ -1 +1,28 @@
+mod cache;
+#[cfg(feature = "use_protobuf")]
+mod codec;
+#[cfg(feature = "compress_lz4")]
+mod compression;
+mod dispatcher;
+mod errors;
+mod fault;
+#[cfg(all(feature = "c_abi", not(feature = "local_set")))]
+mod ffi;
+#[cfg(all(feature = "forward", unix))]
+mod forward;
+mod guard;
+mod interceptor;
+mod journal;
+mod mock;
 mod module;
+mod notification;
+mod plugin;
+mod replay;
+mod request;
+mod scheduler;
+mod shared_buffer;
+mod supervisor;
+mod tester;
+mod time;
+#[cfg(feature = "use_protobuf")]
+mod validate;
@@ -0,0 +1,55 @@
+use lib_dispatch::prelude::*;
+use lib_dispatch::test::EventTester;
+
+async fn hello() -> String {
+  "say hello".to_string()
+}
+
+#[tokio::test]
+async fn mock_event_test() {
+  let tester = EventTester::new(vec![AFPlugin::new().name("greeting").event("hello", hello)]).await;
+  let recorder = MockRecorder::new(MockResponse::new(
+    ResponseBuilder::Ok().data("mocked").build(),
+  ));
+  tester.mock_event("hello", recorder.clone());
+
+  let resp = tester
+    .event("hello")
+    .request(|request| request.correlation_id("first"))
+    .async_send()
+    .await
+    .assert_ok();
+  assert_eq!(resp.response().payload.as_ref(), b"mocked");
+  let calls = recorder.calls();
+  assert_eq!(calls.len(), 1);
+  assert_eq!(calls[0].event, AFPluginEvent::from("hello"));
+  assert_eq!(calls[0].correlation_id.as_deref(), Some("first"));
+
+  // The registered handler handles the event again.
+  tester.dispatcher().unmock_event("hello");
+  let resp = tester.event("hello").async_send().await.assert_ok();
+  assert_eq!(resp.response().payload.as_ref(), b"say hello");
+  assert_eq!(recorder.call_count(), 1);
+
+  tester.teardown().await;
+}
+
+#[tokio::test]
+async fn mock_unregistered_event_test() {
+  let tester = EventTester::new(vec![AFPlugin::new().name("greeting").event("hello", hello)]).await;
+  tester.mock_event("missing", |request: &AFPluginRequest| {
+    let event = request.event.as_str().to_string();
+    ResponseBuilder::Ok().data(event).build()
+  });
+  let resp = tester.event("missing").async_send().await.assert_ok();
+  assert_eq!(resp.response().payload.as_ref(), b"missing");
+
+  tester.dispatcher().clear_mocks();
+  tester
+    .event("missing")
+    .async_send()
+    .await
+    .assert_status(StatusCode::NotFound);
+
+  tester.teardown().await;
+}
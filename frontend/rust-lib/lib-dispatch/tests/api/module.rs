@@ -1,7 +1,9 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
+use bytes::Bytes;
 use lib_dispatch::prelude::*;
 use lib_dispatch::runtime::AFPluginRuntime;
+use lib_dispatch::test::EventTester;
 
 pub async fn hello() -> String {
   "say hello".to_string()
@@ -10,18 +12,359 @@ pub async fn hello() -> String {
 #[tokio::test]
 async fn test() {
   let event = "1";
+  let tester = EventTester::new(vec![AFPlugin::new().event(event, hello)]).await;
+  let request = AFPluginRequest::new(event);
+  let resp =
+    AFPluginDispatcher::async_send_with_callback(tester.dispatcher().as_ref(), request, |resp| {
+      Box::pin(async move {
+        dbg!(&resp);
+      })
+    })
+    .await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(resp.payload.as_ref(), b"say hello");
+
+  tester.teardown().await;
+}
+
+pub async fn compute(content: String) -> String {
+  content.to_uppercase()
+}
+
+#[tokio::test]
+async fn send_event_test() {
   let runtime = Arc::new(AFPluginRuntime::new().unwrap());
   let dispatch = Arc::new(AFPluginDispatcher::new(
     runtime,
-    vec![AFPlugin::new().event(event, hello)],
+    vec![AFPlugin::new().send_event("compute", compute)],
   ));
-  let request = AFPluginRequest::new(event);
-  let _ = AFPluginDispatcher::async_send_with_callback(dispatch.as_ref(), request, |resp| {
+  let request = AFPluginRequest::new("compute").payload("document");
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(resp.payload.as_ref(), b"DOCUMENT");
+
+  std::mem::forget(dispatch);
+}
+
+pub async fn unsupported(event: AFPluginEvent) -> String {
+  format!("{:?} is unsupported", event)
+}
+
+#[tokio::test]
+async fn fallback_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![
+      AFPlugin::new().event("1", hello),
+      AFPlugin::new().name("proxy").fallback(unsupported),
+    ],
+  ));
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("2")).await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(
+    resp.payload.as_ref(),
+    br#"AFPluginEvent("2") is unsupported"#
+  );
+
+  // The registered events are not handled by the fallback.
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("1")).await;
+  assert_eq!(resp.payload.as_ref(), b"say hello");
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn unregister_fallback_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![
+      AFPlugin::new().event("1", hello),
+      AFPlugin::new().name("proxy").fallback(unsupported),
+    ],
+  ));
+  dispatch.unregister_plugin("proxy").await.unwrap();
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("2")).await;
+  assert_eq!(resp.status_code, StatusCode::NotFound);
+
+  std::mem::forget(dispatch);
+}
+
+pub struct UserSession;
+
+pub async fn read_session(_session: AFPluginState<UserSession>) -> String {
+  "signed in".to_string()
+}
+
+#[tokio::test]
+async fn extract_error_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("session", read_session)],
+  ));
+  let resp =
+    AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("session")).await;
+
+  // The response is still the extractor's error.
+  assert_eq!(resp.status_code, StatusCode::Err);
+  let msg = String::from_utf8(resp.payload.to_vec()).unwrap();
+  assert!(msg.starts_with("Failed to get the plugin state of type"));
+
+  std::mem::forget(dispatch);
+}
+
+static MIDDLEWARE_LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct Logged(&'static str, AFPluginBoxService);
+
+impl Service<ServiceRequest> for Logged {
+  type Response = ServiceResponse;
+  type Error = DispatchError;
+  type Future = AFBoxFuture<'static, Result<ServiceResponse, DispatchError>>;
+
+  fn call(&self, req: ServiceRequest) -> Self::Future {
+    let name = self.0;
+    MIDDLEWARE_LOGS
+      .lock()
+      .unwrap()
+      .push(format!("{} before", name));
+    let fut = self.1.call(req);
     Box::pin(async move {
-      dbg!(&resp);
+      let response = fut.await;
+      MIDDLEWARE_LOGS
+        .lock()
+        .unwrap()
+        .push(format!("{} after", name));
+      response
     })
-  })
+  }
+}
+
+fn logged(name: &'static str) -> impl Fn(AFPluginBoxService) -> AFPluginBoxService + AFConcurrent {
+  move |service| Box::new(Logged(name, service))
+}
+
+#[tokio::test]
+async fn middleware_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new()
+      .wrap(logged("outer"))
+      .wrap(logged("inner"))
+      .event("hello", hello)],
+  ));
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("hello")).await;
+  assert_eq!(resp.payload.as_ref(), b"say hello");
+
+  // The middleware that is added first sees the request first and the response last.
+  assert_eq!(
+    *MIDDLEWARE_LOGS.lock().unwrap(),
+    vec!["outer before", "inner before", "inner after", "outer after"]
+  );
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn async_factory_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let factories: Vec<AFPluginFactory> = vec![Box::pin(async {
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    Ok(AFPlugin::new().event("hello", hello))
+  })];
+  let dispatch = Arc::new(
+    AFPluginDispatcher::new_async(runtime.clone(), factories)
+      .await
+      .unwrap(),
+  );
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("hello")).await;
+  assert_eq!(resp.payload.as_ref(), b"say hello");
+
+  // The conflicting events fail the creation instead of panicking.
+  let factories: Vec<AFPluginFactory> = vec![
+    Box::pin(async { Ok(AFPlugin::new().name("a").event("hello", hello)) }),
+    Box::pin(async { Ok(AFPlugin::new().name("b").event("hello", hello)) }),
+  ];
+  assert!(AFPluginDispatcher::new_async(runtime, factories)
+    .await
+    .is_err());
+
+  std::mem::forget(dispatch);
+}
+
+pub async fn panic_handler() -> String {
+  panic!("handler panicked")
+}
+
+#[tokio::test]
+async fn panic_test() {
+  let tester = EventTester::new(vec![AFPlugin::new()
+    .event("1", hello)
+    .event("panic", panic_handler)])
   .await;
+  let resp = tester
+    .event("panic")
+    .async_send()
+    .await
+    .assert_status(StatusCode::Internal);
+  assert_eq!(
+    resp.response().error_code(),
+    Some(DispatchErrorCode::HandlerPanic)
+  );
+
+  // The dispatcher keeps handling the other events.
+  tester.event("1").async_send().await.assert_ok();
+
+  tester.teardown().await;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum DocumentEvent {
+  Open,
+}
+
+impl std::fmt::Display for DocumentEvent {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str("OpenDocument")
+  }
+}
+
+impl AFPluginEventType for DocumentEvent {}
+
+#[tokio::test]
+async fn typed_event_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event(DocumentEvent::Open, hello)],
+  ));
+  let resp =
+    AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new(DocumentEvent::Open))
+      .await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+
+  // The host sends the wire representation of the event.
+  let resp =
+    AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::untyped("OpenDocument"))
+      .await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+
+  std::mem::forget(dispatch);
+}
+
+async fn versioned() -> AFPluginEventResponse {
+  ResponseBuilder::Ok()
+    .data("document")
+    .metadata("version", "3")
+    .build()
+}
+
+#[tokio::test]
+async fn response_metadata_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("read", versioned)],
+  ));
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("read")).await;
+  assert!(resp.status_code.is_ok());
+  assert_eq!(resp.metadata("version"), Some("3"));
+
+  // The errors reported by the dispatcher have their own status codes.
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("write")).await;
+  assert_eq!(resp.status_code, StatusCode::NotFound);
+
+  std::mem::forget(dispatch);
+}
+
+async fn sign_in_first() -> (StatusCode, String) {
+  (StatusCode::Unauthorized, "sign in first".to_string())
+}
+
+async fn raw_payload() -> Payload {
+  Payload::from(vec![1u8, 2, 3])
+}
+
+async fn rejected() -> DispatchError {
+  DispatchError::from("rejected".to_string())
+}
+
+#[tokio::test]
+async fn responder_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new()
+      .event("sign_in_first", sign_in_first)
+      .event("raw", raw_payload)
+      .event("rejected", rejected)],
+  ));
+  let dispatcher = dispatch.as_ref();
+  let send = move |event: &'static str| {
+    AFPluginDispatcher::async_send(dispatcher, AFPluginRequest::new(event))
+  };
+  let resp = send("sign_in_first").await;
+  assert_eq!(resp.status_code, StatusCode::Unauthorized);
+  assert_eq!(resp.payload.as_ref(), b"sign in first");
+
+  let resp = send("raw").await;
+  assert_eq!(resp.payload.as_ref(), &[1u8, 2, 3]);
+
+  let resp = send("rejected").await;
+  assert_eq!(resp.status_code, StatusCode::Err);
+
+  std::mem::forget(dispatch);
+}
+
+struct RenameWorkspace;
+
+impl AFPluginVersioned for RenameWorkspace {
+  const PAYLOAD_VERSION: u32 = 2;
+}
+
+async fn rename_workspace(name: String) -> String {
+  name
+}
+
+fn append_version(payload: Bytes, version: &str) -> Result<Bytes, DispatchError> {
+  let payload = String::from_utf8_lossy(&payload);
+  Ok(Bytes::from(format!("{} {}", payload, version)))
+}
+
+#[tokio::test]
+async fn payload_migration_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new()
+      .event("rename", rename_workspace)
+      .versioned::<_, RenameWorkspace>("rename")
+      .migrate("rename", 0, |payload: Bytes| append_version(payload, "v1"))
+      .migrate("rename", 1, |payload: Bytes| append_version(payload, "v2"))],
+  ));
+  // The migrations run one by one up to the version that the handler expects.
+  let request = AFPluginRequest::new("rename")
+    .payload("notes")
+    .payload_version(0);
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.payload.as_ref(), b"notes v1 v2");
+
+  // The payload of the current version is passed as is.
+  let request = AFPluginRequest::new("rename")
+    .payload("notes")
+    .payload_version(2);
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.payload.as_ref(), b"notes");
+
+  // The payload of a newer version can't be handled.
+  let request = AFPluginRequest::new("rename")
+    .payload("notes")
+    .payload_version(3);
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.status_code, StatusCode::InvalidParams);
 
   std::mem::forget(dispatch);
 }
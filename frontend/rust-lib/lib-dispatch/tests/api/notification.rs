@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+
+#[tokio::test]
+async fn notification_sink_test() {
+  let collect = CollectNotificationSink::new();
+  let collect_id = register_notification_sink(collect.clone());
+  let channel = ChannelNotificationSink::new(8);
+  let mut receiver = channel.subscribe();
+  let channel_id = register_notification_sink(channel);
+
+  DispatchNotification::new("Folder", 1, Bytes::from_static(b"view"))
+    .id("view-1")
+    .send();
+  let notification = receiver.recv().await.unwrap();
+  assert_eq!(notification.id, "view-1");
+  assert_eq!(notification.payload.as_ref(), b"view");
+
+  // The sinks are global, so only the notifications of this test are checked.
+  let collected = collect
+    .take()
+    .into_iter()
+    .filter(|notification| notification.source == "Folder")
+    .collect::<Vec<_>>();
+  assert_eq!(collected, vec![notification]);
+
+  // The unregistered sink doesn't receive the notifications anymore.
+  unregister_notification_sink(collect_id);
+  DispatchNotification::new("Folder", 2, Bytes::new()).send();
+  assert!(collect.notifications().is_empty());
+  assert_eq!(receiver.recv().await.unwrap().ty, 2);
+
+  unregister_notification_sink(channel_id);
+}
+
+async fn hello() -> String {
+  "say hello".to_string()
+}
+
+#[tokio::test]
+async fn subscription_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("hello", hello)],
+  ));
+  let subscriber = connect_subscriber(CollectNotificationSink::new());
+  let subscribe = |event: &'static str| {
+    AFPluginRequest::new(event)
+      .payload("document-1")
+      .context(AFPluginContext::new().extension(subscriber))
+  };
+
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), subscribe(SUBSCRIBE_EVENT)).await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(subscriber_count("document-1"), 1);
+
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), subscribe(UNSUBSCRIBE_EVENT)).await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(subscriber_count("document-1"), 0);
+
+  // The request without a subscriber can't subscribe.
+  let request = AFPluginRequest::new(SUBSCRIBE_EVENT).payload("document-1");
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_ne!(resp.status_code, StatusCode::Ok);
+
+  disconnect_subscriber(subscriber);
+  std::mem::forget(dispatch);
+}
@@ -0,0 +1,648 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+
+fn record(
+  log: &'static Mutex<Vec<String>>,
+  entry: &'static str,
+) -> impl Fn() -> AFBoxFuture<'static, ()> + AFConcurrent + 'static {
+  move || {
+    Box::pin(async move {
+      log.lock().unwrap().push(entry.to_string());
+    })
+  }
+}
+
+async fn hello() -> String {
+  "say hello".to_string()
+}
+
+async fn extra() -> String {
+  "extra".to_string()
+}
+
+#[tokio::test]
+async fn register_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().name("greeting").event("hello", hello)],
+  ));
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("extra")).await;
+  assert_eq!(resp.status_code, StatusCode::NotFound);
+
+  dispatch
+    .register_plugin(AFPlugin::new().name("extra").event("extra", extra))
+    .unwrap();
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("extra")).await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(resp.payload.as_ref(), b"extra");
+
+  // The plugin whose events are already registered is rejected.
+  let duplicate = AFPlugin::new().name("duplicate").event("hello", extra);
+  assert!(dispatch.register_plugin(duplicate).is_err());
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("hello")).await;
+  assert_eq!(resp.payload.as_ref(), b"say hello");
+
+  std::mem::forget(dispatch);
+}
+
+static TEARDOWNS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[tokio::test]
+async fn unregister_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![
+      AFPlugin::new().name("greeting").event("hello", hello),
+      AFPlugin::new()
+        .name("extra")
+        .event("extra", extra)
+        .teardown(record(&TEARDOWNS, "extra")),
+    ],
+  ));
+  dispatch.unregister_plugin("extra").await.unwrap();
+  assert_eq!(*TEARDOWNS.lock().unwrap(), vec!["extra"]);
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("extra")).await;
+  assert_eq!(resp.status_code, StatusCode::NotFound);
+  assert!(dispatch.unregister_plugin("extra").await.is_err());
+
+  // The other plugins are not affected.
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("hello")).await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+
+  std::mem::forget(dispatch);
+}
+
+async fn save_locally() -> String {
+  "saved locally".to_string()
+}
+
+async fn save_remotely() -> String {
+  "saved remotely".to_string()
+}
+
+#[tokio::test]
+async fn duplicate_policy_test() {
+  let dispatch = |policy: DuplicatePolicy| {
+    let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+    let dispatch = Arc::new(
+      AFPluginDispatcher::builder()
+        .runtime(runtime)
+        .plugins(vec![AFPlugin::new()
+          .name("local")
+          .event("save", save_locally)])
+        .duplicate_policy(policy)
+        .build()
+        .unwrap(),
+    );
+    let remote = AFPlugin::new().name("remote").event("save", save_remotely);
+    let result = dispatch.register_plugin(remote);
+    (dispatch, result)
+  };
+
+  // The duplicate event is rejected by default.
+  let (rejected, result) = dispatch(DuplicatePolicy::Error);
+  assert!(result.is_err());
+  let resp = AFPluginDispatcher::async_send(rejected.as_ref(), AFPluginRequest::new("save")).await;
+  assert_eq!(resp.payload.as_ref(), b"saved locally");
+
+  let (overridden, result) = dispatch(DuplicatePolicy::Override);
+  result.unwrap();
+  let resp =
+    AFPluginDispatcher::async_send(overridden.as_ref(), AFPluginRequest::new("save")).await;
+  assert_eq!(resp.payload.as_ref(), b"saved remotely");
+
+  // Both handlers are called and the last response is returned.
+  let (chained, result) = dispatch(DuplicatePolicy::Chain);
+  result.unwrap();
+  let resp = AFPluginDispatcher::async_send(chained.as_ref(), AFPluginRequest::new("save")).await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(resp.payload.as_ref(), b"saved remotely");
+
+  std::mem::forget(rejected);
+  std::mem::forget(overridden);
+  std::mem::forget(chained);
+}
+
+async fn open_document() -> String {
+  "document opened".to_string()
+}
+
+async fn open_database() -> String {
+  "database opened".to_string()
+}
+
+#[tokio::test]
+async fn namespace_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![
+      AFPlugin::new()
+        .name("document")
+        .namespace("document")
+        .event("open", open_document)
+        .event("close", hello),
+      AFPlugin::new()
+        .name("database")
+        .namespace("database")
+        .event("open", open_database),
+    ],
+  ));
+  let send =
+    |event: &str| AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new(event));
+  assert_eq!(
+    send("document.open").await.payload.as_ref(),
+    b"document opened"
+  );
+  assert_eq!(
+    send("database.open").await.payload.as_ref(),
+    b"database opened"
+  );
+
+  // The legacy event is still routed unless it's ambiguous.
+  assert_eq!(send("close").await.status_code, StatusCode::Ok);
+  assert_eq!(send("open").await.status_code, StatusCode::NotFound);
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn namespace_twice_test() {
+  let plugin = || {
+    AFPlugin::new()
+      .name("document")
+      .namespace("document")
+      .namespace("database")
+      .event("open", open_document)
+  };
+  let result = AFPluginDispatcher::builder()
+    .runtime(Arc::new(AFPluginRuntime::current()))
+    .plugin(plugin())
+    .build();
+  assert_eq!(
+    result.err().unwrap().code(),
+    DispatchErrorCode::DuplicateEvent
+  );
+
+  let dispatch = AFPluginDispatcher::builder()
+    .runtime(Arc::new(AFPluginRuntime::current()))
+    .build()
+    .unwrap();
+  assert!(dispatch.register_plugin(plugin()).is_err());
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn conflicting_plugins_test() {
+  let plugins = || {
+    vec![
+      AFPlugin::new().name("local").event("save", save_locally),
+      AFPlugin::new().name("remote").event("save", save_remotely),
+    ]
+  };
+  // The conflicting plugin is left out instead of crashing the dispatcher.
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(runtime, plugins()));
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("save")).await;
+  assert_eq!(resp.payload.as_ref(), b"saved locally");
+
+  // The dispatcher's policy applies to the plugins passed to the builder.
+  let overridden = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(Arc::new(AFPluginRuntime::new().unwrap()))
+      .plugins(plugins())
+      .duplicate_policy(DuplicatePolicy::Override)
+      .build()
+      .unwrap(),
+  );
+  let resp =
+    AFPluginDispatcher::async_send(overridden.as_ref(), AFPluginRequest::new("save")).await;
+  assert_eq!(resp.payload.as_ref(), b"saved remotely");
+
+  std::mem::forget(dispatch);
+  std::mem::forget(overridden);
+}
+
+static HOOKS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[tokio::test]
+async fn lifecycle_hooks_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new()
+      .name("document")
+      .event("hello", hello)
+      .on_start(record(&HOOKS, "start"))
+      .on_idle(record(&HOOKS, "idle"))
+      .on_stop(record(&HOOKS, "stop"))],
+  ));
+  dispatch.start().await.unwrap();
+  assert_eq!(*HOOKS.lock().unwrap(), vec!["start"]);
+
+  // The idle hook is called once the dispatcher has nothing to do.
+  AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("hello")).await;
+  tokio::time::timeout(Duration::from_secs(5), async {
+    while HOOKS.lock().unwrap().len() < 2 {
+      tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+  })
+  .await
+  .unwrap();
+
+  dispatch.shutdown(Duration::from_secs(5)).await.unwrap();
+  assert_eq!(*HOOKS.lock().unwrap(), vec!["start", "idle", "stop"]);
+
+  std::mem::forget(dispatch);
+}
+
+static ORDERED_HOOKS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[tokio::test]
+async fn depends_on_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![
+      AFPlugin::new()
+        .name("document")
+        .depends_on("user")
+        .event("open", open_document)
+        .on_start(record(&ORDERED_HOOKS, "document start"))
+        .on_stop(record(&ORDERED_HOOKS, "document stop")),
+      AFPlugin::new()
+        .name("user")
+        .event("sign_in", hello)
+        .on_start(record(&ORDERED_HOOKS, "user start"))
+        .on_stop(record(&ORDERED_HOOKS, "user stop")),
+    ],
+  ));
+  dispatch.start().await.unwrap();
+  // The dependency starts first, whatever the order of the plugins is.
+  assert_eq!(
+    *ORDERED_HOOKS.lock().unwrap(),
+    vec!["user start", "document start"]
+  );
+
+  // And it stops last.
+  dispatch.shutdown(Duration::from_secs(5)).await.unwrap();
+  assert_eq!(
+    *ORDERED_HOOKS.lock().unwrap(),
+    vec!["user start", "document start", "document stop", "user stop"]
+  );
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn missing_dependency_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new()
+      .name("document")
+      .depends_on("user")
+      .event("hello", hello)],
+  ));
+  assert!(dispatch.start().await.is_err());
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn list_events_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![
+      AFPlugin::new().name("greeting").event("hello", hello),
+      AFPlugin::new()
+        .name("document")
+        .event("open", open_document)
+        .event("extra", extra),
+    ],
+  ));
+
+  // The events are sorted.
+  let events = dispatch
+    .events()
+    .into_iter()
+    .map(|info| (info.event, info.plugin))
+    .collect::<Vec<_>>();
+  assert_eq!(
+    events,
+    vec![
+      (AFPluginEvent::from("extra"), "document".to_string()),
+      (AFPluginEvent::from("hello"), "greeting".to_string()),
+      (AFPluginEvent::from("open"), "document".to_string()),
+    ]
+  );
+
+  std::mem::forget(dispatch);
+}
+
+struct Workspace(String);
+
+async fn workspace_name(workspace: AFPluginState<Workspace>) -> String {
+  workspace.0.clone()
+}
+
+#[tokio::test]
+async fn shared_state_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![
+        AFPlugin::new()
+          .name("document")
+          .event("document", workspace_name),
+        AFPlugin::new()
+          .name("database")
+          .state(Workspace("database".to_string()))
+          .event("database", workspace_name),
+      ])
+      .state(Workspace("shared".to_string()))
+      .build()
+      .unwrap(),
+  );
+  let send =
+    |event: &str| AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new(event));
+  assert_eq!(send("document").await.payload.as_ref(), b"shared");
+
+  // The plugin's own state takes precedence.
+  assert_eq!(send("database").await.payload.as_ref(), b"database");
+
+  std::mem::forget(dispatch);
+}
+
+async fn read_offline() -> AFPluginEventResponse {
+  ResponseBuilder::Err().data("offline").build()
+}
+
+async fn read_cached() -> String {
+  "cached".to_string()
+}
+
+static OBSERVED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+async fn observe(content: String) -> String {
+  OBSERVED.lock().unwrap().push(content.clone());
+  content
+}
+
+#[tokio::test]
+async fn shared_event_policy_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![
+      AFPlugin::new().name("remote").event("read", read_offline),
+      AFPlugin::new()
+        .name("cache")
+        .duplicate_policy(DuplicatePolicy::FirstSuccess)
+        .event("read", read_cached),
+      AFPlugin::new().name("document").event("open", echo_open),
+      AFPlugin::new()
+        .name("analytics")
+        .duplicate_policy(DuplicatePolicy::Observe)
+        .event("open", observe),
+    ],
+  ));
+
+  // The next handler is called until one of them succeeds.
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("read")).await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(resp.payload.as_ref(), b"cached");
+
+  // The observer receives a copy of the request, its response is discarded.
+  let request = AFPluginRequest::new("open").payload("notes");
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.payload.as_ref(), b"notes opened");
+  tokio::time::timeout(Duration::from_secs(5), async {
+    while OBSERVED.lock().unwrap().is_empty() {
+      tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+  })
+  .await
+  .unwrap();
+  assert_eq!(*OBSERVED.lock().unwrap(), vec!["notes"]);
+
+  std::mem::forget(dispatch);
+}
+
+async fn echo_open(name: String) -> String {
+  format!("{} opened", name)
+}
+
+#[tokio::test]
+async fn bundle_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().name("greeting").event("hello", hello)],
+  ));
+  let bundle = AFPluginBundle::new("grid")
+    .plugin(AFPlugin::new().name("grid").event("grid", workspace_name))
+    .plugin(
+      AFPlugin::new()
+        .name("grid_sync")
+        .event("grid_sync", workspace_name),
+    )
+    .state(Workspace("grid".to_string()));
+  dispatch.register_bundle(bundle).unwrap();
+
+  // All the plugins of the bundle read the bundle's state.
+  let send =
+    |event: &str| AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new(event));
+  assert_eq!(send("grid").await.payload.as_ref(), b"grid");
+  assert_eq!(send("grid_sync").await.payload.as_ref(), b"grid");
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn health_check_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![
+      AFPlugin::new()
+        .name("document")
+        .event("open", open_document)
+        .health_check(|| Box::pin(async { AFPluginHealth::healthy() })),
+      AFPlugin::new()
+        .name("sync")
+        .event("sync", hello)
+        .health_check(|| Box::pin(async { AFPluginHealth::degraded("offline") })),
+      AFPlugin::new().name("greeting").event("hello", hello),
+    ],
+  ));
+
+  // The status is the worst of the checked plugins.
+  let health = dispatch.health().await;
+  assert_eq!(health.status, HealthStatus::Degraded);
+  assert_eq!(health.plugins.len(), 2);
+  assert_eq!(health.plugins[1].plugin, "sync");
+  assert_eq!(health.plugins[1].health.message.as_deref(), Some("offline"));
+
+  let resp =
+    AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new(HEALTH_EVENT)).await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+
+  std::mem::forget(dispatch);
+}
+
+static LAZY_BUILDS: AtomicUsize = AtomicUsize::new(0);
+
+async fn import_document() -> String {
+  "imported".to_string()
+}
+
+#[tokio::test]
+async fn lazy_plugin_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().name("import").lazy(vec!["import"], || {
+      Box::pin(async {
+        LAZY_BUILDS.fetch_add(1, Ordering::SeqCst);
+        Ok(
+          AFPlugin::new()
+            .name("import")
+            .event("import", import_document),
+        )
+      })
+    })],
+  ));
+  assert_eq!(LAZY_BUILDS.load(Ordering::SeqCst), 0);
+
+  let responses = futures::future::join_all(
+    (0..3)
+      .map(|_| AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("import"))),
+  )
+  .await;
+  for resp in responses {
+    assert_eq!(resp.status_code, StatusCode::Ok);
+    assert_eq!(resp.payload.as_ref(), b"imported");
+  }
+  // The concurrent first requests share the same build.
+  assert_eq!(LAZY_BUILDS.load(Ordering::SeqCst), 1);
+
+  std::mem::forget(dispatch);
+}
+
+async fn thread_name() -> String {
+  std::thread::current()
+    .name()
+    .unwrap_or_default()
+    .to_string()
+}
+
+async fn block_thread() -> String {
+  std::thread::sleep(Duration::from_millis(50));
+  "blocked".to_string()
+}
+
+#[cfg(not(feature = "local_set"))]
+#[tokio::test]
+async fn executor_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![
+      AFPlugin::new()
+        .name("database")
+        .executor(AFPluginExecutor::Blocking)
+        .event("query", block_thread),
+      AFPlugin::new()
+        .name("search")
+        .executor(AFPluginExecutor::Dedicated)
+        .event("index", thread_name),
+    ],
+  ));
+
+  let (query, index) = futures::join!(
+    AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("query")),
+    AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("index")),
+  );
+  assert_eq!(query.payload.as_ref(), b"blocked");
+  assert_eq!(index.payload.as_ref(), b"dispatch-search");
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn blocking_event_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new()
+      .name("database")
+      .blocking_event("query", block_thread)
+      .blocking_event("thread", thread_name)],
+  ));
+
+  let (query, thread) = futures::join!(
+    AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("query")),
+    AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("thread")),
+  );
+  assert_eq!(query.payload.as_ref(), b"blocked");
+  // The handlers run on the blocking pool instead of the async workers.
+  assert_eq!(thread.payload.as_ref(), b"dispatch-rt-blocking");
+
+  // The pool is already created by the handlers above.
+  assert!(lib_dispatch::runtime::set_blocking_threads(4).is_err());
+
+  std::mem::forget(dispatch);
+}
+
+static AUDITED: Mutex<Vec<(String, String, Option<String>)>> = Mutex::new(Vec::new());
+
+#[tokio::test]
+async fn audit_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let plugin = AFPlugin::new()
+    .name("document")
+    .event("open", open_document)
+    .event("import", import_document)
+    .mutating("import");
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![plugin])
+      .audit(|record: &AuditRecord| {
+        let entry = (
+          record.event.clone(),
+          record.plugin.clone(),
+          record.user_id.clone(),
+        );
+        AUDITED.lock().unwrap().push(entry);
+      })
+      .build()
+      .unwrap(),
+  );
+  let context = AFPluginContext::new().user_id("user-1");
+  let request = AFPluginRequest::new("import").context(context);
+  AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("open")).await;
+
+  // Only the mutating events are recorded.
+  assert_eq!(
+    *AUDITED.lock().unwrap(),
+    vec![(
+      "import".to_string(),
+      "document".to_string(),
+      Some("user-1".to_string())
+    )]
+  );
+
+  std::mem::forget(dispatch);
+}
@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+
+async fn echo(content: String) -> String {
+  content
+}
+
+fn echo_plugin() -> AFPlugin {
+  AFPlugin::new().name("echo").event("echo", echo)
+}
+
+#[tokio::test]
+async fn record_replay_test() {
+  let path = std::env::temp_dir().join(format!("dispatch-recording-{}.log", std::process::id()));
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![echo_plugin()])
+      .record_to(DispatchRecorder::create(&path).unwrap())
+      .build()
+      .unwrap(),
+  );
+  let request = AFPluginRequest::new("echo")
+    .payload("first")
+    .ordering_key("document")
+    .correlation_id("user");
+  AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  let request = AFPluginRequest::new("echo").payload("second");
+  AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  std::mem::forget(dispatch);
+
+  let replay = DispatchReplay::load(&path).unwrap();
+  let requests = replay.requests();
+  assert_eq!(requests.len(), 2);
+  assert_eq!(requests[0].event, AFPluginEvent::from("echo"));
+  assert_eq!(requests[0].ordering_key.as_deref(), Some("document"));
+  assert_eq!(requests[0].correlation_id.as_deref(), Some("user"));
+  assert_eq!(requests[1].ordering_key, None);
+
+  // The recorded requests are sent through a new dispatcher in the recorded order.
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(runtime, vec![echo_plugin()]));
+  let responses = replay
+    .replay(dispatch.as_ref(), ReplayTiming::AsFastAsPossible)
+    .await;
+  let payloads = responses
+    .iter()
+    .map(|response| response.payload.as_ref().to_vec())
+    .collect::<Vec<_>>();
+  assert_eq!(payloads, vec![b"first".to_vec(), b"second".to_vec()]);
+
+  std::mem::forget(dispatch);
+  std::fs::remove_file(&path).unwrap();
+}
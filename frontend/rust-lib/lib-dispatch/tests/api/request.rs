@@ -0,0 +1,582 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+use tokio::sync::oneshot;
+
+async fn slow() -> String {
+  tokio::time::sleep(Duration::from_secs(10)).await;
+  "done".to_string()
+}
+
+async fn echo(content: String) -> String {
+  content
+}
+
+fn request_plugin() -> AFPlugin {
+  AFPlugin::new()
+    .name("request")
+    .event("slow", slow)
+    .event("echo", echo)
+}
+
+#[tokio::test]
+async fn timeout_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(runtime, vec![request_plugin()]));
+  let request = AFPluginRequest::new("slow").timeout(Duration::from_millis(50));
+  let resp = tokio::time::timeout(
+    Duration::from_secs(5),
+    AFPluginDispatcher::async_send(dispatch.as_ref(), request),
+  )
+  .await
+  .expect("the handler is aborted after its timeout");
+  assert_eq!(resp.status_code, StatusCode::Timeout);
+  assert_eq!(resp.error_code(), Some(DispatchErrorCode::Timeout));
+
+  // The requests without the timeout are not affected.
+  let request = AFPluginRequest::new("echo").payload("hello");
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(resp.payload.as_ref(), b"hello");
+
+  std::mem::forget(dispatch);
+}
+
+static RELEASE: Mutex<Option<oneshot::Receiver<()>>> = Mutex::new(None);
+
+async fn block() -> String {
+  let release = RELEASE.lock().unwrap().take().unwrap();
+  let _ = release.await;
+  "released".to_string()
+}
+
+static QUEUED_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+async fn queued() -> String {
+  QUEUED_CALLS.fetch_add(1, Ordering::SeqCst);
+  "queued".to_string()
+}
+
+#[tokio::test]
+async fn cancel_running_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(runtime, vec![request_plugin()]));
+  let (handle, fut) =
+    AFPluginDispatcher::cancellable_async_send(dispatch.as_ref(), AFPluginRequest::new("slow"));
+  let cancel = async {
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    handle.cancel();
+  };
+  let (resp, _) = tokio::time::timeout(Duration::from_secs(5), async { tokio::join!(fut, cancel) })
+    .await
+    .expect("the cancelled request doesn't wait for its handler");
+  assert!(handle.is_cancelled());
+  assert_eq!(resp.status_code, StatusCode::Cancelled);
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn cancel_queued_test() {
+  let (release, rx) = oneshot::channel();
+  *RELEASE.lock().unwrap() = Some(rx);
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![AFPlugin::new()
+        .event("block", block)
+        .event("queued", queued)])
+      .max_concurrent(1)
+      .build()
+      .unwrap(),
+  );
+  let blocking =
+    AFPluginDispatcher::try_async_send(dispatch.as_ref(), AFPluginRequest::new("block")).unwrap();
+  let (handle, fut) =
+    AFPluginDispatcher::cancellable_async_send(dispatch.as_ref(), AFPluginRequest::new("queued"));
+  let cancel = async {
+    tokio::task::yield_now().await;
+    // The cancelled request is removed from the queue without waiting for the slot.
+    handle.cancel();
+  };
+  let (resp, _) = tokio::join!(fut, cancel);
+  assert_eq!(resp.status_code, StatusCode::Cancelled);
+
+  release.send(()).unwrap();
+  blocking.await;
+  assert_eq!(QUEUED_CALLS.load(Ordering::SeqCst), 0);
+
+  std::mem::forget(dispatch);
+}
+
+#[derive(Clone, Debug)]
+struct LockedError;
+
+impl Error for LockedError {
+  fn as_response(&self) -> AFPluginEventResponse {
+    ResponseBuilder::Err().data("database is locked").build()
+  }
+
+  fn is_retryable(&self) -> bool {
+    true
+  }
+}
+
+static SAVE_ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+
+async fn save() -> Result<String, LockedError> {
+  if SAVE_ATTEMPTS.fetch_add(1, Ordering::SeqCst) < 2 {
+    return Err(LockedError);
+  }
+  Ok("saved".to_string())
+}
+
+static LOCKED_ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+
+async fn locked() -> Result<String, LockedError> {
+  LOCKED_ATTEMPTS.fetch_add(1, Ordering::SeqCst);
+  Err(LockedError)
+}
+
+#[tokio::test]
+async fn retry_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![AFPlugin::new()
+        .name("document")
+        .event("save", save)
+        .event("locked", locked)])
+      .retry_policy(DispatchRetryPolicy::new(3).initial_backoff(Duration::from_millis(10)))
+      .build()
+      .unwrap(),
+  );
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("save")).await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(resp.payload.as_ref(), b"saved");
+  assert_eq!(SAVE_ATTEMPTS.load(Ordering::SeqCst), 3);
+
+  // The last error is returned once the attempts are used up.
+  let resp =
+    AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("locked")).await;
+  assert_eq!(resp.status_code, StatusCode::Err);
+  assert!(resp.is_retryable());
+  assert_eq!(LOCKED_ATTEMPTS.load(Ordering::SeqCst), 3);
+
+  std::mem::forget(dispatch);
+}
+
+static SYNC_ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+
+async fn sync(kind: String) -> Result<String, DispatchError> {
+  SYNC_ATTEMPTS.fetch_add(1, Ordering::SeqCst);
+  let err = DispatchError::from("server is down".to_string());
+  match kind.as_str() {
+    "transient" => Err(err.transient()),
+    _ => Err(err.unavailable()),
+  }
+}
+
+#[tokio::test]
+async fn error_kind_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![AFPlugin::new().event("sync", sync)])
+      .retry_policy(DispatchRetryPolicy::new(3).initial_backoff(Duration::from_millis(10)))
+      .build()
+      .unwrap(),
+  );
+  let request = AFPluginRequest::new("sync").payload("transient");
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.error_kind(), Some(DispatchErrorKind::Transient));
+  assert_eq!(SYNC_ATTEMPTS.load(Ordering::SeqCst), 3);
+
+  // The unavailable errors are not retried.
+  let request = AFPluginRequest::new("sync").payload("unavailable");
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.error_kind(), Some(DispatchErrorKind::Unavailable));
+  assert!(!resp.is_retryable());
+  assert_eq!(SYNC_ATTEMPTS.load(Ordering::SeqCst), 4);
+
+  std::mem::forget(dispatch);
+}
+
+static READ_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+async fn read(name: String) -> String {
+  READ_CALLS.fetch_add(1, Ordering::SeqCst);
+  tokio::time::sleep(Duration::from_millis(50)).await;
+  format!("read {}", name)
+}
+
+#[tokio::test]
+async fn coalesce_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new()
+      .name("document")
+      .event("read", read)
+      .coalesce("read")],
+  ));
+  let send = |name: &str| {
+    let request = AFPluginRequest::new("read").payload(name);
+    AFPluginDispatcher::async_send_with_response(dispatch.as_ref(), request)
+  };
+  let first = send("notes");
+  let second = send("notes");
+  let other = send("todos");
+  for pending in vec![first, second] {
+    let resp = pending.await;
+    assert_eq!(resp.status_code, StatusCode::Ok);
+    assert_eq!(resp.payload.as_ref(), b"read notes");
+  }
+  let resp = other.await;
+  assert_eq!(resp.payload.as_ref(), b"read todos");
+
+  // The identical requests shared the running handler.
+  assert_eq!(READ_CALLS.load(Ordering::SeqCst), 2);
+
+  std::mem::forget(dispatch);
+}
+
+async fn import(progress: AFPluginProgress) -> String {
+  for step in 1..=3 {
+    progress.send_response(ResponseBuilder::Ok().data(format!("{}/3", step)).build());
+  }
+  "imported".to_string()
+}
+
+static PROGRESS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[tokio::test]
+async fn progress_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("import", import)],
+  ));
+  let resp = AFPluginDispatcher::async_send_with_progress(
+    dispatch.as_ref(),
+    AFPluginRequest::new("import"),
+    |progress| {
+      Box::pin(async move {
+        let progress = String::from_utf8_lossy(progress.payload.as_ref()).to_string();
+        PROGRESS.lock().unwrap().push(progress);
+      })
+    },
+  )
+  .await;
+  assert_eq!(resp.payload.as_ref(), b"imported");
+
+  // The partial responses are delivered in order before the final one.
+  assert_eq!(*PROGRESS.lock().unwrap(), vec!["1/3", "2/3", "3/3"]);
+
+  std::mem::forget(dispatch);
+}
+
+async fn trace(correlation_id: CorrelationId) -> String {
+  correlation_id.into()
+}
+
+#[tokio::test]
+async fn correlation_id_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("trace", trace)],
+  ));
+  let request = AFPluginRequest::new("trace").correlation_id("import-1");
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.payload.as_ref(), b"import-1");
+  assert_eq!(resp.correlation_id.as_deref(), Some("import-1"));
+
+  // The correlation id is generated if absent.
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("trace")).await;
+  let correlation_id = resp.correlation_id.clone().unwrap();
+  assert!(!correlation_id.is_empty());
+  assert_eq!(resp.payload.as_ref(), correlation_id.as_bytes());
+
+  std::mem::forget(dispatch);
+}
+
+static DRAFTS_SAVED: AtomicUsize = AtomicUsize::new(0);
+static DOCUMENTS_SAVED: AtomicUsize = AtomicUsize::new(0);
+
+async fn save_draft() -> String {
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  DRAFTS_SAVED.fetch_add(1, Ordering::SeqCst);
+  "saved".to_string()
+}
+
+async fn save_document() -> String {
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  DOCUMENTS_SAVED.fetch_add(1, Ordering::SeqCst);
+  "saved".to_string()
+}
+
+#[tokio::test]
+async fn drop_response_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new()
+      .event("draft", save_draft)
+      .event("document", save_document)
+      .must_complete("document")],
+  ));
+  for event in ["draft", "document"] {
+    let pending =
+      AFPluginDispatcher::async_send_with_response(dispatch.as_ref(), AFPluginRequest::new(event));
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    drop(pending);
+  }
+  tokio::time::sleep(Duration::from_millis(200)).await;
+
+  // Dropping the response cancels the request, unless its event must complete.
+  assert_eq!(DRAFTS_SAVED.load(Ordering::SeqCst), 0);
+  assert_eq!(DOCUMENTS_SAVED.load(Ordering::SeqCst), 1);
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn request_builder_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(runtime, vec![request_plugin()]));
+  let resp = dispatch
+    .request("echo")
+    .payload("hello")
+    .priority(DispatchPriority::High)
+    .correlation_id("builder")
+    .send()
+    .await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(resp.payload.as_ref(), b"hello");
+  assert_eq!(resp.correlation_id.as_deref(), Some("builder"));
+
+  let request = dispatch
+    .request("echo")
+    .timeout(Duration::from_secs(1))
+    .build()
+    .unwrap();
+  assert_eq!(request.timeout, Some(Duration::from_secs(1)));
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn batch_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(runtime, vec![request_plugin()]));
+  let responses = AFPluginDispatcher::async_send_batch(
+    dispatch.as_ref(),
+    vec![
+      AFPluginRequest::new("echo").payload("first"),
+      AFPluginRequest::new("missing"),
+      AFPluginRequest::new("echo").payload("second"),
+    ],
+  )
+  .await;
+
+  // The responses are in the order of the requests.
+  assert_eq!(responses.len(), 3);
+  assert_eq!(responses[0].status_code, StatusCode::Ok);
+  assert_eq!(responses[0].payload.as_ref(), b"first");
+  assert_eq!(responses[1].status_code, StatusCode::NotFound);
+  assert_eq!(responses[2].status_code, StatusCode::Ok);
+  assert_eq!(responses[2].payload.as_ref(), b"second");
+
+  std::mem::forget(dispatch);
+}
+
+static RECEIVED_BUFFER: AtomicUsize = AtomicUsize::new(0);
+
+async fn receive_document(data: Bytes) -> String {
+  RECEIVED_BUFFER.store(data.as_ptr() as usize, Ordering::SeqCst);
+  format!("{} bytes", data.len())
+}
+
+#[tokio::test]
+async fn shared_payload_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("receive", receive_document)],
+  ));
+  let document = Bytes::from(vec![1u8; 1024]);
+  let request = AFPluginRequest::new("receive").payload(document.clone());
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.payload.as_ref(), b"1024 bytes");
+
+  // The handler reads the same buffer instead of a copy.
+  assert_eq!(
+    RECEIVED_BUFFER.load(Ordering::SeqCst),
+    document.as_ptr() as usize
+  );
+
+  std::mem::forget(dispatch);
+}
+
+static CHUNKS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+async fn export() -> AFPluginResponseStream {
+  let chunks = vec!["page 1", "page 2"]
+    .into_iter()
+    .map(|chunk| Ok(Bytes::from(chunk)));
+  AFPluginResponseStream::new(futures::stream::iter(chunks))
+}
+
+#[tokio::test]
+async fn stream_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("export", export)],
+  ));
+  let resp = AFPluginDispatcher::async_send_with_stream(
+    dispatch.as_ref(),
+    AFPluginRequest::new("export"),
+    |chunk| {
+      Box::pin(async move {
+        let chunk = String::from_utf8(chunk.payload.to_vec()).unwrap();
+        CHUNKS.lock().unwrap().push(chunk);
+      })
+    },
+  )
+  .await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(*CHUNKS.lock().unwrap(), vec!["page 1", "page 2"]);
+
+  std::mem::forget(dispatch);
+}
+
+static CREATE_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+async fn create() -> String {
+  CREATE_CALLS.fetch_add(1, Ordering::SeqCst);
+  "created".to_string()
+}
+
+#[tokio::test]
+async fn idempotency_key_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![AFPlugin::new()
+        .name("document")
+        .event("create", create)])
+      .idempotency_window(Duration::from_millis(200), 16)
+      .build()
+      .unwrap(),
+  );
+  let dispatcher = dispatch.as_ref();
+  let send = move || {
+    let request = AFPluginRequest::new("create").idempotency_key("create-1");
+    AFPluginDispatcher::async_send(dispatcher, request)
+  };
+  send().await;
+  // The retried request receives the response of the first one.
+  let resp = send().await;
+  assert_eq!(resp.payload.as_ref(), b"created");
+  assert_eq!(CREATE_CALLS.load(Ordering::SeqCst), 1);
+
+  // The request is handled again once the window is over.
+  tokio::time::sleep(Duration::from_millis(300)).await;
+  send().await;
+  assert_eq!(CREATE_CALLS.load(Ordering::SeqCst), 2);
+
+  std::mem::forget(dispatch);
+}
+
+async fn greet_user(ctx: AFPluginContext) -> String {
+  format!(
+    "{} {}",
+    ctx.locale.as_deref().unwrap_or("en"),
+    ctx.user_id.as_deref().unwrap_or("guest")
+  )
+}
+
+#[tokio::test]
+async fn context_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![request_plugin().event("greet", greet_user)],
+  ));
+  let context = AFPluginContext::new().user_id("user-1").locale("fr");
+  let request = AFPluginRequest::new("greet").context(context);
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.payload.as_ref(), b"fr user-1");
+
+  // The handler receives the default context if the request doesn't set it.
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("greet")).await;
+  assert_eq!(resp.payload.as_ref(), b"en guest");
+
+  // The request is timed out at the deadline.
+  let deadline = tokio::time::Instant::now() + Duration::from_millis(50);
+  let request = AFPluginRequest::new("slow").context(AFPluginContext::new().deadline(deadline));
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.status_code, StatusCode::Timeout);
+
+  std::mem::forget(dispatch);
+}
+
+async fn open_document(id: String) -> Result<String, DispatchError> {
+  let err = DispatchError::from(format!("{} is not found", id));
+  Err(err.localized(AFPluginErrorMessage::new("document.not_found").arg("id", id)))
+}
+
+fn translate(message: &AFPluginErrorMessage, locale: Option<&str>) -> Option<String> {
+  match (message.key.as_str(), locale) {
+    ("document.not_found", Some("fr")) => Some(format!(
+      "{} est introuvable",
+      message.get_arg("id").unwrap()
+    )),
+    _ => None,
+  }
+}
+
+#[tokio::test]
+async fn localizer_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![request_plugin().event("open", open_document)])
+      .localizer(translate)
+      .build()
+      .unwrap(),
+  );
+  let request = AFPluginRequest::new("open")
+    .payload("doc-1")
+    .context(AFPluginContext::new().locale("fr"));
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  let message = resp.error_message().unwrap();
+  assert_eq!(message.key, "document.not_found");
+  assert_eq!(message.get_arg("id"), Some("doc-1"));
+  assert_eq!(resp.localized_message(), Some("doc-1 est introuvable"));
+
+  // The message without a translation is left to the frontend.
+  let request = AFPluginRequest::new("open").payload("doc-1");
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.error_message().unwrap().key, "document.not_found");
+  assert_eq!(resp.localized_message(), None);
+
+  // The errors of the dispatcher are named by their codes.
+  let resp =
+    AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("unknown")).await;
+  let key = format!("dispatch.{}", DispatchErrorCode::Unhandled.as_str());
+  assert_eq!(resp.error_message().unwrap().key, key);
+
+  std::mem::forget(dispatch);
+}
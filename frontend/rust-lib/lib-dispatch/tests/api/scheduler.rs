@@ -0,0 +1,303 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+use tokio::sync::oneshot;
+
+static RELEASE: Mutex<Option<oneshot::Receiver<()>>> = Mutex::new(None);
+
+async fn block() -> String {
+  let release = RELEASE.lock().unwrap().take().unwrap();
+  let _ = release.await;
+  "released".to_string()
+}
+
+static PRIORITY_STARTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+async fn sync_priority(name: String) -> String {
+  PRIORITY_STARTED.lock().unwrap().push(name.clone());
+  name
+}
+
+#[tokio::test]
+async fn priority_test() {
+  let (release, rx) = oneshot::channel();
+  *RELEASE.lock().unwrap() = Some(rx);
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![AFPlugin::new()
+        .event("block", block)
+        .event("sync", sync_priority)])
+      .max_concurrent(1)
+      .build()
+      .unwrap(),
+  );
+  let send = |request: AFPluginRequest| {
+    AFPluginDispatcher::try_async_send(dispatch.as_ref(), request).unwrap()
+  };
+  // The blocking request takes the only slot, so the others wait in their lanes.
+  let blocking = send(AFPluginRequest::new("block"));
+  let sync = |name: &str, priority: DispatchPriority| {
+    send(
+      AFPluginRequest::new("sync")
+        .payload(name)
+        .priority(priority),
+    )
+  };
+  let background = sync("background", DispatchPriority::Background);
+  let normal = sync("normal", DispatchPriority::Normal);
+  let high = sync("high", DispatchPriority::High);
+
+  release.send(()).unwrap();
+  blocking.await;
+  for pending in vec![background, normal, high] {
+    assert_eq!(pending.await.status_code, StatusCode::Ok);
+  }
+
+  // The higher lanes are started first.
+  assert_eq!(
+    *PRIORITY_STARTED.lock().unwrap(),
+    vec!["high", "normal", "background"]
+  );
+
+  std::mem::forget(dispatch);
+}
+
+static RELEASE_OVERTAKE: Mutex<Option<oneshot::Receiver<()>>> = Mutex::new(None);
+
+async fn block_overtake() -> String {
+  let release = RELEASE_OVERTAKE.lock().unwrap().take().unwrap();
+  let _ = release.await;
+  "released".to_string()
+}
+
+static OVERTAKE_STARTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+async fn sync_overtake(name: String) -> String {
+  OVERTAKE_STARTED.lock().unwrap().push(name.clone());
+  name
+}
+
+#[tokio::test]
+async fn priority_overtake_test() {
+  let (release, rx) = oneshot::channel();
+  *RELEASE_OVERTAKE.lock().unwrap() = Some(rx);
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![AFPlugin::new()
+        .event("block", block_overtake)
+        .event("sync", sync_overtake)])
+      .max_concurrent(1)
+      .build()
+      .unwrap(),
+  );
+  let send = |request: AFPluginRequest| {
+    AFPluginDispatcher::try_async_send(dispatch.as_ref(), request).unwrap()
+  };
+  let blocking = send(AFPluginRequest::new("block"));
+  // Queued before the high-priority request is sent.
+  let background = send(
+    AFPluginRequest::new("sync")
+      .payload("background")
+      .priority(DispatchPriority::Background),
+  );
+  tokio::task::yield_now().await;
+  let high = send(
+    AFPluginRequest::new("sync")
+      .payload("high")
+      .priority(DispatchPriority::High),
+  );
+
+  release.send(()).unwrap();
+  blocking.await;
+  assert_eq!(high.await.payload.as_ref(), b"high");
+  assert_eq!(background.await.payload.as_ref(), b"background");
+  assert_eq!(
+    *OVERTAKE_STARTED.lock().unwrap(),
+    vec!["high", "background"]
+  );
+
+  std::mem::forget(dispatch);
+}
+
+static ORDERED_WRITES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+async fn write(content: String) -> String {
+  if content == "first" {
+    tokio::time::sleep(Duration::from_millis(50)).await;
+  }
+  ORDERED_WRITES.lock().unwrap().push(content.clone());
+  content
+}
+
+#[tokio::test]
+async fn ordering_key_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("write", write)],
+  ));
+  let send = |content: &str, ordering_key: Option<&str>| {
+    let request = AFPluginRequest::new("write").payload(content);
+    let request = match ordering_key {
+      None => request,
+      Some(key) => request.ordering_key(key),
+    };
+    AFPluginDispatcher::try_async_send(dispatch.as_ref(), request).unwrap()
+  };
+  let first = send("first", Some("document"));
+  let second = send("second", Some("document"));
+  let other = send("other", None);
+  for pending in vec![first, second, other] {
+    assert_eq!(pending.await.status_code, StatusCode::Ok);
+  }
+
+  // The second write waits for the slow first one, the unordered one doesn't.
+  assert_eq!(
+    *ORDERED_WRITES.lock().unwrap(),
+    vec!["other", "first", "second"]
+  );
+
+  std::mem::forget(dispatch);
+}
+
+static RUNNING_IMPORTS: AtomicUsize = AtomicUsize::new(0);
+static MAX_RUNNING_IMPORTS: AtomicUsize = AtomicUsize::new(0);
+
+async fn import() -> String {
+  let running = RUNNING_IMPORTS.fetch_add(1, Ordering::SeqCst) + 1;
+  MAX_RUNNING_IMPORTS.fetch_max(running, Ordering::SeqCst);
+  tokio::time::sleep(Duration::from_millis(20)).await;
+  RUNNING_IMPORTS.fetch_sub(1, Ordering::SeqCst);
+  "imported".to_string()
+}
+
+#[tokio::test]
+async fn plugin_concurrency_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().max_concurrent(1).event("import", import)],
+  ));
+  let send = || AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("import"));
+  let (first, second, third) = tokio::join!(send(), send(), send());
+  for resp in vec![first, second, third] {
+    assert_eq!(resp.status_code, StatusCode::Ok);
+  }
+
+  // The plugin's handlers run one by one.
+  assert_eq!(MAX_RUNNING_IMPORTS.load(Ordering::SeqCst), 1);
+
+  std::mem::forget(dispatch);
+}
+
+static HELLO_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+async fn hello() -> String {
+  HELLO_CALLS.fetch_add(1, Ordering::SeqCst);
+  "say hello".to_string()
+}
+
+#[tokio::test]
+async fn pause_resume_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new().event("hello", hello)],
+  ));
+  dispatch.pause();
+  assert!(dispatch.is_paused());
+
+  let pending =
+    AFPluginDispatcher::try_async_send(dispatch.as_ref(), AFPluginRequest::new("hello")).unwrap();
+  tokio::time::sleep(Duration::from_millis(20)).await;
+  assert_eq!(HELLO_CALLS.load(Ordering::SeqCst), 0);
+
+  // The request that is buffered while paused is started after resuming.
+  dispatch.resume();
+  assert!(!dispatch.is_paused());
+  let resp = pending.await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(resp.payload.as_ref(), b"say hello");
+  assert_eq!(HELLO_CALLS.load(Ordering::SeqCst), 1);
+
+  std::mem::forget(dispatch);
+}
+
+static HIGH_WATER: Mutex<Vec<HighWater>> = Mutex::new(Vec::new());
+
+async fn say_hi() -> String {
+  "hi".to_string()
+}
+
+#[tokio::test]
+async fn high_water_mark_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![AFPlugin::new().event("hi", say_hi)])
+      .high_water_mark(1, |high_water: HighWater| {
+        HIGH_WATER.lock().unwrap().push(high_water)
+      })
+      .build()
+      .unwrap(),
+  );
+  dispatch.pause();
+  let send =
+    || AFPluginDispatcher::try_async_send(dispatch.as_ref(), AFPluginRequest::new("hi")).unwrap();
+  let first = send();
+  assert!(HIGH_WATER.lock().unwrap().is_empty());
+  let second = send();
+  assert_eq!(
+    *HIGH_WATER.lock().unwrap(),
+    vec![HighWater::Exceeded { queued: 2 }]
+  );
+  assert_eq!(dispatch.metrics().queued, 2);
+
+  dispatch.resume();
+  assert_eq!(first.await.status_code, StatusCode::Ok);
+  assert_eq!(second.await.status_code, StatusCode::Ok);
+  let high_water = HIGH_WATER.lock().unwrap().clone();
+  assert_eq!(high_water.len(), 2);
+  assert!(matches!(high_water[1], HighWater::Recovered { .. }));
+  assert_eq!(dispatch.metrics().queued, 0);
+
+  std::mem::forget(dispatch);
+}
+
+#[tokio::test]
+async fn load_shedding_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![AFPlugin::new().event("hi", say_hi)])
+      .max_concurrent(1)
+      .load_shedding(DispatchLoadShedding::BoundedQueue(1))
+      .build()
+      .unwrap(),
+  );
+  dispatch.pause();
+  let send =
+    || AFPluginDispatcher::try_async_send(dispatch.as_ref(), AFPluginRequest::new("hi")).unwrap();
+  let first = send();
+  let second = send();
+
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("hi")).await;
+  assert_eq!(resp.status_code, StatusCode::Busy);
+  assert_eq!(resp.error_code(), Some(DispatchErrorCode::Busy));
+  assert_eq!(dispatch.metrics().shed, 1);
+
+  dispatch.resume();
+  assert_eq!(first.await.status_code, StatusCode::Ok);
+  assert_eq!(second.await.status_code, StatusCode::Ok);
+
+  std::mem::forget(dispatch);
+}
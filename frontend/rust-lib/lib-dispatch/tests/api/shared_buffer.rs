@@ -0,0 +1,30 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+
+#[tokio::test]
+async fn shared_buffer_test() {
+  // The host writes the payload of a request in place, and the handler side takes it over.
+  let buffer = alloc_shared_buffer(4);
+  unsafe { std::ptr::copy_nonoverlapping(b"note".as_ptr(), buffer.ptr as *mut u8, 4) };
+  let payload = take_shared_buffer(buffer.handle).unwrap();
+  assert_eq!(payload.as_ref(), b"note");
+  assert!(take_shared_buffer(buffer.handle).is_none());
+
+  // The host reads the payload of a response in place, then releases it with the event.
+  let buffer = share_buffer(Bytes::from_static(b"snapshot"));
+  let bytes = unsafe { std::slice::from_raw_parts(buffer.ptr, buffer.len) };
+  assert_eq!(bytes, b"snapshot");
+
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(runtime, vec![]));
+  let request =
+    AFPluginRequest::new(RELEASE_BUFFER_EVENT).payload(buffer.handle.as_raw().to_string());
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert!(!release_shared_buffer(buffer.handle));
+
+  std::mem::forget(dispatch);
+}
@@ -0,0 +1,68 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+
+async fn hello() -> String {
+  "say hello".to_string()
+}
+
+static FLAKY_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Crashes the first two dispatches of `flaky` and every dispatch of `crash`.
+fn crash_interceptor(request: &mut AFPluginRequest) -> Option<AFPluginEventResponse> {
+  if request.event == AFPluginEvent::from("flaky") && FLAKY_CALLS.fetch_add(1, Ordering::SeqCst) < 2
+  {
+    panic!("flaky interceptor");
+  }
+  if request.event == AFPluginEvent::from("crash") {
+    panic!("broken interceptor");
+  }
+  None
+}
+
+static CRASHES: Mutex<Vec<(String, usize)>> = Mutex::new(Vec::new());
+
+#[tokio::test]
+async fn supervisor_restart_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let plugin = AFPlugin::new()
+    .name("greeting")
+    .event("flaky", hello)
+    .event("crash", hello);
+  let dispatch = Arc::new(
+    AFPluginDispatcher::builder()
+      .runtime(runtime)
+      .plugins(vec![plugin])
+      .interceptor(crash_interceptor)
+      .supervisor(
+        DispatchSupervisor::new()
+          .max_restarts(2)
+          .on_crash(|crash: &DispatchCrash| {
+            let event = crash.event.as_str().to_string();
+            CRASHES.lock().unwrap().push((event, crash.restarts));
+          }),
+      )
+      .build()
+      .unwrap(),
+  );
+
+  // The request succeeds once it's restarted after each crash.
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("flaky")).await;
+  assert_eq!(resp.status_code, StatusCode::Ok);
+  assert_eq!(resp.payload.as_ref(), b"say hello");
+  assert_eq!(FLAKY_CALLS.load(Ordering::SeqCst), 3);
+  assert_eq!(
+    *CRASHES.lock().unwrap(),
+    vec![("flaky".to_string(), 0), ("flaky".to_string(), 1)]
+  );
+
+  // It's responded with the panic error once the restarts are used up.
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), AFPluginRequest::new("crash")).await;
+  assert_eq!(resp.status_code, StatusCode::Internal);
+  assert_eq!(resp.error_code(), Some(DispatchErrorCode::HandlerPanic));
+  assert_eq!(CRASHES.lock().unwrap().len(), 5);
+
+  std::mem::forget(dispatch);
+}
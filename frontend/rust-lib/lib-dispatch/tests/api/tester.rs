@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use lib_dispatch::prelude::*;
+use lib_dispatch::test::EventTester;
+
+async fn create_document(name: String) -> String {
+  format!("created {}", name)
+}
+
+async fn slow() -> String {
+  tokio::time::sleep(Duration::from_secs(10)).await;
+  "done".to_string()
+}
+
+fn document_plugin() -> AFPlugin {
+  AFPlugin::new()
+    .name("document")
+    .event("create_document", create_document)
+    .event("slow", slow)
+}
+
+#[tokio::test]
+async fn event_tester_test() {
+  let tester = EventTester::new(vec![document_plugin()]).await;
+  let resp = tester
+    .event("create_document")
+    .request(|request| request.payload("notes"))
+    .async_send()
+    .await
+    .assert_ok();
+  assert_eq!(resp.response().payload.as_ref(), b"created notes");
+
+  tester.teardown().await;
+}
+
+#[tokio::test]
+async fn event_tester_timeout_test() {
+  let tester = EventTester::new(vec![document_plugin()]).await;
+  tester.pause_time();
+  let pending = tester
+    .event("slow")
+    .request(|request| request.timeout(Duration::from_secs(1)))
+    .send();
+  tester.advance(Duration::from_secs(1)).await;
+  pending.await.assert_status(StatusCode::Timeout);
+
+  tester.teardown().await;
+}
@@ -0,0 +1,114 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+use lib_dispatch::test::EventTester;
+
+static SAVE_ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+
+async fn save() -> Result<String, DispatchError> {
+  if SAVE_ATTEMPTS.fetch_add(1, Ordering::SeqCst) < 2 {
+    return Err(DispatchError::from("database is locked".to_string()).transient());
+  }
+  Ok("saved".to_string())
+}
+
+#[tokio::test]
+async fn retry_backoff_test() {
+  let dispatcher = AFPluginDispatcher::builder()
+    .runtime(Arc::new(AFPluginRuntime::current()))
+    .plugin(AFPlugin::new().name("document").event("save", save))
+    .retry_policy(DispatchRetryPolicy::new(3).initial_backoff(Duration::from_secs(1)))
+    .build()
+    .unwrap();
+  let tester = EventTester::with_dispatcher(dispatcher).await;
+  tester.pause_time();
+  let pending = tester.event("save").send();
+  tester.advance(Duration::ZERO).await;
+  assert_eq!(SAVE_ATTEMPTS.load(Ordering::SeqCst), 1);
+
+  // The first backoff is the initial one.
+  tester.advance(Duration::from_millis(999)).await;
+  assert_eq!(SAVE_ATTEMPTS.load(Ordering::SeqCst), 1);
+  tester.advance(Duration::from_millis(1)).await;
+  assert_eq!(SAVE_ATTEMPTS.load(Ordering::SeqCst), 2);
+
+  // The second one is doubled.
+  tester.advance(Duration::from_secs(1)).await;
+  assert_eq!(SAVE_ATTEMPTS.load(Ordering::SeqCst), 2);
+  tester.advance(Duration::from_secs(1)).await;
+  assert_eq!(SAVE_ATTEMPTS.load(Ordering::SeqCst), 3);
+
+  let resp = pending.await.assert_ok();
+  assert_eq!(resp.response().payload.as_ref(), b"saved");
+
+  tester.teardown().await;
+}
+
+static READ_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+async fn read() -> String {
+  READ_CALLS.fetch_add(1, Ordering::SeqCst);
+  "document".to_string()
+}
+
+#[tokio::test]
+async fn cache_ttl_test() {
+  let tester = EventTester::new(vec![AFPlugin::new()
+    .name("document")
+    .event("read", read)
+    .cache("read", Duration::from_secs(60))])
+  .await;
+  tester.pause_time();
+  tester.event("read").async_send().await.assert_ok();
+  tester.advance(Duration::from_secs(59)).await;
+  let resp = tester.event("read").async_send().await.assert_ok();
+  assert_eq!(resp.response().payload.as_ref(), b"document");
+  assert_eq!(READ_CALLS.load(Ordering::SeqCst), 1);
+
+  // The expired response is not used.
+  tester.advance(Duration::from_secs(1)).await;
+  tester.event("read").async_send().await.assert_ok();
+  assert_eq!(READ_CALLS.load(Ordering::SeqCst), 2);
+
+  tester.teardown().await;
+}
+
+static CREATE_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+async fn create() -> String {
+  CREATE_CALLS.fetch_add(1, Ordering::SeqCst);
+  "created".to_string()
+}
+
+#[tokio::test]
+async fn idempotency_window_test() {
+  let dispatcher = AFPluginDispatcher::builder()
+    .runtime(Arc::new(AFPluginRuntime::current()))
+    .plugin(AFPlugin::new().name("document").event("create", create))
+    .idempotency_window(Duration::from_secs(60), 16)
+    .build()
+    .unwrap();
+  let tester = EventTester::with_dispatcher(dispatcher).await;
+  tester.pause_time();
+  let send = || {
+    tester
+      .event("create")
+      .request(|request| request.idempotency_key("create-1"))
+      .async_send()
+  };
+  send().await.assert_ok();
+  tester.advance(Duration::from_secs(59)).await;
+  let resp = send().await.assert_ok();
+  assert_eq!(resp.response().payload.as_ref(), b"created");
+  assert_eq!(CREATE_CALLS.load(Ordering::SeqCst), 1);
+
+  // The request is handled again once the window is over.
+  tester.advance(Duration::from_secs(1)).await;
+  send().await.assert_ok();
+  assert_eq!(CREATE_CALLS.load(Ordering::SeqCst), 2);
+
+  tester.teardown().await;
+}
@@ -0,0 +1,69 @@
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use protobuf::well_known_types::StringValue;
+use protobuf::Message;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+use lib_dispatch::prelude::*;
+use lib_dispatch::runtime::AFPluginRuntime;
+
+struct WorkspaceName(String);
+
+impl TryFrom<Bytes> for WorkspaceName {
+  type Error = protobuf::ProtobufError;
+
+  fn try_from(bytes: Bytes) -> Result<Self, Self::Error> {
+    let name = StringValue::parse_from_bytes(&bytes)?;
+    Ok(WorkspaceName(name.value))
+  }
+}
+
+impl Validate for WorkspaceName {
+  fn validate(&self) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::new();
+    if self.0.trim().is_empty() {
+      errors.add("name", ValidationError::new("empty"));
+    }
+    if errors.is_empty() {
+      Ok(())
+    } else {
+      Err(errors)
+    }
+  }
+}
+
+async fn rename_workspace(name: AFPluginData<WorkspaceName>) -> String {
+  name.into_inner().0
+}
+
+fn payload(name: &str) -> Vec<u8> {
+  let mut value = StringValue::new();
+  value.value = name.to_string();
+  value.write_to_bytes().unwrap()
+}
+
+#[tokio::test]
+async fn validate_test() {
+  let runtime = Arc::new(AFPluginRuntime::new().unwrap());
+  let dispatch = Arc::new(AFPluginDispatcher::new(
+    runtime,
+    vec![AFPlugin::new()
+      .name("workspace")
+      .event("rename", rename_workspace)
+      .validate::<_, WorkspaceName>("rename")],
+  ));
+  let request = AFPluginRequest::new("rename").payload(payload("Notes"));
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.payload.as_ref(), b"Notes");
+
+  // The invalid payload is rejected before the handler runs, and the failed fields are listed.
+  let request = AFPluginRequest::new("rename").payload(payload(" "));
+  let resp = AFPluginDispatcher::async_send(dispatch.as_ref(), request).await;
+  assert_eq!(resp.status_code, StatusCode::InvalidParams);
+  let message = String::from_utf8(resp.payload.to_vec()).unwrap();
+  assert!(message.ends_with("name: empty"), "{}", message);
+
+  std::mem::forget(dispatch);
+}